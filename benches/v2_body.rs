@@ -0,0 +1,40 @@
+//! Measures URL encoding a V2 message with many recipients and an attachment, the way
+//! `SGClient::send` does.
+
+#[macro_use]
+extern crate criterion;
+extern crate sendgrid;
+
+use criterion::Criterion;
+
+use sendgrid::mail::Mail;
+use sendgrid::sg_client::make_post_body;
+
+fn message() -> Mail<'static> {
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_subject("Your weekly report");
+    m.add_html("<p>Here is your report.</p>".repeat(200));
+    m.add_text("Here is your report.\n".repeat(200));
+
+    for i in 0..100 {
+        m.add_to(format!("user{}@example.com", i));
+        m.add_to_name(format!("User {}", i));
+    }
+    m.add_header("X-Report", "weekly").unwrap();
+    m.attachments
+        .insert(String::from("report.bin"), vec![7; 256 * 1024]);
+
+    m
+}
+
+fn v2_body(c: &mut Criterion) {
+    let m = message();
+
+    c.bench_function("url encoded body", |b| {
+        b.iter(|| make_post_body(&m).unwrap())
+    });
+}
+
+criterion_group!(benches, v2_body);
+criterion_main!(benches);
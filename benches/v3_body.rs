@@ -0,0 +1,63 @@
+//! Compares building a V3 body by serializing into a growing `String` with serializing
+//! it straight into a presized buffer, the way `V3Sender::send` does, and with reusing
+//! one buffer through `SGMailV3::write_json`.
+
+#[macro_use]
+extern crate criterion;
+extern crate sendgrid;
+extern crate serde_json;
+
+use criterion::Criterion;
+
+use sendgrid::v3::{Attachment, Content, Email, Personalization, SGMailV3};
+
+fn message() -> SGMailV3 {
+    let mut m = SGMailV3::new();
+    let mut from = Email::new();
+    from.set_email("me@example.com");
+    m.set_from(from);
+    m.set_subject("Your weekly report");
+
+    let mut c = Content::new();
+    c.set_content_type("text/html");
+    c.set_value(&"<p>Here is your report.</p>".repeat(200));
+    m.add_content(c);
+
+    for i in 0..100 {
+        let mut to = Email::new();
+        to.set_email(&format!("user{}@example.com", i));
+        let mut p = Personalization::new();
+        p.add_to(to);
+        m.add_personalization(p);
+    }
+
+    let mut a = Attachment::new();
+    a.set_content(&vec![7; 1024 * 1024]);
+    a.set_filename("report.bin");
+    m.add_attachment(a);
+
+    m
+}
+
+fn v3_body(c: &mut Criterion) {
+    let m = message();
+    let size = serde_json::to_vec(&m).unwrap().len();
+
+    c.bench_function("string body", |b| {
+        b.iter(|| serde_json::to_string(&m).unwrap())
+    });
+    c.bench_function("presized byte body", |b| {
+        b.iter(|| {
+            let mut body = Vec::with_capacity(size);
+            serde_json::to_writer(&mut body, &m).unwrap();
+            body
+        })
+    });
+    let mut buffer = Vec::new();
+    c.bench_function("reused byte body", |b| {
+        b.iter(|| m.write_json(&mut buffer).unwrap())
+    });
+}
+
+criterion_group!(benches, v3_body);
+criterion_main!(benches);
@@ -1,32 +1,31 @@
 extern crate sendgrid;
 
-use sendgrid::mail::Mail;
-use sendgrid::sg_client::SGClient;
+use sendgrid::v2::{EmailAddress, Mail, SGClient};
 
 fn main() {
     let mut env_vars = std::env::vars();
     let api_key_check = env_vars.find(|var| var.0 == "SENDGRID_API_KEY");
-    let api_key: String;
-    match api_key_check {
-        Some(key) => api_key = key.1,
+    let api_key = match api_key_check {
+        Some(key) => key.1,
         None => panic!("Must supply API key in environment variables to use!"),
-    }
+    };
 
     let sg = SGClient::new(api_key);
 
     let mut mail_info = Mail::new();
     mail_info.add_to("you@example.com");
-    mail_info.add_from("some@some.com");
+    mail_info.set_from(EmailAddress::with_name("some@some.com", "Test"));
     mail_info.add_subject("Rust is rad");
     mail_info.add_html("<h1>Hello from SendGrid!</h1>");
-    mail_info.add_from_name("Test");
-    mail_info.add_header("x-cool", "indeed");
+    mail_info
+        .add_header("x-cool", "indeed")
+        .expect("valid header");
 
     let mut x_smtpapi = String::new();
     x_smtpapi.push_str(r#"{"unique_args":{"test":7}}"#);
     mail_info.add_x_smtpapi(x_smtpapi);
 
-    match sg.send(mail_info) {
+    match sg.send(&mail_info) {
         Err(err) => println!("Error: {}", err),
         Ok(body) => println!("Response: {}", body),
     };
@@ -0,0 +1,43 @@
+//! Encodes messages built from arbitrary names, subjects, filenames and content as a V2
+//! form body, and checks that decoding the body gives back exactly what went in.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sendgrid;
+extern crate url;
+
+use sendgrid::mail::Mail;
+use sendgrid::sg_client::make_post_body;
+use url::form_urlencoded;
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let mut fields = input.split('\u{0}');
+    let name = fields.next().unwrap_or("").to_string();
+    let subject = fields.next().unwrap_or("").to_string();
+    let filename = fields.next().unwrap_or("").to_string();
+    let content = fields.next().unwrap_or("").to_string();
+
+    let mut m = Mail::new().without_auto_date();
+    m.add_to("you@example.com");
+    m.add_to_name(name.clone());
+    m.add_subject(subject.clone());
+    m.add_text(content.clone());
+    m.attachments.insert(filename.clone(), content.clone().into_bytes());
+
+    // Hostile attachment names are rejected, everything else has to survive encoding.
+    let body = match make_post_body(&m) {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    let decoded: Vec<(String, String)> = form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect();
+    let get = |key: &str| decoded.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+
+    assert_eq!(get("toname[]"), Some(name.as_str()));
+    assert_eq!(get("subject"), Some(subject.as_str()));
+    assert_eq!(get("text"), Some(content.as_str()));
+    assert_eq!(get(&format!("files[{}]", filename)), Some(content.as_str()));
+});
@@ -0,0 +1,47 @@
+//! Encodes V3 messages built from arbitrary names, subjects, filenames and content as
+//! JSON, and checks that the body is valid JSON that holds exactly what went in.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate sendgrid;
+extern crate serde_json;
+
+use sendgrid::v3::{Attachment, Content, Email, Personalization, SGMailV3};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let mut fields = input.split('\u{0}');
+    let name = fields.next().unwrap_or("");
+    let subject = fields.next().unwrap_or("");
+    let filename = fields.next().unwrap_or("");
+    let content = fields.next().unwrap_or("");
+
+    let mut to = Email::new();
+    to.set_email("you@example.com");
+    to.set_name(name);
+    let mut p = Personalization::new();
+    p.add_to(to);
+
+    let mut m = SGMailV3::new();
+    m.set_subject(subject);
+    m.add_personalization(p);
+    let mut c = Content::new();
+    c.set_content_type("text/plain");
+    c.set_value(content);
+    m.add_content(c);
+    let mut a = Attachment::new();
+    a.set_filename(filename);
+    a.set_content(content.as_bytes());
+    m.add_attachment(a);
+
+    let mut body = Vec::new();
+    m.write_json(&mut body).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(value["subject"], subject);
+    assert_eq!(value["personalizations"][0]["to"][0]["name"], name);
+    assert_eq!(value["content"][0]["value"], content);
+    assert_eq!(value["attachments"][0]["filename"], filename);
+    assert_eq!(m.canonical_json().unwrap(), m.canonical_json().unwrap());
+});
@@ -0,0 +1,457 @@
+//! Snapshots of the configuration of an account, for backups and for comparing the
+//! accounts of different environments.
+
+use domains::AuthenticatedDomain;
+use errors::SendgridResult;
+use scopes::Endpoint;
+use suppressions::SuppressionGroup;
+use templates::{Template, TemplateVersion};
+use v3::V3Sender;
+use webhook_settings::EventWebhookSettings;
+
+use reqwest::Method;
+
+use std::fmt;
+
+/// Reading everything that goes into an `AccountSnapshot`.
+pub struct AccountConfig;
+
+impl Endpoint for AccountConfig {
+    const SCOPES: &'static [&'static str] = &[
+        "asm.groups.read",
+        "mail_settings.read",
+        "templates.read",
+        "templates.versions.read",
+        "tracking_settings.read",
+        "user.webhooks.event.settings.read",
+        "whitelabel.read",
+    ];
+}
+
+/// Changing an account to match an `AccountSnapshot`.
+pub struct AccountConfigApply;
+
+impl Endpoint for AccountConfigApply {
+    const SCOPES: &'static [&'static str] = &[
+        "asm.groups.create",
+        "asm.groups.update",
+        "templates.create",
+        "templates.versions.activate.create",
+        "templates.versions.create",
+        "templates.versions.update",
+        "user.webhooks.event.settings.update",
+    ];
+}
+
+/// A mail or tracking setting, and whether it is turned on.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Setting {
+    /// The name used in the API, e.g. `bcc` or `click_tracking`.
+    pub name: String,
+    pub title: String,
+    pub description: String,
+    pub enabled: bool,
+    /// Other fields of the setting, such as the values it is configured with.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The configuration of an account at one point in time. It serializes to JSON or any
+/// other serde format, so it can be stored as a backup or compared with the snapshot of
+/// another environment.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct AccountSnapshot {
+    /// The transactional templates with the content of all their versions.
+    pub templates: Vec<Template>,
+    pub suppression_groups: Vec<SuppressionGroup>,
+    pub mail_settings: Vec<Setting>,
+    pub tracking_settings: Vec<Setting>,
+    pub authenticated_domains: Vec<AuthenticatedDomain>,
+    pub event_webhook: Option<EventWebhookSettings>,
+}
+
+/// A change that makes an account match a desired snapshot. Unsubscribe groups,
+/// templates and template versions are matched by name, since their IDs differ between
+/// accounts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigChange {
+    CreateSuppressionGroup(SuppressionGroup),
+    /// Update the description or default flag of the group with the given ID.
+    UpdateSuppressionGroup(u32, SuppressionGroup),
+    UpdateEventWebhook(EventWebhookSettings),
+    /// Create a template with the given name and generation, along with its versions.
+    CreateTemplate(String, String, Vec<TemplateVersion>),
+    /// Add a version to the template with the given ID.
+    CreateTemplateVersion(String, TemplateVersion),
+    /// Update the subject and content of the version with the given IDs.
+    UpdateTemplateVersion(String, String, TemplateVersion),
+    /// Activate the version with the given IDs.
+    ActivateTemplateVersion(String, String),
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigChange::CreateSuppressionGroup(ref group) => {
+                write!(f, "create unsubscribe group {:?}", group.name)
+            }
+            ConfigChange::UpdateSuppressionGroup(id, ref group) => {
+                write!(f, "update unsubscribe group {:?} ({})", group.name, id)
+            }
+            ConfigChange::UpdateEventWebhook(ref settings) => {
+                write!(f, "update the Event Webhook posting to {:?}", settings.url)
+            }
+            ConfigChange::CreateTemplate(ref name, _, ref versions) => write!(
+                f,
+                "create template {:?} with {} versions",
+                name,
+                versions.len()
+            ),
+            ConfigChange::CreateTemplateVersion(ref template_id, ref version) => {
+                write!(
+                    f,
+                    "create version {:?} of template {}",
+                    version.name, template_id
+                )
+            }
+            ConfigChange::UpdateTemplateVersion(ref template_id, _, ref version) => {
+                write!(
+                    f,
+                    "update version {:?} of template {}",
+                    version.name, template_id
+                )
+            }
+            ConfigChange::ActivateTemplateVersion(ref template_id, ref version_id) => {
+                write!(
+                    f,
+                    "activate version {} of template {}",
+                    version_id, template_id
+                )
+            }
+        }
+    }
+}
+
+impl AccountSnapshot {
+    /// The changes that make the account this snapshot was taken of match a desired
+    /// snapshot. Only the unsubscribe groups, the Event Webhook settings and the templates
+    /// are compared, and nothing is ever deleted: groups, templates and versions that are
+    /// not in the desired snapshot are left alone.
+    pub fn changes_to(&self, desired: &AccountSnapshot) -> Vec<ConfigChange> {
+        let mut changes = Vec::new();
+
+        for group in &desired.suppression_groups {
+            match self
+                .suppression_groups
+                .iter()
+                .find(|g| g.name == group.name)
+            {
+                None => changes.push(ConfigChange::CreateSuppressionGroup(group.clone())),
+                Some(current)
+                    if current.description != group.description
+                        || current.is_default != group.is_default =>
+                {
+                    changes.push(ConfigChange::UpdateSuppressionGroup(
+                        current.id,
+                        group.clone(),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+
+        if let Some(ref webhook) = desired.event_webhook {
+            let unchanged = self
+                .event_webhook
+                .as_ref()
+                .is_some_and(|current| without_extra(current) == without_extra(webhook));
+            if !unchanged {
+                changes.push(ConfigChange::UpdateEventWebhook(without_extra(webhook)));
+            }
+        }
+
+        for template in &desired.templates {
+            let current = match self.templates.iter().find(|t| t.name == template.name) {
+                Some(current) => current,
+                None => {
+                    changes.push(ConfigChange::CreateTemplate(
+                        template.name.clone(),
+                        template.generation.clone(),
+                        template.versions.clone(),
+                    ));
+                    continue;
+                }
+            };
+            for version in &template.versions {
+                match current.versions.iter().find(|v| v.name == version.name) {
+                    None => changes.push(ConfigChange::CreateTemplateVersion(
+                        current.id.clone(),
+                        version.clone(),
+                    )),
+                    Some(existing) => {
+                        if existing.subject != version.subject
+                            || existing.html_content != version.html_content
+                            || existing.plain_content != version.plain_content
+                        {
+                            changes.push(ConfigChange::UpdateTemplateVersion(
+                                current.id.clone(),
+                                existing.id.clone(),
+                                version.clone(),
+                            ));
+                        }
+                        if version.active == 1 && existing.active != 1 {
+                            changes.push(ConfigChange::ActivateTemplateVersion(
+                                current.id.clone(),
+                                existing.id.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+// The settings without the fields that are only read from the API.
+fn without_extra(settings: &EventWebhookSettings) -> EventWebhookSettings {
+    #[allow(unused_mut)]
+    let mut settings = settings.clone();
+    #[cfg(feature = "extra-fields")]
+    settings.extra.clear();
+    settings
+}
+
+#[derive(Serialize)]
+struct GroupBody<'a> {
+    name: &'a str,
+    description: &'a str,
+    is_default: bool,
+}
+
+#[derive(Serialize)]
+struct NewTemplate<'a> {
+    name: &'a str,
+    generation: &'a str,
+}
+
+#[derive(Serialize)]
+struct VersionUpdate<'a> {
+    name: &'a str,
+    subject: &'a str,
+    html_content: &'a str,
+    plain_content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct SettingList {
+    result: Vec<Setting>,
+}
+
+impl V3Sender {
+    /// Get the mail settings, such as `bcc` or `footer`, and whether they are turned on.
+    pub fn mail_settings(&self) -> SendgridResult<Vec<Setting>> {
+        let list: SettingList = self.api_get("/mail_settings")?;
+        Ok(list.result)
+    }
+
+    /// Get the tracking settings, such as `click_tracking` or `open_tracking`, and
+    /// whether they are turned on.
+    pub fn tracking_settings(&self) -> SendgridResult<Vec<Setting>> {
+        let list: SettingList = self.api_get("/tracking_settings")?;
+        Ok(list.result)
+    }
+
+    /// Gather the templates, unsubscribe groups, mail and tracking settings,
+    /// authenticated domains and Event Webhook settings of the account into one
+    /// snapshot. This makes a request for every template on top of one per part.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let snapshot = sender.export_account_config()?;
+    /// fs::write("sendgrid-production.json", serde_json::to_vec_pretty(&snapshot)?)?;
+    /// ```
+    pub fn export_account_config(&self) -> SendgridResult<AccountSnapshot> {
+        let mut templates = Vec::new();
+        for template in self.templates()? {
+            templates.push(self.template(&template.id)?);
+        }
+
+        Ok(AccountSnapshot {
+            templates,
+            suppression_groups: self.suppression_groups()?,
+            mail_settings: self.mail_settings()?,
+            tracking_settings: self.tracking_settings()?,
+            authenticated_domains: self.authenticated_domains()?,
+            event_webhook: Some(self.event_webhook_settings()?),
+        })
+    }
+
+    /// The changes `apply_account_config` would make to match a desired snapshot,
+    /// without making them, e.g. to show them for review or to detect drift in CI.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let desired: AccountSnapshot = serde_json::from_slice(&fs::read("sendgrid.json")?)?;
+    /// for change in sender.diff_account_config(&desired)? {
+    ///     println!("{}", change);
+    /// }
+    /// ```
+    pub fn diff_account_config(
+        &self,
+        desired: &AccountSnapshot,
+    ) -> SendgridResult<Vec<ConfigChange>> {
+        Ok(self.export_account_config()?.changes_to(desired))
+    }
+
+    /// Create and update the unsubscribe groups, Event Webhook settings and templates of
+    /// the account to match a desired snapshot, and return the changes that were made.
+    /// Nothing is deleted. The changes are made in order and stop at the first error.
+    pub fn apply_account_config(
+        &self,
+        desired: &AccountSnapshot,
+    ) -> SendgridResult<Vec<ConfigChange>> {
+        let changes = self.diff_account_config(desired)?;
+        for change in &changes {
+            self.apply_config_change(change)?;
+        }
+        Ok(changes)
+    }
+
+    /// Make one change to the account.
+    pub fn apply_config_change(&self, change: &ConfigChange) -> SendgridResult<()> {
+        match *change {
+            ConfigChange::CreateSuppressionGroup(ref group) => {
+                self.api_send_json(Method::Post, "/asm/groups", &group_body(group))?;
+            }
+            ConfigChange::UpdateSuppressionGroup(id, ref group) => {
+                let path = format!("/asm/groups/{}", id);
+                self.api_send_json(Method::Patch, &path, &group_body(group))?;
+            }
+            ConfigChange::UpdateEventWebhook(ref settings) => {
+                let path = "/user/webhooks/event/settings";
+                self.api_send_json(Method::Patch, path, &without_extra(settings))?;
+            }
+            ConfigChange::CreateTemplate(ref name, ref generation, ref versions) => {
+                let body = NewTemplate { name, generation };
+                let res = self.api_send_json(Method::Post, "/templates", &body)?;
+                let template: Template = self.read_json(res)?;
+                for version in versions {
+                    let created = self.create_template_version(&template.id, version)?;
+                    if version.active == 1 {
+                        self.activate_template_version(&template.id, &created.id)?;
+                    }
+                }
+            }
+            ConfigChange::CreateTemplateVersion(ref template_id, ref version) => {
+                let created = self.create_template_version(template_id, version)?;
+                if version.active == 1 {
+                    self.activate_template_version(template_id, &created.id)?;
+                }
+            }
+            ConfigChange::UpdateTemplateVersion(ref template_id, ref version_id, ref version) => {
+                let body = VersionUpdate {
+                    name: &version.name,
+                    subject: &version.subject,
+                    html_content: &version.html_content,
+                    plain_content: &version.plain_content,
+                };
+                let path = format!("/templates/{}/versions/{}", template_id, version_id);
+                self.api_send_json(Method::Patch, &path, &body)?;
+            }
+            ConfigChange::ActivateTemplateVersion(ref template_id, ref version_id) => {
+                self.activate_template_version(template_id, version_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn group_body(group: &SuppressionGroup) -> GroupBody<'_> {
+    GroupBody {
+        name: &group.name,
+        description: &group.description,
+        is_default: group.is_default,
+    }
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let list: SettingList = ::serde_json::from_str(
+        r#"{"result":[{"name":"bcc","title":"BCC","description":"Copy every message","enabled":true}]}"#,
+    )
+    .unwrap();
+    assert!(list.result[0].enabled);
+
+    let snapshot = AccountSnapshot {
+        mail_settings: list.result,
+        event_webhook: Some(EventWebhookSettings {
+            enabled: true,
+            url: String::from("https://example.com/events"),
+            bounce: true,
+            ..EventWebhookSettings::default()
+        }),
+        ..AccountSnapshot::default()
+    };
+    let json = ::serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        ::serde_json::from_str::<AccountSnapshot>(&json).unwrap(),
+        snapshot
+    );
+    assert!(json.contains(r#""suppression_groups":[]"#));
+}
+
+#[test]
+fn config_changes() {
+    let templates = |json: &str| -> Vec<Template> { ::serde_json::from_str(json).unwrap() };
+    let group = |name: &str, description: &str| SuppressionGroup {
+        id: 7,
+        name: String::from(name),
+        description: String::from(description),
+        ..SuppressionGroup::default()
+    };
+
+    let current = AccountSnapshot {
+        suppression_groups: vec![group("News", "Weekly news"), group("Old", "")],
+        templates: templates(
+            r#"[{"id":"d-1","name":"Welcome","versions":[
+                {"id":"v1","name":"one","active":1,"subject":"Hi"},
+                {"id":"v2","name":"two","active":0,"subject":"Hello"}]}]"#,
+        ),
+        event_webhook: Some(EventWebhookSettings::default()),
+        ..AccountSnapshot::default()
+    };
+    let desired = AccountSnapshot {
+        suppression_groups: vec![group("News", "Monthly news"), group("Alerts", "")],
+        templates: templates(
+            r#"[{"id":"x","name":"Welcome","versions":[
+                {"name":"one","active":0,"subject":"Hi"},
+                {"name":"two","active":1,"subject":"Hello!"},
+                {"name":"three","subject":"Hey"}]},
+               {"id":"y","name":"Receipt","generation":"dynamic","versions":[]}]"#,
+        ),
+        event_webhook: Some(EventWebhookSettings::default()),
+        ..AccountSnapshot::default()
+    };
+
+    let changes = current.changes_to(&desired);
+    let descriptions: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        descriptions,
+        vec![
+            "update unsubscribe group \"News\" (7)",
+            "create unsubscribe group \"Alerts\"",
+            "update version \"two\" of template d-1",
+            "activate version v2 of template d-1",
+            "create version \"three\" of template d-1",
+            "create template \"Receipt\" with 0 versions",
+        ]
+    );
+    assert!(current.changes_to(&current).is_empty());
+}
@@ -0,0 +1,227 @@
+//! Exporting the Email Activity feed as CSV.
+
+#[cfg(feature = "csv")]
+use cursor::Cursor;
+use errors::{SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use v3::V3Sender;
+
+#[cfg(feature = "csv")]
+use std::io::Read;
+#[cfg(feature = "csv")]
+use std::thread;
+#[cfg(feature = "csv")]
+use std::time::Duration;
+
+#[cfg(feature = "csv")]
+use csv;
+#[cfg(feature = "csv")]
+use download::ResumableDownload;
+use reqwest::Method;
+use url::form_urlencoded::byte_serialize;
+
+// How long to wait between checks of whether an export is ready, and how often to check
+// before giving up.
+#[cfg(feature = "csv")]
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+#[cfg(feature = "csv")]
+const MAX_POLLS: usize = 120;
+
+// The kind of cursor of `ActivityRows`.
+#[cfg(feature = "csv")]
+const ROWS_CURSOR: &str = "activity";
+
+/// Exporting the Email Activity feed, which needs the Email Activity add-on.
+pub struct Activity;
+
+impl Endpoint for Activity {
+    const SCOPES: &'static [&'static str] = &["email_activity.read"];
+}
+
+/// The answer to an export request.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ExportRequest {
+    /// The ID used to download the export once it is ready.
+    pub id: Option<String>,
+    pub status: String,
+    pub message: Option<String>,
+    /// Other fields of the answer to the export request.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    presigned_url: Option<String>,
+}
+
+/// One message of an activity export.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ActivityRecord {
+    pub message_id: String,
+    pub from_email: String,
+    pub to_email: String,
+    pub subject: String,
+    pub status: String,
+    pub opens_count: Option<u64>,
+    pub clicks_count: Option<u64>,
+    /// When the last event happened, as reported by SendGrid.
+    pub last_event_time: String,
+    /// Other fields of the message, beyond the summary the activity feed lists.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// An iterator over the records of a downloaded activity export, which are decoded as
+/// they are read.
+#[cfg(feature = "csv")]
+pub struct ActivityRows<R = ResumableDownload> {
+    records: csv::DeserializeRecordsIntoIter<R, ActivityRecord>,
+    export_id: Option<String>,
+    read: usize,
+}
+
+// Where an `ActivityRows` is, as saved in its cursor.
+#[cfg(feature = "csv")]
+#[derive(Deserialize, Serialize)]
+struct RowsPosition {
+    export_id: String,
+    row: usize,
+}
+
+#[cfg(feature = "csv")]
+impl<R: Read> ActivityRows<R> {
+    fn from_reader(reader: R) -> ActivityRows<R> {
+        ActivityRows {
+            records: csv::Reader::from_reader(reader).into_deserialize(),
+            export_id: None,
+            read: 0,
+        }
+    }
+
+    /// The position of the next record, to resume from with
+    /// `V3Sender::resume_activity_export` while SendGrid still keeps the export, which is
+    /// three days.
+    pub fn cursor(&self) -> Option<Cursor> {
+        let position = RowsPosition {
+            export_id: self.export_id.clone()?,
+            row: self.read,
+        };
+        Some(Cursor::encode(ROWS_CURSOR, &position).expect("positions always serialize"))
+    }
+}
+
+#[cfg(feature = "csv")]
+impl<R: Read> Iterator for ActivityRows<R> {
+    type Item = SendgridResult<ActivityRecord>;
+
+    fn next(&mut self) -> Option<SendgridResult<ActivityRecord>> {
+        let record = self.records.next()?;
+        self.read += 1;
+        Some(record.map_err(Into::into))
+    }
+}
+
+impl V3Sender {
+    /// Ask SendGrid to export the messages matching an Email Activity query as CSV.
+    pub fn request_activity_export(&self, query: &str) -> SendgridResult<ExportRequest> {
+        let query: String = byte_serialize(query.as_bytes()).collect();
+        let res = self.api_request(
+            Method::Post,
+            &format!("/messages/download?query={}", query),
+            None,
+        )?;
+        self.read_json(res)
+    }
+
+    /// Get the URL an export can be downloaded from, or `None` if it isn't ready yet.
+    pub fn activity_export_url(&self, id: &str) -> SendgridResult<Option<String>> {
+        match self.api_get::<Download>(&format!("/messages/download/{}", id)) {
+            Ok(download) => Ok(download.presigned_url),
+            Err(e) => match *e.kind() {
+                SendgridErrorKind::Api(404, _) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Export the messages matching an Email Activity query, wait for the export to be
+    /// ready and stream its records. This blocks while SendGrid prepares the export,
+    /// which can take several minutes. The download resumes where it stopped when the
+    /// connection drops.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for record in sender.export_activity("status=\"bounced\"")? {
+    ///     println!("{} bounced", record?.to_email);
+    /// }
+    /// ```
+    #[cfg(feature = "csv")]
+    pub fn export_activity(&self, query: &str) -> SendgridResult<ActivityRows> {
+        let export = self.request_activity_export(query)?;
+        let id = match export.id {
+            Some(id) => id,
+            None => {
+                let reason = export.message.unwrap_or(export.status);
+                return Err(SendgridErrorKind::ActivityExport(reason).into());
+            }
+        };
+
+        for _ in 0..MAX_POLLS {
+            if let Some(url) = self.activity_export_url(&id)? {
+                let mut rows = ActivityRows::from_reader(self.download_resumable(&url)?);
+                rows.export_id = Some(id);
+                return Ok(rows);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Err(SendgridErrorKind::ActivityExport(format!("export {} was never ready", id)).into())
+    }
+
+    /// Download an activity export again and skip to the record a cursor saved from
+    /// `ActivityRows::cursor` points to. Fails with `InvalidCursor` when the cursor is for
+    /// another listing.
+    #[cfg(feature = "csv")]
+    pub fn resume_activity_export(&self, cursor: &Cursor) -> SendgridResult<ActivityRows> {
+        let position: RowsPosition = cursor.decode(ROWS_CURSOR)?;
+        let url = match self.activity_export_url(&position.export_id)? {
+            Some(url) => url,
+            None => {
+                let reason = format!("export {} is no longer available", position.export_id);
+                return Err(SendgridErrorKind::ActivityExport(reason).into());
+            }
+        };
+        let mut rows = ActivityRows::from_reader(self.download_resumable(&url)?);
+        rows.export_id = Some(position.export_id);
+        while rows.read < position.row && rows.next().is_some() {}
+        Ok(rows)
+    }
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn activity_rows() {
+    let csv =
+        "message_id,from_email,to_email,subject,status,opens_count,clicks_count,last_event_time\n\
+               abc,me@example.com,you@example.com,Hi,delivered,2,0,2018-01-01T10:00:00Z\n\
+               def,me@example.com,them@example.com,Hi,bounced,,,\n";
+
+    let records: Vec<ActivityRecord> = ActivityRows::from_reader(csv.as_bytes())
+        .collect::<SendgridResult<_>>()
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].opens_count, Some(2));
+    assert_eq!(records[1].status, "bounced");
+    assert_eq!(records[1].opens_count, None);
+
+    let mut rows = ActivityRows::from_reader(csv.as_bytes());
+    assert!(rows.cursor().is_none());
+    rows.export_id = Some(String::from("export"));
+    rows.next();
+    let position: RowsPosition = rows.cursor().unwrap().decode(ROWS_CURSOR).unwrap();
+    assert_eq!((position.export_id.as_str(), position.row), ("export", 1));
+}
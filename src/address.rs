@@ -0,0 +1,219 @@
+use errors::{SendgridErrorKind, SendgridResult};
+use mail::EmailAddress;
+
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use url::idna;
+
+// Parse an RFC 5322 address list like `Alice <a@example.com>, "Doe, John"
+// <j@example.com>, b@example.com (Bob)`. Groups are flattened, and a comment after a
+// bare address is used as its display name like mail clients do.
+pub fn parse_list(list: &str) -> SendgridResult<Vec<EmailAddress<'static>>> {
+    let invalid = || SendgridErrorKind::InvalidAddress(list.to_string());
+    let mut addresses = Vec::new();
+    let mut entry = Entry::default();
+    let mut chars = list.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let quoted = read_quoted(&mut chars).ok_or_else(invalid)?;
+                entry.raw.push('"');
+                entry
+                    .raw
+                    .push_str(&quoted.replace('\\', "\\\\").replace('"', "\\\""));
+                entry.raw.push('"');
+                entry.phrase.push_str(&quoted);
+            }
+            '(' => {
+                let comment = read_comment(&mut chars).ok_or_else(invalid)?;
+                entry.comment.get_or_insert(comment);
+            }
+            '<' => {
+                let mut addr = String::new();
+                loop {
+                    match chars.next() {
+                        Some('>') => break,
+                        Some(c) => addr.push(c),
+                        None => return Err(invalid().into()),
+                    }
+                }
+                if entry.angle.is_some() {
+                    return Err(invalid().into());
+                }
+                entry.angle = Some(addr);
+            }
+            // The display name of a group, which isn't kept.
+            ':' if entry.angle.is_none() => entry = Entry::default(),
+            ',' | ';' => {
+                if let Some(address) = entry.finish().map_err(|_| invalid())? {
+                    addresses.push(address);
+                }
+                entry = Entry::default();
+            }
+            c => {
+                entry.raw.push(c);
+                entry.phrase.push(c);
+            }
+        }
+    }
+    if let Some(address) = entry.finish().map_err(|_| invalid())? {
+        addresses.push(address);
+    }
+
+    Ok(addresses)
+}
+
+// Check that an address has a local part and a domain and nothing that would need
+// quoting in a header. Local parts and domains can be internationalized (RFC 6531), in
+// which case the domain has to be a valid IDN.
+pub fn validate(email: &str) -> SendgridResult<()> {
+    let invalid = || SendgridErrorKind::InvalidAddress(email.to_string());
+    let at = email.rfind('@').ok_or_else(invalid)?;
+    let (local, domain) = (&email[..at], &email[at + 1..]);
+
+    let quoted = local.len() > 1 && local.starts_with('"') && local.ends_with('"');
+    let bad_local = |c: char| c.is_whitespace() || c.is_control() || "<>()[],;:\"".contains(c);
+    let bad_domain = |c: char| c.is_whitespace() || c.is_control() || "<>()[],;:\"@".contains(c);
+    if local.is_empty()
+        || (!quoted && local.contains(bad_local))
+        || domain.is_empty()
+        || domain.contains(bad_domain)
+        || domain.starts_with('.')
+        || domain.ends_with('.')
+        || (!domain.is_ascii() && idna::domain_to_ascii(domain).is_err())
+    {
+        return Err(invalid().into());
+    }
+    Ok(())
+}
+
+// Convert an internationalized domain to its ASCII form with punycode, which every mail
+// server understands. Non-ASCII local parts are kept as they are, as they can't be
+// converted and need the receiving server to support SMTPUTF8. Addresses whose domain
+// isn't a valid IDN are left alone.
+pub fn encode(email: &str) -> Cow<'_, str> {
+    let at = match email.rfind('@') {
+        Some(at) if !email[at + 1..].is_ascii() => at,
+        _ => return Cow::Borrowed(email),
+    };
+
+    match idna::domain_to_ascii(&email[at + 1..]) {
+        Ok(domain) => Cow::Owned(format!("{}@{}", &email[..at], domain)),
+        Err(_) => Cow::Borrowed(email),
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    // The text outside of angle brackets as written, for bare addresses.
+    raw: String,
+    // The same text with quotes removed, for display names.
+    phrase: String,
+    angle: Option<String>,
+    comment: Option<String>,
+}
+
+impl Entry {
+    fn finish(self) -> SendgridResult<Option<EmailAddress<'static>>> {
+        let phrase = collapse_whitespace(&self.phrase);
+        let comment = self.comment.map(|c| collapse_whitespace(&c));
+
+        let (email, name) = match self.angle {
+            Some(angle) => {
+                let name = Some(phrase).filter(|p| !p.is_empty()).or(comment);
+                (angle.trim().to_string(), name)
+            }
+            None => {
+                let raw = self.raw.trim();
+                if raw.is_empty() {
+                    return Ok(None);
+                }
+                (raw.to_string(), comment)
+            }
+        };
+
+        validate(&email)?;
+        Ok(Some(EmailAddress {
+            email: Cow::Owned(email),
+            name: name.filter(|n| !n.is_empty()).map(Cow::Owned),
+        }))
+    }
+}
+
+// Read a quoted string after its opening quote, resolving escapes.
+fn read_quoted(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut quoted = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(quoted),
+            '\\' => quoted.push(chars.next()?),
+            c => quoted.push(c),
+        }
+    }
+}
+
+// Read a comment after its opening parenthesis. Comments can be nested.
+fn read_comment(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut comment = String::new();
+    let mut depth = 1;
+    loop {
+        match chars.next()? {
+            '(' => {
+                depth += 1;
+                comment.push('(');
+            }
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(comment);
+                }
+                comment.push(')');
+            }
+            '\\' => comment.push(chars.next()?),
+            c => comment.push(c),
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn address_lists() {
+    let list = r#"Alice <a@example.com>, "Doe, John" <j@example.com>,
+                  b@example.com (Bob (the builder)), friends: c@example.com, <d@example.com>;,"#;
+    let addresses = parse_list(list).unwrap();
+    assert_eq!(
+        addresses,
+        vec![
+            EmailAddress::with_name("a@example.com", "Alice"),
+            EmailAddress::with_name("j@example.com", "Doe, John"),
+            EmailAddress::with_name("b@example.com", "Bob (the builder)"),
+            EmailAddress::new("c@example.com"),
+            EmailAddress::new("d@example.com"),
+        ]
+    );
+
+    assert_eq!(
+        parse_list(r#""john doe"@example.com"#).unwrap(),
+        vec![EmailAddress::new(r#""john doe"@example.com"#)]
+    );
+    assert!(parse_list("Alice <a@example.com").is_err());
+    assert!(parse_list("not an address").is_err());
+    assert!(parse_list("a@example.com b@example.com").is_err());
+}
+
+#[test]
+fn internationalized_addresses() {
+    assert!(validate("josé@bücher.de").is_ok());
+    assert!(validate("用户@例子.广告").is_ok());
+    assert!(validate("a@\u{e000}ü.de").is_err());
+
+    assert_eq!(encode("josé@bücher.de"), "josé@xn--bcher-kva.de");
+    assert_eq!(encode("用户@例子.广告"), "用户@xn--fsqu00a.xn--4rr70v");
+    assert_eq!(encode("a@example.com"), "a@example.com");
+}
@@ -0,0 +1,63 @@
+use errors::{SendgridErrorKind, SendgridResult};
+use v3::{MAX_ATTACHMENTS, MAX_ATTACHMENTS_SIZE, MAX_ATTACHMENT_SIZE};
+
+// Check a list of attachment names and sizes against the limits SendGrid enforces, so
+// too large messages fail with a list of the offending attachments instead of an opaque
+// 413 from the API.
+pub fn check<'a, I>(attachments: I) -> SendgridResult<()>
+where
+    I: IntoIterator<Item = (&'a str, usize)>,
+{
+    let mut problems = Vec::new();
+    let mut count = 0;
+    let mut total = 0;
+
+    for (name, size) in attachments {
+        if size > MAX_ATTACHMENT_SIZE {
+            problems.push(format!(
+                "{} is {} bytes, over the limit of {} bytes",
+                name, size, MAX_ATTACHMENT_SIZE
+            ));
+        }
+        count += 1;
+        total += size;
+    }
+
+    if count > MAX_ATTACHMENTS {
+        problems.push(format!(
+            "there are {} attachments, over the limit of {}",
+            count, MAX_ATTACHMENTS
+        ));
+    }
+    if total > MAX_ATTACHMENTS_SIZE {
+        problems.push(format!(
+            "the attachments are {} bytes in total, over the limit of {} bytes",
+            total, MAX_ATTACHMENTS_SIZE
+        ));
+    }
+
+    if !problems.is_empty() {
+        return Err(SendgridErrorKind::InvalidAttachments(problems).into());
+    }
+    Ok(())
+}
+
+#[test]
+fn attachment_limits() {
+    assert!(check(vec![("small.txt", 10), ("other.txt", 20)]).is_ok());
+
+    let err = check(vec![
+        ("huge.bin", MAX_ATTACHMENT_SIZE + 1),
+        ("big.bin", MAX_ATTACHMENT_SIZE),
+        ("more.bin", MAX_ATTACHMENT_SIZE),
+    ])
+    .unwrap_err();
+    match *err.kind() {
+        SendgridErrorKind::InvalidAttachments(ref problems) => {
+            assert_eq!(problems.len(), 2);
+            assert!(problems[0].starts_with("huge.bin is"));
+            assert!(problems[1].starts_with("the attachments are"));
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+}
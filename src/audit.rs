@@ -0,0 +1,213 @@
+//! An audit trail of the changes a sender makes, for environments that have to keep a
+//! record of every message sent and every change to the account. Records are redacted:
+//! they name the endpoint and the fields of the body, but never their values.
+
+use errors::{SendgridError, SendgridErrorKind};
+
+use serde_json::{self, Value};
+
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// How a recorded request ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuditOutcome {
+    /// SendGrid responded with this status, which may be an error status.
+    Status(u16),
+    /// The request failed without a response, e.g. because the connection was refused,
+    /// with the kind of error. The message of the error is left out, as it can name the
+    /// URL of the request.
+    Failed(String),
+}
+
+/// A redacted record of a request that sent mail or changed the account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    /// The HTTP method of the request.
+    pub method: String,
+    /// The path of the request without its query, with path segments that contain an
+    /// email address replaced by `[redacted]`.
+    pub endpoint: String,
+    /// The size of the body and the names of its top level fields.
+    pub summary: String,
+    /// How the request ended, after any retries with fallback keys and hosts.
+    pub outcome: AuditOutcome,
+    /// When the request was made.
+    pub timestamp: SystemTime,
+}
+
+impl AuditRecord {
+    /// Whether SendGrid accepted the request.
+    pub fn succeeded(&self) -> bool {
+        match self.outcome {
+            AuditOutcome::Status(status) => (200..300).contains(&status),
+            AuditOutcome::Failed(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} ({}): ", self.method, self.endpoint, self.summary)?;
+        match self.outcome {
+            AuditOutcome::Status(status) => write!(f, "{}", status),
+            AuditOutcome::Failed(ref e) => write!(f, "failed, {}", e),
+        }
+    }
+}
+
+/// Where a sender writes its audit records, set with `V3Sender::set_audit_sink`.
+/// Implement this on top of the append-only store the audit trail is kept in. It is
+/// called on the thread that made the request, after the request finished.
+pub trait AuditSink: Send + Sync {
+    /// Record a request.
+    fn record(&self, record: &AuditRecord);
+}
+
+/// An audit sink that keeps the records in memory.
+#[derive(Debug, Default)]
+pub struct MemoryAuditSink {
+    records: Mutex<Vec<AuditRecord>>,
+}
+
+impl MemoryAuditSink {
+    /// Construct a new, empty sink.
+    pub fn new() -> MemoryAuditSink {
+        MemoryAuditSink::default()
+    }
+
+    /// The records so far, oldest first.
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn record(&self, record: &AuditRecord) {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(record.clone());
+    }
+}
+
+// Whether requests with a method are recorded. Reads are left out.
+pub(crate) fn is_mutating(method: &str) -> bool {
+    method != "GET" && method != "HEAD" && method != "OPTIONS"
+}
+
+// Build the redacted record of a request, with the summary of its body.
+pub(crate) fn record(
+    method: &str,
+    path: &str,
+    summary: String,
+    outcome: AuditOutcome,
+    timestamp: SystemTime,
+) -> AuditRecord {
+    let endpoint = path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .map(|segment| {
+            if segment.contains('@') || segment.contains("%40") {
+                "[redacted]"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    AuditRecord {
+        method: String::from(method),
+        endpoint,
+        summary,
+        outcome,
+        timestamp,
+    }
+}
+
+// The outcome of a request that failed, without the URL or the values that are part of
+// the message of most errors.
+pub(crate) fn failure(error: &SendgridError) -> AuditOutcome {
+    let reason = match *error.kind() {
+        SendgridErrorKind::ReqwestError(ref e) => {
+            match e.get_ref().and_then(|e| e.downcast_ref::<io::Error>()) {
+                Some(e) => format!("the request failed: {}", e.kind()),
+                None => String::from("the request failed"),
+            }
+        }
+        ref kind => String::from(kind.description()),
+    };
+    AuditOutcome::Failed(reason)
+}
+
+// Summarize a request body by its size and the names of its top level fields.
+pub(crate) fn summarize(body: Option<&[u8]>) -> String {
+    let body = match body {
+        Some(body) => body,
+        None => return String::from("no body"),
+    };
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Object(ref fields)) if !fields.is_empty() => {
+            let mut names: Vec<&str> = fields.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            format!("{} byte body with {}", body.len(), names.join(", "))
+        }
+        Ok(Value::Array(items)) => format!("{} byte body with {} items", body.len(), items.len()),
+        _ => format!("{} byte body", body.len()),
+    }
+}
+
+#[test]
+fn redacted_records() {
+    let body = br#"{"personalizations":[{"to":[{"email":"a@example.com"}]}],"subject":"Hi"}"#;
+    let sent = record(
+        "POST",
+        "/mail/send",
+        summarize(Some(body)),
+        AuditOutcome::Status(202),
+        SystemTime::now(),
+    );
+    assert!(sent.succeeded());
+    assert_eq!(
+        sent.to_string(),
+        format!(
+            "POST /mail/send ({} byte body with personalizations, subject): 202",
+            body.len()
+        )
+    );
+    assert!(!sent.to_string().contains("example.com"));
+
+    let sink = MemoryAuditSink::new();
+    sink.record(&record(
+        "DELETE",
+        "/suppression/bounces/a%40example.com?x=1",
+        summarize(None),
+        AuditOutcome::Failed(String::from("connection refused")),
+        SystemTime::now(),
+    ));
+    let records = sink.records();
+    assert_eq!(records[0].endpoint, "/suppression/bounces/[redacted]");
+    assert_eq!(
+        records[0].to_string(),
+        "DELETE /suppression/bounces/[redacted] (no body): failed, connection refused"
+    );
+    assert!(is_mutating("PATCH") && !is_mutating("GET"));
+}
+
+#[test]
+fn failures_leave_out_values() {
+    let error: SendgridError =
+        SendgridErrorKind::InvalidAddress(String::from("a@example.com")).into();
+    match failure(&error) {
+        AuditOutcome::Failed(reason) => assert!(!reason.contains("a@example.com")),
+        outcome => panic!("unexpected outcome: {:?}", outcome),
+    }
+}
@@ -0,0 +1,174 @@
+//! Suppressing addresses as soon as the Event Webhook reports that they bounced or
+//! marked a message as spam, so they are left out of future sends without waiting for
+//! the next sync of the suppression lists.
+
+use webhooks::Event;
+
+#[cfg(feature = "client")]
+use errors::SendgridResult;
+#[cfg(feature = "client")]
+use scopes::Endpoint;
+#[cfg(feature = "client")]
+use v3::V3Sender;
+
+#[cfg(feature = "client")]
+use reqwest::Method;
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+// The number of addresses added to a group per request.
+#[cfg(feature = "client")]
+const GROUP_BATCH_SIZE: usize = 1000;
+
+/// Adding addresses to unsubscribe groups when events suppress them.
+#[cfg(feature = "client")]
+pub struct AutoSuppressions;
+
+#[cfg(feature = "client")]
+impl Endpoint for AutoSuppressions {
+    const SCOPES: &'static [&'static str] = &["asm.groups.suppressions.create"];
+}
+
+/// Why an address was suppressed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuppressionReason {
+    /// The receiving server permanently rejected a message.
+    Bounce,
+    /// The recipient marked a message as spam.
+    SpamReport,
+}
+
+/// Storage for the addresses suppressed by an `AutoSuppressor`. Implement this on top of
+/// the database the application checks before sending.
+pub trait SuppressionSink {
+    /// Record that a lowercase address should not get any more messages.
+    fn suppress(&self, address: &str, reason: SuppressionReason);
+}
+
+/// A suppression sink that keeps the addresses in memory.
+#[derive(Debug, Default)]
+pub struct MemorySuppressionSink {
+    addresses: Mutex<HashSet<String>>,
+}
+
+impl MemorySuppressionSink {
+    /// Construct a new, empty sink.
+    pub fn new() -> MemorySuppressionSink {
+        MemorySuppressionSink::default()
+    }
+
+    /// Check whether an address was suppressed, without regard to case.
+    pub fn contains(&self, address: &str) -> bool {
+        self.addresses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&address.to_lowercase())
+    }
+}
+
+impl SuppressionSink for MemorySuppressionSink {
+    fn suppress(&self, address: &str, _reason: SuppressionReason) {
+        self.addresses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(String::from(address));
+    }
+}
+
+/// Writes the recipients of bounce and spam report events into a `SuppressionSink`.
+/// Bounces of the `blocked` type are skipped, since blocks are usually temporary.
+///
+/// # Examples
+///
+/// ```ignore
+/// let suppressor = AutoSuppressor::new(db.suppressions());
+/// let (events, _) = parse_events(&body);
+/// suppressor.handle_with_group(&sender, 1234, &events)?;
+/// ```
+pub struct AutoSuppressor<S: SuppressionSink = MemorySuppressionSink> {
+    sink: S,
+}
+
+impl<S: SuppressionSink> AutoSuppressor<S> {
+    /// Construct a suppressor that writes into the given sink.
+    pub fn new(sink: S) -> AutoSuppressor<S> {
+        AutoSuppressor { sink }
+    }
+
+    /// The sink the addresses are written into.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Suppress the recipients of the bounce and spam report events, and return their
+    /// lowercase addresses with duplicates removed.
+    pub fn handle(&self, events: &[Event]) -> Vec<String> {
+        let mut suppressed = Vec::new();
+        for event in events {
+            let reason = match *event {
+                Event::Bounce(ref bounce) if bounce.bounce_type.as_deref() != Some("blocked") => {
+                    SuppressionReason::Bounce
+                }
+                Event::SpamReport(_) => SuppressionReason::SpamReport,
+                _ => continue,
+            };
+            let address = event.info().email.trim().to_lowercase();
+            self.sink.suppress(&address, reason);
+            if !suppressed.contains(&address) {
+                suppressed.push(address);
+            }
+        }
+        suppressed
+    }
+
+    /// Suppress the recipients of the bounce and spam report events like `handle`, and
+    /// also add them to the suppressions of an unsubscribe group, so SendGrid drops
+    /// messages to them that are not checked against the sink.
+    #[cfg(feature = "client")]
+    pub fn handle_with_group(
+        &self,
+        sender: &V3Sender,
+        group_id: u32,
+        events: &[Event],
+    ) -> SendgridResult<Vec<String>> {
+        let suppressed = self.handle(events);
+        let path = format!("/asm/groups/{}/suppressions", group_id);
+        for batch in suppressed.chunks(GROUP_BATCH_SIZE) {
+            let body = RecipientEmails {
+                recipient_emails: batch,
+            };
+            sender.api_send_json(Method::Post, &path, &body)?;
+        }
+        Ok(suppressed)
+    }
+}
+
+#[cfg(feature = "client")]
+#[derive(Serialize)]
+struct RecipientEmails<'a> {
+    recipient_emails: &'a [String],
+}
+
+#[test]
+fn suppress_bounces() {
+    let (events, _) = ::webhooks::parse_events(
+        br#"[
+            {"email":"A@example.com","timestamp":1,"sg_event_id":"1","event":"bounce","reason":"550","type":"bounce"},
+            {"email":"b@example.com","timestamp":1,"sg_event_id":"2","event":"bounce","reason":"421","type":"blocked"},
+            {"email":"c@example.com","timestamp":1,"sg_event_id":"3","event":"spamreport"},
+            {"email":"a@example.com","timestamp":2,"sg_event_id":"4","event":"spamreport"},
+            {"email":"d@example.com","timestamp":1,"sg_event_id":"5","event":"delivered","response":"250 OK"}
+        ]"#,
+    );
+    assert_eq!(events.len(), 5);
+
+    let suppressor = AutoSuppressor::new(MemorySuppressionSink::new());
+    assert_eq!(
+        suppressor.handle(&events),
+        vec!["a@example.com", "c@example.com"]
+    );
+    assert!(suppressor.sink().contains("A@example.com"));
+    assert!(!suppressor.sink().contains("b@example.com"));
+    assert!(!suppressor.sink().contains("d@example.com"));
+}
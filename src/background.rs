@@ -0,0 +1,332 @@
+//! Sending messages from a queue on a background thread, so callers can hand off mail
+//! without waiting for the API.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use events::{ClientEvent, Events};
+use outbox::Priority;
+use v3::{RequestOptions, SGMailV3, V3Sender};
+
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sends queued messages one at a time on a background thread. Send errors don't stop
+/// the thread; they are counted in the report returned by `shutdown`.
+///
+/// Messages are sent by priority: `High` ones first, then `Normal` ones, and `Bulk` ones
+/// only when nothing else is queued and at most one per bulk interval, so a password
+/// reset never waits behind a newsletter.
+///
+/// # Examples
+///
+/// ```ignore
+/// let background = BackgroundSender::spawn(V3Sender::new(api_key));
+/// background.send(message)?;
+/// // During a deploy:
+/// let report = background.shutdown(Duration::from_secs(10));
+/// if report.unsent > 0 {
+///     eprintln!("{} messages were not sent", report.unsent);
+/// }
+/// ```
+pub struct BackgroundSender {
+    shared: Arc<Shared>,
+}
+
+/// What happened to the messages of a background sender by the time it shut down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// The number of messages that were accepted.
+    pub sent: usize,
+    /// The number of messages that were rejected or could not be sent.
+    pub failed: usize,
+    /// The number of messages that were still queued or being sent when the timeout
+    /// ran out.
+    pub unsent: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Notified when a message is queued or the sender is closed, and when the worker
+    // finishes a message or stops.
+    changed: Condvar,
+    events: Arc<Events>,
+}
+
+// The time between bulk messages unless `set_bulk_interval` changes it.
+const DEFAULT_BULK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct State {
+    // The queued messages by the rank of their priority.
+    queues: [VecDeque<SGMailV3>; 3],
+    bulk_interval: Duration,
+    // When the next bulk message may be sent.
+    next_bulk: Option<Instant>,
+    closed: bool,
+    abandoned: bool,
+    in_flight: bool,
+    stopped: bool,
+    report: ShutdownReport,
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    // Take the next message to send at `now`, or return how long to wait for a bulk
+    // message to be due, or `None` when nothing is queued.
+    fn pop(&mut self, now: Instant) -> Result<SGMailV3, Option<Duration>> {
+        for priority in &[Priority::High, Priority::Normal] {
+            if let Some(message) = self.queues[priority.rank() as usize].pop_front() {
+                return Ok(message);
+            }
+        }
+        let bulk = &mut self.queues[Priority::Bulk.rank() as usize];
+        if bulk.is_empty() {
+            return Err(None);
+        }
+        match self.next_bulk {
+            Some(due) if due > now => Err(Some(due - now)),
+            _ => {
+                self.next_bulk = Some(now + self.bulk_interval);
+                Ok(bulk.pop_front().expect("the bulk queue is not empty"))
+            }
+        }
+    }
+}
+
+impl BackgroundSender {
+    /// Start a thread that sends the queued messages with the sender.
+    pub fn spawn(sender: V3Sender) -> BackgroundSender {
+        let options = RequestOptions::default();
+        let events = Arc::clone(sender.events());
+        BackgroundSender::spawn_with(events, move |message| {
+            sender.send_chunk(message, &options).map(|_| ())
+        })
+    }
+
+    fn spawn_with<F>(events: Arc<Events>, mut send: F) -> BackgroundSender
+    where
+        F: FnMut(&SGMailV3) -> SendgridResult<()> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                bulk_interval: DEFAULT_BULK_INTERVAL,
+                ..State::default()
+            }),
+            changed: Condvar::new(),
+            events,
+        });
+
+        let worker = Arc::clone(&shared);
+        thread::spawn(move || loop {
+            let message = {
+                let mut state = worker.state.lock().unwrap();
+                loop {
+                    if state.abandoned || state.closed && state.queued() == 0 {
+                        state.stopped = true;
+                        worker.changed.notify_all();
+                        return;
+                    }
+                    // Waiting on the condition variable rather than sleeping lets urgent
+                    // messages that are queued meanwhile go before bulk ones.
+                    match state.pop(Instant::now()) {
+                        Ok(message) => {
+                            state.in_flight = true;
+                            let depth = state.queued();
+                            worker
+                                .events
+                                .emit(|| ClientEvent::QueueDepthChanged { depth });
+                            break message;
+                        }
+                        Err(None) => state = worker.changed.wait(state).unwrap(),
+                        Err(Some(wait)) => {
+                            state = worker.changed.wait_timeout(state, wait).unwrap().0;
+                        }
+                    }
+                }
+            };
+
+            let result = send(&message);
+
+            let mut state = worker.state.lock().unwrap();
+            state.in_flight = false;
+            match result {
+                Ok(()) => state.report.sent += 1,
+                Err(_) => state.report.failed += 1,
+            }
+            worker.changed.notify_all();
+        });
+
+        BackgroundSender { shared }
+    }
+
+    /// Queue a message to be sent with `Normal` priority. Fails with `SenderClosed` once
+    /// the sender is shut down.
+    pub fn send(&self, message: SGMailV3) -> SendgridResult<()> {
+        self.send_with_priority(message, Priority::Normal)
+    }
+
+    /// Queue a message to be sent like `send`, with the given priority.
+    pub fn send_with_priority(&self, message: SGMailV3, priority: Priority) -> SendgridResult<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(SendgridErrorKind::SenderClosed.into());
+        }
+        state.queues[priority.rank() as usize].push_back(message);
+        let depth = state.queued();
+        self.shared
+            .events
+            .emit(|| ClientEvent::QueueDepthChanged { depth });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Subscribe to the events of the sender, including changes to the number of
+    /// queued messages. Like `V3Sender::subscribe`, a subscriber that falls
+    /// `EVENT_CAPACITY` events behind misses the events after that.
+    pub fn subscribe(&self) -> Receiver<ClientEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Set the least time between two bulk messages. Defaults to 100 milliseconds.
+    pub fn set_bulk_interval(&self, bulk_interval: Duration) {
+        self.shared.state.lock().unwrap().bulk_interval = bulk_interval;
+    }
+
+    /// The number of messages waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.shared.state.lock().unwrap().queued()
+    }
+
+    /// Stop taking new messages and wait up to `timeout` for the queued ones to be
+    /// sent. Later calls to `send` fail with `SenderClosed`. Messages that are still
+    /// queued when the timeout runs out are dropped, and a send that is in progress is
+    /// left to finish on its own.
+    pub fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.changed.notify_all();
+
+        while !state.stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        let mut report = state.report;
+        if !state.stopped {
+            state.abandoned = true;
+            report.unsent = state.queued() + state.in_flight as usize;
+            for queue in &mut state.queues {
+                queue.clear();
+            }
+        }
+        report
+    }
+}
+
+impl Drop for BackgroundSender {
+    // Without a shutdown the thread carries on until the queue is empty.
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[test]
+fn background_shutdown() {
+    let mut calls = 0;
+    let background = BackgroundSender::spawn_with(Arc::default(), move |_| {
+        calls += 1;
+        thread::sleep(Duration::from_millis(5));
+        if calls == 2 {
+            return Err(SendgridErrorKind::Api(400, String::new()).into());
+        }
+        Ok(())
+    });
+    let events = background.subscribe();
+    for _ in 0..3 {
+        background.send(SGMailV3::new()).unwrap();
+    }
+    let report = background.shutdown(Duration::from_secs(5));
+    assert_eq!(
+        report,
+        ShutdownReport {
+            sent: 2,
+            failed: 1,
+            unsent: 0,
+        }
+    );
+    assert!(background.send(SGMailV3::new()).is_err());
+    let last = events.try_iter().last();
+    assert_eq!(last, Some(ClientEvent::QueueDepthChanged { depth: 0 }));
+
+    let background = BackgroundSender::spawn_with(Arc::default(), |_| {
+        thread::sleep(Duration::from_millis(200));
+        Ok(())
+    });
+    for _ in 0..5 {
+        background.send(SGMailV3::new()).unwrap();
+    }
+    let report = background.shutdown(Duration::from_millis(50));
+    assert_eq!(report.sent + report.unsent, 5);
+    assert!(report.unsent >= 4);
+    assert_eq!(background.queued(), 0);
+}
+
+#[test]
+fn background_priorities() {
+    use std::sync::mpsc;
+
+    let (release, gate) = mpsc::channel::<()>();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_by_worker = Arc::clone(&sent);
+    let background = BackgroundSender::spawn_with(Arc::default(), move |message| {
+        let subject = ::serde_json::to_value(message).unwrap()["subject"].clone();
+        if subject == "first" {
+            // Hold the worker until the rest is queued.
+            gate.recv().unwrap();
+        }
+        sent_by_worker.lock().unwrap().push(subject);
+        Ok(())
+    });
+    background.set_bulk_interval(Duration::from_millis(1));
+
+    let message = |subject: &str| {
+        let mut message = SGMailV3::new();
+        message.set_subject(subject);
+        message
+    };
+    background.send(message("first")).unwrap();
+    while background.queued() > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    background
+        .send_with_priority(message("newsletter"), Priority::Bulk)
+        .unwrap();
+    background.send(message("receipt")).unwrap();
+    background
+        .send_with_priority(message("reset"), Priority::High)
+        .unwrap();
+    release.send(()).unwrap();
+
+    let report = background.shutdown(Duration::from_secs(5));
+    assert_eq!(report.sent, 4);
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec!["first", "reset", "receipt", "newsletter"]
+    );
+}
@@ -0,0 +1,238 @@
+//! Classifying bounces by the reason and status code the receiving server gave, so code
+//! that handles them can match on what went wrong instead of on SMTP replies.
+
+use std::fmt;
+
+/// What went wrong with a bounced message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BounceClass {
+    /// The address doesn't exist or can't receive mail, and sending to it again won't
+    /// work.
+    HardBounce,
+    /// The receiving server had a temporary problem, and sending again later may work.
+    SoftBounce,
+    /// The receiving server refused mail from the sender, e.g. because its IP address is
+    /// on a block list.
+    Blocked,
+    /// The mailbox exists but is over its quota.
+    MailboxFull,
+    /// The message was refused by a policy of the receiving server, e.g. because of its
+    /// content or failed authentication.
+    PolicyRejection,
+    /// The reason didn't match any of the other classes.
+    Unknown,
+}
+
+impl BounceClass {
+    /// Classify a bounce by the reason the receiving server gave and its status code,
+    /// e.g. `5.1.1`. An enhanced status code in the reason is used when there is no
+    /// status.
+    ///
+    /// The reason is checked for the wording of a full mailbox, a block and a policy
+    /// rejection first, in that order, since servers use generic codes like `5.7.1`
+    /// for all of them. Otherwise the code decides: a subject of 2.2 means a full
+    /// mailbox, 7 a policy rejection, a temporary code a soft bounce and a permanent
+    /// code a hard bounce.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sendgrid::bounces::BounceClass;
+    ///
+    /// assert_eq!(
+    ///     BounceClass::classify("550 5.1.1 The email account does not exist", None),
+    ///     BounceClass::HardBounce
+    /// );
+    /// assert_eq!(
+    ///     BounceClass::classify("452 Mailbox full", Some("4.2.2")),
+    ///     BounceClass::MailboxFull
+    /// );
+    /// ```
+    pub fn classify(reason: &str, status: Option<&str>) -> BounceClass {
+        let reason = reason.to_ascii_lowercase();
+        let contains = |phrases: &[&str]| phrases.iter().any(|p| reason.contains(p));
+        let code = status.and_then(StatusCode::parse).or_else(|| {
+            reason
+                .split_whitespace()
+                .filter_map(StatusCode::parse)
+                .next()
+        });
+
+        if contains(MAILBOX_FULL) || code.is_some_and(|c| (c.subject, c.detail) == (2, 2)) {
+            return BounceClass::MailboxFull;
+        }
+        if contains(BLOCKED) {
+            return BounceClass::Blocked;
+        }
+        if contains(POLICY) || code.is_some_and(|c| c.subject == 7) {
+            return BounceClass::PolicyRejection;
+        }
+        let class = code.map(|c| c.class).or_else(|| basic_class(&reason));
+        match class {
+            Some(4) => BounceClass::SoftBounce,
+            Some(_) => BounceClass::HardBounce,
+            None if contains(UNKNOWN_ADDRESS) => BounceClass::HardBounce,
+            None => BounceClass::Unknown,
+        }
+    }
+
+    /// Classify a bounce by SendGrid's own classification of it, e.g. `Invalid
+    /// Address`, for when the reason is too vague to tell.
+    pub fn from_sendgrid(classification: &str) -> BounceClass {
+        match classification {
+            "Invalid Address" | "Mailbox Unavailable" => BounceClass::HardBounce,
+            "Technical" | "Frequency or Volume" => BounceClass::SoftBounce,
+            "Reputation" => BounceClass::Blocked,
+            "Content" => BounceClass::PolicyRejection,
+            _ => BounceClass::Unknown,
+        }
+    }
+
+    /// Whether sending to the address again may work without anything changing on the
+    /// recipient's side.
+    pub fn is_temporary(self) -> bool {
+        self == BounceClass::SoftBounce || self == BounceClass::MailboxFull
+    }
+}
+
+impl fmt::Display for BounceClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BounceClass::HardBounce => "hard bounce",
+            BounceClass::SoftBounce => "soft bounce",
+            BounceClass::Blocked => "blocked",
+            BounceClass::MailboxFull => "mailbox full",
+            BounceClass::PolicyRejection => "policy rejection",
+            BounceClass::Unknown => "unknown",
+        })
+    }
+}
+
+// Wording of the replies for each class, in lowercase.
+const MAILBOX_FULL: &[&str] = &[
+    "mailbox full",
+    "mailbox is full",
+    "over quota",
+    "overquota",
+    "quota exceeded",
+    "exceeded storage",
+    "insufficient storage",
+];
+const BLOCKED: &[&str] = &[
+    "blocked",
+    "blacklist",
+    "blocklist",
+    "block list",
+    "denylist",
+    "spamhaus",
+    "listed at",
+    "listed by",
+    " rbl",
+    "reputation",
+];
+const POLICY: &[&str] = &[
+    "policy",
+    "dmarc",
+    "spf",
+    "dkim",
+    "content rejected",
+    "message content",
+    "spam",
+    "virus",
+];
+const UNKNOWN_ADDRESS: &[&str] = &[
+    "does not exist",
+    "doesn't exist",
+    "no such user",
+    "user unknown",
+    "unknown user",
+    "invalid recipient",
+    "recipient rejected",
+    "no mailbox",
+];
+
+// An enhanced status code, e.g. 5.1.1.
+#[derive(Clone, Copy)]
+struct StatusCode {
+    class: u8,
+    subject: u16,
+    detail: u16,
+}
+
+impl StatusCode {
+    fn parse(text: &str) -> Option<StatusCode> {
+        let text = text.trim_matches(|c: char| !c.is_ascii_digit());
+        let mut parts = text.split('.');
+        let class = parts.next()?.parse().ok()?;
+        let subject = parts.next()?.parse().ok()?;
+        let detail = parts.next()?.parse().ok()?;
+        if parts.next().is_some() || (class != 4 && class != 5) {
+            return None;
+        }
+        Some(StatusCode {
+            class,
+            subject,
+            detail,
+        })
+    }
+}
+
+// The class of the basic reply code a reason starts with, e.g. 5 for `550 ...`.
+fn basic_class(reason: &str) -> Option<u8> {
+    let code = reason.split(|c: char| !c.is_ascii_digit()).next()?;
+    match code.as_bytes() {
+        [class @ b'4', _, _] | [class @ b'5', _, _] => Some(class - b'0'),
+        _ => None,
+    }
+}
+
+#[test]
+fn classify_bounces() {
+    let cases = [
+        (
+            "550 5.1.1 <a@example.com>: Recipient address rejected: User unknown",
+            None,
+            BounceClass::HardBounce,
+        ),
+        (
+            "550 Requested action not taken",
+            Some("5.0.0"),
+            BounceClass::HardBounce,
+        ),
+        ("421 Try again later", None, BounceClass::SoftBounce),
+        (
+            "452 4.2.2 The email account that you tried to reach is over quota",
+            None,
+            BounceClass::MailboxFull,
+        ),
+        (
+            "554 5.7.1 Service unavailable; Client host blocked using zen.spamhaus.org",
+            None,
+            BounceClass::Blocked,
+        ),
+        (
+            "550 5.7.26 Unauthenticated email is not accepted due to the domain's DMARC policy",
+            None,
+            BounceClass::PolicyRejection,
+        ),
+        (
+            "550 5.7.1 Message rejected",
+            None,
+            BounceClass::PolicyRejection,
+        ),
+        (
+            "The recipient does not exist",
+            None,
+            BounceClass::HardBounce,
+        ),
+        ("Something went wrong", None, BounceClass::Unknown),
+    ];
+    for &(reason, status, class) in &cases {
+        assert_eq!(BounceClass::classify(reason, status), class, "{}", reason);
+    }
+    assert_eq!(
+        BounceClass::from_sendgrid("Reputation"),
+        BounceClass::Blocked
+    );
+    assert!(BounceClass::MailboxFull.is_temporary());
+}
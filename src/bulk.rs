@@ -0,0 +1,314 @@
+//! Sending many messages at once, such as the chunks built by `MailMerge`, and keeping
+//! track of which of them were accepted.
+
+use cancel;
+use errors::{SendgridError, SendgridErrorKind, SendgridResult};
+use events::ClientEvent;
+use failover;
+use v3::{RequestOptions, Response, SGMailV3, V3Sender};
+
+use std::cmp;
+use std::mem;
+use std::time::Duration;
+
+/// A message of a bulk send that SendGrid accepted.
+#[derive(Clone, Debug)]
+pub struct SentChunk {
+    /// The id SendGrid gave the message, which is the start of the `sg_message_id` of
+    /// its webhook events.
+    pub message_id: Option<String>,
+    /// The number of recipients of the message.
+    pub recipients: usize,
+}
+
+/// A message of a bulk send that could not be sent, kept so it can be sent again.
+pub struct FailedChunk {
+    /// The message that failed.
+    pub message: SGMailV3,
+    /// Why it failed.
+    pub error: SendgridError,
+}
+
+impl FailedChunk {
+    /// The addresses of every recipient of the message.
+    pub fn recipients(&self) -> Vec<&str> {
+        self.message.recipients().collect()
+    }
+}
+
+/// How often and how long apart failed messages are sent again. The delay doubles after
+/// every attempt, up to the maximum.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    /// The number of times to try again.
+    pub attempts: u32,
+    /// The delay before the first attempt.
+    pub initial: Duration,
+    /// The longest delay between attempts.
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            attempts: 3,
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    // The delay before the given attempt, counting from zero.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        cmp::min(
+            self.initial.checked_mul(factor).unwrap_or(self.max),
+            self.max,
+        )
+    }
+}
+
+/// The outcome of a bulk send, with the messages that were accepted and the ones that
+/// failed.
+#[derive(Default)]
+pub struct SendReport {
+    /// The messages that were accepted, in the order they were sent.
+    pub sent: Vec<SentChunk>,
+    /// The messages that failed, in the order they were sent.
+    pub failed: Vec<FailedChunk>,
+}
+
+impl SendReport {
+    /// The number of recipients in messages that were accepted.
+    pub fn accepted(&self) -> usize {
+        self.sent.iter().map(|chunk| chunk.recipients).sum()
+    }
+
+    /// The ids of the messages that were accepted.
+    pub fn message_ids(&self) -> Vec<&str> {
+        self.sent
+            .iter()
+            .filter_map(|chunk| chunk.message_id.as_deref())
+            .collect()
+    }
+
+    /// The addresses of every recipient in messages that failed.
+    pub fn failed_recipients(&self) -> Vec<&str> {
+        self.failed
+            .iter()
+            .flat_map(|chunk| chunk.message.recipients())
+            .collect()
+    }
+
+    /// Whether every message was accepted.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Send the failed messages again with the default `Backoff`, moving the ones that
+    /// are accepted to `sent`. Only failures that could go away are retried, such as
+    /// connection errors and 429 or 5xx responses.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut report = sender.send_all(messages);
+    /// report.retry_failed(&sender);
+    /// if !report.is_complete() {
+    ///     queue.save(report.failed)?;
+    /// }
+    /// ```
+    pub fn retry_failed(&mut self, sender: &V3Sender) {
+        self.retry_failed_with(sender, &Backoff::default());
+    }
+
+    /// Send the failed messages again like `retry_failed`, with a custom backoff.
+    pub fn retry_failed_with(&mut self, sender: &V3Sender, backoff: &Backoff) {
+        self.retry_failed_with_options(sender, backoff, &RequestOptions::default());
+    }
+
+    /// Send the failed messages again like `retry_failed_with`, making every request
+    /// with the given options. Retrying stops early, keeping the remaining failures,
+    /// once the deadline of the options would pass during a wait or their cancellation
+    /// token is cancelled.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let options = RequestOptions {
+    ///     deadline: Some(Instant::now() + Duration::from_secs(30)),
+    ///     ..Default::default()
+    /// };
+    /// report.retry_failed_with_options(&sender, &Backoff::default(), &options);
+    /// ```
+    pub fn retry_failed_with_options(
+        &mut self,
+        sender: &V3Sender,
+        backoff: &Backoff,
+        options: &RequestOptions,
+    ) {
+        for attempt in 0..backoff.attempts {
+            if !self.failed.iter().any(|chunk| is_retryable(&chunk.error)) {
+                return;
+            }
+            let waited = cancel::sleep(
+                backoff.delay(attempt),
+                options.cancellation.as_ref(),
+                options.deadline,
+            );
+            if waited.is_err() {
+                return;
+            }
+
+            for chunk in mem::take(&mut self.failed) {
+                if !is_retryable(&chunk.error) {
+                    self.failed.push(chunk);
+                    continue;
+                }
+                sender.events().emit(|| ClientEvent::Retried {
+                    attempt: attempt + 1,
+                });
+                sender.counters().retried();
+                match sender.send_chunk(&chunk.message, options) {
+                    Ok(message_id) => self.sent.push(SentChunk {
+                        message_id,
+                        recipients: chunk.message.recipients().count(),
+                    }),
+                    Err(error) => self.failed.push(FailedChunk {
+                        message: chunk.message,
+                        error,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+// Whether sending again could succeed without delivering the message twice: rate
+// limits, server errors and connections that failed before the request was sent.
+// Other errors, like a rejected message, would just fail again, and errors like a
+// timeout can happen after SendGrid accepted the message.
+pub(crate) fn is_retryable(error: &SendgridError) -> bool {
+    match *error.kind() {
+        SendgridErrorKind::Api(status, _) => status == 429 || status >= 500,
+        SendgridErrorKind::ReqwestError(ref e) => failover::is_connect_error(e),
+        _ => false,
+    }
+}
+
+impl V3Sender {
+    /// Send every message, carrying on past the ones that fail, and report what
+    /// happened to each. Responses with a status other than 2xx count as failures.
+    /// Requires the `mail.send` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let report = sender.send_all(merge.build(recipients)?);
+    /// for chunk in &report.failed {
+    ///     println!("{} recipients failed: {}", chunk.recipients().len(), chunk.error);
+    /// }
+    /// ```
+    pub fn send_all<I: IntoIterator<Item = SGMailV3>>(&self, messages: I) -> SendReport {
+        let mut report = SendReport::default();
+
+        for message in messages {
+            match self.send_chunk(&message, &RequestOptions::default()) {
+                Ok(message_id) => report.sent.push(SentChunk {
+                    message_id,
+                    recipients: message.recipients().count(),
+                }),
+                Err(error) => report.failed.push(FailedChunk { message, error }),
+            }
+        }
+
+        report
+    }
+
+    // Send one message of a bulk send, returning the message id from the response.
+    pub(crate) fn send_chunk(
+        &self,
+        message: &SGMailV3,
+        options: &RequestOptions,
+    ) -> SendgridResult<Option<String>> {
+        let res = self.send_with(message, options)?;
+        self.accepted(res)
+    }
+
+    // The message id of a mail send response, or an `Api` error for a status other than
+    // 2xx.
+    pub(crate) fn accepted(&self, mut res: Response) -> SendgridResult<Option<String>> {
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = self.error_text(&mut res);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
+
+        let message_id = res
+            .headers()
+            .get_raw("X-Message-Id")
+            .and_then(|raw| raw.one())
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+        Ok(message_id)
+    }
+}
+
+#[test]
+fn send_report() {
+    use v3::{Email, Personalization};
+
+    let mut message = SGMailV3::new();
+    for address in &["a@example.com", "b@example.com"] {
+        let mut to = Email::new();
+        to.set_email(address);
+        let mut p = Personalization::new();
+        p.add_to(to);
+        message.add_personalization(p);
+    }
+
+    let report = SendReport {
+        sent: vec![
+            SentChunk {
+                message_id: Some(String::from("abc")),
+                recipients: 1000,
+            },
+            SentChunk {
+                message_id: None,
+                recipients: 10,
+            },
+        ],
+        failed: vec![FailedChunk {
+            message,
+            error: SendgridErrorKind::Api(500, String::new()).into(),
+        }],
+    };
+
+    assert_eq!(report.accepted(), 1010);
+    assert_eq!(report.message_ids(), vec!["abc"]);
+    assert_eq!(
+        report.failed_recipients(),
+        vec!["a@example.com", "b@example.com"]
+    );
+    assert!(!report.is_complete());
+}
+
+#[test]
+fn retry_backoff() {
+    let backoff = Backoff::default();
+    let delays: Vec<u64> = (0..7).map(|i| backoff.delay(i).as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+    assert_eq!(backoff.delay(40), backoff.max);
+
+    assert!(is_retryable(
+        &SendgridErrorKind::Api(503, String::new()).into()
+    ));
+    assert!(is_retryable(
+        &SendgridErrorKind::Api(429, String::new()).into()
+    ));
+    assert!(!is_retryable(
+        &SendgridErrorKind::Api(400, String::new()).into()
+    ));
+    let timeout = ::std::io::Error::new(::std::io::ErrorKind::TimedOut, "timed out");
+    assert!(!is_retryable(&SendgridErrorKind::Io(timeout).into()));
+}
@@ -0,0 +1,136 @@
+//! The campaigns of the legacy Marketing Campaigns, for integrations that haven't moved
+//! to Single Sends yet.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use reqwest::Method;
+
+/// Creating, scheduling and sending legacy marketing campaigns.
+pub struct Campaigns;
+
+impl Endpoint for Campaigns {
+    const SCOPES: &'static [&'static str] = &[
+        "marketing_campaigns.create",
+        "marketing_campaigns.read",
+        "marketing_campaigns.update",
+    ];
+}
+
+/// A legacy marketing campaign. Only the title is needed to create one, but everything
+/// but the segments is needed to send it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Campaign {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub list_ids: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub segment_ids: Vec<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    /// The unsubscribe group, unless a custom unsubscribe URL is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suppression_group_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_unsubscribe_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_pool: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plain_content: Option<String>,
+}
+
+impl Campaign {
+    /// Construct a campaign with the given title.
+    pub fn new(title: &str) -> Campaign {
+        Campaign {
+            title: String::from(title),
+            ..Default::default()
+        }
+    }
+}
+
+/// A campaign as stored by SendGrid.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct CampaignInfo {
+    pub id: u64,
+    pub title: String,
+    /// Either `Draft`, `Scheduled` or `Sent`.
+    pub status: String,
+    /// Other fields of the campaign, such as its schedule or list IDs.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Schedule {
+    send_at: u64,
+}
+
+#[derive(Serialize)]
+struct TestSend<'a> {
+    to: &'a [&'a str],
+}
+
+impl V3Sender {
+    /// Create a legacy marketing campaign as a draft.
+    pub fn create_campaign(&self, campaign: &Campaign) -> SendgridResult<CampaignInfo> {
+        let res = self.api_send_json(Method::Post, "/campaigns", campaign)?;
+        self.read_json(res)
+    }
+
+    /// Get a legacy marketing campaign.
+    pub fn campaign(&self, id: u64) -> SendgridResult<CampaignInfo> {
+        self.api_get(&format!("/campaigns/{}", id))
+    }
+
+    /// Schedule a campaign to be sent at a Unix timestamp.
+    pub fn schedule_campaign(&self, id: u64, send_at: u64) -> SendgridResult<()> {
+        let path = format!("/campaigns/{}/schedules", id);
+        self.api_send_json(Method::Post, &path, &Schedule { send_at })?;
+        Ok(())
+    }
+
+    /// Send a campaign right away.
+    pub fn send_campaign_now(&self, id: u64) -> SendgridResult<()> {
+        self.api_request(
+            Method::Post,
+            &format!("/campaigns/{}/schedules/now", id),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Send a test of a campaign to up to 10 addresses.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let campaign = sender.create_campaign(&campaign)?;
+    /// sender.send_campaign_test(campaign.id, &["qa@example.com"])?;
+    /// ```
+    pub fn send_campaign_test(&self, id: u64, to: &[&str]) -> SendgridResult<()> {
+        let path = format!("/campaigns/{}/schedules/test", id);
+        self.api_send_json(Method::Post, &path, &TestSend { to })?;
+        Ok(())
+    }
+}
+
+#[test]
+fn campaign_body() {
+    let mut campaign = Campaign::new("March newsletter");
+    campaign.sender_id = Some(7);
+    campaign.list_ids = vec![1, 2];
+    assert_eq!(
+        ::serde_json::to_string(&campaign).unwrap(),
+        r#"{"title":"March newsletter","sender_id":7,"list_ids":[1,2]}"#
+    );
+}
@@ -0,0 +1,126 @@
+//! Giving up on sends when the caller no longer needs them, such as when the request that
+//! triggered them timed out.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a waiting sleep checks whether it was cancelled.
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A flag shared between a caller and the sends it started. Once cancelled, sends that
+/// have not started yet fail with `Cancelled` and retry waits end early. A request that
+/// is already on the wire still runs until its timeout, so pair this with a deadline to
+/// bound it.
+///
+/// # Examples
+///
+/// ```ignore
+/// let token = CancellationToken::new();
+/// let options = RequestOptions {
+///     cancellation: Some(token.clone()),
+///     ..Default::default()
+/// };
+/// // On another thread, when the caller goes away:
+/// token.cancel();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Construct a new token that isn't cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel every send using this token or one of its clones.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `cancel` was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// Fail if the token was cancelled or the deadline passed, otherwise return the time
+// left until the deadline.
+pub(crate) fn check(
+    token: Option<&CancellationToken>,
+    deadline: Option<Instant>,
+) -> SendgridResult<Option<Duration>> {
+    if token.is_some_and(|token| token.is_cancelled()) {
+        return Err(SendgridErrorKind::Cancelled.into());
+    }
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(left) if left > Duration::from_millis(0) => Ok(Some(left)),
+            _ => Err(SendgridErrorKind::DeadlineExceeded.into()),
+        },
+        None => Ok(None),
+    }
+}
+
+// Sleep for the duration, waking up early with an error when the token is cancelled or
+// the deadline would pass first.
+pub(crate) fn sleep(
+    duration: Duration,
+    token: Option<&CancellationToken>,
+    deadline: Option<Instant>,
+) -> SendgridResult<()> {
+    let until = Instant::now() + duration;
+    if deadline.is_some_and(|deadline| deadline < until) {
+        return Err(SendgridErrorKind::DeadlineExceeded.into());
+    }
+
+    loop {
+        check(token, None)?;
+        let now = Instant::now();
+        if now >= until {
+            return Ok(());
+        }
+        let step = until - now;
+        thread::sleep(match token {
+            Some(_) => cmp::min(step, CHECK_INTERVAL),
+            None => step,
+        });
+    }
+}
+
+#[test]
+fn cancelled_sleep() {
+    let token = CancellationToken::new();
+    assert!(check(Some(&token), None).unwrap().is_none());
+    let left = check(None, Some(Instant::now() + Duration::from_secs(60))).unwrap();
+    assert!(left.unwrap() > Duration::from_secs(59));
+    match *check(None, Some(Instant::now())).unwrap_err().kind() {
+        SendgridErrorKind::DeadlineExceeded => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(10);
+    assert!(sleep(Duration::from_secs(60), None, Some(deadline)).is_err());
+
+    let clone = token.clone();
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        clone.cancel();
+    });
+    let started = Instant::now();
+    match *sleep(Duration::from_secs(60), Some(&token), None)
+        .unwrap_err()
+        .kind()
+    {
+        SendgridErrorKind::Cancelled => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(started.elapsed() < Duration::from_secs(5));
+    canceller.join().unwrap();
+}
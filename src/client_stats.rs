@@ -0,0 +1,97 @@
+//! Counters kept by the senders for monitoring.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A snapshot of the requests made by a sender since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ClientStats {
+    /// The number of requests that were sent, whether they succeeded or not.
+    pub requests_sent: u64,
+    /// The number of bytes in the bodies of the requests that were sent.
+    pub bytes_uploaded: u64,
+    /// The number of times a request was sent again: to a fallback host, with the next
+    /// API key or by `SendReport::retry_failed`.
+    pub retries: u64,
+    /// The number of requests that are being sent right now.
+    pub in_flight: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    requests_sent: AtomicUsize,
+    bytes_uploaded: AtomicUsize,
+    retries: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+impl Counters {
+    // Count a request with a body of the given size. The request is in flight until the
+    // returned guard is dropped.
+    pub fn start(&self, body_size: usize) -> InFlight<'_> {
+        self.requests_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_uploaded.fetch_add(body_size, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    // Count an attempt at sending a request, which is a retry if `attempted` shows an
+    // earlier attempt was made.
+    pub fn attempt(&self, attempted: &mut bool) {
+        if mem::replace(attempted, true) {
+            self.retried();
+        }
+    }
+
+    pub fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ClientStats {
+        ClientStats {
+            requests_sent: self.requests_sent.load(Ordering::Relaxed) as u64,
+            bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed) as u64,
+            retries: self.retries.load(Ordering::Relaxed) as u64,
+            in_flight: self.in_flight.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+pub(crate) struct InFlight<'a>(&'a Counters);
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn in_flight_requests() {
+    let counters = Counters::default();
+    {
+        let _first = counters.start(10);
+        let _second = counters.start(5);
+        assert_eq!(
+            counters.snapshot(),
+            ClientStats {
+                requests_sent: 2,
+                bytes_uploaded: 15,
+                retries: 0,
+                in_flight: 2,
+            }
+        );
+    }
+    assert_eq!(counters.snapshot().in_flight, 0);
+}
+
+#[test]
+fn retried_requests() {
+    let counters = Counters::default();
+    let mut attempted = false;
+    counters.attempt(&mut attempted);
+    assert_eq!(counters.snapshot().retries, 0);
+    counters.attempt(&mut attempted);
+    counters.attempt(&mut attempted);
+    counters.retried();
+    assert_eq!(counters.snapshot().retries, 3);
+}
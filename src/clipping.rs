@@ -0,0 +1,168 @@
+//! Measuring the HTML content of messages against the size Gmail clips messages at, so
+//! template regressions show up before recipients see "[Message clipped]" above the
+//! footer with the unsubscribe link.
+
+use mail::Mail;
+use v3::SGMailV3;
+
+use std::fmt;
+
+/// The size of the HTML content in bytes at which Gmail hides the rest of a message
+/// behind a "View entire message" link.
+pub const GMAIL_CLIP_SIZE: usize = 102 * 1024;
+
+// A rough estimate of how much longer every link gets when SendGrid rewrites it for click
+// tracking.
+const TRACKED_LINK_GROWTH: usize = 400;
+
+/// How likely Gmail is to clip a message.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum ClippingRisk {
+    /// The content is well below the limit.
+    Low,
+    /// The content is close to the limit, or over it once SendGrid rewrites the links for
+    /// click tracking.
+    Likely,
+    /// The content is over the limit as it is.
+    Certain,
+}
+
+/// The size of the HTML content of a message, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ContentSize {
+    /// The size of the whole HTML content.
+    pub html: usize,
+    /// The size of the CSS in `<style>` blocks.
+    pub style_blocks: usize,
+    /// The size of the CSS in `style` attributes, which grows when styles are inlined.
+    pub inline_styles: usize,
+    /// The number of links, which get longer with click tracking.
+    pub links: usize,
+}
+
+impl ContentSize {
+    /// Measure HTML content.
+    pub fn measure(html: &str) -> ContentSize {
+        let lower = html.to_ascii_lowercase();
+        ContentSize {
+            html: html.len(),
+            style_blocks: between(&lower, "<style", "</style>")
+                .map(|block| block.len() - block.find('>').map_or(0, |i| i + 1))
+                .sum(),
+            inline_styles: between(&lower, " style=\"", "\"")
+                .chain(between(&lower, " style='", "'"))
+                .map(str::len)
+                .sum(),
+            links: lower.matches("href=\"http").count() + lower.matches("href='http").count(),
+        }
+    }
+
+    /// An estimate of the size once SendGrid rewrote the links for click tracking.
+    pub fn tracked_size(&self) -> usize {
+        self.html + self.links * TRACKED_LINK_GROWTH
+    }
+
+    /// How likely Gmail is to clip the message. Above 90% of the limit clipping counts
+    /// as likely, since open tracking and substitutions add to the size as well.
+    pub fn clipping_risk(&self) -> ClippingRisk {
+        if self.html >= GMAIL_CLIP_SIZE {
+            ClippingRisk::Certain
+        } else if self.tracked_size() >= GMAIL_CLIP_SIZE || self.html >= GMAIL_CLIP_SIZE / 10 * 9 {
+            ClippingRisk::Likely
+        } else {
+            ClippingRisk::Low
+        }
+    }
+
+    /// A warning to log or fail a build on, or `None` when clipping is unlikely.
+    pub fn warning(&self) -> Option<String> {
+        if self.clipping_risk() == ClippingRisk::Low {
+            None
+        } else {
+            Some(self.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ContentSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = match self.clipping_risk() {
+            ClippingRisk::Low => "Gmail is unlikely to clip it",
+            ClippingRisk::Likely => "Gmail is likely to clip it",
+            ClippingRisk::Certain => "Gmail will clip it",
+        };
+        write!(
+            f,
+            "the HTML content is {} bytes ({} of CSS in style blocks, {} in style attributes, \
+             about {} with tracked links), {} at {} bytes",
+            self.html,
+            self.style_blocks,
+            self.inline_styles,
+            self.tracked_size(),
+            verdict,
+            GMAIL_CLIP_SIZE
+        )
+    }
+}
+
+impl SGMailV3 {
+    /// Measure the HTML content of the message, or `None` when it has none.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// if let Some(warning) = message.content_size().and_then(|size| size.warning()) {
+    ///     warn!("newsletter {}: {}", issue, warning);
+    /// }
+    /// ```
+    pub fn content_size(&self) -> Option<ContentSize> {
+        self.html().map(ContentSize::measure)
+    }
+}
+
+impl<'a> Mail<'a> {
+    /// Measure the HTML content of the message, or `None` when it has none.
+    pub fn content_size(&self) -> Option<ContentSize> {
+        if self.html.is_empty() {
+            None
+        } else {
+            Some(ContentSize::measure(&self.html))
+        }
+    }
+}
+
+// The text from every `start` up to the next `end`, without either.
+fn between<'h>(haystack: &'h str, start: &'h str, end: &'h str) -> impl Iterator<Item = &'h str> {
+    haystack.match_indices(start).filter_map(move |(i, _)| {
+        let rest = &haystack[i + start.len()..];
+        rest.find(end).map(|j| &rest[..j])
+    })
+}
+
+#[test]
+fn measure_content() {
+    let html = "<html><head><style type=\"text/css\">p { color: red; }</style></head>\
+                <body><p style=\"margin: 0\">Hi</p><A HREF=\"https://example.com\">Shop</A>\
+                <a href='http://example.com/b'>More</a></body></html>";
+    let size = ContentSize::measure(html);
+    assert_eq!(size.html, html.len());
+    assert_eq!(size.style_blocks, "p { color: red; }".len());
+    assert_eq!(size.inline_styles, "margin: 0".len());
+    assert_eq!(size.links, 2);
+    assert_eq!(size.clipping_risk(), ClippingRisk::Low);
+    assert!(size.warning().is_none());
+
+    let mut m = SGMailV3::new();
+    assert!(m.content_size().is_none());
+    let mut c = ::v3::Content::new();
+    c.set_content_type("text/html");
+    c.set_value(&"<a href=\"https://example.com\">x</a>".repeat(2000));
+    m.add_content(c);
+    let size = m.content_size().unwrap();
+    assert!(size.html < GMAIL_CLIP_SIZE);
+    assert_eq!(size.clipping_risk(), ClippingRisk::Likely);
+    assert!(size.warning().unwrap().contains("likely to clip"));
+
+    let size = ContentSize::measure(&"x".repeat(GMAIL_CLIP_SIZE));
+    assert_eq!(size.clipping_risk(), ClippingRisk::Certain);
+}
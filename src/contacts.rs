@@ -0,0 +1,775 @@
+//! Importing Marketing Campaigns contacts from CSV files or adding them one by one,
+//! counting, looking up and exporting them, and removing them from lists.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use fields::{FieldDefinitions, FieldValue};
+use ndjson::NdjsonRecords;
+use scopes::Endpoint;
+use suppressions;
+use v3::V3Sender;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::Method;
+use url::form_urlencoded;
+
+pub use reqwest::Body;
+
+/// Importing, exporting, looking up and removing contacts.
+pub struct MarketingContacts;
+
+impl Endpoint for MarketingContacts {
+    const SCOPES: &'static [&'static str] = &["marketing.read", "marketing.write"];
+}
+
+// How long to wait between checks of whether an import is done, and how often to check
+// before giving up.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_POLLS: usize = 120;
+
+/// How the columns of a CSV file map to contact fields, and the lists the imported
+/// contacts are added to.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ContactImport {
+    file_type: &'static str,
+    field_mappings: Vec<Option<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    list_ids: Vec<String>,
+}
+
+impl ContactImport {
+    /// Construct an import with the field ID of every column of the CSV file, in order.
+    /// Reserved fields have IDs like `_rf0_T` for the email address, see
+    /// `GET /v3/marketing/field_definitions`. Columns mapped to `None` are skipped.
+    pub fn new(field_mappings: &[Option<&str>]) -> ContactImport {
+        ContactImport {
+            file_type: "csv",
+            field_mappings: field_mappings
+                .iter()
+                .map(|field| field.map(String::from))
+                .collect(),
+            list_ids: Vec::new(),
+        }
+    }
+
+    /// Add the imported contacts to a list.
+    pub fn add_list(&mut self, list_id: &str) {
+        self.list_ids.push(String::from(list_id));
+    }
+}
+
+/// An import job and how far it got.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ImportJob {
+    pub id: String,
+    /// One of `pending`, `completed`, `errored` or `failed`.
+    pub status: String,
+    pub results: ImportResults,
+    /// Other fields of the job, such as when it started and finished.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The counts of an import job.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ImportResults {
+    pub requested_count: u64,
+    pub created_count: u64,
+    pub updated_count: u64,
+    pub deleted_count: u64,
+    pub errored_count: u64,
+    /// Where to download a CSV file with the rows that could not be imported.
+    pub errors_url: Option<String>,
+    /// Other counts of the import, such as deleted or updated contacts.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl ImportJob {
+    /// Whether the job is still running.
+    pub fn is_pending(&self) -> bool {
+        self.status == "pending"
+    }
+}
+
+/// The number of contacts, and how many of them count towards the plan.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ContactCount {
+    /// The number of contacts of the account.
+    pub contact_count: u64,
+    /// The number of contacts the account is billed for.
+    pub billable_count: u64,
+    /// The billable contacts of the account and each of its subusers, only set for
+    /// accounts with subusers.
+    pub billable_breakdown: Option<BillableBreakdown>,
+    /// Other fields of the count, such as counts of new contact types.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The billable contacts of an account split by subuser.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct BillableBreakdown {
+    /// The billable contacts of the account and its subusers together.
+    pub total: u64,
+    /// The billable contacts of the account and each subuser, by username.
+    pub breakdown: BTreeMap<String, u64>,
+}
+
+impl ContactCount {
+    /// The contacts that are not billed, e.g. because they unsubscribed.
+    pub fn non_billable_count(&self) -> u64 {
+        self.contact_count.saturating_sub(self.billable_count)
+    }
+}
+
+/// A handle for the contacts of the account, returned by `V3Sender::contacts`.
+///
+/// # Examples
+///
+/// ```ignore
+/// if let Some(contact) = sender.contacts().get_by_email("ada@example.com")? {
+///     println!("{} is on {} lists", contact.email, contact.list_ids.len());
+/// }
+/// ```
+pub struct Contacts<'a> {
+    sender: &'a V3Sender,
+}
+
+/// A contact with all its fields.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Contact {
+    pub id: String,
+    pub email: String,
+    pub alternate_emails: Vec<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub address_line_1: Option<String>,
+    pub address_line_2: Option<String>,
+    pub city: Option<String>,
+    pub state_province_region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+    pub phone_number: Option<String>,
+    /// The IDs of the lists the contact is on.
+    pub list_ids: Vec<String>,
+    /// The IDs of the segments the contact is in.
+    pub segment_ids: Vec<String>,
+    /// The values of the custom fields of the contact, by field name.
+    pub custom_fields: HashMap<String, ::serde_json::Value>,
+    /// When the contact was created, formatted as RFC 3339.
+    pub created_at: Option<String>,
+    /// When the contact was last changed, formatted as RFC 3339.
+    pub updated_at: Option<String>,
+    /// The reserved fields this type has no field for yet, such as the social handles.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A contact to add or update with `V3Sender::upsert_contacts`. Contacts are matched by
+/// their email address.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut contact = NewContact::new("ada@example.com");
+/// contact.set_first_name("Ada");
+/// contact.set_custom_field("seats", 3);
+/// contact.set_custom_field("renewal", FieldValue::date(2025, 1, 31));
+/// let job_id = sender.upsert_contacts(&[contact], &[])?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewContact {
+    email: String,
+    alternate_emails: Vec<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    address_line_1: Option<String>,
+    address_line_2: Option<String>,
+    city: Option<String>,
+    state_province_region: Option<String>,
+    postal_code: Option<String>,
+    country: Option<String>,
+    phone_number: Option<String>,
+    custom_fields: Vec<(String, FieldValue)>,
+}
+
+impl NewContact {
+    /// Construct a contact with an email address.
+    pub fn new(email: &str) -> NewContact {
+        NewContact {
+            email: String::from(email),
+            ..NewContact::default()
+        }
+    }
+
+    /// Set the first name of the contact.
+    pub fn set_first_name(&mut self, first_name: &str) {
+        self.first_name = Some(String::from(first_name));
+    }
+
+    /// Set the last name of the contact.
+    pub fn set_last_name(&mut self, last_name: &str) {
+        self.last_name = Some(String::from(last_name));
+    }
+
+    /// Add another email address of the contact, which SendGrid matches it by too.
+    pub fn add_alternate_email(&mut self, email: &str) {
+        self.alternate_emails.push(String::from(email));
+    }
+
+    /// Set the first line of the address of the contact.
+    pub fn set_address_line_1(&mut self, address_line_1: &str) {
+        self.address_line_1 = Some(String::from(address_line_1));
+    }
+
+    /// Set the second line of the address of the contact.
+    pub fn set_address_line_2(&mut self, address_line_2: &str) {
+        self.address_line_2 = Some(String::from(address_line_2));
+    }
+
+    /// Set the city of the contact.
+    pub fn set_city(&mut self, city: &str) {
+        self.city = Some(String::from(city));
+    }
+
+    /// Set the state, province or region of the contact.
+    pub fn set_state_province_region(&mut self, state_province_region: &str) {
+        self.state_province_region = Some(String::from(state_province_region));
+    }
+
+    /// Set the postal code of the contact.
+    pub fn set_postal_code(&mut self, postal_code: &str) {
+        self.postal_code = Some(String::from(postal_code));
+    }
+
+    /// Set the country of the contact.
+    pub fn set_country(&mut self, country: &str) {
+        self.country = Some(String::from(country));
+    }
+
+    /// Set the phone number of the contact.
+    pub fn set_phone_number(&mut self, phone_number: &str) {
+        self.phone_number = Some(String::from(phone_number));
+    }
+
+    /// Set a custom field by its name. The value is checked against the definition of
+    /// the field before the contact is sent.
+    pub fn set_custom_field<V: Into<FieldValue>>(&mut self, name: &str, value: V) {
+        self.custom_fields.push((String::from(name), value.into()));
+    }
+}
+
+#[derive(Serialize)]
+struct Upsert<'a> {
+    #[serde(skip_serializing_if = "<[&str]>::is_empty")]
+    list_ids: &'a [&'a str],
+    contacts: Vec<UpsertContact<'a>>,
+}
+
+#[derive(Serialize)]
+struct UpsertContact<'a> {
+    email: &'a str,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    alternate_emails: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address_line_1: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address_line_2: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    city: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_province_region: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    postal_code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phone_number: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    custom_fields: BTreeMap<&'a str, &'a FieldValue>,
+}
+
+#[derive(Deserialize)]
+struct UpsertJob {
+    job_id: String,
+}
+
+/// Which contacts to export. Without lists or segments, every contact is exported.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ContactExport {
+    file_type: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    list_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    segment_ids: Vec<String>,
+}
+
+impl ContactExport {
+    /// Construct an export of every contact.
+    pub fn new() -> ContactExport {
+        ContactExport {
+            file_type: "json",
+            ..ContactExport::default()
+        }
+    }
+
+    /// Only export the contacts of a list.
+    pub fn add_list(&mut self, list_id: &str) {
+        self.list_ids.push(String::from(list_id));
+    }
+
+    /// Only export the contacts of a segment.
+    pub fn add_segment(&mut self, segment_id: &str) {
+        self.segment_ids.push(String::from(segment_id));
+    }
+}
+
+/// A contact export job and the files it was written to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ExportJob {
+    pub id: String,
+    /// One of `pending`, `ready` or `failure`.
+    pub status: String,
+    /// Where to download the files of the export from, once it is ready.
+    pub urls: Vec<String>,
+    pub message: Option<String>,
+    /// Other fields of the export, such as its contact count or message.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// An iterator over the contacts of an export, which downloads the files of the export
+/// one after the other and decodes them as they are read.
+pub struct ExportedContacts<'a> {
+    sender: &'a V3Sender,
+    urls: VecDeque<String>,
+    current: Option<NdjsonRecords<Contact>>,
+}
+
+impl<'a> Iterator for ExportedContacts<'a> {
+    type Item = SendgridResult<Contact>;
+
+    fn next(&mut self) -> Option<SendgridResult<Contact>> {
+        loop {
+            if let Some(contact) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(contact);
+            }
+            let url = self.urls.pop_front()?;
+            match self
+                .sender
+                .download_resumable(&url)
+                .and_then(NdjsonRecords::new)
+            {
+                Ok(records) => self.current = Some(records),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportId {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct EmailSearch<'a> {
+    emails: &'a [&'a str],
+}
+
+#[derive(Deserialize)]
+struct EmailSearchResults {
+    #[serde(default)]
+    result: HashMap<String, EmailSearchResult>,
+}
+
+#[derive(Deserialize)]
+struct EmailSearchResult {
+    contact: Option<Contact>,
+}
+
+#[derive(Deserialize)]
+struct UploadHeader {
+    header: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct ImportUpload {
+    job_id: String,
+    upload_uri: String,
+    #[serde(default)]
+    upload_headers: Vec<UploadHeader>,
+}
+
+impl V3Sender {
+    /// Import contacts from a CSV file and wait for the import to finish. The file is
+    /// streamed to the upload URL SendGrid hands out, so `File`s and readers wrapped in
+    /// `Body::sized` aren't loaded into memory. Rows that could not be imported are
+    /// counted in the results of the job, and `contact_import_errors` gets them.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut import = ContactImport::new(&[Some("_rf0_T"), Some("_rf1_T"), None]);
+    /// import.add_list("ca7a3796-e8a8-4029-9ccb-df8937940562");
+    /// let job = sender.import_contacts_csv(File::open("contacts.csv")?, &import)?;
+    /// if job.results.errored_count > 0 {
+    ///     eprintln!("{}", sender.contact_import_errors(&job)?.unwrap_or_default());
+    /// }
+    /// ```
+    pub fn import_contacts_csv<B: Into<Body>>(
+        &self,
+        csv: B,
+        import: &ContactImport,
+    ) -> SendgridResult<ImportJob> {
+        let res = self.api_send_json(Method::Put, "/marketing/contacts/imports", import)?;
+        let upload: ImportUpload = self.read_json(res)?;
+        let headers: Vec<(String, String)> = upload
+            .upload_headers
+            .into_iter()
+            .map(|h| (h.header, h.value))
+            .collect();
+        self.upload(&upload.upload_uri, &headers, csv.into())?;
+
+        for _ in 0..MAX_POLLS {
+            let job = self.contact_import(&upload.job_id)?;
+            if job.status == "failed" {
+                let reason = format!("import {} failed", job.id);
+                return Err(SendgridErrorKind::ContactImport(reason).into());
+            }
+            if !job.is_pending() {
+                return Ok(job);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        let reason = format!("import {} never finished", upload.job_id);
+        Err(SendgridErrorKind::ContactImport(reason).into())
+    }
+
+    /// Count the contacts, for showing the size of the audience and what it costs
+    /// without getting every contact.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let count = sender.contact_count()?;
+    /// println!("{} contacts, {} billable", count.contact_count, count.billable_count);
+    /// ```
+    pub fn contact_count(&self) -> SendgridResult<ContactCount> {
+        self.api_get("/marketing/contacts/count")
+    }
+
+    /// Add or update contacts, and add them to lists. Custom fields are set by name and
+    /// checked against the field definitions of the account first, so a value that
+    /// doesn't fit its field fails with `InvalidCustomField` before anything is sent.
+    /// Returns the ID of the job SendGrid processes the contacts in.
+    pub fn upsert_contacts(
+        &self,
+        contacts: &[NewContact],
+        list_ids: &[&str],
+    ) -> SendgridResult<String> {
+        let definitions = if contacts.iter().any(|c| !c.custom_fields.is_empty()) {
+            self.field_definitions()?
+        } else {
+            FieldDefinitions::default()
+        };
+        let body = upsert_body(&definitions, contacts, list_ids)?;
+        let res = self.api_send_json(Method::Put, "/marketing/contacts", &body)?;
+        let job: UpsertJob = self.read_json(res)?;
+        Ok(job.job_id)
+    }
+
+    /// Export contacts and wait for the export to be ready, then stream them. The files
+    /// of the export are downloaded one at a time and decompressed as they are read,
+    /// so memory use stays flat no matter how many contacts there are, and downloads
+    /// resume where they stopped when the connection drops.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for contact in sender.export_contacts(&ContactExport::new())? {
+    ///     warehouse.insert(&contact?)?;
+    /// }
+    /// ```
+    pub fn export_contacts(&self, export: &ContactExport) -> SendgridResult<ExportedContacts<'_>> {
+        let res = self.api_send_json(Method::Post, "/marketing/contacts/exports", export)?;
+        let export: ExportId = self.read_json(res)?;
+
+        for _ in 0..MAX_POLLS {
+            let job = self.contact_export(&export.id)?;
+            match &job.status[..] {
+                "ready" => {
+                    return Ok(ExportedContacts {
+                        sender: self,
+                        urls: job.urls.into_iter().collect(),
+                        current: None,
+                    })
+                }
+                "failure" => {
+                    let reason = job.message.unwrap_or(format!("export {} failed", job.id));
+                    return Err(SendgridErrorKind::ContactExport(reason).into());
+                }
+                _ => thread::sleep(POLL_INTERVAL),
+            }
+        }
+        let reason = format!("export {} was never ready", export.id);
+        Err(SendgridErrorKind::ContactExport(reason).into())
+    }
+
+    /// Get the status of a contact export job.
+    pub fn contact_export(&self, job_id: &str) -> SendgridResult<ExportJob> {
+        self.api_get(&format!("/marketing/contacts/exports/{}", job_id))
+    }
+
+    /// Get a handle for the contacts of the account.
+    pub fn contacts(&self) -> Contacts<'_> {
+        Contacts { sender: self }
+    }
+
+    /// Get the status of an import job.
+    pub fn contact_import(&self, job_id: &str) -> SendgridResult<ImportJob> {
+        self.api_get(&format!("/marketing/contacts/imports/{}", job_id))
+    }
+
+    /// Download the CSV file with the rows of an import that could not be imported and
+    /// why, or `None` if every row was imported.
+    pub fn contact_import_errors(&self, job: &ImportJob) -> SendgridResult<Option<String>> {
+        let url = match job.results.errors_url {
+            Some(ref url) if !url.is_empty() => url,
+            _ => return Ok(None),
+        };
+        let errors = self.read_text(self.download(url)?)?;
+        Ok(Some(errors))
+    }
+}
+
+impl<'a> Contacts<'a> {
+    /// Get a contact by its ID, or `None` if there is no such contact.
+    pub fn get_by_id(&self, id: &str) -> SendgridResult<Option<Contact>> {
+        not_found_as_none(self.sender.api_get(&contact_path(id)))
+    }
+
+    /// Get the contact with an email address, or `None` if there is none. Addresses are
+    /// compared without regard to case.
+    pub fn get_by_email(&self, email: &str) -> SendgridResult<Option<Contact>> {
+        Ok(self.get_by_emails(&[email])?.into_iter().next())
+    }
+
+    /// Get the contacts with any of a list of email addresses. Addresses without a
+    /// contact are left out. Long lists are searched 100 addresses at a time, the most
+    /// the search takes at once.
+    pub fn get_by_emails(&self, emails: &[&str]) -> SendgridResult<Vec<Contact>> {
+        let mut contacts = Vec::new();
+        for chunk in emails.chunks(SEARCH_BATCH_SIZE) {
+            contacts.extend(self.search_emails(chunk)?);
+        }
+        Ok(contacts)
+    }
+
+    // Search for up to 100 addresses.
+    fn search_emails(&self, emails: &[&str]) -> SendgridResult<Vec<Contact>> {
+        let body = EmailSearch { emails };
+        let path = "/marketing/contacts/search/emails";
+        let res = match self.sender.api_send_json(Method::Post, path, &body) {
+            Ok(res) => res,
+            // The search answers 404 when none of the addresses has a contact.
+            Err(ref e) if is_not_found(e.kind()) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let results: EmailSearchResults = self.sender.read_json(res)?;
+        let mut results = results.result;
+        Ok(emails
+            .iter()
+            .filter_map(|email| {
+                let key = results
+                    .keys()
+                    .find(|k| k.eq_ignore_ascii_case(email))
+                    .cloned()?;
+                results.remove(&key)?.contact
+            })
+            .collect())
+    }
+
+    /// Remove contacts from a list, by their IDs. The contacts themselves are kept.
+    /// Returns the IDs of the jobs SendGrid removes them in, one for every 100
+    /// contacts.
+    pub fn remove_from_list(
+        &self,
+        list_id: &str,
+        contact_ids: &[&str],
+    ) -> SendgridResult<Vec<String>> {
+        let mut jobs = Vec::new();
+        for chunk in contact_ids.chunks(LIST_BATCH_SIZE) {
+            let path = list_contacts_path(list_id, chunk);
+            let res = self.sender.api_request(Method::Delete, &path, None)?;
+            let job: UpsertJob = self.sender.read_json(res)?;
+            jobs.push(job.job_id);
+        }
+        Ok(jobs)
+    }
+}
+
+// The most contacts removed from a list at once.
+const LIST_BATCH_SIZE: usize = 100;
+
+// The most addresses looked up in one contact search.
+const SEARCH_BATCH_SIZE: usize = 100;
+
+fn contact_path(id: &str) -> String {
+    format!("/marketing/contacts/{}", suppressions::encode(id))
+}
+
+// The path that removes contacts from a list. The IDs are encoded one by one, as the
+// commas between them separate the IDs.
+fn list_contacts_path(list_id: &str, contact_ids: &[&str]) -> String {
+    let contact_ids: Vec<String> = contact_ids
+        .iter()
+        .map(|id| form_urlencoded::byte_serialize(id.as_bytes()).collect())
+        .collect();
+    format!(
+        "/marketing/lists/{}/contacts?contact_ids={}",
+        suppressions::encode(list_id),
+        contact_ids.join(",")
+    )
+}
+
+// The body that adds or updates contacts, with their custom fields set by ID.
+fn upsert_body<'a>(
+    definitions: &'a FieldDefinitions,
+    contacts: &'a [NewContact],
+    list_ids: &'a [&'a str],
+) -> SendgridResult<Upsert<'a>> {
+    let contacts = contacts
+        .iter()
+        .map(|contact| {
+            let mut custom_fields = BTreeMap::new();
+            for (name, value) in &contact.custom_fields {
+                custom_fields.insert(definitions.check(name, value)?, value);
+            }
+            Ok(UpsertContact {
+                email: &contact.email,
+                alternate_emails: &contact.alternate_emails,
+                first_name: contact.first_name.as_deref(),
+                last_name: contact.last_name.as_deref(),
+                address_line_1: contact.address_line_1.as_deref(),
+                address_line_2: contact.address_line_2.as_deref(),
+                city: contact.city.as_deref(),
+                state_province_region: contact.state_province_region.as_deref(),
+                postal_code: contact.postal_code.as_deref(),
+                country: contact.country.as_deref(),
+                phone_number: contact.phone_number.as_deref(),
+                custom_fields,
+            })
+        })
+        .collect::<SendgridResult<Vec<_>>>()?;
+    Ok(Upsert { list_ids, contacts })
+}
+
+fn not_found_as_none<T>(res: SendgridResult<T>) -> SendgridResult<Option<T>> {
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if is_not_found(e.kind()) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_not_found(kind: &SendgridErrorKind) -> bool {
+    matches!(*kind, SendgridErrorKind::Api(404, _))
+}
+
+#[test]
+fn import_body() {
+    let mut import = ContactImport::new(&[Some("_rf0_T"), None]);
+    import.add_list("list");
+    assert_eq!(
+        ::serde_json::to_string(&import).unwrap(),
+        r#"{"file_type":"csv","field_mappings":["_rf0_T",null],"list_ids":["list"]}"#
+    );
+
+    let job: ImportJob = ::serde_json::from_str(
+        r#"{"id":"job","status":"errored","results":{"requested_count":2,"created_count":1,"errored_count":1,"errors_url":"https://example.com/errors.csv"}}"#,
+    )
+    .unwrap();
+    assert!(!job.is_pending());
+    assert_eq!(job.results.errored_count, 1);
+}
+
+#[test]
+fn upsert_bodies() {
+    let definitions: FieldDefinitions = ::serde_json::from_str(
+        r#"{"custom_fields":[{"id":"e1_N","name":"seats","field_type":"Number"}]}"#,
+    )
+    .unwrap();
+    let mut contact = NewContact::new("ada@example.com");
+    contact.set_first_name("Ada");
+    contact.set_custom_field("seats", 3i64);
+    let contacts = [contact];
+    let body = upsert_body(&definitions, &contacts, &["l1"]).unwrap();
+    assert_eq!(
+        ::serde_json::to_string(&body).unwrap(),
+        r#"{"list_ids":["l1"],"contacts":[{"email":"ada@example.com","first_name":"Ada","custom_fields":{"e1_N":3.0}}]}"#
+    );
+
+    let mut contact = NewContact::new("grace@example.com");
+    contact.add_alternate_email("grace@work.example.com");
+    contact.set_city("Arlington");
+    contact.set_postal_code("22201");
+    let contacts = [contact];
+    let body = upsert_body(&definitions, &contacts, &[]).unwrap();
+    assert_eq!(
+        ::serde_json::to_string(&body).unwrap(),
+        r#"{"contacts":[{"email":"grace@example.com","alternate_emails":["grace@work.example.com"],"city":"Arlington","postal_code":"22201"}]}"#
+    );
+}
+
+#[test]
+fn contact_counts() {
+    let count: ContactCount = ::serde_json::from_str(
+        r#"{"contact_count":120,"billable_count":100,"billable_breakdown":{"total":100,"breakdown":{"user":60,"subuser":40}}}"#,
+    )
+    .unwrap();
+    assert_eq!(count.non_billable_count(), 20);
+    assert_eq!(count.billable_breakdown.unwrap().breakdown["subuser"], 40);
+}
+
+#[test]
+fn contact_lookups() {
+    let results: EmailSearchResults = ::serde_json::from_str(
+        r#"{"result":{"ada@example.com":{"contact":{"id":"c1","email":"ada@example.com","first_name":"Ada","list_ids":["l1"],"custom_fields":{"plan":"pro","seats":3},"created_at":"2021-01-01T00:00:00Z"}},"nobody@example.com":{"error":"contact not found"}}}"#,
+    )
+    .unwrap();
+    let contact = results.result["ada@example.com"].contact.clone().unwrap();
+    assert_eq!(contact.first_name.as_deref(), Some("Ada"));
+    assert_eq!(contact.custom_fields["seats"], 3);
+    assert!(results.result["nobody@example.com"].contact.is_none());
+
+    assert_eq!(contact_path("c1"), "/marketing/contacts/c1");
+    assert_eq!(contact_path("../lists"), "/marketing/contacts/..%2Flists");
+}
+
+#[test]
+fn list_contacts_paths() {
+    assert_eq!(
+        list_contacts_path("l/1", &["c1", "c&2"]),
+        "/marketing/lists/l%2F1/contacts?contact_ids=c1,c%262"
+    );
+}
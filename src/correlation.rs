@@ -0,0 +1,144 @@
+//! Matching webhook events to the messages they are about, through the correlation ID
+//! stored in the custom arguments of a message.
+
+use v3::{SGMailV3, CORRELATION_ID_ARG};
+use webhooks::Event;
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+#[cfg(all(feature = "client", feature = "uuid"))]
+use errors::SendgridResult;
+#[cfg(all(feature = "client", feature = "uuid"))]
+use reqwest::Response;
+#[cfg(all(feature = "client", feature = "uuid"))]
+use uuid::Uuid;
+#[cfg(all(feature = "client", feature = "uuid"))]
+use v3::V3Sender;
+
+/// Storage for the metadata of sent messages by their correlation ID. Implement this on
+/// top of a database when the events are handled by another process than the one that
+/// sent the messages.
+pub trait CorrelationStore<M> {
+    /// Record the metadata of a message.
+    fn insert(&self, correlation_id: &str, metadata: M);
+
+    /// Get the metadata of a message.
+    fn get(&self, correlation_id: &str) -> Option<M>;
+}
+
+/// A correlation store that keeps the metadata in memory.
+#[derive(Debug)]
+pub struct MemoryCorrelationStore<M> {
+    entries: Mutex<HashMap<String, M>>,
+}
+
+impl<M> Default for MemoryCorrelationStore<M> {
+    fn default() -> MemoryCorrelationStore<M> {
+        MemoryCorrelationStore {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<M: Clone> CorrelationStore<M> for MemoryCorrelationStore<M> {
+    fn insert(&self, correlation_id: &str, metadata: M) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(String::from(correlation_id), metadata);
+    }
+
+    fn get(&self, correlation_id: &str) -> Option<M> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(correlation_id).cloned()
+    }
+}
+
+/// Records metadata about messages when they are sent, such as the ID of the user or
+/// job that sent them, and resolves webhook events back to that metadata.
+///
+/// # Examples
+///
+/// ```ignore
+/// let tracker = CorrelationTracker::new();
+/// tracker.send(&sender, &mut message, user.id)?;
+///
+/// for event in parse_events(&body).0 {
+///     if let Some(user_id) = tracker.resolve(&event) {
+///         println!("{} got a {:?} event", user_id, event);
+///     }
+/// }
+/// ```
+pub struct CorrelationTracker<M, S: CorrelationStore<M> = MemoryCorrelationStore<M>> {
+    store: S,
+    metadata: PhantomData<M>,
+}
+
+impl<M: Clone> CorrelationTracker<M, MemoryCorrelationStore<M>> {
+    /// Construct a tracker that keeps the metadata in memory.
+    pub fn new() -> CorrelationTracker<M> {
+        CorrelationTracker::with_store(MemoryCorrelationStore::default())
+    }
+}
+
+impl<M: Clone> Default for CorrelationTracker<M, MemoryCorrelationStore<M>> {
+    fn default() -> CorrelationTracker<M> {
+        CorrelationTracker::new()
+    }
+}
+
+impl<M, S: CorrelationStore<M>> CorrelationTracker<M, S> {
+    /// Construct a tracker that keeps the metadata in the given store.
+    pub fn with_store(store: S) -> CorrelationTracker<M, S> {
+        CorrelationTracker {
+            store,
+            metadata: PhantomData,
+        }
+    }
+
+    /// Stamp a message with a correlation ID and record its metadata.
+    pub fn track(&self, mail: &mut SGMailV3, correlation_id: &str, metadata: M) {
+        mail.add_custom_arg(CORRELATION_ID_ARG, correlation_id);
+        self.store.insert(correlation_id, metadata);
+    }
+
+    /// Send a message with a generated correlation ID, recording its metadata once it
+    /// was sent.
+    #[cfg(all(feature = "client", feature = "uuid"))]
+    pub fn send(
+        &self,
+        sender: &V3Sender,
+        mail: &mut SGMailV3,
+        metadata: M,
+    ) -> SendgridResult<(Uuid, Response)> {
+        let (correlation_id, res) = sender.send_tracked(mail)?;
+        if res.status().is_success() {
+            self.store.insert(&correlation_id.to_string(), metadata);
+        }
+        Ok((correlation_id, res))
+    }
+
+    /// Get the metadata of the message an event is about, if it was tracked.
+    pub fn resolve(&self, event: &Event) -> Option<M> {
+        let correlation_id = event.info().correlation_id.as_ref()?;
+        self.store.get(correlation_id)
+    }
+}
+
+#[test]
+fn resolve_events() {
+    let tracker = CorrelationTracker::new();
+    let mut m = SGMailV3::new();
+    tracker.track(&mut m, "abc", 42);
+    assert!(::serde_json::to_string(&m)
+        .unwrap()
+        .contains(r#""custom_args":{"correlation_id":"abc"}"#));
+
+    let events = ::webhooks::parse_events(
+        br#"[{"event":"processed","email":"a@example.com","timestamp":1,"sg_event_id":"e1","correlation_id":"abc"},
+             {"event":"processed","email":"b@example.com","timestamp":1,"sg_event_id":"e2"}]"#,
+    )
+    .0;
+    assert_eq!(tracker.resolve(&events[0]), Some(42));
+    assert_eq!(tracker.resolve(&events[1]), None);
+}
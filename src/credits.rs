@@ -0,0 +1,137 @@
+//! Estimating how many plan credits a send uses, and comparing that with what is left.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::{SGMailV3, V3Sender};
+
+/// Reading the credits of the plan.
+pub struct Credits;
+
+impl Endpoint for Credits {
+    const SCOPES: &'static [&'static str] = &["user.credits.read"];
+}
+
+/// The credits of the plan for the current period. Every recipient of a message uses one
+/// credit.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CreditBalance {
+    /// The credits left in the current period.
+    pub remain: i64,
+    /// The credits of the period.
+    pub total: i64,
+    /// The credits used beyond the total.
+    #[serde(default)]
+    pub overage: i64,
+    /// The credits used in the current period.
+    pub used: i64,
+    /// The date the credits were last reset, formatted as `YYYY-MM-DD`.
+    pub last_reset: String,
+    /// The date the credits will be reset next, formatted as `YYYY-MM-DD`.
+    pub next_reset: String,
+    /// How often the credits are reset, e.g. `monthly`.
+    pub reset_frequency: String,
+    /// Other fields of the balance SendGrid adds after this release.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The credits a batch of messages would use, next to what is left of the plan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CreditEstimate {
+    /// The credits the messages would use.
+    pub needed: u64,
+    /// The credits left in the current period.
+    pub remaining: i64,
+}
+
+impl CreditEstimate {
+    /// Whether sending the messages would use more credits than are left.
+    pub fn exceeds_remaining(&self) -> bool {
+        self.remaining < 0 || self.needed > self.remaining as u64
+    }
+}
+
+/// The credits a batch of messages would use, which is the number of recipients of
+/// every message.
+pub fn credits_needed<'a, I: IntoIterator<Item = &'a SGMailV3>>(messages: I) -> u64 {
+    messages
+        .into_iter()
+        .map(|message| message.recipients().count() as u64)
+        .sum()
+}
+
+impl V3Sender {
+    /// Get the credits of the plan for the current period. Requires the
+    /// `user.credits.read` scope.
+    pub fn credits(&self) -> SendgridResult<CreditBalance> {
+        self.api_get("/user/credits")
+    }
+
+    /// Estimate the credits a batch of messages would use and get what is left of the
+    /// plan, so a large send can be held back when it would run over. Requires the
+    /// `user.credits.read` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let messages = merge.build(recipients)?;
+    /// let estimate = sender.estimate_credits(&messages)?;
+    /// if estimate.exceeds_remaining() {
+    ///     return Err(format!("{} credits needed, {} left", estimate.needed, estimate.remaining));
+    /// }
+    /// sender.send_all(messages);
+    /// ```
+    pub fn estimate_credits<'a, I>(&self, messages: I) -> SendgridResult<CreditEstimate>
+    where
+        I: IntoIterator<Item = &'a SGMailV3>,
+    {
+        let needed = credits_needed(messages);
+        let balance = self.credits()?;
+        let estimate = CreditEstimate {
+            needed,
+            remaining: balance.remain,
+        };
+        #[cfg(feature = "log")]
+        {
+            if estimate.exceeds_remaining() {
+                warn!(
+                    "sending would use {} credits but only {} are left",
+                    estimate.needed, estimate.remaining
+                );
+            }
+        }
+        Ok(estimate)
+    }
+}
+
+#[test]
+fn estimate_batch_credits() {
+    use v3::{Email, Personalization};
+
+    let mut to = Email::new();
+    to.set_email("a@example.com");
+    let mut message = SGMailV3::new();
+    for _ in 0..3 {
+        let mut p = Personalization::new();
+        p.add_to(to.clone());
+        p.add_cc(to.clone());
+        message.add_personalization(p);
+    }
+    assert_eq!(credits_needed(&[message, SGMailV3::new()]), 6);
+
+    let balance: CreditBalance = ::serde_json::from_str(
+        r#"{"remain":5,"total":100,"overage":0,"used":95,"last_reset":"2018-01-01","next_reset":"2018-02-01","reset_frequency":"monthly"}"#,
+    )
+    .unwrap();
+    let estimate = CreditEstimate {
+        needed: 6,
+        remaining: balance.remain,
+    };
+    assert!(estimate.exceeds_remaining());
+    assert!(!CreditEstimate {
+        needed: 5,
+        remaining: 5
+    }
+    .exceeds_remaining());
+}
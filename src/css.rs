@@ -0,0 +1,508 @@
+//! Inlining the CSS of `<style>` blocks into the `style` attributes of the elements it
+//! applies to, since Gmail, Outlook and many other clients drop or ignore style blocks.
+//!
+//! Rules with simple selectors are inlined: type, class and ID selectors and
+//! combinations of them like `td.header` or `#footer`, in comma separated lists. Rules
+//! the inliner can't apply, such as `@media` queries, pseudo-classes and descendant
+//! selectors, are kept in a style block for the clients that support them.
+
+use mail::Mail;
+use v3::SGMailV3;
+
+use indexmap::IndexMap;
+
+use std::mem;
+
+/// Inline the CSS of the `<style>` blocks of HTML content. Declarations of a style
+/// attribute win over the ones from style blocks, unless those are `!important` and
+/// the attribute's aren't. `!important` declarations of a style attribute keep their
+/// flag.
+///
+/// # Examples
+///
+/// ```ignore
+/// let html = css::inline("<style>p { color: red }</style><p>Hi</p>");
+/// assert_eq!(html, "<p style=\"color: red\">Hi</p>");
+/// ```
+pub fn inline(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let blocks = style_blocks(html, &lower);
+    let mut rules = Vec::new();
+    let mut kept = String::new();
+    for block in &blocks {
+        parse_stylesheet(&html[block.css.0..block.css.1], &mut rules, &mut kept);
+    }
+    if rules.is_empty() {
+        return String::from(html);
+    }
+
+    let mut out = String::with_capacity(html.len() + html.len() / 4);
+    let mut emitted_kept = false;
+    let mut i = 0;
+    while let Some(offset) = lower[i..].find('<') {
+        let start = i + offset;
+        out.push_str(&html[i..start]);
+        let rest = &lower[start..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(html.len(), |e| start + e + 3);
+            out.push_str(&html[start..end]);
+            i = end;
+            continue;
+        }
+        if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            out.push('<');
+            i = start + 1;
+            continue;
+        }
+
+        let end = tag_end(html, start);
+        let tag = &html[start..end];
+        let name = tag_name(tag);
+        match &name[..] {
+            "style" if blocks.iter().any(|b| b.start == start) => {
+                // The blocks are replaced by a single one with the rules that were kept.
+                let close = close_tag(&lower, end, "style");
+                if !emitted_kept && !kept.is_empty() {
+                    out.push_str("<style type=\"text/css\">");
+                    out.push_str(kept.trim());
+                    out.push_str("</style>");
+                }
+                emitted_kept = true;
+                i = close;
+            }
+            "script" | "style" => {
+                let close = close_tag(&lower, end, &name);
+                out.push_str(&html[start..close]);
+                i = close;
+            }
+            _ => {
+                out.push_str(&inline_tag(tag, &name, &rules));
+                i = end;
+            }
+        }
+    }
+    out.push_str(&html[i..]);
+    out
+}
+
+impl SGMailV3 {
+    /// Inline the CSS of the `<style>` blocks of the HTML content into the elements it
+    /// applies to. See the `css` module for which rules are inlined.
+    pub fn inline_css(&mut self) {
+        if let Some(html) = self.html_mut() {
+            *html = inline(html);
+        }
+    }
+}
+
+impl<'a> Mail<'a> {
+    /// Inline the CSS of the `<style>` blocks of the HTML content into the elements it
+    /// applies to. See the `css` module for which rules are inlined.
+    pub fn inline_css(&mut self) {
+        if !self.html.is_empty() {
+            self.html = inline(&self.html).into();
+        }
+    }
+}
+
+struct StyleBlock {
+    // Where the opening tag starts.
+    start: usize,
+    // The byte range of the CSS within the HTML.
+    css: (usize, usize),
+}
+
+struct Rule {
+    selector: Selector,
+    specificity: u32,
+    declarations: Vec<Declaration>,
+}
+
+#[derive(Clone)]
+struct Declaration {
+    property: String,
+    value: String,
+    important: bool,
+}
+
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    // Parse a simple selector, or return `None` for the ones the inliner can't apply.
+    fn parse(selector: &str) -> Option<Selector> {
+        let selector = selector.trim();
+        if selector.is_empty() || selector.contains(&[' ', '>', '+', '~', ':', '[', '*'][..]) {
+            return None;
+        }
+
+        let mut parsed = Selector {
+            tag: None,
+            id: None,
+            classes: Vec::new(),
+        };
+        // Split into the type and the names following every `.` and `#`.
+        let mut pieces = Vec::new();
+        let mut prefix = None;
+        let mut name = String::new();
+        for c in selector.chars() {
+            if c == '.' || c == '#' {
+                pieces.push((prefix, mem::take(&mut name)));
+                prefix = Some(c);
+            } else {
+                name.push(c);
+            }
+        }
+        pieces.push((prefix, name));
+
+        for (prefix, name) in pieces {
+            match prefix {
+                None if name.is_empty() => {}
+                None => parsed.tag = Some(name.to_ascii_lowercase()),
+                Some(_) if name.is_empty() => return None,
+                Some('#') if parsed.id.is_some() => return None,
+                Some('#') => parsed.id = Some(name),
+                Some(_) => parsed.classes.push(name),
+            }
+        }
+        Some(parsed)
+    }
+
+    fn specificity(&self) -> u32 {
+        self.id.iter().count() as u32 * 100
+            + self.classes.len() as u32 * 10
+            + self.tag.iter().count() as u32
+    }
+
+    fn matches(&self, tag: &str, id: Option<&str>, classes: &[&str]) -> bool {
+        self.tag.as_ref().is_none_or(|t| t == tag)
+            && self.id.as_ref().is_none_or(|i| Some(&i[..]) == id)
+            && self.classes.iter().all(|c| classes.contains(&&c[..]))
+    }
+}
+
+// Find the style blocks that apply to every medium.
+fn style_blocks(html: &str, lower: &str) -> Vec<StyleBlock> {
+    let mut blocks = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = lower[from..].find("<style") {
+        let start = from + offset;
+        let open_end = tag_end(html, start);
+        let close = lower[open_end..].find("</style").map(|e| open_end + e);
+        let close = match close {
+            Some(close) => close,
+            None => break,
+        };
+        let open = &lower[start..open_end];
+        let media = attributes(&html[start..open_end])
+            .find(|&(name, _)| name.eq_ignore_ascii_case("media"))
+            .map(|(_, value)| value.to_ascii_lowercase());
+        if tag_name(open) == "style" && media.as_ref().is_none_or(|m| m == "all" || m == "screen") {
+            blocks.push(StyleBlock {
+                start,
+                css: (open_end, close),
+            });
+        }
+        from = close;
+    }
+    blocks
+}
+
+// Split a stylesheet into the rules that can be inlined and the CSS that has to stay in
+// a style block.
+fn parse_stylesheet(css: &str, rules: &mut Vec<Rule>, kept: &mut String) {
+    let css = strip_comments(css);
+    let mut rest = &css[..];
+
+    loop {
+        rest = rest.trim_start();
+        let open = match rest.find(&['{', ';'][..]) {
+            Some(open) => open,
+            None => return,
+        };
+        let prelude = rest[..open].trim();
+
+        if rest[open..].starts_with(';') {
+            // A statement at-rule such as `@import`.
+            kept.push_str(&rest[..=open]);
+            kept.push(' ');
+            rest = &rest[open + 1..];
+            continue;
+        }
+
+        let close = matching_brace(rest, open);
+        let body = &rest[open + 1..close];
+        let close = (close + 1).min(rest.len());
+        if prelude.starts_with('@') {
+            kept.push_str(&rest[..close]);
+            kept.push(' ');
+        } else {
+            let declarations = parse_declarations(body);
+            let mut unsupported = Vec::new();
+            for selector in prelude.split(',') {
+                match Selector::parse(selector) {
+                    Some(selector) => rules.push(Rule {
+                        specificity: selector.specificity(),
+                        selector,
+                        declarations: declarations.clone(),
+                    }),
+                    None => unsupported.push(selector.trim()),
+                }
+            }
+            if !unsupported.is_empty() {
+                kept.push_str(&format!("{} {{{}}} ", unsupported.join(", "), body));
+            }
+        }
+        rest = &rest[close..];
+    }
+}
+
+fn parse_declarations(body: &str) -> Vec<Declaration> {
+    body.split(';')
+        .filter_map(|declaration| {
+            let colon = declaration.find(':')?;
+            let property = declaration[..colon].trim().to_ascii_lowercase();
+            let mut value = declaration[colon + 1..].trim();
+            let important = value.to_ascii_lowercase().ends_with("!important");
+            if important {
+                value = value[..value.len() - "!important".len()].trim_end();
+            }
+            if property.is_empty() || value.is_empty() {
+                return None;
+            }
+            Some(Declaration {
+                property,
+                // The value ends up in a double quoted attribute.
+                value: value.replace('"', "'"),
+                important,
+            })
+        })
+        .collect()
+}
+
+// Add the declarations of the matching rules to the style attribute of a start tag.
+fn inline_tag(tag: &str, name: &str, rules: &[Rule]) -> String {
+    let mut id = None;
+    let mut class = "";
+    let mut style = None;
+    for (attribute, value) in attributes(tag) {
+        if attribute.eq_ignore_ascii_case("id") {
+            id = Some(value);
+        } else if attribute.eq_ignore_ascii_case("class") {
+            class = value;
+        } else if attribute.eq_ignore_ascii_case("style") {
+            style = Some(value);
+        }
+    }
+    let classes: Vec<&str> = class.split_whitespace().collect();
+
+    let mut matching: Vec<&Rule> = rules
+        .iter()
+        .filter(|r| r.selector.matches(name, id, &classes))
+        .collect();
+    if matching.is_empty() {
+        return String::from(tag);
+    }
+    // Later rules win over earlier ones of the same specificity, so the sort is stable.
+    matching.sort_by_key(|r| r.specificity);
+
+    let normal = matching
+        .iter()
+        .flat_map(|r| r.declarations.iter().filter(|d| !d.important));
+    let own = style.map(parse_declarations).unwrap_or_default();
+    let important = matching
+        .iter()
+        .flat_map(|r| r.declarations.iter().filter(|d| d.important));
+
+    // As in the cascade, important declarations of the attribute come last and win over
+    // the important ones of the rules.
+    let mut merged = IndexMap::new();
+    for d in normal
+        .chain(own.iter().filter(|d| !d.important))
+        .chain(important)
+    {
+        merged.insert(&d.property[..], (&d.value[..], ""));
+    }
+    for d in own.iter().filter(|d| d.important) {
+        merged.insert(&d.property[..], (&d.value[..], " !important"));
+    }
+    let declarations: Vec<String> = merged
+        .iter()
+        .map(|(property, (value, flag))| format!("{}: {}{}", property, value, flag))
+        .collect();
+    let new_style = format!(" style=\"{}\"", declarations.join("; "));
+
+    let without_style = match style_span(tag) {
+        Some((start, end)) => format!("{}{}", &tag[..start], &tag[end..]),
+        None => String::from(tag),
+    };
+    let name_end = 1 + name.len();
+    format!(
+        "{}{}{}",
+        &without_style[..name_end],
+        new_style,
+        &without_style[name_end..]
+    )
+}
+
+// The attributes of a start tag, with the quotes removed from the values.
+fn attributes(tag: &str) -> impl Iterator<Item = (&str, &str)> {
+    attribute_spans(tag).map(|(name, value, _)| (name, value))
+}
+
+// The byte range of the style attribute of a start tag, including the space before it.
+fn style_span(tag: &str) -> Option<(usize, usize)> {
+    attribute_spans(tag)
+        .find(|&(name, _, _)| name.eq_ignore_ascii_case("style"))
+        .map(|(_, _, span)| span)
+}
+
+fn attribute_spans(tag: &str) -> impl Iterator<Item = (&str, &str, (usize, usize))> {
+    let bytes = tag.as_bytes();
+    let mut i = 1 + tag_name(tag).len();
+    ::std::iter::from_fn(move || {
+        let span_start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        let name_start = i;
+        while i < bytes.len() && !b" \t\r\n=>/".contains(&bytes[i]) {
+            i += 1;
+        }
+        if i == name_start {
+            return None;
+        }
+        let name = &tag[name_start..i];
+
+        let mut value = "";
+        if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            match bytes.get(i) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    let end = tag[i + 1..]
+                        .find(quote as char)
+                        .map_or(tag.len(), |e| i + 1 + e);
+                    value = &tag[i + 1..end];
+                    i = (end + 1).min(tag.len());
+                }
+                _ => {
+                    let start = i;
+                    while i < bytes.len() && !b" \t\r\n>".contains(&bytes[i]) {
+                        i += 1;
+                    }
+                    value = &tag[start..i];
+                }
+            }
+        }
+        Some((name, value, (span_start, i)))
+    })
+}
+
+// The lowercase name of a start tag.
+fn tag_name(tag: &str) -> String {
+    tag[1..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+// The index just past the `>` of the tag starting at `start`, skipping quoted values.
+fn tag_end(html: &str, start: usize) -> usize {
+    let mut quote = None;
+    for (i, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return start + i + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+// The index just past the closing tag of an element whose content starts at `from`.
+fn close_tag(lower: &str, from: usize, name: &str) -> usize {
+    let closing = format!("</{}", name);
+    match lower[from..].find(&closing) {
+        Some(e) => tag_end(lower, from + e),
+        None => lower.len(),
+    }
+}
+
+// The index of the brace closing the one at `open`, or the end of unclosed CSS.
+fn matching_brace(css: &str, open: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in css[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return open + i;
+                }
+            }
+            _ => {}
+        }
+    }
+    css.len()
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+#[test]
+fn inline_styles() {
+    let html = "<html><head><STYLE type=\"text/css\">\
+                /* brand */ p { color: red; margin: 0 } .note, #intro { color: blue }\
+                td.cell { padding: 4px !important } a:hover { color: green }\
+                @media (max-width: 600px) { p { font-size: 18px } }\
+                </STYLE><style media=\"print\">p { color: black }</style></head>\
+                <body><p id=\"intro\">Hi</p><p class=\"x note\" style=\"margin: 2px\">Note</p>\
+                <table><tr><TD class='cell' style=\"padding: 0\">1</TD></tr></table>\
+                <!-- <p>not parsed</p> --><p>Plain</p></body></html>";
+    let inlined = inline(html);
+
+    assert!(inlined.contains("<p style=\"color: blue; margin: 0\" id=\"intro\">Hi</p>"));
+    assert!(inlined.contains("<p style=\"color: blue; margin: 2px\" class=\"x note\">Note</p>"));
+    assert!(inlined.contains("<TD style=\"padding: 4px\" class='cell'>1</TD>"));
+    assert!(inlined.contains("<!-- <p>not parsed</p> -->"));
+    assert!(inlined.contains("<p style=\"color: red; margin: 0\">Plain</p>"));
+    // The rules that can't be inlined stay in a single style block.
+    assert!(inlined.contains(
+        "<style type=\"text/css\">a:hover { color: green } \
+         @media (max-width: 600px) { p { font-size: 18px } }</style>"
+    ));
+    assert!(!inlined.contains("brand"));
+    // Style blocks for other media are left alone.
+    assert!(inlined.contains("<style media=\"print\">p { color: black }</style>"));
+
+    assert_eq!(inline("<p>Hi</p>"), "<p>Hi</p>");
+    assert!(Selector::parse("div p").is_none());
+    assert!(Selector::parse(".a.").is_none());
+}
+
+#[test]
+fn inline_important_styles() {
+    let html = "<style>p { color: red !important; margin: 0 !important }</style>\
+                <p style=\"color: blue !important; margin: 2px\">Hi</p>";
+    assert_eq!(
+        inline(html),
+        "<p style=\"margin: 0; color: blue !important\">Hi</p>"
+    );
+}
@@ -0,0 +1,99 @@
+//! Positions in long listings and exports that can be saved and resumed from, so syncs
+//! that take hours can checkpoint their progress and pick up where they stopped after a
+//! restart.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use data_encoding::BASE64URL_NOPAD;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+
+use std::fmt;
+
+/// An opaque position in a listing or export. Store the token it displays as and turn it
+/// back into a cursor with `Cursor::from_token` to resume from the same position. The
+/// token is an encoding of the position, not signed, so only resume from tokens the
+/// application stored itself.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut pages = match checkpoint.load()? {
+///     Some(token) => sender.resume_suppression_pages(&Cursor::from_token(&token))?,
+///     None => sender.suppression_pages(SuppressionList::Bounces, &ListOptions::new()),
+/// };
+/// while let Some(entry) = pages.next() {
+///     db.insert(entry?)?;
+///     checkpoint.save(pages.cursor().as_str())?;
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    token: String,
+}
+
+impl Cursor {
+    /// Turn a token saved from `Cursor::as_str` back into a cursor. Tokens are only
+    /// checked when they are resumed from.
+    pub fn from_token(token: &str) -> Cursor {
+        Cursor {
+            token: String::from(token),
+        }
+    }
+
+    /// The token to store.
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    // A cursor for the position of a kind of listing.
+    pub(crate) fn encode<T: Serialize>(kind: &str, position: &T) -> SendgridResult<Cursor> {
+        let json = serde_json::to_vec(position)?;
+        Ok(Cursor {
+            token: format!("{}.{}", kind, BASE64URL_NOPAD.encode(&json)),
+        })
+    }
+
+    // The position of a kind of listing, failing with `InvalidCursor` when the token is
+    // for another kind or doesn't decode.
+    pub(crate) fn decode<T: DeserializeOwned>(&self, kind: &str) -> SendgridResult<T> {
+        let invalid = || SendgridErrorKind::InvalidCursor(self.token.clone()).into();
+        let encoded = match self.token.split_at(self.token.find('.').unwrap_or(0)) {
+            (prefix, encoded) if prefix == kind => &encoded[1..],
+            _ => return Err(invalid()),
+        };
+        let json = BASE64URL_NOPAD
+            .decode(encoded.as_bytes())
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(&json).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.token)
+    }
+}
+
+#[test]
+fn cursor_tokens() {
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Position {
+        offset: usize,
+    }
+
+    let cursor = Cursor::encode("bounces", &Position { offset: 1500 }).unwrap();
+    let resumed = Cursor::from_token(&cursor.to_string());
+    assert_eq!(
+        resumed.decode::<Position>("bounces").unwrap(),
+        Position { offset: 1500 }
+    );
+    assert!(resumed.decode::<Position>("activity").is_err());
+    assert!(Cursor::from_token("bounces.!!")
+        .decode::<Position>("bounces")
+        .is_err());
+    assert!(Cursor::from_token("")
+        .decode::<Position>("bounces")
+        .is_err());
+}
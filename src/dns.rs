@@ -0,0 +1,408 @@
+//! Checking that the DNS records of an authenticated domain are published before asking
+//! SendGrid to validate it. Lookups are made with a small stub resolver over UDP, falling
+//! back to TCP for truncated answers.
+//!
+//! The stub resolver only asks the configured resolver for CNAME, MX and TXT records
+//! and leaves recursion, caching and DNSSEC to it, which is all a check before
+//! validation needs, without a DNS library as a dependency. Answers are matched to
+//! their query by a random ID and by the question they repeat. A spoofed answer can
+//! only make the check wrong, as SendGrid looks the records up again when it validates
+//! the domain.
+
+use domains::{AuthenticatedDomain, DnsRecord};
+use errors::{SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use reqwest::Method;
+
+/// Checking the DNS records of authenticated domains and validating them.
+pub struct DomainChecks;
+
+impl Endpoint for DomainChecks {
+    const SCOPES: &'static [&'static str] = &["whitelabel.read", "whitelabel.update"];
+}
+
+const TYPE_CNAME: u16 = 5;
+const TYPE_MX: u16 = 15;
+const TYPE_TXT: u16 = 16;
+
+// Answers with this response code mean the name doesn't exist at all.
+const NXDOMAIN: u8 = 3;
+
+/// The answer of the validate endpoint, with the result of every DNS record.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DomainValidation {
+    pub id: u64,
+    /// Whether every record was found.
+    pub valid: bool,
+    /// The result of every record, by the names used in `AuthenticatedDomain::dns`.
+    #[serde(default)]
+    pub validation_results: ::std::collections::BTreeMap<String, RecordValidation>,
+    /// Other fields of the validation result, kept as SendGrid sent them.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// Whether SendGrid found one of the DNS records of a domain.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RecordValidation {
+    pub valid: bool,
+    /// Why the record was not found.
+    pub reason: Option<String>,
+    /// Other details SendGrid gives about the record, such as what it found instead.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A DNS record of an authenticated domain that is not published with the value
+/// SendGrid expects.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingRecord {
+    /// The name of the record in `AuthenticatedDomain::dns`, e.g. `dkim1`.
+    pub name: String,
+    /// The record SendGrid expects.
+    pub expected: DnsRecord,
+    /// The values that were found for the host instead, if any.
+    pub found: Vec<String>,
+}
+
+/// Looks up the DNS records of authenticated domains through a resolver.
+///
+/// # Examples
+///
+/// ```ignore
+/// let checker = DnsChecker::from_system()?;
+/// let domain = sender.create_authenticated_domain(...)?;
+/// for missing in checker.missing_records(&domain)? {
+///     println!("{} {} should be {}", missing.expected.kind, missing.expected.host, missing.expected.data);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DnsChecker {
+    resolver: SocketAddr,
+    timeout: Duration,
+}
+
+impl DnsChecker {
+    /// Construct a checker that uses the resolver at `resolver`, e.g. `1.1.1.1:53`.
+    pub fn new(resolver: SocketAddr) -> DnsChecker {
+        DnsChecker {
+            resolver,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Construct a checker that uses the first name server of `/etc/resolv.conf`.
+    pub fn from_system() -> SendgridResult<DnsChecker> {
+        let conf = fs::read_to_string("/etc/resolv.conf")?;
+        let server = conf
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                match words.next() {
+                    Some("nameserver") => words.next(),
+                    _ => None,
+                }
+            })
+            .filter_map(|addr| addr.parse::<IpAddr>().ok())
+            .next();
+        match server {
+            Some(ip) => Ok(DnsChecker::new(SocketAddr::new(ip, 53))),
+            None => Err(SendgridErrorKind::Dns(String::from(
+                "/etc/resolv.conf has no name server",
+            ))
+            .into()),
+        }
+    }
+
+    /// Set how long to wait for the resolver to answer. Defaults to 5 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Look up the records of a DNS record's type at its host, returning their values.
+    /// CNAME and MX values are host names without the trailing dot, and TXT values have
+    /// their strings joined.
+    pub fn lookup(&self, host: &str, kind: &str) -> SendgridResult<Vec<String>> {
+        let qtype = match &kind.to_ascii_lowercase()[..] {
+            "cname" => TYPE_CNAME,
+            "mx" => TYPE_MX,
+            "txt" => TYPE_TXT,
+            _ => {
+                return Err(
+                    SendgridErrorKind::Dns(format!("unsupported record type {}", kind)).into(),
+                )
+            }
+        };
+
+        let id = RandomState::new().build_hasher().finish() as u16;
+        let query = build_query(id, host, qtype)?;
+        let socket = UdpSocket::bind(if self.resolver.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.connect(self.resolver)?;
+        socket.send(&query)?;
+        let mut buf = vec![0; 4096];
+        let len = socket.recv(&mut buf)?;
+        buf.truncate(len);
+
+        // Truncated answers are asked for again over TCP, where they fit.
+        if buf.len() > 2 && buf[2] & 0x02 != 0 {
+            let mut stream = TcpStream::connect_timeout(&self.resolver, self.timeout)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.write_all(&(query.len() as u16).to_be_bytes())?;
+            stream.write_all(&query)?;
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            buf = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf)?;
+        }
+
+        parse_response(&buf, id, host, qtype)
+    }
+
+    /// The DNS records of the domain that are not published with the value SendGrid
+    /// expects. An empty list means the domain should pass validation.
+    pub fn missing_records(
+        &self,
+        domain: &AuthenticatedDomain,
+    ) -> SendgridResult<Vec<MissingRecord>> {
+        let mut missing = Vec::new();
+        for (name, record) in &domain.dns {
+            let found = self.lookup(&record.host, &record.kind)?;
+            if !found.iter().any(|value| matches(record, value)) {
+                missing.push(MissingRecord {
+                    name: name.clone(),
+                    expected: record.clone(),
+                    found,
+                });
+            }
+        }
+        Ok(missing)
+    }
+}
+
+impl V3Sender {
+    /// Ask SendGrid to check the DNS records of an authenticated domain. Requires the
+    /// `whitelabel.update` scope.
+    pub fn validate_domain(&self, id: u64) -> SendgridResult<DomainValidation> {
+        let res = self.api_request(
+            Method::Post,
+            &format!("/whitelabel/domains/{}/validate", id),
+            None,
+        )?;
+        self.read_json(res)
+    }
+
+    /// Check the DNS records of an authenticated domain with the checker first, and
+    /// only ask SendGrid to validate it once they are all published. Fails with
+    /// `MissingDnsRecords` naming the records that are missing otherwise. Requires the
+    /// `whitelabel.update` scope.
+    pub fn verify_and_validate_domain(
+        &self,
+        checker: &DnsChecker,
+        domain: &AuthenticatedDomain,
+    ) -> SendgridResult<DomainValidation> {
+        let missing = checker.missing_records(domain)?;
+        if !missing.is_empty() {
+            let records = missing
+                .iter()
+                .map(|m| {
+                    format!(
+                        "{} {}",
+                        m.expected.kind.to_ascii_uppercase(),
+                        m.expected.host
+                    )
+                })
+                .collect();
+            return Err(SendgridErrorKind::MissingDnsRecords(records).into());
+        }
+        self.validate_domain(domain.id)
+    }
+}
+
+// Whether a value that was looked up is the one the record expects.
+fn matches(record: &DnsRecord, value: &str) -> bool {
+    let normalize = |v: &str| v.trim().trim_end_matches('.').to_ascii_lowercase();
+    if record.kind.eq_ignore_ascii_case("txt") {
+        value.trim() == record.data.trim().trim_matches('"')
+    } else {
+        normalize(value) == normalize(&record.data)
+    }
+}
+
+fn build_query(id: u16, host: &str, qtype: u16) -> SendgridResult<Vec<u8>> {
+    let mut query = Vec::with_capacity(host.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // A standard query that asks for recursion, with a single question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(SendgridErrorKind::Dns(format!("{:?} is not a valid host", host)).into());
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    Ok(query)
+}
+
+// The values of the answers to a query, which fails unless the answer repeats the
+// query's ID and question.
+fn parse_response(msg: &[u8], id: u16, host: &str, qtype: u16) -> SendgridResult<Vec<String>> {
+    let malformed = || SendgridErrorKind::Dns(String::from("malformed answer"));
+    if msg.len() < 12 || u16::from_be_bytes([msg[0], msg[1]]) != id {
+        return Err(malformed().into());
+    }
+    let questions = u16::from_be_bytes([msg[4], msg[5]]);
+    let answers = u16::from_be_bytes([msg[6], msg[7]]);
+    if questions != 1 {
+        return Err(malformed().into());
+    }
+
+    let mut name = String::new();
+    let mut pos = read_name(msg, 12, &mut name).ok_or_else(malformed)?;
+    let question = msg.get(pos..pos + 4).ok_or_else(malformed)?;
+    if !name.eq_ignore_ascii_case(host.trim_end_matches('.'))
+        || u16::from_be_bytes([question[0], question[1]]) != qtype
+        || u16::from_be_bytes([question[2], question[3]]) != 1
+    {
+        return Err(
+            SendgridErrorKind::Dns(String::from("the answer is for another question")).into(),
+        );
+    }
+    pos += 4;
+
+    match msg[3] & 0x0f {
+        0 => {}
+        NXDOMAIN => return Ok(Vec::new()),
+        rcode => {
+            return Err(SendgridErrorKind::Dns(format!(
+                "the resolver answered with code {}",
+                rcode
+            ))
+            .into())
+        }
+    }
+
+    let mut values = Vec::new();
+    for _ in 0..answers {
+        pos = read_name(msg, pos, &mut String::new()).ok_or_else(malformed)?;
+        let header = msg.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let start = pos + 10;
+        let rdata = msg.get(start..start + len).ok_or_else(malformed)?;
+        pos = start + len;
+
+        if rtype != qtype {
+            continue;
+        }
+        let value = match rtype {
+            TYPE_CNAME => {
+                let mut name = String::new();
+                read_name(msg, start, &mut name).ok_or_else(malformed)?;
+                name
+            }
+            TYPE_MX => {
+                let mut name = String::new();
+                read_name(msg, start + 2, &mut name).ok_or_else(malformed)?;
+                name
+            }
+            _ => {
+                let mut text = Vec::new();
+                let mut i = 0;
+                while i < rdata.len() {
+                    let n = rdata[i] as usize;
+                    text.extend_from_slice(rdata.get(i + 1..i + 1 + n).ok_or_else(malformed)?);
+                    i += 1 + n;
+                }
+                String::from_utf8_lossy(&text).into_owned()
+            }
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+// Read a possibly compressed name starting at `pos`, returning the position after it.
+fn read_name(msg: &[u8], mut pos: usize, name: &mut String) -> Option<usize> {
+    let mut end = None;
+    // Pointers can only go back, but a limit keeps loops in broken answers finite.
+    for _ in 0..128 {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            return Some(end.unwrap_or(pos + 1));
+        }
+        if len & 0xc0 == 0xc0 {
+            let target = ((len & 0x3f) << 8) | *msg.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = msg.get(pos + 1..pos + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        pos += 1 + len;
+    }
+    None
+}
+
+#[test]
+fn dns_answers() {
+    let query = build_query(7, "s1._domainkey.example.com", TYPE_CNAME).unwrap();
+    assert_eq!(&query[..4], &[0, 7, 1, 0]);
+    assert_eq!(&query[12..16], b"\x02s1\x0a");
+
+    // The query followed by a CNAME answer whose name points back at the question.
+    let mut answer = query.clone();
+    answer[2] = 0x81;
+    answer[3] = 0x80;
+    answer[7] = 1;
+    answer.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60]);
+    let target = b"\x02s1\x09domainkey\x02u1\x02wl\x08sendgrid\x03net\x00";
+    answer.extend_from_slice(&(target.len() as u16).to_be_bytes());
+    answer.extend_from_slice(target);
+    let host = "S1._domainkey.example.com.";
+    assert_eq!(
+        parse_response(&answer, 7, host, TYPE_CNAME).unwrap(),
+        vec!["s1.domainkey.u1.wl.sendgrid.net"]
+    );
+    assert!(parse_response(&answer, 8, host, TYPE_CNAME).is_err());
+    assert!(parse_response(&answer, 7, "s2._domainkey.example.com", TYPE_CNAME).is_err());
+    assert!(parse_response(&answer, 7, host, TYPE_TXT).is_err());
+
+    let mut missing = answer.clone();
+    missing[3] = 0x83;
+    assert!(parse_response(&missing, 7, host, TYPE_CNAME)
+        .unwrap()
+        .is_empty());
+
+    let record = DnsRecord {
+        host: String::from("s1._domainkey.example.com"),
+        kind: String::from("cname"),
+        data: String::from("s1.domainkey.u1.wl.sendgrid.net."),
+        valid: false,
+        #[cfg(feature = "extra-fields")]
+        extra: Default::default(),
+    };
+    assert!(matches(&record, "S1.domainkey.u1.wl.sendgrid.net"));
+    assert!(!matches(&record, "s2.domainkey.u1.wl.sendgrid.net"));
+}
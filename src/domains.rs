@@ -0,0 +1,147 @@
+//! The authenticated domains of the account, and a policy that only lets messages be sent
+//! from them.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Reading the authenticated domains of the account.
+pub struct Domains;
+
+impl Endpoint for Domains {
+    const SCOPES: &'static [&'static str] = &["whitelabel.read"];
+}
+
+/// A domain that was set up for domain authentication.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuthenticatedDomain {
+    pub id: u64,
+    /// The domain messages are sent from, e.g. `example.com`.
+    pub domain: String,
+    /// The subdomain the DNS records are published under.
+    #[serde(default)]
+    pub subdomain: Option<String>,
+    /// Whether SendGrid found all the DNS records.
+    pub valid: bool,
+    /// The DNS records to publish, by their name, e.g. `dkim1` or `mail_cname`.
+    #[serde(default)]
+    pub dns: BTreeMap<String, DnsRecord>,
+    /// Other fields of the domain, such as the IPs it is linked to.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A DNS record that has to be published to authenticate a domain.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DnsRecord {
+    /// The name of the record, e.g. `s1._domainkey.example.com`.
+    pub host: String,
+    /// Either `cname`, `txt` or `mx`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The value the record must have.
+    pub data: String,
+    /// Whether SendGrid found the record the last time the domain was validated.
+    #[serde(default)]
+    pub valid: bool,
+    /// Other fields of the record, beyond its host, type and data.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+// Only lets messages be sent from the valid authenticated domains, which are fetched
+// again once they are older than `refresh_after`.
+#[derive(Debug)]
+pub(crate) struct DomainPolicy {
+    refresh_after: Duration,
+    cache: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+// Copies keep the refresh interval but fetch the domains again, as they can belong to
+// another subuser.
+impl Clone for DomainPolicy {
+    fn clone(&self) -> DomainPolicy {
+        DomainPolicy::new(self.refresh_after)
+    }
+}
+
+impl DomainPolicy {
+    pub fn new(refresh_after: Duration) -> DomainPolicy {
+        DomainPolicy {
+            refresh_after,
+            cache: Mutex::new(None),
+        }
+    }
+
+    // Fail with `UnauthenticatedDomain` unless the address is at a valid authenticated
+    // domain or one of its subdomains.
+    pub fn check(&self, sender: &V3Sender, address: &str) -> SendgridResult<()> {
+        let domain = address
+            .rsplit('@')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('>')
+            .to_ascii_lowercase();
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let stale = cache
+            .as_ref()
+            .is_none_or(|&(fetched, _)| fetched.elapsed() >= self.refresh_after);
+        if stale {
+            let domains = sender
+                .authenticated_domains()?
+                .into_iter()
+                .filter(|d| d.valid)
+                .map(|d| d.domain.to_ascii_lowercase())
+                .collect();
+            *cache = Some((Instant::now(), domains));
+        }
+
+        let authenticated = cache.as_ref().map_or(&[][..], |(_, domains)| &domains[..]);
+        if covers(authenticated, &domain) {
+            Ok(())
+        } else {
+            Err(SendgridErrorKind::UnauthenticatedDomain(domain).into())
+        }
+    }
+}
+
+// Whether the domain is one of the authenticated domains or a subdomain of one.
+fn covers(authenticated: &[String], domain: &str) -> bool {
+    authenticated.iter().any(|a| {
+        domain == a
+            || (domain.len() > a.len()
+                && domain.ends_with(&a[..])
+                && domain.as_bytes()[domain.len() - a.len() - 1] == b'.')
+    })
+}
+
+impl V3Sender {
+    /// Get the authenticated domains of the account. Requires the `whitelabel.read`
+    /// scope.
+    pub fn authenticated_domains(&self) -> SendgridResult<Vec<AuthenticatedDomain>> {
+        self.api_get("/whitelabel/domains")
+    }
+}
+
+#[test]
+fn authenticated_domain_policy() {
+    let authenticated = vec![String::from("example.com")];
+    assert!(covers(&authenticated, "example.com"));
+    assert!(covers(&authenticated, "news.example.com"));
+    assert!(!covers(&authenticated, "badexample.com"));
+    assert!(!covers(&authenticated, "example.org"));
+
+    let domains: Vec<AuthenticatedDomain> = ::serde_json::from_str(
+        r#"[{"id":1,"domain":"example.com","subdomain":"em123","valid":false,"dns":{"dkim1":{"valid":false,"type":"cname","host":"s1._domainkey.example.com","data":"s1.domainkey.u1.wl.sendgrid.net"}}}]"#,
+    )
+    .unwrap();
+    assert_eq!(domains[0].dns["dkim1"].kind, "cname");
+    assert!(!domains[0].valid);
+}
@@ -0,0 +1,179 @@
+//! Downloading export files in a way that survives dropped connections: when reading
+//! the body fails, the download picks up where it stopped with a range request instead
+//! of starting over.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use reqwest::header::{ByteRangeSpec, ContentLength, ETag, IfRange, Range};
+use reqwest::{Client, Response};
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// How many times in a row a download is resumed before its error is returned.
+pub const MAX_RESUMES: u32 = 5;
+
+// How long to wait before resuming, multiplied by the number of the attempt.
+const RESUME_DELAY: Duration = Duration::from_secs(1);
+
+/// The body of a download that resumes when reading it fails. Every failure that
+/// follows a successful read starts the count of attempts over, so a long download can
+/// survive many dropped connections, as long as it makes progress in between.
+pub struct ResumableDownload {
+    client: Client,
+    url: String,
+    response: Response,
+    etag: Option<ETag>,
+    length: Option<u64>,
+    position: u64,
+    attempts: u32,
+    // Set when the last range request failed, so `response` is the one that broke.
+    needs_resume: bool,
+}
+
+// What to do with the response to a range request.
+#[derive(Debug, PartialEq)]
+enum Resumed {
+    // The server sent the rest of the file.
+    Partial,
+    // The server ignored the range and sent the whole file, so the part that was
+    // already read has to be skipped.
+    Whole,
+    // The file changed since the download started.
+    Changed,
+}
+
+fn resumed(status: u16, validated: bool) -> SendgridResult<Resumed> {
+    match status {
+        206 => Ok(Resumed::Partial),
+        200 if validated => Ok(Resumed::Changed),
+        200 => Ok(Resumed::Whole),
+        status => Err(SendgridErrorKind::Api(status, String::new()).into()),
+    }
+}
+
+// Whether a body that ended at `position` is missing its end.
+fn ended_early(length: Option<u64>, position: u64) -> bool {
+    length.is_some_and(|length| position < length)
+}
+
+fn is_transient(kind: &SendgridErrorKind) -> bool {
+    match *kind {
+        SendgridErrorKind::Api(status, _) => status >= 500,
+        SendgridErrorKind::DownloadChanged(_) => false,
+        _ => true,
+    }
+}
+
+impl ResumableDownload {
+    // Start a download with the response to its first request.
+    pub(crate) fn new(client: Client, url: &str, response: Response) -> ResumableDownload {
+        let etag = response.headers().get::<ETag>().cloned();
+        let length = response.headers().get::<ContentLength>().map(|l| l.0);
+        ResumableDownload {
+            client,
+            url: String::from(url),
+            response,
+            etag,
+            length,
+            position: 0,
+            attempts: 0,
+            needs_resume: false,
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Request the rest of the file from the position that was reached.
+    fn resume(&mut self) -> SendgridResult<()> {
+        let mut request = self.client.get(&self.url[..]);
+        request.header(Range::Bytes(vec![ByteRangeSpec::AllFrom(self.position)]));
+        // Without a validator, a file that changed would be stitched together from
+        // two versions when the server ignores the range.
+        if let Some(ETag(ref tag)) = self.etag {
+            request.header(IfRange::EntityTag(tag.clone()));
+        }
+        let mut response = request.send()?;
+
+        match resumed(response.status().as_u16(), self.etag.is_some())? {
+            Resumed::Partial => {}
+            Resumed::Whole => {
+                let skipped = io::copy(&mut (&mut response).take(self.position), &mut io::sink())?;
+                if skipped < self.position {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+            }
+            Resumed::Changed => {
+                return Err(SendgridErrorKind::DownloadChanged(self.url.clone()).into());
+            }
+        }
+        self.response = response;
+        Ok(())
+    }
+
+    // Count an attempt at getting past `error`, or give up with it.
+    fn retry(&mut self, error: io::Error) -> io::Result<()> {
+        if self.attempts == MAX_RESUMES {
+            return Err(error);
+        }
+        self.attempts += 1;
+        thread::sleep(RESUME_DELAY * self.attempts);
+        self.needs_resume = true;
+        Ok(())
+    }
+}
+
+impl Read for ResumableDownload {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.needs_resume {
+                match self.resume() {
+                    Ok(()) => self.needs_resume = false,
+                    // Errors of the request are tried again on the next attempt,
+                    // unless the file can't be resumed at all.
+                    Err(ref e) if !is_transient(e.kind()) => {
+                        return Err(io::Error::other(e.to_string()));
+                    }
+                    Err(e) => {
+                        self.retry(io::Error::other(e.to_string()))?;
+                        continue;
+                    }
+                }
+            }
+            match self.response.read(buf) {
+                // A connection that closes cleanly before the end of the file is
+                // resumed like any other failure.
+                Ok(0) if !buf.is_empty() && ended_early(self.length, self.position) => {
+                    self.retry(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                }
+                Ok(n) => {
+                    self.position += n as u64;
+                    self.attempts = 0;
+                    return Ok(n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => self.retry(e)?,
+            }
+        }
+    }
+}
+
+#[test]
+fn resumed_responses() {
+    assert_eq!(resumed(206, true).unwrap(), Resumed::Partial);
+    assert_eq!(resumed(200, false).unwrap(), Resumed::Whole);
+    assert_eq!(resumed(200, true).unwrap(), Resumed::Changed);
+    assert!(!is_transient(resumed(403, false).unwrap_err().kind()));
+    assert!(is_transient(resumed(503, false).unwrap_err().kind()));
+}
+
+#[test]
+fn early_ends() {
+    assert!(ended_early(Some(10), 4));
+    assert!(!ended_early(Some(10), 10));
+    assert!(!ended_early(None, 4));
+}
@@ -0,0 +1,514 @@
+use address;
+use headers;
+use mail::{EmailAddress, Mail};
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use data_encoding::BASE64;
+
+// Boundaries start with "=_", which can never show up in quoted-printable or base64
+// encoded parts, so they can't collide with the content.
+const MIXED_BOUNDARY: &str = "=_sendgrid-rs-mixed";
+const ALTERNATIVE_BOUNDARY: &str = "=_sendgrid-rs-alternative";
+
+// The line length used for folded headers and encoded bodies.
+const LINE_LENGTH: usize = 76;
+
+// A body part of a document, like the plain text or the HTML version of the message.
+pub(crate) struct EmlBody<'a> {
+    pub mime_type: &'a str,
+    pub text: &'a str,
+}
+
+// A file attached to a document. Attachments without a MIME type are sent as
+// `application/octet-stream`, and ones without a disposition are inline if they have a
+// content ID.
+pub(crate) struct EmlAttachment<'a> {
+    pub filename: &'a str,
+    pub mime_type: Option<&'a str>,
+    pub disposition: Option<&'a str>,
+    pub content_id: Option<&'a str>,
+    pub contents: Cow<'a, [u8]>,
+}
+
+impl<'a> Mail<'a> {
+    /// Render the message as an RFC 5322 MIME document, which can be saved as a
+    /// `.eml` file to preview it in a mail client or to archive a copy of what was
+    /// sent. BCC recipients are left out like a mail client would. Unless the message
+    /// has a date, or opted out with `without_auto_date`, the `Date` header is the
+    /// current time.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.add_to("you@example.com");
+    /// message.add_text("Hello!");
+    /// fs::write("preview.eml", message.to_eml())?;
+    /// ```
+    pub fn to_eml(&self) -> String {
+        let text = self.make_text();
+        let mut bodies = Vec::new();
+        if !text.is_empty() || self.html.is_empty() {
+            bodies.push(EmlBody {
+                mime_type: "text/plain",
+                text: &text,
+            });
+        }
+        if !self.html.is_empty() {
+            bodies.push(EmlBody {
+                mime_type: "text/html",
+                text: &self.html,
+            });
+        }
+
+        let attachments: Vec<_> = self
+            .attachments
+            .iter()
+            .map(|(path, contents)| EmlAttachment {
+                filename: Path::new(path)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or(path),
+                mime_type: None,
+                disposition: None,
+                content_id: self.content.get(&path[..]).map(|id| &id[..]),
+                contents: Cow::Borrowed(contents),
+            })
+            .collect();
+
+        self.render_eml(&bodies, &attachments)
+    }
+
+    // Render the headers of the message with the given body parts and attachments.
+    pub(crate) fn render_eml(&self, bodies: &[EmlBody], attachments: &[EmlAttachment]) -> String {
+        let mut eml = String::new();
+
+        push_header(&mut eml, "From", &format_address(&self.from));
+        let to = format_address_list(&self.to, &self.to_names);
+        if !to.is_empty() {
+            push_header(&mut eml, "To", &to);
+        }
+        let cc = format_address_list(&self.cc, &self.cc_names);
+        if !cc.is_empty() {
+            push_header(&mut eml, "Cc", &cc);
+        }
+        if !self.reply_to.email.is_empty() {
+            push_header(&mut eml, "Reply-To", &format_address(&self.reply_to));
+        }
+        let date = self.make_date();
+        if !date.is_empty() {
+            push_header(&mut eml, "Date", &date);
+        }
+        push_header(&mut eml, "Subject", &encode_header_text(&self.subject));
+        for (name, value) in &self.headers {
+            push_header(&mut eml, name, value);
+        }
+        push_header(&mut eml, "MIME-Version", "1.0");
+
+        if attachments.is_empty() {
+            push_body(&mut eml, bodies);
+            return eml;
+        }
+
+        push_header(
+            &mut eml,
+            "Content-Type",
+            &format!("multipart/mixed; boundary=\"{}\"", MIXED_BOUNDARY),
+        );
+        eml.push_str("\r\n");
+
+        eml.push_str(&format!("--{}\r\n", MIXED_BOUNDARY));
+        push_body(&mut eml, bodies);
+
+        for attachment in attachments {
+            eml.push_str(&format!("\r\n--{}\r\n", MIXED_BOUNDARY));
+            push_attachment(&mut eml, attachment);
+        }
+        eml.push_str(&format!("\r\n--{}--\r\n", MIXED_BOUNDARY));
+
+        eml
+    }
+}
+
+// Write the headers and body for the body parts. When there are several they are sent as
+// alternatives of each other, in the order given.
+fn push_body(eml: &mut String, bodies: &[EmlBody]) {
+    if bodies.len() > 1 {
+        push_header(
+            eml,
+            "Content-Type",
+            &format!(
+                "multipart/alternative; boundary=\"{}\"",
+                ALTERNATIVE_BOUNDARY
+            ),
+        );
+        eml.push_str("\r\n");
+        for (i, body) in bodies.iter().enumerate() {
+            if i > 0 {
+                eml.push_str("\r\n");
+            }
+            eml.push_str(&format!("--{}\r\n", ALTERNATIVE_BOUNDARY));
+            push_text_part(eml, body.mime_type, body.text);
+        }
+        eml.push_str(&format!("\r\n--{}--\r\n", ALTERNATIVE_BOUNDARY));
+    } else if let Some(body) = bodies.first() {
+        push_text_part(eml, body.mime_type, body.text);
+    } else {
+        push_text_part(eml, "text/plain", "");
+    }
+}
+
+fn push_text_part(eml: &mut String, mime_type: &str, body: &str) {
+    // Types like the one of calendar invites can come with a charset already.
+    if mime_type.to_ascii_lowercase().contains("charset=") {
+        push_header(eml, "Content-Type", mime_type);
+    } else {
+        push_header(
+            eml,
+            "Content-Type",
+            &format!("{}; charset=utf-8", mime_type),
+        );
+    }
+    push_header(eml, "Content-Transfer-Encoding", "quoted-printable");
+    eml.push_str("\r\n");
+    eml.push_str(&quoted_printable(body));
+}
+
+fn push_attachment(eml: &mut String, attachment: &EmlAttachment) {
+    let name = quote_string(&encode_header_text(attachment.filename));
+    let mime_type = attachment.mime_type.unwrap_or("application/octet-stream");
+    let disposition = attachment.disposition.unwrap_or({
+        if attachment.content_id.is_some() {
+            "inline"
+        } else {
+            "attachment"
+        }
+    });
+
+    push_header(
+        eml,
+        "Content-Type",
+        &format!("{}; name={}", mime_type, name),
+    );
+    push_header(
+        eml,
+        "Content-Disposition",
+        &format!("{}; {}", disposition, filename_param(attachment.filename)),
+    );
+    if let Some(content_id) = attachment.content_id {
+        push_header(eml, "Content-ID", &format!("<{}>", content_id));
+    }
+    push_header(eml, "Content-Transfer-Encoding", "base64");
+    eml.push_str("\r\n");
+
+    let encoded = BASE64.encode(&attachment.contents);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        // Base64 output is always ASCII.
+        eml.push_str(&String::from_utf8_lossy(line));
+        eml.push_str("\r\n");
+    }
+}
+
+// Write a header, folding at the comma separated items of address lists when the line
+// gets too long.
+fn push_header(eml: &mut String, name: &str, value: &str) {
+    let mut line_length = name.len() + 2;
+    eml.push_str(name);
+    eml.push_str(": ");
+
+    for (i, item) in value.split(", ").enumerate() {
+        if i > 0 {
+            if line_length + item.len() + 2 > LINE_LENGTH {
+                eml.push_str(",\r\n ");
+                line_length = 1;
+            } else {
+                eml.push_str(", ");
+                line_length += 2;
+            }
+        }
+        eml.push_str(item);
+        line_length += item.len();
+    }
+    eml.push_str("\r\n");
+}
+
+fn format_address_list(addrs: &[Cow<str>], names: &[Cow<str>]) -> String {
+    addrs
+        .iter()
+        .enumerate()
+        .map(|(i, addr)| EmailAddress {
+            email: Cow::Borrowed(addr),
+            name: names
+                .get(i)
+                .filter(|name| !name.is_empty())
+                .map(|name| Cow::Borrowed(&name[..])),
+        })
+        .map(|addr| format_address(&addr))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_address(addr: &EmailAddress) -> String {
+    let email = address::encode(&addr.email);
+    match addr.name {
+        Some(ref name) if !name.is_empty() => {
+            format!("{} <{}>", quote(&encode_header_text(name)), email)
+        }
+        _ => format!("<{}>", email),
+    }
+}
+
+// Encode text for use in a header, falling back to the original text if it can't be
+// encoded because it contains line breaks.
+fn encode_header_text(text: &str) -> Cow<'_, str> {
+    headers::encode_value(Cow::Borrowed(text))
+        .unwrap_or_else(|_| Cow::Owned(text.replace(&['\r', '\n'][..], " ")))
+}
+
+// Quote a display name or parameter value if it has characters that aren't allowed in an
+// RFC 5322 atom. Encoded words are left alone as they can't be quoted.
+fn quote(text: &str) -> Cow<'_, str> {
+    let is_atom =
+        |c: char| c.is_ascii_alphanumeric() || c == ' ' || "!#$%&'*+-/=?^_`{|}~".contains(c);
+    if text.starts_with("=?") || text.chars().all(is_atom) {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(quote_string(text))
+    }
+}
+
+// Wrap text in a quoted string, escaping quotes and backslashes.
+fn quote_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The filename parameter for a Content-Disposition header. Names that aren't plain
+// ASCII are percent-encoded as an RFC 2231 extended parameter, as encoded words aren't
+// allowed inside of parameter values.
+fn filename_param(filename: &str) -> String {
+    if filename
+        .chars()
+        .all(|c| c.is_ascii() && !c.is_ascii_control())
+    {
+        return format!("filename={}", quote_string(filename));
+    }
+
+    let mut param = String::from("filename*=utf-8''");
+    for &b in filename.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+            param.push(b as char);
+        } else {
+            param.push_str(&format!("%{:02X}", b));
+        }
+    }
+    param
+}
+
+// Encode a text body with quoted-printable encoding from RFC 2045, using CRLF line breaks
+// and soft line breaks to keep lines under 76 characters.
+fn quoted_printable(body: &str) -> String {
+    let mut out = String::with_capacity(body.len() + body.len() / 8);
+
+    for line in body.lines() {
+        let mut line_length = 0;
+        let bytes = line.as_bytes();
+
+        for (i, &b) in bytes.iter().enumerate() {
+            let last = i == bytes.len() - 1;
+            let literal =
+                ((b == b' ' || b == b'\t') && !last) || ((33..=126).contains(&b) && b != b'=');
+            let encoded = if literal { 1 } else { 3 };
+
+            if line_length + encoded > LINE_LENGTH - 1 {
+                out.push_str("=\r\n");
+                line_length = 0;
+            }
+            if literal {
+                out.push(b as char);
+            } else {
+                out.push_str(&format!("={:02X}", b));
+            }
+            line_length += encoded;
+        }
+        out.push_str("\r\n");
+    }
+
+    out
+}
+
+#[test]
+fn simple_eml() {
+    let mut m = Mail::new().without_auto_date();
+    m.set_from(EmailAddress::with_name("me@example.com", "Me, Myself"));
+    m.add_tos(vec![("a@example.com", Some("A")), ("b@example.com", None)]);
+    m.add_subject("Grüße");
+    m.add_text("It works = yes");
+
+    let want = "From: \"Me, Myself\" <me@example.com>\r\n\
+                To: A <a@example.com>, <b@example.com>\r\n\
+                Subject: =?UTF-8?B?R3LDvMOfZQ==?=\r\n\
+                MIME-Version: 1.0\r\n\
+                Content-Type: text/plain; charset=utf-8\r\n\
+                Content-Transfer-Encoding: quoted-printable\r\n\
+                \r\n\
+                It works =3D yes\r\n";
+    assert_eq!(m.to_eml(), want);
+}
+
+#[test]
+fn eml_dates() {
+    assert!(Mail::new().to_eml().contains("\r\nDate: "));
+    let mut m = Mail::new();
+    m.add_date_timestamp(0);
+    assert!(m
+        .to_eml()
+        .contains("\r\nDate: Thu, 01 Jan 1970 00:00:00 +0000\r\n"));
+}
+
+#[test]
+fn v3_eml() {
+    use v3::{Content, Email, Personalization, SGMailV3};
+
+    let email = |address: &str, name: Option<&str>| {
+        let mut e = Email::new();
+        e.set_email(address);
+        if let Some(name) = name {
+            e.set_name(name);
+        }
+        e
+    };
+    let mut m = SGMailV3::new();
+    m.set_from(email("me@example.com", Some("Me")));
+    m.set_subject("Hello");
+    m.set_send_at(0);
+    let mut content = Content::new();
+    content.set_content_type("text/plain");
+    content.set_value("Hi there");
+    m.add_content(content);
+    for (to, subject) in [("a@example.com", None), ("b@example.com", Some("Hello B"))] {
+        let mut p = Personalization::new();
+        p.add_to(email(to, Some("Friend")));
+        p.add_bcc(email("hidden@example.com", None));
+        if let Some(subject) = subject {
+            p.set_subject(subject);
+        }
+        m.add_personalization(p);
+    }
+
+    let emls = m.to_eml();
+    assert_eq!(emls.len(), 2);
+    assert_eq!(
+        emls[0],
+        "From: Me <me@example.com>\r\n\
+         To: Friend <a@example.com>\r\n\
+         Date: Thu, 01 Jan 1970 00:00:00 +0000\r\n\
+         Subject: Hello\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: quoted-printable\r\n\
+         \r\n\
+         Hi there\r\n"
+    );
+    assert!(emls[1].contains("To: Friend <b@example.com>\r\n"));
+    assert!(emls[1].contains("Subject: Hello B\r\n"));
+    assert!(!emls[1].contains("hidden@example.com"));
+}
+
+#[test]
+fn v3_eml_parts() {
+    use v3::{Attachment, Content, Email, Personalization, SGMailV3};
+
+    let mut m = SGMailV3::new();
+    let mut to = Email::new();
+    to.set_email("a@example.com");
+    let mut p = Personalization::new();
+    p.add_to(to);
+    m.add_personalization(p);
+    for (mime_type, value) in [
+        ("text/plain", "Hi"),
+        ("text/html", "<img src=\"cid:logo\">"),
+    ] {
+        let mut content = Content::new();
+        content.set_content_type(mime_type);
+        content.set_value(value);
+        m.add_content(content);
+    }
+    m.add_calendar_invite("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n");
+    for (filename, data) in [("logo.png", &b"png"[..]), ("logo.png", &b"gif"[..])] {
+        let mut a = Attachment::new();
+        a.set_content(data);
+        a.set_filename(filename);
+        a.set_mime_type("image/png");
+        a.set_disposition("inline");
+        a.set_content_id("logo");
+        m.add_attachment(a);
+    }
+
+    let eml = &m.to_eml()[0];
+    let alternatives = eml.matches("--=_sendgrid-rs-alternative\r\n").count();
+    assert_eq!(alternatives, 3);
+    assert!(eml.contains(
+        "--=_sendgrid-rs-alternative\r\n\
+         Content-Type: text/calendar; method=REQUEST; charset=UTF-8\r\n"
+    ));
+    assert!(eml.contains(
+        "Content-Type: text/calendar; method=REQUEST; charset=UTF-8; name=\"invite.ics\"\r\n"
+    ));
+    assert!(eml.contains(
+        "Content-Type: image/png; name=\"logo.png\"\r\n\
+         Content-Disposition: inline; filename=\"logo.png\"\r\n\
+         Content-ID: <logo>\r\n"
+    ));
+    assert!(eml.contains("\r\ncG5n\r\n"));
+    assert!(eml.contains("\r\nZ2lm\r\n"));
+}
+
+#[test]
+fn multipart_eml() {
+    let mut m = Mail::new();
+    m.add_text("Text");
+    m.add_html("<p>HTML</p>");
+    m.attachments
+        .insert("/tmp/report.txt".to_string(), b"data".to_vec());
+
+    let eml = m.to_eml();
+    assert!(eml.contains("Content-Type: multipart/mixed; boundary=\"=_sendgrid-rs-mixed\"\r\n"));
+    assert!(eml.contains("Content-Type: multipart/alternative;"));
+    assert!(eml.contains("Content-Disposition: attachment; filename=\"report.txt\"\r\n"));
+    assert!(eml.contains("\r\nZGF0YQ==\r\n"));
+    assert!(eml.ends_with("--=_sendgrid-rs-mixed--\r\n"));
+}
+
+#[test]
+fn quoted_printable_soft_breaks() {
+    let encoded = quoted_printable(&"a".repeat(100));
+    assert!(encoded.lines().all(|line| line.len() <= LINE_LENGTH));
+    assert!(encoded.starts_with(&format!("{}=\r\n", "a".repeat(75))));
+}
+
+#[test]
+fn non_ascii_attachment_filename() {
+    let mut m = Mail::new();
+    m.add_text("Text");
+    m.attachments
+        .insert("Rechnungsübersicht.pdf".to_string(), b"data".to_vec());
+
+    let eml = m.to_eml();
+    assert!(eml.contains(
+        "Content-Type: application/octet-stream; name=\"=?UTF-8?B?UmVjaG51bmdzw7xiZXJzaWNodC5wZGY=?=\"\r\n"
+    ));
+    assert!(eml.contains(
+        "Content-Disposition: attachment; filename*=utf-8''Rechnungs%C3%BCbersicht.pdf\r\n"
+    ));
+}
@@ -0,0 +1,200 @@
+//! Scoring how engaged recipients are from the events the Event Webhook posted about
+//! them, e.g. to stop mailing addresses that haven't opened anything in months.
+
+use bounces::BounceClass;
+use webhooks::Event;
+
+use std::collections::HashMap;
+
+/// How much each kind of event adds to or takes from a score.
+#[derive(Clone, Debug)]
+pub struct EngagementWeights {
+    /// The points of an open.
+    pub open: f64,
+    /// The points of a click. Clicks count for more than opens, since mail clients
+    /// that load images on their own report opens nobody made.
+    pub click: f64,
+    /// The points of a soft bounce or a full mailbox, usually negative.
+    pub soft_bounce: f64,
+    /// The points of a hard bounce, usually negative.
+    pub hard_bounce: f64,
+    /// The points of a spam report, usually negative.
+    pub spam_report: f64,
+    /// The number of days after which an event counts for half as much.
+    pub half_life_days: f64,
+}
+
+impl Default for EngagementWeights {
+    fn default() -> EngagementWeights {
+        EngagementWeights {
+            open: 1.0,
+            click: 3.0,
+            soft_bounce: -1.0,
+            hard_bounce: -10.0,
+            spam_report: -20.0,
+            half_life_days: 30.0,
+        }
+    }
+}
+
+/// The engagement of one address.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngagementScore {
+    /// The sum of the points of the events, where each event's points are halved for
+    /// every half life that passed since it happened.
+    pub score: f64,
+    pub delivered: u32,
+    pub opens: u32,
+    pub clicks: u32,
+    /// The bounces that count against the address. Blocks and policy rejections are
+    /// about the sender and are left out.
+    pub bounces: u32,
+    pub spam_reports: u32,
+    /// The Unix timestamp of the last open or click.
+    pub last_engaged: Option<i64>,
+}
+
+impl EngagementScore {
+    /// The number of whole days since the last open or click, or `None` if the address
+    /// never engaged.
+    pub fn days_since_engaged(&self, now: i64) -> Option<i64> {
+        self.last_engaged
+            .map(|last| (now - last).max(0) / SECONDS_PER_DAY)
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Scores addresses from their events.
+///
+/// The score of an address is the sum of the points of its events: opens and clicks
+/// add points, bounces and spam reports take them away, and other events count
+/// nothing. An event's points halve every `half_life_days` since it happened, so an
+/// address that clicked every week last year scores lower than one that opened a
+/// message yesterday, and a bounce from long ago is mostly forgotten.
+///
+/// # Examples
+///
+/// ```ignore
+/// let scorer = EngagementScorer::new();
+/// for (address, score) in scorer.score_addresses(&history, now) {
+///     if score.opens + score.clicks == 0 && score.delivered >= 10 {
+///         println!("{} never engaged", address);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EngagementScorer {
+    weights: EngagementWeights,
+}
+
+impl EngagementScorer {
+    /// Construct a scorer with the default weights.
+    pub fn new() -> EngagementScorer {
+        EngagementScorer::default()
+    }
+
+    /// Construct a scorer with custom weights.
+    pub fn with_weights(weights: EngagementWeights) -> EngagementScorer {
+        EngagementScorer { weights }
+    }
+
+    /// Score the events of one address at the Unix timestamp `now`. Events after `now`
+    /// count fully.
+    pub fn score<'a, I>(&self, events: I, now: i64) -> EngagementScore
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut score = EngagementScore::default();
+        for event in events {
+            self.add(&mut score, event, now);
+        }
+        score
+    }
+
+    /// Score the events of many addresses at once, by their address in lowercase.
+    pub fn score_addresses<'a, I>(&self, events: I, now: i64) -> HashMap<String, EngagementScore>
+    where
+        I: IntoIterator<Item = &'a Event>,
+    {
+        let mut scores: HashMap<String, EngagementScore> = HashMap::new();
+        for event in events {
+            let address = event.info().email.to_lowercase();
+            self.add(scores.entry(address).or_default(), event, now);
+        }
+        scores
+    }
+
+    fn add(&self, score: &mut EngagementScore, event: &Event, now: i64) {
+        let weights = &self.weights;
+        let timestamp = event.info().timestamp;
+        let points = match *event {
+            Event::Delivered(_) => {
+                score.delivered += 1;
+                return;
+            }
+            Event::Open(_) => {
+                score.opens += 1;
+                score.last_engaged = score.last_engaged.max(Some(timestamp));
+                weights.open
+            }
+            Event::Click(_) => {
+                score.clicks += 1;
+                score.last_engaged = score.last_engaged.max(Some(timestamp));
+                weights.click
+            }
+            Event::Bounce(ref bounce) => match bounce.classification() {
+                BounceClass::Blocked | BounceClass::PolicyRejection => return,
+                BounceClass::HardBounce => {
+                    score.bounces += 1;
+                    weights.hard_bounce
+                }
+                _ => {
+                    score.bounces += 1;
+                    weights.soft_bounce
+                }
+            },
+            Event::SpamReport(_) => {
+                score.spam_reports += 1;
+                weights.spam_report
+            }
+            _ => return,
+        };
+        let age_days = (now - timestamp).max(0) as f64 / SECONDS_PER_DAY as f64;
+        score.score += points * 0.5f64.powf(age_days / weights.half_life_days);
+    }
+}
+
+#[test]
+fn score_engagement() {
+    let day = SECONDS_PER_DAY;
+    let now = 100 * day;
+    let body = format!(
+        r#"[
+            {{"email":"A@example.com","timestamp":{},"sg_event_id":"1","event":"delivered"}},
+            {{"email":"a@example.com","timestamp":{},"sg_event_id":"2","event":"open"}},
+            {{"email":"a@example.com","timestamp":{},"sg_event_id":"3","event":"click","url":"https://example.com"}},
+            {{"email":"b@example.com","timestamp":{},"sg_event_id":"4","event":"bounce","reason":"550 5.1.1 User unknown"}},
+            {{"email":"b@example.com","timestamp":{},"sg_event_id":"5","event":"bounce","reason":"554 5.7.1 Blocked by Spamhaus","type":"blocked"}}
+        ]"#,
+        now - day,
+        now,
+        now - 30 * day,
+        now,
+        now,
+    );
+    let (events, errors) = ::webhooks::parse_events(body.as_bytes());
+    assert!(errors.is_empty());
+
+    let scores = EngagementScorer::new().score_addresses(&events, now);
+    let a = &scores["a@example.com"];
+    assert_eq!((a.delivered, a.opens, a.clicks), (1, 1, 1));
+    // A full point for the open today and half the points of the click a half life ago.
+    assert!((a.score - 2.5).abs() < 1e-9);
+    assert_eq!(a.days_since_engaged(now + 2 * day), Some(2));
+
+    let b = &scores["b@example.com"];
+    assert_eq!(b.bounces, 1);
+    assert!((b.score + 10.0).abs() < 1e-9);
+    assert_eq!(b.last_engaged, None);
+}
@@ -1,7 +1,23 @@
+// error_chain still implements the deprecated `Error::description` and `Error::cause`.
+#![allow(deprecated)]
+
 use std::io;
 
+#[cfg(feature = "csv")]
+use csv;
+#[cfg(feature = "templates-local")]
+use handlebars;
+#[cfg(feature = "mime")]
+use mailparse;
+#[cfg(any(feature = "minimal-blocking", feature = "smtp"))]
+use native_tls;
+#[cfg(feature = "signed-webhooks")]
+use openssl;
+#[cfg(feature = "client")]
 use reqwest;
 use serde_json;
+#[cfg(feature = "tera")]
+use tera;
 
 error_chain! {
     types {
@@ -9,15 +25,232 @@ error_chain! {
     }
 
     foreign_links {
+        Csv(csv::Error) #[cfg(feature = "csv")];
         Io(io::Error);
         JSONDecode(serde_json::Error);
-        ReqwestError(reqwest::Error);
+        ReqwestError(reqwest::Error) #[cfg(feature = "client")];
+        MailParse(mailparse::MailParseError) #[cfg(feature = "mime")];
+        Openssl(openssl::error::ErrorStack) #[cfg(feature = "signed-webhooks")];
+        TemplateParse(handlebars::TemplateError) #[cfg(feature = "templates-local")];
+        TemplateRender(handlebars::RenderError) #[cfg(feature = "templates-local")];
+        Tera(tera::Error) #[cfg(feature = "tera")];
+        Tls(native_tls::Error) #[cfg(any(feature = "minimal-blocking", feature = "smtp"))];
     }
 
     errors {
+        ActivityExport(reason: String) {
+            description("the activity export failed")
+            display("the activity export failed: {}", reason)
+        }
+
+        Api(status: u16, body: String) {
+            description("SendGrid responded with an error")
+            display("SendGrid responded with status {}: {}", status, body)
+        }
+
+        BatcherClosed {
+            description("the event batcher was shut down")
+            display("the event batcher was shut down and takes no new events")
+        }
+
+        BufferFull(capacity: usize) {
+            description("the event buffer is full")
+            display("the buffer of {} events was still full when the timeout ran out", capacity)
+        }
+
+        Cancelled {
+            description("the request was cancelled")
+            display("the request was cancelled")
+        }
+
+        ContactExport(reason: String) {
+            description("the contact export failed")
+            display("the contact export failed: {}", reason)
+        }
+
+        ContactImport(reason: String) {
+            description("the contact import failed")
+            display("the contact import failed: {}", reason)
+        }
+
+        DeadlineExceeded {
+            description("the deadline passed before the request was made")
+            display("the deadline passed before the request was made")
+        }
+
+        Dns(reason: String) {
+            description("a DNS lookup failed")
+            display("a DNS lookup failed: {}", reason)
+        }
+
+        DownloadChanged(url: String) {
+            description("a file changed while it was downloaded")
+            display("{} changed while it was downloaded, so it can't be resumed", url)
+        }
+
+        EventsUnwritten {
+            description("the events were not written in time")
+            display("the events were not written to the sink before the timeout ran out")
+        }
+
+        InactiveTemplate(template_id: String) {
+            description("the template has no active version")
+            display("the template {} has no active version", template_id)
+        }
+
+        InvalidAddress(address: String) {
+            description("invalid address")
+            display("{:?} is not a valid address or address list", address)
+        }
+
+        InvalidAttachments(problems: Vec<String>) {
+            description("attachments exceed SendGrid's limits")
+            display("attachments exceed SendGrid's limits: {}", problems.join("; "))
+        }
+
+        InvalidCertificate(reason: String) {
+            description("invalid certificate")
+            display("invalid certificate: {}", reason)
+        }
+
+        InvalidCursor(token: String) {
+            description("invalid cursor")
+            display("'{}' is not a cursor for this listing", token)
+        }
+
+        InvalidCustomField(field: String, reason: String) {
+            description("invalid custom field value")
+            display("the value of the custom field '{}' is invalid: {}", field, reason)
+        }
+
+        InvalidDate(date: String) {
+            description("invalid date")
+            display("'{}' is not a date formatted as YYYY-MM-DD", date)
+        }
+
+        InvalidFieldName(name: String) {
+            description("invalid field name")
+            display("'{}' is not a valid field name for a query", name)
+        }
+
         InvalidFilename {
             description("invalid filename")
             display("could not UTF-8 decode this filename")
         }
+
+        InvalidFormKey(key: String) {
+            description("invalid form key")
+            display("{:?} can not be used as an attachment or content name", key)
+        }
+
+        InvalidHeaderName(name: String) {
+            description("invalid header name")
+            display("'{}' is not a valid header name", name)
+        }
+
+        InvalidHeaderValue(value: String) {
+            description("invalid header value")
+            display("header values can not contain line breaks: {:?}", value)
+        }
+
+        InvalidMultipart(reason: String) {
+            description("invalid multipart body")
+            display("invalid multipart body: {}", reason)
+        }
+
+        InvalidProxy(reason: String) {
+            description("invalid proxy")
+            display("invalid proxy: {}", reason)
+        }
+
+        InvalidPublicKey(key: String) {
+            description("invalid public key")
+            display("'{}' is not a base64 encoded DER public key", key)
+        }
+
+        InvalidSendEachAt(timestamps: usize, recipients: usize) {
+            description("send_each_at does not match the recipient list")
+            display("send_each_at has {} timestamps but there are {} recipients", timestamps, recipients)
+        }
+
+        InvalidTimeOfDay(hour: u32, minute: u32) {
+            description("invalid time of day")
+            display("{:02}:{:02} is not a time of day", hour, minute)
+        }
+
+        InvalidTraceparent(value: String) {
+            description("invalid traceparent")
+            display("{:?} is not a valid W3C traceparent", value)
+        }
+
+        MissingDnsRecords(records: Vec<String>) {
+            description("DNS records of the domain are not published")
+            display("these DNS records of the domain are not published: {}", records.join(", "))
+        }
+
+        MissingScopes(scopes: Vec<String>) {
+            description("the API key is missing scopes")
+            display("the API key is missing these scopes: {}", scopes.join(", "))
+        }
+
+        NoProviders {
+            description("the provider chain is empty")
+            display("the provider chain is empty")
+        }
+
+        NoSeedAddresses {
+            description("a test send needs seed addresses")
+            display("a test send needs at least one seed address")
+        }
+
+        PoolClosed {
+            description("the task pool was shut down")
+            display("the task pool was shut down before the task ran")
+        }
+
+        QuotaExceeded(category: String, limit: u64) {
+            description("the quota of a category was used up")
+            display("sending would exceed the quota of {} sends for the category {}", limit, category)
+        }
+
+        ResponseTooLarge(limit: usize) {
+            description("the response is too large")
+            display("the response is larger than the limit of {} bytes", limit)
+        }
+
+        SenderClosed {
+            description("the background sender was shut down")
+            display("the background sender was shut down and takes no new messages")
+        }
+
+        Smtp(code: u16, reply: String) {
+            description("the SMTP server rejected a command")
+            display("the SMTP server replied with {}: {}", code, reply)
+        }
+
+        TooManyCategories(count: usize) {
+            description("the message has too many categories")
+            display("the message has {} categories, more than the {} SendGrid accepts", count, ::v3::MAX_CATEGORIES)
+        }
+
+        UnauthenticatedDomain(domain: String) {
+            description("the domain is not authenticated")
+            display("messages can't be sent from {}, which is not an authenticated domain", domain)
+        }
+
+        Unconfirmed(reason: String) {
+            description("the message may have been accepted")
+            display("the message was sent but not confirmed, so it may have been accepted: {}", reason)
+        }
+
+        UnconfirmedDeleteAll(list: String) {
+            description("deleting a whole suppression list was not confirmed")
+            display("deleting every entry of the {} list needs to be confirmed", list)
+        }
+
+        UsageCapExceeded(used: u64, needed: u64, cap: u64) {
+            description("sending would exceed the monthly usage cap")
+            display("sending to {} more recipients would exceed the monthly cap of {} requests, {} were used so far", needed, cap, used)
+        }
     }
 }
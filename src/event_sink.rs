@@ -0,0 +1,465 @@
+//! Writing webhook events to a database or a message queue in batches, on a background
+//! thread, so webhook handlers can answer SendGrid quickly without losing events.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use webhooks::Event;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where an `EventBatcher` writes events, e.g. a database table or a Kafka producer.
+pub trait EventSink: Send + 'static {
+    /// Write a batch of events. A batch that fails is written again later, so a batch
+    /// may be written more than once and writes should be idempotent, e.g. keyed by
+    /// `sg_event_id`.
+    fn write(&mut self, events: &[Event]) -> SendgridResult<()>;
+}
+
+impl<F> EventSink for F
+where
+    F: FnMut(&[Event]) -> SendgridResult<()> + Send + 'static,
+{
+    fn write(&mut self, events: &[Event]) -> SendgridResult<()> {
+        self(events)
+    }
+}
+
+/// An event sink that keeps the events in memory, shared with its clones so they can
+/// be read while a batcher owns one.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryEventSink {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl MemoryEventSink {
+    /// Construct a new, empty sink.
+    pub fn new() -> MemoryEventSink {
+        MemoryEventSink::default()
+    }
+
+    /// The events written so far, oldest first.
+    pub fn events(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl EventSink for MemoryEventSink {
+    fn write(&mut self, events: &[Event]) -> SendgridResult<()> {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(events);
+        Ok(())
+    }
+}
+
+/// How an `EventBatcher` batches events.
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// The most events written at once. A batch always has at least one event, so 0
+    /// means 1.
+    pub max_batch: usize,
+    /// How long an event waits for a batch to fill up before the batch is written
+    /// anyway.
+    pub max_delay: Duration,
+    /// The most events waiting to be written. Pushing more blocks until there is room,
+    /// which slows down the webhook handlers when the sink can't keep up.
+    pub capacity: usize,
+    /// How long to wait before writing a failed batch again. The wait doubles after
+    /// every failure in a row, up to a minute.
+    pub retry_delay: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions {
+            max_batch: 500,
+            max_delay: Duration::from_secs(1),
+            capacity: 10_000,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+// The longest wait between writes of a failed batch.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How many times in a row a batch is written after the batcher was dropped without a
+/// shutdown, before the thread gives up on the events left.
+pub const MAX_WRITES_AFTER_DROP: u32 = 5;
+
+/// What an event batcher did by the time it shut down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatchReport {
+    /// The number of events written to the sink.
+    pub written: usize,
+    /// The number of batches written to the sink.
+    pub batches: usize,
+    /// The number of writes that failed and were tried again.
+    pub failed_writes: usize,
+    /// The number of events that were not written when the timeout ran out.
+    pub unwritten: usize,
+}
+
+/// Buffers verified webhook events and writes them to an `EventSink` in batches on a
+/// background thread. Events are kept until the sink accepts them, so none are lost
+/// while the process runs. To not lose events when it stops, answer the webhook only
+/// once `push_and_wait` returns, so SendGrid sends the events again otherwise.
+///
+/// # Examples
+///
+/// ```ignore
+/// let batcher = EventBatcher::spawn(move |events: &[Event]| db.insert_events(events), BatchOptions::default());
+///
+/// // In the webhook handler, after checking the signature:
+/// let (events, _) = parse_events(&body);
+/// match batcher.push_and_wait(events, Duration::from_secs(5)) {
+///     Ok(()) => Response::ok(),
+///     Err(_) => Response::service_unavailable(),
+/// }
+/// ```
+pub struct EventBatcher {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    // Notified when events are pushed, written or the batcher is closed.
+    changed: Condvar,
+    options: BatchOptions,
+}
+
+#[derive(Default)]
+struct State {
+    buffer: VecDeque<Event>,
+    // The number of events ever pushed, and written to the sink.
+    pushed: u64,
+    written: u64,
+    // The number of events taken from the buffer for the batch being written.
+    in_flight: usize,
+    // When the oldest buffered event was pushed.
+    oldest: Option<Instant>,
+    closed: bool,
+    // Set when the batcher was dropped, so nobody waits for the events any more.
+    dropped: bool,
+    abandoned: bool,
+    stopped: bool,
+    report: BatchReport,
+}
+
+impl EventBatcher {
+    /// Start a thread that writes the pushed events to a sink.
+    pub fn spawn<S: EventSink>(mut sink: S, options: BatchOptions) -> EventBatcher {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            options,
+        });
+
+        let worker = Arc::clone(&shared);
+        thread::spawn(move || {
+            while let Some(batch) = worker.next_batch() {
+                let mut delay = worker.options.retry_delay;
+                let mut failures = 0;
+                loop {
+                    if sink.write(&batch).is_ok() {
+                        worker.finish_batch(batch.len());
+                        break;
+                    }
+                    failures += 1;
+                    if !worker.failed_write(failures) {
+                        return;
+                    }
+                    thread::sleep(delay);
+                    delay = cmp::min(delay * 2, MAX_RETRY_DELAY);
+                }
+            }
+        });
+
+        EventBatcher { shared }
+    }
+
+    /// Buffer events to be written, blocking while the buffer is full. Fails with
+    /// `BatcherClosed` once the batcher is shut down.
+    pub fn push(&self, events: Vec<Event>) -> SendgridResult<()> {
+        self.push_until(events, None).map(|_| ())
+    }
+
+    /// Buffer events like `push` and wait until the sink accepted them. Fails with
+    /// `BufferFull` when there is no room for them before the timeout runs out, and
+    /// with `EventsUnwritten` when the sink didn't accept them in time, in which case
+    /// they are still written later.
+    pub fn push_and_wait(&self, events: Vec<Event>, timeout: Duration) -> SendgridResult<()> {
+        let deadline = Instant::now() + timeout;
+        let target = self.push_until(events, Some(deadline))?;
+        let mut state = self.shared.lock();
+        while state.written < target {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(SendgridErrorKind::EventsUnwritten.into());
+            }
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        Ok(())
+    }
+
+    // Buffer events, waiting for room until the deadline, and return the number of
+    // events pushed so far once they are.
+    fn push_until(&self, events: Vec<Event>, deadline: Option<Instant>) -> SendgridResult<u64> {
+        let capacity = self.shared.options.capacity;
+        let mut state = self.shared.lock();
+        loop {
+            if state.closed {
+                return Err(SendgridErrorKind::BatcherClosed.into());
+            }
+            // A push larger than the whole buffer only has to wait for it to empty.
+            let room = state.buffer.is_empty() || state.buffer.len() + events.len() <= capacity;
+            if room {
+                break;
+            }
+            state = match deadline {
+                None => self
+                    .shared
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(SendgridErrorKind::BufferFull(capacity).into());
+                    }
+                    self.shared
+                        .changed
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+
+        if !events.is_empty() && state.oldest.is_none() {
+            state.oldest = Some(Instant::now());
+        }
+        state.pushed += events.len() as u64;
+        state.buffer.extend(events);
+        self.shared.changed.notify_all();
+        Ok(state.pushed)
+    }
+
+    /// The number of events waiting to be written.
+    pub fn buffered(&self) -> usize {
+        let state = self.shared.lock();
+        state.buffer.len() + state.in_flight
+    }
+
+    /// Stop taking new events and wait up to `timeout` for the buffered ones to be
+    /// written. Later pushes fail with `BatcherClosed`. Events that are still buffered
+    /// when the timeout runs out are dropped and counted as unwritten.
+    pub fn shutdown(&self, timeout: Duration) -> BatchReport {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        state.closed = true;
+        self.shared.changed.notify_all();
+
+        while !state.stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        let mut report = state.report;
+        if !state.stopped {
+            state.abandoned = true;
+            report.unwritten = state.buffer.len() + state.in_flight;
+            state.buffer.clear();
+        }
+        report
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Wait for a full batch, or for the oldest event to have waited long enough, and
+    // take it from the buffer. Returns `None` once the batcher is closed and empty, or
+    // abandoned.
+    fn next_batch(&self) -> Option<Vec<Event>> {
+        let max_batch = cmp::max(self.options.max_batch, 1);
+        let mut state = self.lock();
+        loop {
+            if state.abandoned {
+                state.stopped = true;
+                self.changed.notify_all();
+                return None;
+            }
+            let due = state
+                .oldest
+                .map(|oldest| oldest + self.options.max_delay)
+                .unwrap_or_else(Instant::now);
+            let now = Instant::now();
+            if state.buffer.len() >= max_batch
+                || !state.buffer.is_empty() && (state.closed || now >= due)
+            {
+                break;
+            }
+            if state.buffer.is_empty() && state.closed {
+                state.stopped = true;
+                self.changed.notify_all();
+                return None;
+            }
+            state = if state.buffer.is_empty() {
+                self.changed.wait(state).unwrap_or_else(|e| e.into_inner())
+            } else {
+                self.changed
+                    .wait_timeout(state, due - now)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            };
+        }
+
+        let size = cmp::min(state.buffer.len(), max_batch);
+        let batch: Vec<Event> = state.buffer.drain(..size).collect();
+        state.in_flight = batch.len();
+        // The events left are newer than the batch, so waiting on its oldest event
+        // only writes them sooner.
+        if state.buffer.is_empty() {
+            state.oldest = None;
+        }
+        self.changed.notify_all();
+        Some(batch)
+    }
+
+    fn finish_batch(&self, size: usize) {
+        let mut state = self.lock();
+        state.in_flight = 0;
+        state.written += size as u64;
+        state.report.written += size;
+        state.report.batches += 1;
+        self.changed.notify_all();
+    }
+
+    // Count the failed write of a batch that failed `failures` times in a row, and
+    // return whether to try again.
+    fn failed_write(&self, failures: u32) -> bool {
+        let mut state = self.lock();
+        state.report.failed_writes += 1;
+        if state.dropped && failures >= MAX_WRITES_AFTER_DROP {
+            state.abandoned = true;
+        }
+        if state.abandoned {
+            state.stopped = true;
+            self.changed.notify_all();
+            return false;
+        }
+        true
+    }
+}
+
+impl Drop for EventBatcher {
+    // Without a shutdown the thread carries on until the buffer is empty, or until a
+    // batch failed `MAX_WRITES_AFTER_DROP` times in a row.
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.dropped = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[test]
+fn batch_events() {
+    let memory = MemoryEventSink::new();
+    let mut sink = memory.clone();
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let sink_batches = Arc::clone(&batches);
+    let mut failures = 1;
+    let batcher = EventBatcher::spawn(
+        move |events: &[Event]| {
+            if failures > 0 {
+                failures -= 1;
+                return Err("the database is down".into());
+            }
+            sink_batches.lock().unwrap().push(events.len());
+            sink.write(events)
+        },
+        BatchOptions {
+            max_batch: 3,
+            max_delay: Duration::from_millis(20),
+            retry_delay: Duration::from_millis(1),
+            ..BatchOptions::default()
+        },
+    );
+
+    let (events, _) = ::webhooks::parse_events(
+        br#"[
+            {"email":"a@example.com","timestamp":1,"sg_event_id":"1","event":"delivered","response":"250 OK"},
+            {"email":"b@example.com","timestamp":1,"sg_event_id":"2","event":"delivered","response":"250 OK"},
+            {"email":"c@example.com","timestamp":1,"sg_event_id":"3","event":"spamreport"},
+            {"email":"d@example.com","timestamp":1,"sg_event_id":"4","event":"spamreport"}
+        ]"#,
+    );
+    batcher
+        .push_and_wait(events, Duration::from_secs(5))
+        .unwrap();
+    let report = batcher.shutdown(Duration::from_secs(5));
+    assert_eq!(*batches.lock().unwrap(), vec![3, 1]);
+    assert_eq!(memory.events().len(), 4);
+    assert_eq!(
+        report,
+        BatchReport {
+            written: 4,
+            batches: 2,
+            failed_writes: 1,
+            unwritten: 0,
+        }
+    );
+    assert!(batcher.push(Vec::new()).is_err());
+}
+
+#[test]
+fn dropped_batcher_gives_up() {
+    let batcher = EventBatcher::spawn(
+        |_: &[Event]| Err("the database is down".into()),
+        BatchOptions {
+            max_batch: 0,
+            retry_delay: Duration::from_millis(1),
+            ..BatchOptions::default()
+        },
+    );
+    let (events, _) = ::webhooks::parse_events(
+        br#"[{"email":"a@example.com","timestamp":1,"sg_event_id":"1","event":"spamreport"}]"#,
+    );
+    batcher.push(events).unwrap();
+    let shared = Arc::clone(&batcher.shared);
+    drop(batcher);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !shared.lock().stopped {
+        assert!(Instant::now() < deadline, "the thread kept writing");
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(
+        shared.lock().report.failed_writes,
+        MAX_WRITES_AFTER_DROP as usize
+    );
+}
@@ -0,0 +1,113 @@
+//! A stream of what a sender is doing, for dashboards and tests that want to watch it
+//! without polling its stats.
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// The number of events a subscriber can fall behind by. Events that happen while a
+/// subscriber has this many waiting are dropped for it, so a slow subscriber never holds
+/// up sending or uses more and more memory.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Something that happened in a sender. Every subscriber gets every event from the
+/// moment it subscribed, unless it fell `EVENT_CAPACITY` events behind.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// A message got a response from the mail send endpoint.
+    Sent {
+        /// The status of the response.
+        status: u16,
+        /// How long the request took.
+        elapsed: Duration,
+    },
+    /// A failed message of a bulk send was sent again.
+    Retried {
+        /// The retry attempt, starting at 1.
+        attempt: u32,
+    },
+    /// SendGrid responded with 429 Too Many Requests.
+    RateLimited {
+        /// The path of the request, without its query.
+        path: String,
+    },
+    /// Requests moved to a fallback host because the current one couldn't be reached.
+    FailedOver {
+        /// The base URL of the host requests are now made to.
+        host: String,
+    },
+    /// The number of messages waiting in a `BackgroundSender` changed.
+    QueueDepthChanged {
+        /// The number of messages waiting to be sent.
+        depth: usize,
+    },
+}
+
+// The subscribers of a sender. Subscribers that dropped their receiver are removed the
+// next time an event is sent.
+#[derive(Debug, Default)]
+pub(crate) struct Events {
+    subscribers: Mutex<Vec<SyncSender<ClientEvent>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> Receiver<ClientEvent> {
+        let (tx, rx) = mpsc::sync_channel(EVENT_CAPACITY);
+        self.lock().push(tx);
+        rx
+    }
+
+    // Send an event to every subscriber that isn't full. The event is only built if there
+    // is a subscriber.
+    pub fn emit<F: FnOnce() -> ClientEvent>(&self, event: F) {
+        let mut subscribers = self.lock();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = event();
+        subscribers.retain(|tx| match tx.try_send(event.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<SyncSender<ClientEvent>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[test]
+fn broadcast_events() {
+    let events = Events::default();
+    events.emit(|| panic!("no one is subscribed"));
+
+    let first = events.subscribe();
+    let second = events.subscribe();
+    events.emit(|| ClientEvent::QueueDepthChanged { depth: 1 });
+    drop(second);
+    events.emit(|| ClientEvent::Retried { attempt: 1 });
+
+    let got: Vec<ClientEvent> = first.try_iter().collect();
+    assert_eq!(
+        got,
+        vec![
+            ClientEvent::QueueDepthChanged { depth: 1 },
+            ClientEvent::Retried { attempt: 1 },
+        ]
+    );
+    assert_eq!(events.lock().len(), 1);
+}
+
+#[test]
+fn full_subscribers_miss_events() {
+    let events = Events::default();
+    let slow = events.subscribe();
+    for attempt in 0..EVENT_CAPACITY as u32 + 10 {
+        events.emit(|| ClientEvent::Retried { attempt });
+    }
+    assert_eq!(slow.try_iter().count(), EVENT_CAPACITY);
+
+    events.emit(|| ClientEvent::Retried { attempt: 0 });
+    assert_eq!(slow.try_recv(), Ok(ClientEvent::Retried { attempt: 0 }));
+    assert_eq!(events.lock().len(), 1);
+}
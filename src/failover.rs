@@ -0,0 +1,201 @@
+//! Switching between API hosts when the current one can't be reached.
+
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest;
+
+// How long to stay on a fallback host before trying the primary host again.
+const FAILBACK_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub(crate) struct Hosts {
+    // The base URLs, starting with the primary one.
+    bases: Vec<String>,
+    failback_after: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    current: usize,
+    // When requests last moved to a fallback host, or the primary host last failed
+    // again.
+    failed_over_at: Option<Instant>,
+}
+
+// Copies start on the primary host again.
+impl Clone for Hosts {
+    fn clone(&self) -> Hosts {
+        Hosts {
+            bases: self.bases.clone(),
+            failback_after: self.failback_after,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl Hosts {
+    pub fn new(primary: &str) -> Hosts {
+        Hosts::with_fallbacks(primary, &[])
+    }
+
+    pub fn with_fallbacks(primary: &str, fallbacks: &[&str]) -> Hosts {
+        let mut bases = vec![base(primary)];
+        bases.extend(fallbacks.iter().map(|f| base(f)));
+        Hosts {
+            bases,
+            failback_after: FAILBACK_AFTER,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn set_failback_after(&mut self, failback_after: Duration) {
+        self.failback_after = failback_after;
+    }
+
+    // Make a request against every host in turn until one can be reached. The body is
+    // only copied when there is another host left to try.
+    pub fn request<F>(&self, body: Option<Vec<u8>>, mut f: F) -> reqwest::Result<reqwest::Response>
+    where
+        F: FnMut(&str, Option<Vec<u8>>) -> reqwest::Result<reqwest::Response>,
+    {
+        let order = self.order();
+        let mut body = body;
+        let mut last_error = None;
+
+        for (n, &i) in order.iter().enumerate() {
+            let attempt_body = if n + 1 < order.len() {
+                body.clone()
+            } else {
+                body.take()
+            };
+
+            match f(&self.bases[i], attempt_body) {
+                Ok(res) => {
+                    self.reached(i);
+                    return Ok(res);
+                }
+                Err(e) => {
+                    if !is_connect_error(&e) {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("there is always a primary host"))
+    }
+
+    // The fallback host requests are made to, if they moved away from the primary one.
+    pub fn fallback(&self) -> Option<&str> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.current {
+            0 => None,
+            i => Some(&self.bases[i]),
+        }
+    }
+
+    // The order to try the hosts in: the current host first, except that the primary
+    // host is tried first again once the fallback has been used for long enough.
+    fn order(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let first = if self.failed_back(&state) {
+            0
+        } else {
+            state.current
+        };
+
+        let mut order = vec![first];
+        order.extend((0..self.bases.len()).filter(|&i| i != first));
+        order
+    }
+
+    fn reached(&self, i: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if i == 0 {
+            state.failed_over_at = None;
+        } else if state.current == 0 || self.failed_back(&state) {
+            // Either requests just moved away from the primary host, or it was tried
+            // again and is still down.
+            state.failed_over_at = Some(Instant::now());
+        }
+        state.current = i;
+    }
+
+    // Whether the fallback host has been used long enough to try the primary host again.
+    fn failed_back(&self, state: &State) -> bool {
+        state
+            .failed_over_at
+            .is_some_and(|at| at.elapsed() >= self.failback_after)
+    }
+}
+
+fn base(url: &str) -> String {
+    String::from(url.trim_end_matches('/'))
+}
+
+// Whether a request failed because the host couldn't be reached, so it never got the
+// request. Only connection and DNS failures count: other errors, like a timeout, can
+// happen after the host got the request, and sending it again could deliver a message
+// twice.
+pub(crate) fn is_connect_error(e: &reqwest::Error) -> bool {
+    e.get_ref()
+        .and_then(|e| e.downcast_ref::<io::Error>())
+        .is_some_and(is_unreachable)
+}
+
+pub(crate) fn is_unreachable(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::AddrNotAvailable
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::HostUnreachable
+        | io::ErrorKind::NetworkUnreachable => true,
+        // The standard library reports failed lookups without a kind of their own.
+        _ => e.to_string().starts_with("failed to lookup address"),
+    }
+}
+
+#[test]
+fn fail_over_and_back() {
+    let mut hosts = Hosts::with_fallbacks(
+        "https://api.example.com/v3/",
+        &["https://eu.example.com/v3"],
+    );
+    assert_eq!(hosts.bases[0], "https://api.example.com/v3");
+    assert_eq!(hosts.order(), vec![0, 1]);
+
+    assert_eq!(hosts.fallback(), None);
+    hosts.reached(1);
+    assert_eq!(hosts.order(), vec![1, 0]);
+    assert_eq!(hosts.fallback(), Some("https://eu.example.com/v3"));
+    assert_eq!(hosts.clone().fallback(), None);
+
+    hosts.set_failback_after(Duration::from_secs(0));
+    assert_eq!(hosts.order(), vec![0, 1]);
+    hosts.reached(0);
+    assert_eq!(hosts.order(), vec![0, 1]);
+}
+
+#[test]
+fn unreachable_errors() {
+    let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+    assert!(is_unreachable(&refused));
+    let lookup =
+        io::Error::other("failed to lookup address information: Name or service not known");
+    assert!(is_unreachable(&lookup));
+
+    for kind in &[
+        io::ErrorKind::TimedOut,
+        io::ErrorKind::ConnectionReset,
+        io::ErrorKind::UnexpectedEof,
+        io::ErrorKind::Other,
+    ] {
+        assert!(!is_unreachable(&io::Error::new(
+            *kind,
+            "after the request was sent"
+        )));
+    }
+}
@@ -0,0 +1,216 @@
+//! The field definitions of Marketing Campaigns contacts, and typed values for custom
+//! fields that are checked against them before contacts are sent to SendGrid.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use serde::{Serialize, Serializer};
+
+/// Reading the field definitions of contacts.
+pub struct ContactFields;
+
+impl Endpoint for ContactFields {
+    const SCOPES: &'static [&'static str] = &["marketing.read"];
+}
+
+/// The type of a contact field.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum FieldType {
+    Text,
+    Number,
+    Date,
+}
+
+/// The definition of a reserved or custom contact field.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FieldDefinition {
+    /// The ID custom fields are set by, e.g. `e1_T`.
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub field_type: FieldType,
+    /// Other fields of the definition, such as its read-only flag.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The reserved and custom fields of the contacts of an account.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FieldDefinitions {
+    pub custom_fields: Vec<FieldDefinition>,
+    pub reserved_fields: Vec<FieldDefinition>,
+}
+
+impl FieldDefinitions {
+    /// Find a custom field by its name.
+    pub fn custom_field(&self, name: &str) -> Option<&FieldDefinition> {
+        self.custom_fields.iter().find(|f| f.name == name)
+    }
+
+    /// Check a value for a custom field, and return the ID to set it by. Fails with
+    /// `InvalidCustomField` when there is no field with that name or the value doesn't
+    /// fit it.
+    pub fn check(&self, name: &str, value: &FieldValue) -> SendgridResult<&str> {
+        let invalid = |reason: String| -> SendgridResult<&str> {
+            Err(SendgridErrorKind::InvalidCustomField(String::from(name), reason).into())
+        };
+        let field = match self.custom_field(name) {
+            Some(field) => field,
+            None => return invalid(String::from("there is no custom field with this name")),
+        };
+        if field.field_type != value.field_type() {
+            return invalid(format!(
+                "the field holds {:?} values, not {:?}",
+                field.field_type,
+                value.field_type()
+            ));
+        }
+        match *value {
+            FieldValue::Text(ref text) if text.chars().count() > MAX_TEXT_LENGTH => invalid(
+                format!("text values can't be over {} characters", MAX_TEXT_LENGTH),
+            ),
+            FieldValue::Number(number) if !number.is_finite() => {
+                invalid(format!("{} is not a number SendGrid can store", number))
+            }
+            FieldValue::Date(ref date) if !is_date(date) => {
+                invalid(format!("'{}' is not a date formatted as MM/DD/YYYY", date))
+            }
+            _ => Ok(&field.id),
+        }
+    }
+}
+
+// The longest value of a text field SendGrid stores.
+const MAX_TEXT_LENGTH: usize = 1000;
+
+/// The value of a custom field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    /// A date formatted as `MM/DD/YYYY`.
+    Date(String),
+}
+
+impl FieldValue {
+    /// A date value.
+    pub fn date(year: u32, month: u32, day: u32) -> FieldValue {
+        FieldValue::Date(format!("{:02}/{:02}/{:04}", month, day, year))
+    }
+
+    /// The type of field the value fits.
+    pub fn field_type(&self) -> FieldType {
+        match *self {
+            FieldValue::Text(_) => FieldType::Text,
+            FieldValue::Number(_) => FieldType::Number,
+            FieldValue::Date(_) => FieldType::Date,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for FieldValue {
+    fn from(text: &'a str) -> FieldValue {
+        FieldValue::Text(String::from(text))
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(number: f64) -> FieldValue {
+        FieldValue::Number(number)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(number: i64) -> FieldValue {
+        FieldValue::Number(number as f64)
+    }
+}
+
+impl Serialize for FieldValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            FieldValue::Text(ref text) | FieldValue::Date(ref text) => {
+                serializer.serialize_str(text)
+            }
+            FieldValue::Number(number) => serializer.serialize_f64(number),
+        }
+    }
+}
+
+// Whether a date is formatted as MM/DD/YYYY with a month and day that can exist.
+fn is_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('/').collect();
+    if parts.len() != 3
+        || parts[0].len() != 2
+        || parts[1].len() != 2
+        || parts[2].len() != 4
+        || !parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return false;
+    }
+    let month: u32 = parts[0].parse().unwrap_or(0);
+    let day: u32 = parts[1].parse().unwrap_or(0);
+    let year: u32 = parts[2].parse().unwrap_or(0);
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+impl V3Sender {
+    /// Get the definitions of the reserved and custom contact fields.
+    pub fn field_definitions(&self) -> SendgridResult<FieldDefinitions> {
+        self.api_get("/marketing/field_definitions")
+    }
+}
+
+#[test]
+fn check_values() {
+    let definitions: FieldDefinitions = ::serde_json::from_str(
+        r#"{"custom_fields":[{"id":"e1_N","name":"seats","field_type":"Number"},{"id":"e2_D","name":"renewal","field_type":"Date"}],"reserved_fields":[{"id":"_rf0_T","name":"first_name","field_type":"Text"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(definitions.check("seats", &3i64.into()).unwrap(), "e1_N");
+    assert_eq!(
+        definitions
+            .check("renewal", &FieldValue::date(2024, 2, 9))
+            .unwrap(),
+        "e2_D"
+    );
+
+    let error = definitions.check("seats", &"three".into()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the value of the custom field 'seats' is invalid: the field holds Number values, not Text"
+    );
+    let error = definitions
+        .check("renewal", &FieldValue::Date(String::from("2024-02-09")))
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("not a date formatted as MM/DD/YYYY"));
+    assert!(definitions.check("plan", &"pro".into()).is_err());
+    assert_eq!(
+        ::serde_json::to_string(&FieldValue::date(2024, 2, 9)).unwrap(),
+        r#""02/09/2024""#
+    );
+}
+
+#[test]
+fn dates_that_exist() {
+    assert!(is_date("02/29/2024"));
+    assert!(is_date("12/31/2023"));
+    assert!(!is_date("02/29/2023"));
+    assert!(!is_date("02/31/2024"));
+    assert!(!is_date("04/31/2024"));
+    assert!(!is_date("13/01/2024"));
+    assert!(!is_date("00/10/2024"));
+}
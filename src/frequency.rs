@@ -0,0 +1,296 @@
+//! Capping how many messages every recipient gets within a window of time, so a bug or
+//! a burst of notifications can't flood anyone's inbox.
+
+use v3::SGMailV3;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// SendGrid only accepts a `send_at` up to 72 hours ahead.
+const MAX_DEFER: u64 = 72 * 60 * 60;
+
+/// Storage for when messages were sent to every address. Implement this on top of a
+/// shared cache such as Redis to cap sends across processes.
+pub trait FrequencyStore {
+    /// Record a send to an address at a Unix timestamp, which can be in the future for
+    /// scheduled sends.
+    fn record(&self, address: &str, at: u64);
+
+    /// The Unix timestamps of the sends to an address at or after `since`, oldest first.
+    /// Reading leaves the sends in the store.
+    fn sends_since(&self, address: &str, since: u64) -> Vec<u64>;
+
+    /// Forget the sends before a Unix timestamp, which no longer count against the cap.
+    /// Stores that expire sends on their own, like Redis keys with a TTL, can leave this
+    /// out.
+    fn forget_before(&self, _before: u64) {}
+}
+
+/// A frequency store that keeps the send times in memory. Sends that left the window
+/// are forgotten as time passes, for every address.
+#[derive(Debug, Default)]
+pub struct MemoryFrequencyStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    sends: HashMap<String, VecDeque<u64>>,
+    // The timestamp sends were last forgotten before.
+    forgotten_before: u64,
+}
+
+// How far time has to move on before the memory store looks for old sends again, as it
+// goes through every address.
+const FORGET_INTERVAL: u64 = 60;
+
+impl MemoryFrequencyStore {
+    /// Construct a new, empty store.
+    pub fn new() -> MemoryFrequencyStore {
+        MemoryFrequencyStore::default()
+    }
+}
+
+impl FrequencyStore for MemoryFrequencyStore {
+    fn record(&self, address: &str, at: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let times = state.sends.entry(String::from(address)).or_default();
+        let position = times.iter().rposition(|&t| t <= at).map_or(0, |i| i + 1);
+        times.insert(position, at);
+    }
+
+    fn sends_since(&self, address: &str, since: u64) -> Vec<u64> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.sends.get(address) {
+            Some(times) => times.iter().cloned().filter(|&t| t >= since).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn forget_before(&self, before: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if before < state.forgotten_before.saturating_add(FORGET_INTERVAL) {
+            return;
+        }
+        state.forgotten_before = before;
+        state.sends.retain(|_, times| {
+            while times.front().is_some_and(|&t| t < before) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+    }
+}
+
+/// What happens to recipients that reached their cap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CapAction {
+    /// Drop them from the message.
+    Block,
+    /// Schedule them for when the oldest send of the window expires, as long as that is
+    /// within the 72 hours SendGrid can schedule ahead, and drop them otherwise.
+    Defer,
+}
+
+/// The recipients a `FrequencyCap` held back from a message.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CapReport {
+    /// The recipients that were dropped from the message.
+    pub blocked: Vec<String>,
+    /// The recipients that were scheduled for later, with the Unix timestamp.
+    pub deferred: Vec<(String, u64)>,
+}
+
+/// Limits every recipient to `limit` messages within a window of time. Addresses are
+/// compared without regard to case.
+///
+/// Recipients are checked and recorded per personalization when a message is applied,
+/// so apply it right before sending it. A personalization is held back as a whole when
+/// any of its recipients reached their cap.
+///
+/// # Examples
+///
+/// ```ignore
+/// let cap = FrequencyCap::new(3, Duration::from_secs(24 * 60 * 60), CapAction::Defer);
+/// let report = cap.apply(&mut message, now);
+/// if !report.blocked.is_empty() {
+///     info!("not sent to {:?}, who got too many messages", report.blocked);
+/// }
+/// sender.send(&message)?;
+/// ```
+pub struct FrequencyCap<S: FrequencyStore = MemoryFrequencyStore> {
+    limit: usize,
+    window: u64,
+    action: CapAction,
+    store: S,
+}
+
+impl FrequencyCap<MemoryFrequencyStore> {
+    /// Construct a cap that keeps the send times in memory.
+    pub fn new(limit: usize, window: Duration, action: CapAction) -> FrequencyCap {
+        FrequencyCap::with_store(limit, window, action, MemoryFrequencyStore::new())
+    }
+}
+
+impl<S: FrequencyStore> FrequencyCap<S> {
+    /// Construct a cap that keeps the send times in the given store.
+    pub fn with_store(
+        limit: usize,
+        window: Duration,
+        action: CapAction,
+        store: S,
+    ) -> FrequencyCap<S> {
+        FrequencyCap {
+            limit,
+            window: window.as_secs(),
+            action,
+            store,
+        }
+    }
+
+    /// The earliest Unix timestamp at or after `at` when an address can get another
+    /// message without going over the cap. A send counts against the cap from its
+    /// timestamp until the window has passed after it.
+    pub fn next_allowed(&self, address: &str, at: u64) -> u64 {
+        // The sends still in the window at `at`, which ends right before `t + window`
+        // for a send at `t`.
+        let since = (at + 1).saturating_sub(self.window);
+        let sends = self.store.sends_since(&normalize(address), since);
+        if self.limit == 0 {
+            return u64::MAX;
+        }
+        if sends.len() < self.limit {
+            return at;
+        }
+        // Enough sends have to expire for one to fit again.
+        (sends[sends.len() - self.limit] + self.window).max(at)
+    }
+
+    /// Hold back the personalizations whose recipients reached their cap, and record
+    /// a send for the recipients of the others. Deferred personalizations get a
+    /// `send_at`, and blocked ones are removed from the message.
+    pub fn apply(&self, message: &mut SGMailV3, now: u64) -> CapReport {
+        self.store.forget_before(now.saturating_sub(self.window));
+        let mut report = CapReport::default();
+        let default_send_at = message.send_at().unwrap_or(now).max(now);
+
+        message.personalizations_mut().retain_mut(|p| {
+            let at = p.send_at().unwrap_or(default_send_at);
+            let allowed = p
+                .recipients()
+                .map(|address| self.next_allowed(address, at))
+                .max()
+                .unwrap_or(at);
+
+            let send_at = if allowed == at {
+                at
+            } else if self.action == CapAction::Defer && allowed - now <= MAX_DEFER {
+                for address in p.recipients() {
+                    report.deferred.push((String::from(address), allowed));
+                }
+                p.set_send_at(allowed);
+                allowed
+            } else {
+                report.blocked.extend(p.recipients().map(String::from));
+                return false;
+            };
+
+            for address in p.recipients() {
+                self.store.record(&normalize(address), send_at);
+            }
+            true
+        });
+
+        report
+    }
+}
+
+fn normalize(address: &str) -> String {
+    address.trim().to_ascii_lowercase()
+}
+
+#[test]
+fn cap_recipients() {
+    use v3::{Email, Personalization};
+
+    let message = |addresses: &[&str]| {
+        let mut m = SGMailV3::new();
+        for address in addresses {
+            let mut to = Email::new();
+            to.set_email(address);
+            let mut p = Personalization::new();
+            p.add_to(to);
+            m.add_personalization(p);
+        }
+        m
+    };
+    let hour = 60 * 60;
+    let now = 1_514_849_415;
+
+    let cap = FrequencyCap::new(2, Duration::from_secs(hour), CapAction::Defer);
+    for i in 0..2 {
+        let report = cap.apply(&mut message(&["a@example.com"]), now + i);
+        assert_eq!(report, CapReport::default());
+    }
+    let mut m = message(&["A@example.com", "b@example.com"]);
+    let report = cap.apply(&mut m, now + 10);
+    assert_eq!(
+        report.deferred,
+        vec![(String::from("A@example.com"), now + hour)]
+    );
+    let json = ::serde_json::to_value(&m).unwrap();
+    assert_eq!(json["personalizations"][0]["send_at"], now + hour);
+    assert!(json["personalizations"][1].get("send_at").is_none());
+    // The deferred send counts against the next window.
+    assert_eq!(
+        cap.next_allowed("a@example.com", now + hour),
+        now + hour + 1
+    );
+
+    let cap = FrequencyCap::new(1, Duration::from_secs(100 * hour), CapAction::Defer);
+    cap.apply(&mut message(&["a@example.com"]), now);
+    let mut m = message(&["a@example.com", "b@example.com"]);
+    let report = cap.apply(&mut m, now);
+    assert_eq!(report.blocked, vec!["a@example.com"]);
+    assert_eq!(
+        ::serde_json::to_value(&m).unwrap()["personalizations"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let store = MemoryFrequencyStore::new();
+    store.record("a", 5);
+    store.record("a", 3);
+    store.record("a", 9);
+    assert_eq!(store.sends_since("a", 4), vec![5, 9]);
+    assert!(store.sends_since("a", 10).is_empty());
+    assert_eq!(store.sends_since("a", 0), vec![3, 5, 9]);
+}
+
+#[test]
+fn cap_windows() {
+    let hour = 60 * 60;
+    let now = 1_514_849_415;
+    let cap = FrequencyCap::new(1, Duration::from_secs(hour), CapAction::Defer);
+    cap.store.record("a@example.com", now);
+
+    // The send stops counting once the window passed after it, and not a second later.
+    assert_eq!(cap.next_allowed("a@example.com", now), now + hour);
+    assert_eq!(cap.next_allowed("a@example.com", now + hour), now + hour);
+
+    // Checking a send scheduled days ahead leaves the sends of the current window.
+    assert_eq!(
+        cap.next_allowed("a@example.com", now + 48 * hour),
+        now + 48 * hour
+    );
+    assert_eq!(cap.next_allowed("a@example.com", now + 10), now + hour);
+
+    // Addresses that are never checked again are forgotten once their sends are old.
+    cap.store.record("b@example.com", now);
+    cap.store.forget_before(now + 2 * hour);
+    let state = cap.store.state.lock().unwrap();
+    assert!(state.sends.is_empty());
+}
@@ -0,0 +1,213 @@
+use errors::{SendgridErrorKind, SendgridResult};
+
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE64;
+
+// The number of bytes that fit in one encoded word without going over the 75 character
+// limit from RFC 2047 once base64 encoded and wrapped in "=?UTF-8?B?" and "?=".
+const ENCODED_WORD_BYTES: usize = 45;
+
+// Check that a header name only has the printable ASCII characters allowed by RFC 5322.
+pub fn validate_name(name: &str) -> SendgridResult<()> {
+    let valid = !name.is_empty() && name.bytes().all(|b| b > 32 && b < 127 && b != b':');
+    if !valid {
+        return Err(SendgridErrorKind::InvalidHeaderName(name.to_string()).into());
+    }
+    Ok(())
+}
+
+// Check a header value for line breaks that could inject other headers.
+pub fn check_value(value: &str) -> SendgridResult<()> {
+    if value.contains(&['\r', '\n'][..]) {
+        return Err(SendgridErrorKind::InvalidHeaderValue(value.to_string()).into());
+    }
+    Ok(())
+}
+
+// Check a header value like `check_value`, and encode it with RFC 2047 encoded words if
+// it is not plain ASCII.
+pub fn encode_value<'a>(value: Cow<'a, str>) -> SendgridResult<Cow<'a, str>> {
+    check_value(&value)?;
+    if value.is_ascii() {
+        return Ok(value);
+    }
+
+    let mut encoded = String::with_capacity(value.len() * 2);
+    let mut rest: &str = &value;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(ENCODED_WORD_BYTES);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if !encoded.is_empty() {
+            encoded.push(' ');
+        }
+        encoded.push_str("=?UTF-8?B?");
+        encoded.push_str(&BASE64.encode(&rest.as_bytes()[..end]));
+        encoded.push_str("?=");
+        rest = &rest[end..];
+    }
+
+    Ok(Cow::Owned(encoded))
+}
+
+// Generate a new Message-ID for the given domain. The local part combines the time,
+// the process, a per-process counter and a random value, so ids are unique without
+// any coordination between hosts.
+pub fn message_id(domain: &str) -> SendgridResult<String> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let valid = !domain.is_empty()
+        && domain
+            .bytes()
+            .all(|b| b > 32 && b < 127 && !b"<>@()[]\\,;:\"".contains(&b));
+    if !valid {
+        return Err(SendgridErrorKind::InvalidHeaderValue(domain.to_string()).into());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let random = RandomState::new().build_hasher().finish();
+    Ok(format!(
+        "<{}.{:09}.{}.{}.{:016x}@{}>",
+        now.as_secs(),
+        now.subsec_nanos(),
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        random,
+        domain
+    ))
+}
+
+// Format a Unix timestamp as an RFC 2822 date in UTC, such as
+// "Thu, 01 Jan 1970 00:00:00 +0000".
+pub fn format_date(timestamp: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+
+    let (year, month, day) = civil_date(timestamp);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// The year, month and day in UTC of a Unix timestamp.
+pub fn civil_date(timestamp: u64) -> (u64, u64, u64) {
+    let days = timestamp / 86400;
+
+    // Convert the days since the epoch into a civil date, counting from March so leap
+    // days come at the end of the year.
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+// The current time as an RFC 2822 date.
+pub fn now() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format_date(now.as_secs())
+}
+
+// Replace line breaks and tabs with spaces and drop all other control characters.
+// Returns `None` when the value didn't have any.
+pub fn strip_control(value: &str) -> Option<String> {
+    if !value.chars().any(char::is_control) {
+        return None;
+    }
+
+    let stripped = value
+        .chars()
+        .filter_map(|c| match c {
+            '\r' | '\n' | '\t' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+    Some(stripped)
+}
+
+#[test]
+fn header_names() {
+    assert!(validate_name("X-Cool").is_ok());
+    assert!(validate_name("").is_err());
+    assert!(validate_name("X Cool").is_err());
+    assert!(validate_name("X-Cool:").is_err());
+}
+
+#[test]
+fn header_values() {
+    assert_eq!(encode_value(Cow::Borrowed("indeed")).unwrap(), "indeed");
+    assert_eq!(
+        encode_value(Cow::Borrowed("Grüße")).unwrap(),
+        "=?UTF-8?B?R3LDvMOfZQ==?="
+    );
+    assert!(encode_value(Cow::Borrowed("a\r\nBcc: evil@example.com")).is_err());
+
+    let long = "ü".repeat(40);
+    let long = encode_value(Cow::Borrowed(&long)).unwrap();
+    assert!(long.split(' ').all(|word| word.len() <= 75));
+    assert_eq!(long.split(' ').count(), 2);
+}
+
+#[test]
+fn message_ids() {
+    let first = message_id("mail.example.com").unwrap();
+    let second = message_id("mail.example.com").unwrap();
+    assert!(first.starts_with('<') && first.ends_with("@mail.example.com>"));
+    assert_ne!(first, second);
+
+    assert!(message_id("").is_err());
+    assert!(message_id("example.com>\r\nBcc: evil@example.com").is_err());
+}
+
+#[test]
+fn dates() {
+    assert_eq!(format_date(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+    assert_eq!(format_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 +0000");
+    assert_eq!(
+        format_date(1_700_000_000),
+        "Tue, 14 Nov 2023 22:13:20 +0000"
+    );
+    assert_eq!(civil_date(1_700_000_000), (2023, 11, 14));
+}
+
+#[test]
+fn control_characters() {
+    assert_eq!(strip_control("indeed"), None);
+    assert_eq!(
+        strip_control("Hi\r\nBcc: evil@example.com\u{7}"),
+        Some("Hi  Bcc: evil@example.com".to_string())
+    );
+}
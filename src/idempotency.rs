@@ -0,0 +1,120 @@
+//! Guards against sending the same message twice when a job is retried.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Storage for the idempotency keys of messages that were sent. Implement this on top
+/// of a shared cache such as Redis to dedupe sends across processes.
+pub trait IdempotencyStore {
+    /// Record a key, returning `false` if it was already recorded.
+    fn insert(&self, key: &str) -> bool;
+
+    /// Forget a key, so a message that failed to send can be tried again.
+    fn remove(&self, key: &str);
+}
+
+/// An idempotency store that keeps the keys in memory, which is enough to dedupe
+/// retries within a single process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    keys: Mutex<HashSet<String>>,
+}
+
+impl MemoryStore {
+    /// Construct a new, empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn insert(&self, key: &str) -> bool {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.insert(String::from(key))
+    }
+
+    fn remove(&self, key: &str) {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        keys.remove(key);
+    }
+}
+
+/// An idempotency store that keeps a bounded number of keys in memory, forgetting the
+/// least recently used ones first.
+#[derive(Debug)]
+pub struct LruStore {
+    capacity: usize,
+    inner: Mutex<LruKeys>,
+}
+
+#[derive(Debug, Default)]
+struct LruKeys {
+    tick: u64,
+    // When every key was last used, and the keys by when they were last used.
+    last_used: HashMap<String, u64>,
+    by_use: BTreeMap<u64, String>,
+}
+
+impl LruStore {
+    /// Construct a new store that keeps at most `capacity` keys.
+    pub fn new(capacity: usize) -> LruStore {
+        LruStore {
+            capacity,
+            inner: Mutex::new(LruKeys::default()),
+        }
+    }
+}
+
+impl IdempotencyStore for LruStore {
+    fn insert(&self, key: &str) -> bool {
+        let mut keys = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        keys.tick += 1;
+        let tick = keys.tick;
+
+        if let Some(used) = keys.last_used.insert(String::from(key), tick) {
+            keys.by_use.remove(&used);
+            keys.by_use.insert(tick, String::from(key));
+            return false;
+        }
+        keys.by_use.insert(tick, String::from(key));
+
+        while keys.last_used.len() > self.capacity {
+            let oldest = match keys.by_use.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(key) = keys.by_use.remove(&oldest) {
+                keys.last_used.remove(&key);
+            }
+        }
+        true
+    }
+
+    fn remove(&self, key: &str) {
+        let mut keys = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(used) = keys.last_used.remove(key) {
+            keys.by_use.remove(&used);
+        }
+    }
+}
+
+#[test]
+fn memory_store() {
+    let store = MemoryStore::new();
+    assert!(store.insert("welcome-42"));
+    assert!(!store.insert("welcome-42"));
+    store.remove("welcome-42");
+    assert!(store.insert("welcome-42"));
+}
+
+#[test]
+fn lru_store() {
+    let store = LruStore::new(2);
+    assert!(store.insert("a"));
+    assert!(store.insert("b"));
+    assert!(!store.insert("a"));
+    // "b" is now the least recently used key, so it is the one that gets evicted.
+    assert!(store.insert("c"));
+    assert!(!store.insert("a"));
+    assert!(store.insert("b"));
+}
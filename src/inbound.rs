@@ -0,0 +1,347 @@
+//! Types for the messages posted by the SendGrid Inbound Parse webhook.
+//!
+//! Inbound Parse posts every received message as a multipart/form-data request, with the
+//! parts of the message as form fields and the attachments as files. When it is set up
+//! to post the raw message instead, the full MIME message is sent in the `email` field.
+
+use errors::SendgridResult;
+use multipart;
+
+use std::collections::HashMap;
+
+#[cfg(feature = "mime")]
+use mailparse::{self, ParsedMail};
+use serde_json;
+
+/// A file attached to a received message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InboundAttachment {
+    pub filename: String,
+    pub mime_type: String,
+    /// The content ID of inline attachments, which the HTML part references with
+    /// `cid:` URLs.
+    pub content_id: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// A message received through Inbound Parse.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InboundEmail {
+    /// All the text fields of the request, e.g. `from`, `subject` or `spam_score`.
+    pub fields: HashMap<String, String>,
+    pub attachments: Vec<InboundAttachment>,
+    /// The full MIME message, when Inbound Parse posts raw messages.
+    pub raw: Option<Vec<u8>>,
+}
+
+/// The result of an SPF or DKIM check of a received message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthResult {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+    /// The domain doesn't publish a record, or the message isn't signed.
+    NoRecord,
+    TempError,
+    PermError,
+    /// A result this library doesn't know about.
+    Other(String),
+}
+
+impl AuthResult {
+    fn parse(result: &str) -> AuthResult {
+        match result.trim().to_ascii_lowercase().as_str() {
+            "pass" => AuthResult::Pass,
+            "fail" => AuthResult::Fail,
+            "softfail" => AuthResult::SoftFail,
+            "neutral" => AuthResult::Neutral,
+            "none" => AuthResult::NoRecord,
+            "temperror" => AuthResult::TempError,
+            "permerror" => AuthResult::PermError,
+            other => AuthResult::Other(other.to_string()),
+        }
+    }
+}
+
+/// The result of checking one DKIM signature of a received message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DkimSignature {
+    /// The signing domain.
+    pub domain: String,
+    pub result: AuthResult,
+}
+
+/// The SMTP envelope of a received message, which can differ from its headers.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Envelope {
+    pub from: String,
+    pub to: Vec<String>,
+    /// Other fields SendGrid adds to the envelope.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+// The details SendGrid sends about every attachment in the `attachment-info` field.
+#[derive(Deserialize)]
+struct AttachmentInfo {
+    filename: Option<String>,
+    #[serde(rename = "type")]
+    mime_type: Option<String>,
+    #[serde(rename = "content-id")]
+    content_id: Option<String>,
+}
+
+impl InboundEmail {
+    /// Parse the body of an Inbound Parse request, given the value of its Content-Type
+    /// header.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let email = InboundEmail::parse(&content_type, &body)?;
+    /// for attachment in &email.attachments {
+    ///     fs::write(&attachment.filename, &attachment.data)?;
+    /// }
+    /// ```
+    pub fn parse(content_type: &str, body: &[u8]) -> SendgridResult<InboundEmail> {
+        let mut email = InboundEmail::default();
+        let mut files = Vec::new();
+
+        for part in multipart::parse_form(content_type, body)? {
+            if part.filename.is_some() {
+                files.push(part);
+            } else if part.name == "email" {
+                // Keep the raw message as bytes, as it doesn't have to be valid UTF-8.
+                email.raw = Some(part.data.to_vec());
+            } else {
+                let value = String::from_utf8_lossy(part.data).into_owned();
+                email.fields.insert(part.name, value);
+            }
+        }
+
+        let mut info: HashMap<String, AttachmentInfo> = match email.fields.get("attachment-info") {
+            Some(info) => serde_json::from_str(info)?,
+            None => HashMap::new(),
+        };
+        // Maps content IDs to the names of the attachment fields.
+        let content_ids: HashMap<String, String> = match email.fields.get("content-ids") {
+            Some(ids) => serde_json::from_str(ids)?,
+            None => HashMap::new(),
+        };
+
+        for part in files {
+            let info = info.remove(&part.name);
+            let content_id = info
+                .as_ref()
+                .and_then(|i| i.content_id.clone())
+                .or_else(|| {
+                    content_ids
+                        .iter()
+                        .find(|&(_, name)| *name == part.name)
+                        .map(|(id, _)| id.clone())
+                });
+            let (filename, mime_type) = match info {
+                Some(info) => (info.filename, info.mime_type),
+                None => (None, None),
+            };
+
+            email.attachments.push(InboundAttachment {
+                filename: filename.or(part.filename).unwrap_or_default(),
+                mime_type: mime_type
+                    .or(part.content_type)
+                    .unwrap_or_else(|| String::from("application/octet-stream")),
+                content_id,
+                data: part.data.to_vec(),
+            });
+        }
+
+        Ok(email)
+    }
+
+    /// Parse the raw MIME message, when Inbound Parse posts raw messages. Returns `None`
+    /// when it doesn't.
+    #[cfg(feature = "mime")]
+    pub fn parse_raw(&self) -> SendgridResult<Option<ParsedMail<'_>>> {
+        match self.raw {
+            Some(ref raw) => Ok(Some(mailparse::parse_mail(raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The result of the SPF check of the sending server.
+    pub fn spf(&self) -> Option<AuthResult> {
+        self.field("SPF").map(AuthResult::parse)
+    }
+
+    /// The results of checking the DKIM signatures of the message. SendGrid sends them
+    /// like `{@example.com : pass, @example.net : fail}`.
+    pub fn dkim(&self) -> Vec<DkimSignature> {
+        let dkim = match self.field("dkim") {
+            Some(dkim) => dkim.trim().trim_start_matches('{').trim_end_matches('}'),
+            None => return Vec::new(),
+        };
+
+        dkim.split(',')
+            .filter_map(|signature| {
+                let colon = signature.rfind(':')?;
+                let domain = signature[..colon].trim().trim_start_matches('@');
+                if domain.is_empty() {
+                    return None;
+                }
+                Some(DkimSignature {
+                    domain: domain.to_string(),
+                    result: AuthResult::parse(&signature[colon + 1..]),
+                })
+            })
+            .collect()
+    }
+
+    /// The SMTP envelope of the message.
+    pub fn envelope(&self) -> SendgridResult<Option<Envelope>> {
+        match self.field("envelope") {
+            Some(envelope) => Ok(Some(serde_json::from_str(envelope)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a text field of the request.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|value| value.as_str())
+    }
+
+    /// The sender of the message, as given in the From header.
+    pub fn from(&self) -> Option<&str> {
+        self.field("from")
+    }
+
+    /// The recipients of the message, as given in the To header.
+    pub fn to(&self) -> Option<&str> {
+        self.field("to")
+    }
+
+    /// The subject of the message.
+    pub fn subject(&self) -> Option<&str> {
+        self.field("subject")
+    }
+
+    /// The text part of the message.
+    pub fn text(&self) -> Option<&str> {
+        self.field("text")
+    }
+
+    /// The HTML part of the message.
+    pub fn html(&self) -> Option<&str> {
+        self.field("html")
+    }
+}
+
+#[test]
+fn inbound_attachments() {
+    let body = "--xYzZY\r\n\
+                Content-Disposition: form-data; name=\"from\"\r\n\
+                \r\n\
+                Me <me@example.com>\r\n\
+                --xYzZY\r\n\
+                Content-Disposition: form-data; name=\"attachment-info\"\r\n\
+                \r\n\
+                {\"attachment1\":{\"filename\":\"logo.png\",\"name\":\"logo.png\",\"type\":\"image/png\",\"content-id\":\"ii_123\"},\"attachment2\":{\"filename\":\"notes.txt\",\"type\":\"text/plain\"}}\r\n\
+                --xYzZY\r\n\
+                Content-Disposition: form-data; name=\"content-ids\"\r\n\
+                \r\n\
+                {\"ii_123\":\"attachment1\"}\r\n\
+                --xYzZY\r\n\
+                Content-Disposition: form-data; name=\"attachment1\"; filename=\"logo.png\"\r\n\
+                Content-Type: image/png\r\n\
+                \r\n\
+                PNG\r\n\
+                --xYzZY\r\n\
+                Content-Disposition: form-data; name=\"attachment2\"; filename=\"notes.txt\"\r\n\
+                Content-Type: text/plain\r\n\
+                \r\n\
+                notes\r\n\
+                --xYzZY--\r\n";
+    let email =
+        InboundEmail::parse("multipart/form-data; boundary=xYzZY", body.as_bytes()).unwrap();
+
+    assert_eq!(email.from(), Some("Me <me@example.com>"));
+    assert_eq!(
+        email.attachments,
+        vec![
+            InboundAttachment {
+                filename: String::from("logo.png"),
+                mime_type: String::from("image/png"),
+                content_id: Some(String::from("ii_123")),
+                data: b"PNG".to_vec(),
+            },
+            InboundAttachment {
+                filename: String::from("notes.txt"),
+                mime_type: String::from("text/plain"),
+                content_id: None,
+                data: b"notes".to_vec(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn inbound_raw_message() {
+    let body = b"--xYzZY\r\n\
+                 Content-Disposition: form-data; name=\"to\"\r\n\
+                 \r\n\
+                 inbox@example.com\r\n\
+                 --xYzZY\r\n\
+                 Content-Disposition: form-data; name=\"email\"\r\n\
+                 \r\n\
+                 Subject: Hi\r\n\
+                 Content-Type: text/plain; charset=latin1\r\n\
+                 \r\n\
+                 Gr\xfc\xdfe\r\n\
+                 --xYzZY--\r\n";
+    let email = InboundEmail::parse("multipart/form-data; boundary=xYzZY", body).unwrap();
+
+    assert_eq!(email.to(), Some("inbox@example.com"));
+    assert!(email.field("email").is_none());
+    assert!(email.raw.as_ref().unwrap().ends_with(b"Gr\xfc\xdfe"));
+
+    #[cfg(feature = "mime")]
+    {
+        let parsed = email.parse_raw().unwrap().unwrap();
+        assert_eq!(parsed.get_body().unwrap(), "Grüße");
+    }
+}
+
+#[test]
+fn inbound_authentication() {
+    let mut email = InboundEmail::default();
+    email
+        .fields
+        .insert(String::from("SPF"), String::from("softfail"));
+    email.fields.insert(
+        String::from("dkim"),
+        String::from("{@example.com : pass, @example.net : fail}"),
+    );
+    email.fields.insert(
+        String::from("envelope"),
+        String::from(r#"{"to":["inbox@example.com"],"from":"bounces@example.com"}"#),
+    );
+
+    assert_eq!(email.spf(), Some(AuthResult::SoftFail));
+    assert_eq!(
+        email.dkim(),
+        vec![
+            DkimSignature {
+                domain: String::from("example.com"),
+                result: AuthResult::Pass,
+            },
+            DkimSignature {
+                domain: String::from("example.net"),
+                result: AuthResult::Fail,
+            },
+        ]
+    );
+    let envelope = email.envelope().unwrap().unwrap();
+    assert_eq!(envelope.from, "bounces@example.com");
+    assert_eq!(envelope.to, vec!["inbox@example.com"]);
+}
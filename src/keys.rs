@@ -0,0 +1,221 @@
+//! Falling back to other API keys while a key is being rotated.
+
+#[cfg(feature = "client")]
+use errors::SendgridResult;
+
+#[cfg(feature = "client")]
+use std::fmt;
+#[cfg(feature = "client")]
+use std::str::FromStr;
+#[cfg(feature = "client")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "client")]
+use std::sync::Arc;
+
+#[cfg(feature = "client")]
+use reqwest::header::{Authorization, Scheme};
+#[cfg(feature = "client")]
+use reqwest::Response;
+#[cfg(feature = "secrets")]
+use secrecy::{ExposeSecret, SecretString};
+
+// With the `secrets` feature, API keys are kept in a `SecretString`, which is wiped from
+// memory when dropped.
+#[cfg(feature = "secrets")]
+pub(crate) type Key = SecretString;
+#[cfg(not(feature = "secrets"))]
+pub(crate) type Key = String;
+
+#[cfg(feature = "secrets")]
+pub(crate) fn to_key(key: String) -> Key {
+    SecretString::new(key)
+}
+
+#[cfg(not(feature = "secrets"))]
+pub(crate) fn to_key(key: String) -> Key {
+    key
+}
+
+#[cfg(feature = "secrets")]
+pub(crate) fn expose(key: &Key) -> &str {
+    key.expose_secret()
+}
+
+#[cfg(not(feature = "secrets"))]
+pub(crate) fn expose(key: &Key) -> &str {
+    key
+}
+
+// The bearer scheme of an Authorization header. It keeps the key as a `Key` until the
+// header is written to a request, where `Bearer` would copy it into a plain `String`.
+#[cfg(feature = "client")]
+#[derive(Clone)]
+pub(crate) struct BearerKey(Key);
+
+#[cfg(feature = "client")]
+impl fmt::Debug for BearerKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BearerKey([redacted])")
+    }
+}
+
+#[cfg(feature = "client")]
+impl FromStr for BearerKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<BearerKey, ()> {
+        Ok(BearerKey(to_key(String::from(s))))
+    }
+}
+
+#[cfg(feature = "client")]
+impl Scheme for BearerKey {
+    fn scheme() -> Option<&'static str> {
+        Some("Bearer")
+    }
+
+    fn fmt_scheme(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(expose(&self.0))
+    }
+}
+
+// The Authorization header for an API key.
+#[cfg(feature = "client")]
+pub(crate) fn authorization(key: &Key) -> Authorization<BearerKey> {
+    Authorization(BearerKey(key.clone()))
+}
+
+/// Sent to the observer set with `V3Sender::on_key_rejected` when SendGrid rejected an
+/// API key. Keys are identified by their position, with the first key at 0, so the keys
+/// themselves never end up in logs.
+#[cfg(feature = "client")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyRejected {
+    /// The key SendGrid rejected.
+    pub rejected: usize,
+    /// The key that is tried next.
+    pub next: usize,
+}
+
+#[cfg(feature = "client")]
+type Observer = Arc<dyn Fn(&KeyRejected) + Send + Sync>;
+
+#[cfg(feature = "client")]
+pub(crate) struct ApiKeys {
+    keys: Vec<Key>,
+    // The key to use first, which is the last one that was accepted.
+    current: AtomicUsize,
+    observer: Option<Observer>,
+}
+
+// Copies start from the key the original currently uses, and share its observer.
+#[cfg(feature = "client")]
+impl Clone for ApiKeys {
+    fn clone(&self) -> ApiKeys {
+        ApiKeys {
+            keys: self.keys.clone(),
+            current: AtomicUsize::new(self.current.load(Ordering::Relaxed)),
+            observer: self.observer.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl ApiKeys {
+    pub fn new(key: Key) -> ApiKeys {
+        ApiKeys {
+            keys: vec![key],
+            current: AtomicUsize::new(0),
+            observer: None,
+        }
+    }
+
+    pub fn push(&mut self, key: Key) {
+        self.keys.push(key);
+    }
+
+    pub fn set_observer<F: Fn(&KeyRejected) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    // Make a request with the current key, moving on to the next key while SendGrid
+    // responds with 401 Unauthorized. The body is only copied when there is another key
+    // left to try.
+    pub fn request<F>(&self, body: Option<Vec<u8>>, mut f: F) -> SendgridResult<Response>
+    where
+        F: FnMut(&Key, Option<Vec<u8>>) -> SendgridResult<Response>,
+    {
+        let mut body = body;
+        let mut key = self.current.load(Ordering::Relaxed) % self.keys.len();
+
+        for tried in 1.. {
+            let last = tried == self.keys.len();
+            let attempt_body = if last { body.take() } else { body.clone() };
+
+            let res = f(&self.keys[key], attempt_body)?;
+            if last || res.status().as_u16() != 401 {
+                return Ok(res);
+            }
+            key = self.rejected(key);
+        }
+        unreachable!("every key was tried")
+    }
+
+    // Move on from a rejected key, returning the next key to try.
+    fn rejected(&self, key: usize) -> usize {
+        let next = (key + 1) % self.keys.len();
+        self.current.store(next, Ordering::Relaxed);
+        if let Some(ref observer) = self.observer {
+            observer(&KeyRejected {
+                rejected: key,
+                next,
+            });
+        }
+        next
+    }
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn rotate_keys() {
+    use std::sync::Mutex;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut keys = ApiKeys::new(to_key(String::from("old")));
+    keys.push(to_key(String::from("new")));
+    let seen = events.clone();
+    keys.set_observer(move |e| seen.lock().unwrap().push(*e));
+    assert_eq!(authorization(&keys.keys[1]).to_string(), "Bearer new");
+    assert!(!format!("{:?}", authorization(&keys.keys[0])).contains("old"));
+
+    assert_eq!(keys.rejected(0), 1);
+    assert_eq!(keys.current.load(Ordering::Relaxed), 1);
+
+    // Copies, like the keys of `V3Sender::for_subuser`, start from the current key and
+    // report to the same observer.
+    let copy = keys.clone();
+    assert_eq!(copy.current.load(Ordering::Relaxed), 1);
+    assert_eq!(copy.rejected(1), 0);
+    assert_eq!(keys.current.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        events.lock().unwrap().pop(),
+        Some(KeyRejected {
+            rejected: 1,
+            next: 0
+        })
+    );
+    assert_eq!(keys.rejected(1), 0);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            KeyRejected {
+                rejected: 0,
+                next: 1
+            },
+            KeyRejected {
+                rejected: 1,
+                next: 0
+            }
+        ]
+    );
+}
@@ -1,16 +1,169 @@
+#![allow(clippy::result_large_err)]
+// error_chain needs more room to expand the list of error kinds.
+#![recursion_limit = "256"]
+
 #[macro_use]
 extern crate error_chain;
 
 #[macro_use]
 extern crate serde_derive;
 
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "csv")]
+extern crate csv;
 extern crate data_encoding;
+#[cfg(feature = "templates-local")]
+extern crate handlebars;
+extern crate indexmap;
+extern crate libflate;
+#[cfg(all(feature = "client", feature = "log"))]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "mime")]
+extern crate mailparse;
+#[cfg(any(feature = "minimal-blocking", feature = "smtp"))]
+extern crate native_tls;
+#[cfg(any(feature = "signed-webhooks", all(feature = "client", feature = "log")))]
+extern crate openssl;
+#[cfg(feature = "markdown")]
+extern crate pulldown_cmark;
+#[cfg(feature = "client")]
 extern crate reqwest;
+#[cfg(feature = "secrets")]
+extern crate secrecy;
 extern crate serde;
 extern crate serde_json;
+#[cfg(feature = "tera")]
+extern crate tera;
 extern crate url;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
+#[cfg(feature = "client")]
+pub mod account;
+#[cfg(feature = "client")]
+pub mod activity;
+mod address;
+mod attachments;
+#[cfg(feature = "client")]
+pub mod audit;
+pub mod auto_suppress;
+#[cfg(feature = "client")]
+pub mod background;
+pub mod bounces;
+#[cfg(feature = "client")]
+pub mod bulk;
+#[cfg(feature = "client")]
+pub mod campaigns;
+#[cfg(feature = "client")]
+pub mod cancel;
+#[cfg(feature = "client")]
+pub mod client_stats;
+pub mod clipping;
+#[cfg(feature = "client")]
+mod connection;
+#[cfg(feature = "client")]
+pub mod contacts;
+pub mod correlation;
+#[cfg(feature = "client")]
+pub mod credits;
+#[cfg(feature = "css-inline")]
+pub mod css;
+#[cfg(feature = "client")]
+pub mod cursor;
+#[cfg(feature = "dns-check")]
+pub mod dns;
+#[cfg(feature = "client")]
+pub mod domains;
+#[cfg(feature = "client")]
+pub mod download;
+mod eml;
+pub mod engagement;
 pub mod errors;
+pub mod event_sink;
+#[cfg(feature = "client")]
+pub mod events;
+#[cfg(feature = "client")]
+mod failover;
+#[cfg(feature = "client")]
+pub mod fields;
+pub mod frequency;
+mod headers;
+pub mod idempotency;
+pub mod inbound;
+#[cfg(any(feature = "client", feature = "minimal-blocking", feature = "smtp"))]
+pub mod keys;
+pub mod links;
+pub mod localized;
+#[cfg(all(feature = "client", feature = "log"))]
+pub mod logging;
 pub mod mail;
+#[cfg(feature = "client")]
+pub mod marketing_stats;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+pub mod migrate;
+#[cfg(feature = "mime")]
+mod mime;
+#[cfg(feature = "minimal-blocking")]
+pub mod minimal;
+mod multipart;
+pub mod ndjson;
+pub mod outbox;
+#[cfg(feature = "client")]
+pub mod plan;
+pub mod preheader;
+#[cfg(feature = "client")]
+pub mod provider;
+pub mod query;
+pub mod quiet_hours;
+#[cfg(feature = "client")]
+mod quotas;
+#[cfg(feature = "client")]
+pub mod reports;
+#[cfg(feature = "client")]
+mod response;
+#[cfg(feature = "client")]
+pub mod scheduled_sends;
+#[cfg(feature = "client")]
+pub mod scoped;
+#[cfg(feature = "client")]
+pub mod scopes;
+#[cfg(feature = "client")]
+pub mod senders;
+#[cfg(feature = "legacy-v2")]
 pub mod sg_client;
+#[cfg(feature = "client")]
+pub mod signing;
+#[cfg(feature = "smtp")]
+pub mod smtp;
+pub mod split_test;
+#[cfg(feature = "client")]
+pub mod sso;
+#[cfg(feature = "client")]
+pub mod stats;
+#[cfg(feature = "client")]
+pub mod sunset;
+#[cfg(feature = "client")]
+pub mod suppressions;
+#[cfg(feature = "client")]
+pub mod task_pool;
+#[cfg(feature = "client")]
+pub mod templates;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod text;
+#[cfg(feature = "client")]
+mod throttle;
+#[cfg(feature = "client")]
+pub mod usage;
+#[cfg(feature = "legacy-v2")]
+pub mod v2;
 pub mod v3;
+pub mod vcard;
+#[cfg(feature = "signed-webhooks")]
+pub mod webhook_self_test;
+#[cfg(feature = "client")]
+pub mod webhook_settings;
+pub mod webhooks;
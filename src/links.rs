@@ -0,0 +1,209 @@
+//! Rewriting the links of HTML content before sending, most often to tag them with UTM
+//! parameters so analytics can attribute visits to the message they came from.
+
+use mail::Mail;
+use v3::SGMailV3;
+
+use url::Url;
+
+/// The UTM parameters added to links by `SGMailV3::add_utm_params`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut utm = UtmParams::new("newsletter", "email", "spring-sale");
+/// utm.add_domain("shop.example.com");
+/// message.add_utm_params(&utm);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UtmParams {
+    source: String,
+    medium: String,
+    campaign: String,
+    term: Option<String>,
+    content: Option<String>,
+    domains: Vec<String>,
+}
+
+impl UtmParams {
+    /// Construct the parameters with the required `utm_source`, `utm_medium` and
+    /// `utm_campaign`.
+    pub fn new(source: &str, medium: &str, campaign: &str) -> UtmParams {
+        UtmParams {
+            source: String::from(source),
+            medium: String::from(medium),
+            campaign: String::from(campaign),
+            ..UtmParams::default()
+        }
+    }
+
+    /// Set the `utm_term` parameter.
+    pub fn set_term(&mut self, term: &str) {
+        self.term = Some(String::from(term));
+    }
+
+    /// Set the `utm_content` parameter, e.g. to tell apart the links of a message.
+    pub fn set_content(&mut self, content: &str) {
+        self.content = Some(String::from(content));
+    }
+
+    /// Only tag links to this domain and its subdomains. Without any domains, links to
+    /// every domain are tagged.
+    pub fn add_domain(&mut self, domain: &str) {
+        self.domains.push(domain.to_ascii_lowercase());
+    }
+
+    /// Add the parameters to a URL, or return `None` when it is not an HTTP URL to one
+    /// of the domains. Parameters already in the URL are kept as they are.
+    pub fn tag(&self, link: &str) -> Option<String> {
+        let mut url = Url::parse(link).ok()?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return None;
+        }
+        let host = url.host_str()?.to_ascii_lowercase();
+        let allowed = self.domains.is_empty()
+            || self.domains.iter().any(|d| {
+                host == *d || host.ends_with(d) && host[..host.len() - d.len()].ends_with('.')
+            });
+        if !allowed {
+            return None;
+        }
+
+        let existing: Vec<String> = url.query_pairs().map(|(k, _)| k.into_owned()).collect();
+        let params = [
+            ("utm_source", Some(&self.source)),
+            ("utm_medium", Some(&self.medium)),
+            ("utm_campaign", Some(&self.campaign)),
+            ("utm_term", self.term.as_ref()),
+            ("utm_content", self.content.as_ref()),
+        ];
+        let missing: Vec<_> = params
+            .iter()
+            .filter_map(|&(key, value)| value.map(|v| (key, v)))
+            .filter(|&(key, _)| !existing.iter().any(|e| e == key))
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+        url.query_pairs_mut().extend_pairs(missing);
+        Some(url.into_string())
+    }
+}
+
+/// Rewrite the `href` of every link in HTML content with a closure, which gets the
+/// decoded URL and returns the new one, or `None` to leave the link alone. Links with
+/// SendGrid or template placeholders like `<%asm_group_unsubscribe_raw_url%>` or
+/// `{{url}}` are skipped.
+pub fn rewrite_links<F>(html: &str, mut rewrite: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut i = 0;
+
+    while let Some(offset) = lower[i..].find("href=") {
+        let value_start = i + offset + "href=".len();
+        let preceded_by_space = lower[..i + offset].ends_with(|c: char| c.is_ascii_whitespace());
+        let (start, end) = match html[value_start..].chars().next() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                let start = value_start + 1;
+                match html[start..].find(quote) {
+                    Some(e) => (start, start + e),
+                    None => break,
+                }
+            }
+            _ => {
+                let e = html[value_start..]
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(html.len() - value_start);
+                (value_start, value_start + e)
+            }
+        };
+        out.push_str(&html[i..start]);
+
+        let link = &html[start..end];
+        let placeholder = link.contains("<%") || link.contains("{{") || link.contains("%%");
+        match rewrite_one(link, preceded_by_space && !placeholder, &mut rewrite) {
+            Some(new) => out.push_str(&new),
+            None => out.push_str(link),
+        }
+        i = end;
+    }
+    out.push_str(&html[i..]);
+    out
+}
+
+fn rewrite_one<F>(link: &str, eligible: bool, rewrite: &mut F) -> Option<String>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    if !eligible {
+        return None;
+    }
+    let decoded = link.replace("&amp;", "&");
+    rewrite(decoded.trim()).map(|new| new.replace('&', "&amp;").replace('"', "%22"))
+}
+
+impl SGMailV3 {
+    /// Rewrite the links of the HTML content with a closure, see `links::rewrite_links`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// message.rewrite_links(|url| Some(format!("https://r.example.com/?to={}", url)));
+    /// ```
+    pub fn rewrite_links<F: FnMut(&str) -> Option<String>>(&mut self, rewrite: F) {
+        if let Some(html) = self.html_mut() {
+            *html = rewrite_links(html, rewrite);
+        }
+    }
+
+    /// Add UTM parameters to the links of the HTML content.
+    pub fn add_utm_params(&mut self, params: &UtmParams) {
+        self.rewrite_links(|link| params.tag(link));
+    }
+}
+
+impl<'a> Mail<'a> {
+    /// Rewrite the links of the HTML content with a closure, see `links::rewrite_links`.
+    pub fn rewrite_links<F: FnMut(&str) -> Option<String>>(&mut self, rewrite: F) {
+        if !self.html.is_empty() {
+            self.html = rewrite_links(&self.html, rewrite).into();
+        }
+    }
+
+    /// Add UTM parameters to the links of the HTML content.
+    pub fn add_utm_params(&mut self, params: &UtmParams) {
+        self.rewrite_links(|link| params.tag(link));
+    }
+}
+
+#[test]
+fn tag_links() {
+    let mut utm = UtmParams::new("newsletter", "email", "spring sale");
+    utm.add_domain("example.com");
+
+    let html = "<a href=\"https://shop.example.com/p?id=1&amp;utm_source=blog#top\">Shop</a>\
+                <a class=x href='http://example.com'>Home</a>\
+                <a href=https://other.org/>Other</a>\
+                <a href=\"mailto:me@example.com\">Mail</a>\
+                <a href=\"<%asm_group_unsubscribe_raw_url%>\">Unsubscribe</a>\
+                <a href=\"{{profile_url}}\">Profile</a> data-href=\"https://example.com\"";
+    let mut m = Mail::new();
+    m.add_html(html);
+    m.add_utm_params(&utm);
+
+    assert_eq!(
+        m.html,
+        "<a href=\"https://shop.example.com/p?id=1&amp;utm_source=blog\
+         &amp;utm_medium=email&amp;utm_campaign=spring+sale#top\">Shop</a>\
+         <a class=x href='http://example.com/?utm_source=newsletter&amp;utm_medium=email\
+         &amp;utm_campaign=spring+sale'>Home</a>\
+         <a href=https://other.org/>Other</a>\
+         <a href=\"mailto:me@example.com\">Mail</a>\
+         <a href=\"<%asm_group_unsubscribe_raw_url%>\">Unsubscribe</a>\
+         <a href=\"{{profile_url}}\">Profile</a> data-href=\"https://example.com\""
+    );
+    assert!(utm.tag("https://example.community/").is_none());
+}
@@ -0,0 +1,220 @@
+//! Messages for multilingual audiences, where every recipient gets the subject and
+//! content or template of their own locale.
+
+use v3::{Content, Email, Personalization, SGMailV3, MAX_PERSONALIZATIONS};
+
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+/// The substitution tag that is replaced with the localized HTML content.
+pub const HTML_TAG: &str = "-localized_html-";
+
+/// The substitution tag that is replaced with the localized text content.
+pub const TEXT_TAG: &str = "-localized_text-";
+
+/// The subject and either the content or the template of one locale.
+#[derive(Clone, Default)]
+pub struct Localization {
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+    template_id: Option<String>,
+}
+
+impl Localization {
+    /// Construct a new localization with the given subject.
+    pub fn new(subject: &str) -> Localization {
+        Localization {
+            subject: String::from(subject),
+            ..Localization::default()
+        }
+    }
+
+    /// Set the HTML content.
+    pub fn set_html(&mut self, html: &str) {
+        self.html = Some(String::from(html));
+    }
+
+    /// Set the text content.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = Some(String::from(text));
+    }
+
+    /// Use a template instead of the HTML and text content.
+    pub fn set_template_id(&mut self, template_id: &str) {
+        self.template_id = Some(String::from(template_id));
+    }
+}
+
+/// Builds messages where every recipient gets the localization of their locale. A
+/// locale like `de-AT` falls back to `de` and then to the default locale when there is
+/// no localization for it.
+///
+/// Recipients whose localizations have content share one message, with the content
+/// filled in through per-personalization substitutions. SendGrid limits the
+/// substitutions of a personalization to 10,000 bytes, so this suits notifications
+/// rather than long newsletters, which should use templates. A message can only have
+/// one template, so recipients with templates get a message per template.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut en = Localization::new("Your order has shipped");
+/// en.set_text("It is on its way!");
+/// let mut de = Localization::new("Ihre Bestellung wurde versandt");
+/// de.set_text("Sie ist unterwegs!");
+///
+/// let mut localized = LocalizedMail::new(from, "en");
+/// localized.add_localization("en", en);
+/// localized.add_localization("de", de);
+/// for message in localized.build(users.iter().map(|u| (u.email(), u.locale()))) {
+///     sender.send(&message)?;
+/// }
+/// ```
+pub struct LocalizedMail {
+    from: Email,
+    default_locale: String,
+    localizations: HashMap<String, Localization>,
+}
+
+impl LocalizedMail {
+    /// Construct a new localized mail sent from the given address.
+    pub fn new(from: Email, default_locale: &str) -> LocalizedMail {
+        LocalizedMail {
+            from,
+            default_locale: default_locale.to_ascii_lowercase(),
+            localizations: HashMap::new(),
+        }
+    }
+
+    /// Add the localization of a locale. Locales are matched without regard to case.
+    pub fn add_localization(&mut self, locale: &str, localization: Localization) {
+        self.localizations
+            .insert(locale.to_ascii_lowercase(), localization);
+    }
+
+    /// Find the localization for a locale, falling back to the language and then to
+    /// the default locale.
+    pub fn localization(&self, locale: &str) -> Option<&Localization> {
+        let locale = locale.to_ascii_lowercase().replace('_', "-");
+        let language = locale.split('-').next().unwrap_or("");
+
+        self.localizations
+            .get(&locale)
+            .or_else(|| self.localizations.get(language))
+            .or_else(|| self.localizations.get(&self.default_locale))
+    }
+
+    /// Build the messages for a list of recipients and their locales. Recipients
+    /// without a localization for their locale or the default locale are skipped.
+    pub fn build<I, L>(&self, recipients: I) -> Vec<SGMailV3>
+    where
+        I: IntoIterator<Item = (Email, L)>,
+        L: AsRef<str>,
+    {
+        let mut groups: IndexMap<Option<&str>, Vec<Vec<Personalization>>> = IndexMap::new();
+
+        for (to, locale) in recipients {
+            let localization = match self.localization(locale.as_ref()) {
+                Some(localization) => localization,
+                None => continue,
+            };
+
+            let mut p = Personalization::new();
+            p.add_to(to);
+            p.set_subject(&localization.subject);
+            if localization.template_id.is_none() {
+                p.add_substitution(HTML_TAG, localization.html.as_ref().map_or("", |h| h));
+                p.add_substitution(TEXT_TAG, localization.text.as_ref().map_or("", |t| t));
+            }
+
+            let chunks = groups
+                .entry(localization.template_id.as_deref())
+                .or_default();
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() < MAX_PERSONALIZATIONS => chunk.push(p),
+                _ => chunks.push(vec![p]),
+            }
+        }
+
+        let mut messages = Vec::new();
+        for (template_id, chunks) in groups {
+            for chunk in chunks {
+                let mut message = self.new_message(template_id);
+                for p in chunk {
+                    message.add_personalization(p);
+                }
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    fn new_message(&self, template_id: Option<&str>) -> SGMailV3 {
+        let mut message = SGMailV3::new();
+        message.set_from(self.from.clone());
+
+        match template_id {
+            Some(template_id) => message.set_template_id(template_id),
+            None => {
+                // SendGrid requires the text part to come before the HTML part.
+                let has_html = self.localizations.values().any(|l| l.html.is_some());
+                let has_text = self.localizations.values().any(|l| l.text.is_some());
+                for &(content_type, tag, used) in &[
+                    ("text/plain", TEXT_TAG, has_text),
+                    ("text/html", HTML_TAG, has_html),
+                ] {
+                    if used {
+                        let mut c = Content::new();
+                        c.set_content_type(content_type);
+                        c.set_value(tag);
+                        message.add_content(c);
+                    }
+                }
+            }
+        }
+
+        message
+    }
+}
+
+#[test]
+fn localized_messages() {
+    let mut from = Email::new();
+    from.set_email("me@example.com");
+
+    let mut en = Localization::new("Shipped");
+    en.set_text("On its way");
+    let mut de = Localization::new("Versandt");
+    de.set_text("Unterwegs");
+    let mut fr = Localization::new("Expédié");
+    fr.set_template_id("d-fr");
+
+    let mut localized = LocalizedMail::new(from, "en");
+    localized.add_localization("en", en);
+    localized.add_localization("de", de);
+    localized.add_localization("fr", fr);
+
+    let recipients = ["a@example.com", "b@example.com", "c@example.com"]
+        .iter()
+        .zip(["de_AT", "pt-BR", "FR"].iter())
+        .map(|(email, locale)| {
+            let mut to = Email::new();
+            to.set_email(email);
+            (to, *locale)
+        });
+    let messages = localized.build(recipients);
+    assert_eq!(messages.len(), 2);
+
+    let json = ::serde_json::to_string(&messages[0]).unwrap();
+    assert!(json.contains(r#""content":[{"type":"text/plain","value":"-localized_text-"}]"#));
+    assert!(json.contains(r#""subject":"Versandt""#));
+    assert!(json.contains(r#""-localized_text-":"Unterwegs""#));
+    assert!(json.contains(r#""subject":"Shipped""#));
+
+    let json = ::serde_json::to_string(&messages[1]).unwrap();
+    assert!(json.contains(r#""template_id":"d-fr""#));
+    assert!(json.contains(r#""subject":"Expédié""#));
+    assert!(!json.contains("substitutions"));
+}
@@ -0,0 +1,303 @@
+//! Debug logs of the requests made to the API, through the `log` crate. Addresses and
+//! the names that go with them are redacted so the logs can be kept without storing
+//! personal data.
+
+use std::time::Duration;
+
+use openssl::sha::sha256;
+use serde_json::{self, Value};
+
+// The fields of request bodies that hold personal data used to fill in templates.
+const TEMPLATE_DATA_FIELDS: [&str; 3] = ["dynamic_template_data", "substitutions", "sections"];
+
+/// How recipient addresses are written to the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AddressRedaction {
+    /// Keep the first character of the local part and the domain, as in
+    /// `j***@example.com`. This is the default.
+    #[default]
+    Mask,
+    /// Replace the address with a hash, so log lines about the same recipient can be
+    /// matched without showing the address. The hash is the start of the SHA-256 hash
+    /// of the lowercased address, so it is the same across releases and deployments. It
+    /// is not meant to stop someone from guessing addresses.
+    Hash,
+    /// Log the addresses as they are.
+    Plain,
+}
+
+impl AddressRedaction {
+    /// Redact an address.
+    pub fn apply(self, address: &str) -> String {
+        match self {
+            AddressRedaction::Mask => match address.rfind('@') {
+                Some(at) => {
+                    let first = address.chars().next().filter(|_| at > 0);
+                    format!(
+                        "{}***{}",
+                        first.map(String::from).unwrap_or_default(),
+                        &address[at..]
+                    )
+                }
+                None => String::from("***"),
+            },
+            AddressRedaction::Hash => sha256(address.to_ascii_lowercase().as_bytes())[..8]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            AddressRedaction::Plain => String::from(address),
+        }
+    }
+}
+
+/// What gets logged of request bodies, and how it is redacted. Bodies are logged at
+/// trace level, along with the bodies of error responses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedactionPolicy {
+    /// Whether to log bodies at all. Off by default.
+    pub log_bodies: bool,
+    /// How addresses are written, both in the request logs and in bodies.
+    pub addresses: AddressRedaction,
+    /// Whether to replace the data used to fill in templates, which often holds
+    /// personal data. On by default.
+    pub drop_template_data: bool,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> RedactionPolicy {
+        RedactionPolicy {
+            log_bodies: false,
+            addresses: AddressRedaction::default(),
+            drop_template_data: true,
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Redact a JSON body. Attachment contents are always left out, and bodies that
+    /// aren't JSON are replaced with their size.
+    pub fn apply(&self, body: &[u8]) -> String {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                self.redact(&mut value);
+                value.to_string()
+            }
+            Err(_) => format!("[{} bytes]", body.len()),
+        }
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match *value {
+            Value::String(ref mut s) if looks_like_address(s) => {
+                *s = self.addresses.apply(s);
+            }
+            Value::Array(ref mut values) => {
+                for value in values {
+                    self.redact(value);
+                }
+            }
+            Value::Object(ref mut fields) => {
+                // The display name of an address is as personal as the address.
+                let address = fields.get("email").and_then(Value::as_str).is_some();
+                for (name, value) in fields.iter_mut() {
+                    let template_data = TEMPLATE_DATA_FIELDS.contains(&name.as_str());
+                    if name == "name" && address && self.addresses != AddressRedaction::Plain {
+                        *value = Value::String(String::from("[redacted]"));
+                    } else if name == "attachments" {
+                        strip_attachments(value);
+                    } else if template_data && self.drop_template_data {
+                        *value = Value::String(String::from("[redacted]"));
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn looks_like_address(s: &str) -> bool {
+    s.contains('@') && !s.contains(char::is_whitespace)
+}
+
+fn strip_attachments(attachments: &mut Value) {
+    if let Value::Array(ref mut attachments) = *attachments {
+        for attachment in attachments {
+            if let Some(content) = attachment.get_mut("content") {
+                let len = content.as_str().map_or(0, str::len);
+                *content = Value::String(format!("[{} base64 characters]", len));
+            }
+        }
+    }
+}
+
+// Log a request or response body under the policy.
+pub(crate) fn body(method: &str, path: &str, body: &[u8], policy: &RedactionPolicy) {
+    if !policy.log_bodies || !log_enabled!(::log::Level::Trace) {
+        return;
+    }
+    trace!(
+        "{} {}: {}",
+        method,
+        redact_path(path, policy.addresses),
+        policy.apply(body)
+    );
+}
+
+// Log a request that got a response. Recipients are only collected when debug logs are
+// enabled.
+pub(crate) fn request<'a, F, I>(
+    method: &str,
+    path: &str,
+    status: u16,
+    elapsed: Duration,
+    redaction: AddressRedaction,
+    recipients: F,
+) where
+    F: FnOnce() -> I,
+    I: Iterator<Item = &'a str>,
+{
+    if !log_enabled!(::log::Level::Debug) {
+        return;
+    }
+
+    let path = redact_path(path, redaction);
+    let recipients: Vec<String> = recipients().map(|r| redaction.apply(r)).collect();
+    let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+    if recipients.is_empty() {
+        debug!("{} {} returned {} in {}ms", method, path, status, millis);
+    } else {
+        debug!(
+            "{} {} returned {} in {}ms for {}",
+            method,
+            path,
+            status,
+            millis,
+            recipients.join(", ")
+        );
+    }
+}
+
+// Log a request that failed without a response. Errors can name the URL of the
+// request, so their addresses are redacted too.
+pub(crate) fn failure<E: ::std::fmt::Display>(
+    method: &str,
+    path: &str,
+    elapsed: Duration,
+    redaction: AddressRedaction,
+    error: &E,
+) {
+    if !log_enabled!(::log::Level::Debug) {
+        return;
+    }
+
+    let millis = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+    debug!(
+        "{} {} failed after {}ms: {}",
+        method,
+        redact_path(path, redaction),
+        millis,
+        redact_text(&error.to_string(), redaction)
+    );
+}
+
+// Redact the addresses in the words of a message, keeping the rest of a URL they are
+// part of and the punctuation after them.
+fn redact_text(text: &str, redaction: AddressRedaction) -> String {
+    text.split(' ')
+        .map(|word| {
+            if word.contains('@') || word.contains("%40") {
+                let end = word.trim_end_matches(|c| ":,;.)".contains(c)).len();
+                let (word, punctuation) = word.split_at(end);
+                format!("{}{}", redact_path(word, redaction), punctuation)
+            } else {
+                String::from(word)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Drop the query of a path and redact the segments that are addresses, such as in
+// `/suppression/bounces/{email}`.
+fn redact_path(path: &str, redaction: AddressRedaction) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    path.split('/')
+        .map(|segment| {
+            if segment.contains('@') || segment.contains("%40") {
+                redaction.apply(&segment.replace("%40", "@"))
+            } else {
+                String::from(segment)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[test]
+fn redact_addresses() {
+    assert_eq!(
+        AddressRedaction::Mask.apply("jane@example.com"),
+        "j***@example.com"
+    );
+    assert_eq!(
+        AddressRedaction::Mask.apply("@example.com"),
+        "***@example.com"
+    );
+    assert_eq!(AddressRedaction::Mask.apply("nonsense"), "***");
+    assert_eq!(
+        AddressRedaction::Hash.apply("Jane@Example.com"),
+        AddressRedaction::Hash.apply("jane@example.com")
+    );
+    assert!(!AddressRedaction::Hash
+        .apply("jane@example.com")
+        .contains("jane"));
+    // The first 64 bits of the SHA-256 hash of the address.
+    assert_eq!(
+        AddressRedaction::Hash.apply("jane@example.com"),
+        "8c87b489ce35cf2e"
+    );
+    assert_eq!(
+        AddressRedaction::Plain.apply("jane@example.com"),
+        "jane@example.com"
+    );
+    assert_eq!(
+        redact_path(
+            "/suppression/bounces/jane%40example.com?x=1",
+            AddressRedaction::Mask
+        ),
+        "/suppression/bounces/j***@example.com"
+    );
+    assert_eq!(
+        redact_text(
+            "https://api.sendgrid.com/v3/suppression/bounces/jane%40example.com: timed out",
+            AddressRedaction::Hash
+        ),
+        format!(
+            "https://api.sendgrid.com/v3/suppression/bounces/{}: timed out",
+            AddressRedaction::Hash.apply("jane@example.com")
+        )
+    );
+}
+
+#[test]
+fn redact_bodies() {
+    let body = br#"{"personalizations":[{"to":[{"email":"jane@example.com","name":"Jane"}],"dynamic_template_data":{"name":"Jane"}}],"from":{"email":"me@example.com"},"subject":"Hi @ you","attachments":[{"content":"ZGF0YQ==","filename":"a.txt"}]}"#;
+
+    let policy = RedactionPolicy::default();
+    assert_eq!(
+        policy.apply(body),
+        r#"{"attachments":[{"content":"[8 base64 characters]","filename":"a.txt"}],"from":{"email":"m***@example.com"},"personalizations":[{"dynamic_template_data":"[redacted]","to":[{"email":"j***@example.com","name":"[redacted]"}]}],"subject":"Hi @ you"}"#
+    );
+
+    let policy = RedactionPolicy {
+        addresses: AddressRedaction::Plain,
+        drop_template_data: false,
+        ..Default::default()
+    };
+    assert!(policy
+        .apply(body)
+        .contains(r#""dynamic_template_data":{"name":"Jane"}"#));
+    assert_eq!(policy.apply(b"\x1f\x8b"), "[2 bytes]");
+}
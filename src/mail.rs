@@ -1,115 +1,470 @@
+use address;
 use errors::{SendgridErrorKind, SendgridResult};
+use headers;
+use preheader;
+use text;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
-use serde_json;
+#[cfg(feature = "templates-local")]
+use handlebars::Handlebars;
+use indexmap::IndexMap;
+#[cfg(feature = "markdown")]
+use pulldown_cmark;
+#[cfg(any(feature = "templates-local", feature = "tera"))]
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+#[cfg(feature = "tera")]
+use tera::Tera;
+
+/// An email address with an optional display name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmailAddress<'a> {
+    pub email: Cow<'a, str>,
+    pub name: Option<Cow<'a, str>>,
+}
+
+impl<'a> EmailAddress<'a> {
+    /// Construct an address without a display name.
+    pub fn new<T: Into<Cow<'a, str>>>(email: T) -> EmailAddress<'a> {
+        EmailAddress {
+            email: email.into(),
+            name: None,
+        }
+    }
+
+    /// Construct an address with a display name.
+    pub fn with_name<T, N>(email: T, name: N) -> EmailAddress<'a>
+    where
+        T: Into<Cow<'a, str>>,
+        N: Into<Cow<'a, str>>,
+    {
+        EmailAddress {
+            email: email.into(),
+            name: Some(name.into()),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for EmailAddress<'a> {
+    fn from(email: &'a str) -> EmailAddress<'a> {
+        EmailAddress::new(email)
+    }
+}
+
+impl<'a> From<String> for EmailAddress<'a> {
+    fn from(email: String) -> EmailAddress<'a> {
+        EmailAddress::new(email)
+    }
+}
+
+/// The priority of a message, as shown by mail clients that support it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    // The values for the `X-Priority`, `Importance` and `X-MSMail-Priority` headers.
+    fn header_values(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Priority::High => ("1 (Highest)", "High", "High"),
+            Priority::Normal => ("3 (Normal)", "Normal", "Normal"),
+            Priority::Low => ("5 (Lowest)", "Low", "Low"),
+        }
+    }
+}
+
+// Strip control characters from a field, recording the label of the field if it
+// changed.
+fn sanitize_field(field: &mut Cow<str>, label: String, changes: &mut Vec<String>) {
+    if let Some(stripped) = headers::strip_control(field) {
+        *field = Cow::Owned(stripped);
+        changes.push(label);
+    }
+}
+
+// Push a recipient and keep the matching list of names aligned with it. Names are only
+// tracked once at least one recipient has one, as unnamed recipients are padded with an
+// empty name.
+fn push_recipient<'a>(
+    addrs: &mut Vec<Cow<'a, str>>,
+    names: &mut Vec<Cow<'a, str>>,
+    addr: Cow<'a, str>,
+    name: Option<Cow<'a, str>>,
+) {
+    if name.is_some() || !names.is_empty() {
+        while names.len() < addrs.len() {
+            names.push(Cow::Borrowed(""));
+        }
+        names.push(name.unwrap_or(Cow::Borrowed("")));
+    }
+    addrs.push(addr);
+}
+
+// Wrap a message id in angle brackets unless it already is.
+fn format_message_id(message_id: &str) -> String {
+    let message_id = message_id.trim();
+    if message_id.starts_with('<') && message_id.ends_with('>') {
+        message_id.to_string()
+    } else {
+        format!("<{}>", message_id)
+    }
+}
 
 #[derive(Debug)]
 /// This is a representation of a valid SendGrid message. It has support for
-/// all of the fields in the V2 API.
-pub struct Mail {
-    pub to: Vec<String>,
-    pub to_names: Vec<String>,
-    pub cc: Vec<String>,
-    pub bcc: Vec<String>,
-    pub from: String,
-    pub subject: String,
-    pub html: String,
-    pub text: String,
-    pub from_name: String,
-    pub reply_to: String,
-    pub date: String,
-    pub attachments: HashMap<String, String>,
-    pub content: HashMap<String, String>,
-    pub headers: HashMap<String, String>,
-    pub x_smtpapi: String,
+/// all of the fields in the V2 API. The string fields can either borrow from
+/// the caller or own their data, so static or borrowed values don't need to be
+/// allocated again. The attachments, content and headers keep the order they
+/// were added in, so the encoded body is always the same for the same message.
+pub struct Mail<'a> {
+    pub to: Vec<Cow<'a, str>>,
+    pub to_names: Vec<Cow<'a, str>>,
+    pub cc: Vec<Cow<'a, str>>,
+    pub cc_names: Vec<Cow<'a, str>>,
+    pub bcc: Vec<Cow<'a, str>>,
+    pub bcc_names: Vec<Cow<'a, str>>,
+    pub from: EmailAddress<'a>,
+    pub subject: Cow<'a, str>,
+    pub html: Cow<'a, str>,
+    pub text: Cow<'a, str>,
+    pub reply_to: EmailAddress<'a>,
+    pub date: Cow<'a, str>,
+    pub attachments: IndexMap<String, Vec<u8>>,
+    pub content: IndexMap<Cow<'a, str>, Cow<'a, str>>,
+    pub headers: IndexMap<Cow<'a, str>, Cow<'a, str>>,
+    pub x_smtpapi: Cow<'a, str>,
+    pub send_each_at: Vec<u64>,
+    pub auto_text: bool,
+    pub normalize_text: bool,
+    pub auto_date: bool,
+}
+
+impl<'a> Default for Mail<'a> {
+    fn default() -> Mail<'a> {
+        Mail::new()
+    }
 }
 
-impl Mail {
+impl<'a> Mail<'a> {
     /// Returns a new Mail struct to send with a client. All of the fields are
     /// initially empty.
-    pub fn new() -> Mail {
+    pub fn new() -> Mail<'a> {
         Mail {
             to: Vec::new(),
             to_names: Vec::new(),
             cc: Vec::new(),
+            cc_names: Vec::new(),
             bcc: Vec::new(),
-            from: String::new(),
-            subject: String::new(),
-            html: String::new(),
-            text: String::new(),
-            from_name: String::new(),
-            reply_to: String::new(),
-            date: String::new(),
-            attachments: HashMap::new(),
-            content: HashMap::new(),
-            headers: HashMap::new(),
-            x_smtpapi: String::new(),
+            bcc_names: Vec::new(),
+            from: EmailAddress::default(),
+            subject: Cow::Borrowed(""),
+            html: Cow::Borrowed(""),
+            text: Cow::Borrowed(""),
+            reply_to: EmailAddress::default(),
+            date: Cow::Borrowed(""),
+            attachments: IndexMap::new(),
+            content: IndexMap::new(),
+            headers: IndexMap::new(),
+            x_smtpapi: Cow::Borrowed(""),
+            send_each_at: Vec::new(),
+            auto_text: false,
+            normalize_text: false,
+            auto_date: true,
         }
     }
 
+    /// Opt in to deriving the text content from the HTML content when no text
+    /// content is set. This gives receivers a text/plain part without any extra work.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new().with_auto_text();
+    /// message.add_html("<p>Hello!</p>");
+    /// ```
+    pub fn with_auto_text(mut self) -> Mail<'a> {
+        self.auto_text = true;
+        self
+    }
+
+    /// Opt in to normalizing the text content with `text::normalize_text`, which wraps
+    /// it at 78 columns, uses CRLF line endings and removes control characters, for
+    /// strict text clients.
+    pub fn with_normalized_text(mut self) -> Mail<'a> {
+        self.normalize_text = true;
+        self
+    }
+
+    /// Opt out of setting the date to the time the message is sent when no date is set,
+    /// so the date is left for SendGrid to fill in.
+    pub fn without_auto_date(mut self) -> Mail<'a> {
+        self.auto_date = false;
+        self
+    }
+
+    /// Returns a new Mail struct with the HTML content rendered from a Tera template,
+    /// so applications that already use Tera can reuse their templates for email.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let tera = compile_templates!("templates/**/*");
+    /// let mut context = Context::new();
+    /// context.insert("name", "Ferris");
+    ///
+    /// let mut message = Mail::from_tera(&tera, "welcome.html", &context)?;
+    /// ```
+    #[cfg(feature = "tera")]
+    pub fn from_tera<T: Serialize>(
+        tera: &Tera,
+        name: &str,
+        context: &T,
+    ) -> SendgridResult<Mail<'a>> {
+        let mut mail = Mail::new();
+        mail.html = Cow::Owned(tera.render(name, context)?);
+        Ok(mail)
+    }
+
     /// Adds a CC recipient to the Mail struct.
-    pub fn add_cc<T: Into<String>>(&mut self, cc_addr: T) {
+    pub fn add_cc<T: Into<Cow<'a, str>>>(&mut self, cc_addr: T) {
         self.cc.push(cc_addr.into())
     }
 
     /// Adds a to recipient to the Mail struct.
-    pub fn add_to<T: Into<String>>(&mut self, to_addr: T) {
+    pub fn add_to<T: Into<Cow<'a, str>>>(&mut self, to_addr: T) {
         self.to.push(to_addr.into())
     }
 
-    /// Set the from address for the Mail struct. This can be changed, but there
-    /// is only one from address per message.
-    pub fn add_from<T: Into<String>>(&mut self, from_addr: T) {
-        self.from = from_addr.into()
+    /// Set the from address and display name for the Mail struct. This can be
+    /// changed, but there is only one from address per message.
+    pub fn set_from<T: Into<EmailAddress<'a>>>(&mut self, from: T) {
+        self.from = from.into()
+    }
+
+    /// Set the from address for the Mail struct, keeping any existing from name.
+    #[deprecated(since = "0.8.0", note = "use `set_from` instead")]
+    pub fn add_from<T: Into<Cow<'a, str>>>(&mut self, from_addr: T) {
+        self.from.email = from_addr.into()
     }
 
     /// Set the subject of the message.
-    pub fn add_subject<T: Into<String>>(&mut self, subject: T) {
+    pub fn add_subject<T: Into<Cow<'a, str>>>(&mut self, subject: T) {
         self.subject = subject.into()
     }
 
     /// This function sets the HTML content for the message.
-    pub fn add_html<T: Into<String>>(&mut self, html: T) {
+    pub fn add_html<T: Into<Cow<'a, str>>>(&mut self, html: T) {
         self.html = html.into()
     }
 
+    /// Add several "to" recipients with optional names at once. The names are kept
+    /// aligned with the addresses, so there is no need to call `add_to_name`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.add_tos(vec![("a@example.com", Some("A")), ("b@example.com", None)]);
+    /// ```
+    pub fn add_tos<I>(&mut self, recipients: I)
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    {
+        for (addr, name) in recipients {
+            push_recipient(
+                &mut self.to,
+                &mut self.to_names,
+                Cow::Borrowed(addr),
+                name.map(Cow::Borrowed),
+            );
+        }
+    }
+
+    /// Add several CC recipients with optional names at once.
+    pub fn add_ccs<I>(&mut self, recipients: I)
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    {
+        for (addr, name) in recipients {
+            push_recipient(
+                &mut self.cc,
+                &mut self.cc_names,
+                Cow::Borrowed(addr),
+                name.map(Cow::Borrowed),
+            );
+        }
+    }
+
+    /// Add several BCC recipients with optional names at once.
+    pub fn add_bccs<I>(&mut self, recipients: I)
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    {
+        for (addr, name) in recipients {
+            push_recipient(
+                &mut self.bcc,
+                &mut self.bcc_names,
+                Cow::Borrowed(addr),
+                name.map(Cow::Borrowed),
+            );
+        }
+    }
+
+    /// Add the "to" recipients of an RFC 5322 address list, like the value of a `To`
+    /// header. Display names can be quoted, and a comment after an address is used as
+    /// its name.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.add_to_list(r#"Alice <a@example.com>, "Doe, John" <j@example.com>, b@example.com"#)?;
+    /// ```
+    pub fn add_to_list(&mut self, list: &str) -> SendgridResult<()> {
+        for address in address::parse_list(list)? {
+            push_recipient(
+                &mut self.to,
+                &mut self.to_names,
+                address.email,
+                address.name,
+            );
+        }
+        Ok(())
+    }
+
+    /// Add the CC recipients of an RFC 5322 address list.
+    pub fn add_cc_list(&mut self, list: &str) -> SendgridResult<()> {
+        for address in address::parse_list(list)? {
+            push_recipient(
+                &mut self.cc,
+                &mut self.cc_names,
+                address.email,
+                address.name,
+            );
+        }
+        Ok(())
+    }
+
+    /// Add the BCC recipients of an RFC 5322 address list.
+    pub fn add_bcc_list(&mut self, list: &str) -> SendgridResult<()> {
+        for address in address::parse_list(list)? {
+            push_recipient(
+                &mut self.bcc,
+                &mut self.bcc_names,
+                address.email,
+                address.name,
+            );
+        }
+        Ok(())
+    }
+
     /// Add a name for the "to" field in the message. The number of to names
     /// must match the number of "to" addresses.
-    pub fn add_to_name<T: Into<String>>(&mut self, to_name: T) {
+    pub fn add_to_name<T: Into<Cow<'a, str>>>(&mut self, to_name: T) {
         self.to_names.push(to_name.into());
     }
 
+    /// Set the HTML content of the message by rendering Markdown, and use the
+    /// Markdown source itself as the text content.
+    #[cfg(feature = "markdown")]
+    pub fn add_markdown<T: Into<Cow<'a, str>>>(&mut self, markdown: T) {
+        let markdown = markdown.into();
+        let mut html = String::with_capacity(markdown.len() * 3 / 2);
+        pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&markdown));
+
+        self.html = Cow::Owned(html);
+        self.text = markdown;
+    }
+
+    /// Set the HTML content of the message by rendering a template that has been
+    /// registered with a Handlebars registry.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut registry = Handlebars::new();
+    /// registry.register_template_string("welcome", "<p>Hello {{name}}!</p>")?;
+    ///
+    /// let mut message = Mail::new();
+    /// message.render_html_template(&registry, "welcome", &json!({"name": "Ferris"}))?;
+    /// ```
+    #[cfg(feature = "templates-local")]
+    pub fn render_html_template<T: Serialize>(
+        &mut self,
+        registry: &Handlebars,
+        name: &str,
+        data: &T,
+    ) -> SendgridResult<()> {
+        self.html = Cow::Owned(registry.render(name, data)?);
+        Ok(())
+    }
+
+    /// Set the text content of the message by rendering a template that has been
+    /// registered with a Handlebars registry. The registry's escape function still
+    /// applies, so a registry used for text templates usually wants `no_escape`.
+    #[cfg(feature = "templates-local")]
+    pub fn render_text_template<T: Serialize>(
+        &mut self,
+        registry: &Handlebars,
+        name: &str,
+        data: &T,
+    ) -> SendgridResult<()> {
+        self.text = Cow::Owned(registry.render(name, data)?);
+        Ok(())
+    }
+
     /// Set the text content of the message.
-    pub fn add_text<T: Into<String>>(&mut self, text: T) {
+    pub fn add_text<T: Into<Cow<'a, str>>>(&mut self, text: T) {
         self.text = text.into()
     }
 
     /// Add a BCC address to the message.
-    pub fn add_bcc<T: Into<String>>(&mut self, bcc_addr: T) {
+    pub fn add_bcc<T: Into<Cow<'a, str>>>(&mut self, bcc_addr: T) {
         self.bcc.push(bcc_addr.into())
     }
 
-    /// Set the from name for the message.
-    pub fn add_from_name<T: Into<String>>(&mut self, from_name: T) {
-        self.from_name = from_name.into()
+    /// Set the from name for the message, keeping the existing from address.
+    #[deprecated(
+        since = "0.8.0",
+        note = "use `set_from` with `EmailAddress::with_name` instead"
+    )]
+    pub fn add_from_name<T: Into<Cow<'a, str>>>(&mut self, from_name: T) {
+        self.from.name = Some(from_name.into())
     }
 
-    /// Set the reply to address for the message.
-    pub fn add_reply_to<T: Into<String>>(&mut self, reply_to: T) {
+    /// Set the reply to address for the message. The V2 API only accepts a bare address
+    /// so the display name is not sent.
+    pub fn set_reply_to<T: Into<EmailAddress<'a>>>(&mut self, reply_to: T) {
         self.reply_to = reply_to.into()
     }
 
-    /// Set the date for the message. This must be a valid RFC 822 timestamp.
-    pub fn add_date(&mut self, date: String) {
-        self.date = date
+    /// Set the reply to address for the message.
+    #[deprecated(since = "0.8.0", note = "use `set_reply_to` instead")]
+    pub fn add_reply_to<T: Into<Cow<'a, str>>>(&mut self, reply_to: T) {
+        self.reply_to.email = reply_to.into()
+    }
+
+    /// Set the date for the message. This must be a valid RFC 822 timestamp. When no
+    /// date is set, the time the message is sent is used.
+    pub fn add_date<T: Into<Cow<'a, str>>>(&mut self, date: T) {
+        self.date = date.into()
+    }
+
+    /// Set the date for the message from a Unix timestamp.
+    pub fn add_date_timestamp(&mut self, timestamp: u64) {
+        self.date = Cow::Owned(headers::format_date(timestamp));
     }
 
     /// Add an attachment for the message. You can pass the name of a file as a
-    /// path on the file system.
+    /// path on the file system. The file is read as raw bytes, so binary files such as
+    /// PDFs and images are sent unchanged.
     ///
     /// # Examples
     ///
@@ -119,8 +474,8 @@ impl Mail {
     /// ```
     pub fn add_attachment<P: AsRef<Path>>(&mut self, path: P) -> SendgridResult<()> {
         let mut file = File::open(&path)?;
-        let mut data = String::new();
-        file.read_to_string(&mut data)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
 
         if let Some(name) = path.as_ref().to_str() {
             self.attachments.insert(String::from(name), data);
@@ -131,19 +486,224 @@ impl Mail {
         Ok(())
     }
 
+    /// Add an image or other file that is shown inline in the HTML content. The file is
+    /// attached under `name` and paired with `content_id`, which the HTML refers to as
+    /// `cid:<content_id>`, so the attachment and its content id can't get out of sync.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.add_html("<img src=\"cid:logo\">");
+    /// message.add_inline_attachment("logo.png", fs::read("logo.png")?, "logo");
+    /// ```
+    pub fn add_inline_attachment<C: Into<Cow<'a, str>>>(
+        &mut self,
+        name: &str,
+        data: Vec<u8>,
+        content_id: C,
+    ) {
+        self.attachments.insert(String::from(name), data);
+        self.content
+            .insert(Cow::Owned(String::from(name)), content_id.into());
+    }
+
     /// Add content for inline images in the message.
-    pub fn add_content(&mut self, id: &str, value: &str) {
-        self.content.insert(id.to_string(), value.to_string());
+    pub fn add_content<I, V>(&mut self, id: I, value: V)
+    where
+        I: Into<Cow<'a, str>>,
+        V: Into<Cow<'a, str>>,
+    {
+        self.content.insert(id.into(), value.into());
     }
 
     /// Add a custom header for the message. These are usually prefixed with
-    /// 'X' or 'x' per the RFC specifications.
-    pub fn add_header(&mut self, header: &str, value: &str) {
-        self.headers.insert(header.to_string(), value.to_string());
+    /// 'X' or 'x' per the RFC specifications. The name must be printable ASCII
+    /// without a colon and the value can not contain line breaks, which prevents
+    /// header injection. Values that are not ASCII are encoded per RFC 2047.
+    pub fn add_header<H, V>(&mut self, header: H, value: V) -> SendgridResult<()>
+    where
+        H: Into<Cow<'a, str>>,
+        V: Into<Cow<'a, str>>,
+    {
+        let header = header.into();
+        headers::validate_name(&header)?;
+        let value = headers::encode_value(value.into())?;
+
+        self.headers.insert(header, value);
+        Ok(())
+    }
+
+    /// Set the `List-Unsubscribe` header from an unsubscribe address and/or an HTTPS
+    /// URL. When a URL is given, `List-Unsubscribe-Post: List-Unsubscribe=One-Click` is
+    /// also set so mailbox providers can offer one-click unsubscribes (RFC 8058), which
+    /// Gmail and Yahoo require from bulk senders.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.set_list_unsubscribe(
+    ///     Some("unsubscribe@example.com"),
+    ///     Some("https://example.com/unsubscribe?id=123"),
+    /// )?;
+    /// ```
+    pub fn set_list_unsubscribe(
+        &mut self,
+        mailto: Option<&str>,
+        url: Option<&str>,
+    ) -> SendgridResult<()> {
+        let mut targets = Vec::new();
+        if let Some(mailto) = mailto {
+            if mailto.starts_with("mailto:") {
+                targets.push(format!("<{}>", mailto));
+            } else {
+                targets.push(format!("<mailto:{}>", mailto));
+            }
+        }
+        if let Some(url) = url {
+            targets.push(format!("<{}>", url));
+        }
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        self.add_header("List-Unsubscribe", targets.join(", "))?;
+        if url.is_some() {
+            self.add_header("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")?;
+        }
+        Ok(())
+    }
+
+    /// Strip control characters from the subject, display names and header values,
+    /// replacing line breaks and tabs with spaces. This is useful when these fields
+    /// come from untrusted user input. The bodies are left alone.
+    ///
+    /// Returns a description of every field that was changed, e.g. `"subject"` or
+    /// `"header X-Campaign"`, which is empty when nothing needed to be changed.
+    pub fn sanitize(&mut self) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        sanitize_field(&mut self.subject, "subject".to_string(), &mut changes);
+        if let Some(ref mut name) = self.from.name {
+            sanitize_field(name, "from name".to_string(), &mut changes);
+        }
+        if let Some(ref mut name) = self.reply_to.name {
+            sanitize_field(name, "reply-to name".to_string(), &mut changes);
+        }
+        for (kind, addrs, names) in [
+            ("to", &self.to, &mut self.to_names),
+            ("cc", &self.cc, &mut self.cc_names),
+            ("bcc", &self.bcc, &mut self.bcc_names),
+        ] {
+            for (addr, name) in addrs.iter().zip(names.iter_mut()) {
+                sanitize_field(name, format!("{} name of {}", kind, addr), &mut changes);
+            }
+        }
+
+        for (name, value) in &mut self.headers {
+            sanitize_field(value, format!("header {}", name), &mut changes);
+        }
+
+        changes
+    }
+
+    /// Set the priority of the message. Different mail clients look at different
+    /// headers, so this sets `X-Priority`, `Importance` and `X-MSMail-Priority` to
+    /// matching values.
+    pub fn set_priority(&mut self, priority: Priority) {
+        let (x_priority, importance, ms_priority) = priority.header_values();
+        self.headers
+            .insert(Cow::Borrowed("X-Priority"), Cow::Borrowed(x_priority));
+        self.headers
+            .insert(Cow::Borrowed("Importance"), Cow::Borrowed(importance));
+        self.headers.insert(
+            Cow::Borrowed("X-MSMail-Priority"),
+            Cow::Borrowed(ms_priority),
+        );
+    }
+
+    /// Set the `In-Reply-To` header so the message is threaded under an earlier
+    /// message in the recipients' mail clients. The id is also added to the
+    /// `References` header, as RFC 5322 asks for. Angle brackets are added to the id
+    /// when they are missing.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// message.set_in_reply_to("<order-123@example.com>")?;
+    /// ```
+    pub fn set_in_reply_to(&mut self, message_id: &str) -> SendgridResult<()> {
+        let message_id = format_message_id(message_id);
+        self.add_header("In-Reply-To", message_id.clone())?;
+        self.add_reference(&message_id)
+    }
+
+    /// Add an earlier message of the thread to the `References` header. References
+    /// are kept in the order they were added, which should be oldest first.
+    pub fn add_reference(&mut self, message_id: &str) -> SendgridResult<()> {
+        let message_id = format_message_id(message_id);
+        let references = match self.headers.get("References") {
+            Some(references) if references.split(' ').any(|id| id == message_id) => {
+                return Ok(());
+            }
+            Some(references) => format!("{} {}", references, message_id),
+            None => message_id,
+        };
+        self.add_header("References", references)
+    }
+
+    /// Make sure the message has a `Message-ID` header and return it. A new id at
+    /// `domain` is generated unless the header was already set, so calling this again
+    /// before retrying a send keeps the same id. The id can be stored to match replies,
+    /// bounces and webhook events to the message.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut message = Mail::new();
+    /// let message_id = message.generate_message_id("mail.example.com")?;
+    /// client.send(&message)?;
+    /// db.save_sent_message(user.id, &message_id)?;
+    /// ```
+    pub fn generate_message_id(&mut self, domain: &str) -> SendgridResult<String> {
+        if let Some(message_id) = self.headers.get("Message-ID") {
+            return Ok(message_id.to_string());
+        }
+
+        let message_id = headers::message_id(domain)?;
+        self.add_header("Message-ID", message_id.clone())?;
+        Ok(message_id)
+    }
+
+    /// Used internally to get the text content, which may be derived from the HTML
+    /// content. Not needed for message building.
+    pub fn make_text(&self) -> Cow<'_, str> {
+        let text = if self.auto_text && self.text.is_empty() && !self.html.is_empty() {
+            Cow::Owned(text::html_to_text(&preheader::remove(&self.html)))
+        } else {
+            Cow::Borrowed(&self.text[..])
+        };
+        if self.normalize_text {
+            Cow::Owned(text::normalize_text(&text))
+        } else {
+            text
+        }
+    }
+
+    /// Used internally to get the date, which is the current time when no date is set.
+    /// Not needed for message building.
+    pub fn make_date(&self) -> Cow<'_, str> {
+        if self.auto_date && self.date.is_empty() {
+            Cow::Owned(headers::now())
+        } else {
+            Cow::Borrowed(&self.date)
+        }
     }
 
     /// Used internally for string encoding. Not needed for message building.
-    pub fn make_header_string(&mut self) -> SendgridResult<String> {
+    pub fn make_header_string(&self) -> SendgridResult<String> {
         let string = serde_json::to_string(&self.headers)?;
         Ok(string)
     }
@@ -151,7 +711,52 @@ impl Mail {
     /// Add an X-SMTPAPI string to the message. This can be done by using the
     /// 'rustc_serialize' crate and JSON encoding a map or custom struct. Or
     /// a regular String type can be escaped and used.
-    pub fn add_x_smtpapi(&mut self, x_smtpapi: String) {
-        self.x_smtpapi = x_smtpapi
+    pub fn add_x_smtpapi<T: Into<Cow<'a, str>>>(&mut self, x_smtpapi: T) {
+        self.x_smtpapi = x_smtpapi.into()
+    }
+
+    /// Add a UNIX timestamp to the SMTPAPI `send_each_at` list. Each timestamp is paired
+    /// with the "to" recipient at the same position, so there must be exactly one per
+    /// recipient by the time the message is sent.
+    pub fn add_send_each_at(&mut self, timestamp: u64) {
+        self.send_each_at.push(timestamp)
+    }
+
+    /// Used internally to merge `send_each_at` into the X-SMTPAPI string. Not needed for
+    /// message building.
+    pub fn make_x_smtpapi_string(&self) -> SendgridResult<Cow<'_, str>> {
+        if self.send_each_at.is_empty() {
+            return Ok(Cow::Borrowed(&self.x_smtpapi));
+        }
+
+        let mut smtpapi: Map<String, Value> = if self.x_smtpapi.is_empty() {
+            Map::new()
+        } else {
+            serde_json::from_str(&self.x_smtpapi)?
+        };
+
+        // SendGrid pairs the timestamps with the "to" array of the SMTPAPI header, which
+        // is the recipient list unless the header already has one. The addresses are
+        // encoded like the ones of `to[]`, so both lists agree.
+        let recipients = match smtpapi.get("to") {
+            Some(to) => to.as_array().map_or(0, Vec::len),
+            None => self.to.len(),
+        };
+        if self.send_each_at.len() != recipients {
+            return Err(
+                SendgridErrorKind::InvalidSendEachAt(self.send_each_at.len(), recipients).into(),
+            );
+        }
+        if !smtpapi.contains_key("to") {
+            let to: Vec<_> = self.to.iter().map(|to| address::encode(to)).collect();
+            smtpapi.insert("to".to_string(), serde_json::to_value(&to)?);
+        }
+        smtpapi.insert(
+            "send_each_at".to_string(),
+            serde_json::to_value(&self.send_each_at)?,
+        );
+
+        let string = serde_json::to_string(&smtpapi)?;
+        Ok(Cow::Owned(string))
     }
 }
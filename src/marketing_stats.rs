@@ -0,0 +1,273 @@
+//! The Marketing Campaigns statistics endpoints, for Single Sends and automations.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use throttle::PageThrottle;
+use v3::V3Sender;
+
+use serde::de::DeserializeOwned;
+use url::form_urlencoded::Serializer;
+
+/// Reading the statistics of Single Sends and automations.
+pub struct MarketingStats;
+
+impl Endpoint for MarketingStats {
+    const SCOPES: &'static [&'static str] = &["marketing.read"];
+}
+
+// The number of results requested per page.
+const PAGE_SIZE: usize = 50;
+
+/// The date range and aggregation of a statistics request for one Single Send or
+/// automation. Dates are formatted as `YYYY-MM-DD`, and everything is optional.
+#[derive(Clone, Debug, Default)]
+pub struct MarketingStatsQuery {
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    /// Either `total`, `day`, `week` or `month`. Defaults to `total`.
+    pub aggregated_by: Option<String>,
+    /// An IANA time zone like `America/Chicago` that the dates are in.
+    pub timezone: Option<String>,
+}
+
+impl MarketingStatsQuery {
+    fn to_query_string(&self, group_by: &str) -> String {
+        let mut query = Serializer::new(String::new());
+        let params = [
+            ("start_date", &self.start_date),
+            ("end_date", &self.end_date),
+            ("aggregated_by", &self.aggregated_by),
+            ("timezone", &self.timezone),
+        ];
+        for &(key, value) in &params {
+            if let Some(ref value) = *value {
+                query.append_pair(key, value);
+            }
+        }
+        query.append_pair("group_by", group_by);
+        query.finish()
+    }
+}
+
+/// The statistics of a Single Send, or of one A/B test variant of it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SingleSendStats {
+    pub id: String,
+    /// Either `test`, `send` or `all`.
+    pub ab_phase: String,
+    /// The ID of the A/B test variant, or `all`.
+    pub ab_variation: String,
+    /// The first day of the period, or `total`.
+    pub aggregation: String,
+    pub stats: MarketingMetrics,
+    /// Other fields of the stats row, such as ones for new breakdowns.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The statistics of an automation, or of one step of it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AutomationStats {
+    pub id: String,
+    /// The ID of the step, or `all`.
+    pub step_id: String,
+    /// The first day of the period, or `total`.
+    pub aggregation: String,
+    pub stats: MarketingMetrics,
+    /// Other fields of the stats row, such as the step name.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The number of events of every type.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct MarketingMetrics {
+    pub bounce_drops: u64,
+    pub bounces: u64,
+    pub clicks: u64,
+    pub delivered: u64,
+    pub invalid_emails: u64,
+    pub opens: u64,
+    pub requests: u64,
+    pub spam_report_drops: u64,
+    pub spam_reports: u64,
+    pub unique_clicks: u64,
+    pub unique_opens: u64,
+    pub unsubscribes: u64,
+    /// Metrics SendGrid added to Marketing Campaigns that don't have a field here yet.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl MarketingMetrics {
+    /// The share of delivered messages that were opened at least once.
+    pub fn open_rate(&self) -> f64 {
+        rate(self.unique_opens, self.delivered)
+    }
+
+    /// The share of delivered messages with at least one click.
+    pub fn click_rate(&self) -> f64 {
+        rate(self.unique_clicks, self.delivered)
+    }
+}
+
+fn rate(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    results: Vec<T>,
+    #[serde(rename = "_metadata", default)]
+    metadata: Metadata,
+}
+
+#[derive(Default, Deserialize)]
+struct Metadata {
+    next: Option<String>,
+}
+
+impl V3Sender {
+    /// Get the total statistics of the given Single Sends, or of all of them when no
+    /// IDs are given.
+    pub fn single_send_stats(&self, ids: &[&str]) -> SendgridResult<Vec<SingleSendStats>> {
+        self.marketing_stats("/marketing/stats/singlesends", "singlesend_ids", ids)
+    }
+
+    /// Get the statistics of a Single Send by A/B test variant and phase, e.g. to pull
+    /// the results of an A/B test into a dashboard.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for variant in sender.single_send_variant_stats(id, &MarketingStatsQuery::default())? {
+    ///     println!("{}: {:.1}%", variant.ab_variation, variant.stats.open_rate() * 100.0);
+    /// }
+    /// ```
+    pub fn single_send_variant_stats(
+        &self,
+        id: &str,
+        query: &MarketingStatsQuery,
+    ) -> SendgridResult<Vec<SingleSendStats>> {
+        let page: Page<SingleSendStats> = self.api_get(&format!(
+            "/marketing/stats/singlesends/{}?{}",
+            id,
+            query.to_query_string("ab_variation,ab_phase")
+        ))?;
+        Ok(page.results)
+    }
+
+    /// Get the total statistics of the given automations, or of all of them when no IDs
+    /// are given.
+    pub fn automation_stats(&self, ids: &[&str]) -> SendgridResult<Vec<AutomationStats>> {
+        self.marketing_stats("/marketing/stats/automations", "automation_ids", ids)
+    }
+
+    /// Get the statistics of every step of an automation, or only of the given steps.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for step in sender.automation_step_stats(id, &[], &MarketingStatsQuery::default())? {
+    ///     println!("{}: {} delivered", step.step_id, step.stats.delivered);
+    /// }
+    /// ```
+    pub fn automation_step_stats(
+        &self,
+        id: &str,
+        step_ids: &[&str],
+        query: &MarketingStatsQuery,
+    ) -> SendgridResult<Vec<AutomationStats>> {
+        let mut query = query.to_query_string("step_id");
+        if !step_ids.is_empty() {
+            let mut steps = Serializer::for_suffix(query, 0);
+            steps.append_pair("step_ids", &step_ids.join(","));
+            query = steps.finish();
+        }
+        let page: Page<AutomationStats> =
+            self.api_get(&format!("/marketing/stats/automations/{}?{}", id, query))?;
+        Ok(page.results)
+    }
+
+    // Get every page of the statistics of a list of Single Sends or automations.
+    fn marketing_stats<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        ids_param: &str,
+        ids: &[&str],
+    ) -> SendgridResult<Vec<T>> {
+        let mut query = Serializer::new(String::new());
+        query.append_pair("page_size", &PAGE_SIZE.to_string());
+        if !ids.is_empty() {
+            query.append_pair(ids_param, &ids.join(","));
+        }
+
+        let mut throttle = PageThrottle::new();
+        let mut next = format!("{}?{}", path, query.finish());
+        let mut results = Vec::new();
+        loop {
+            let page: Page<T> = self.get_page(&next, &mut throttle)?;
+            results.extend(page.results);
+            match page
+                .metadata
+                .next
+                .as_ref()
+                .and_then(|url| relative_path(url))
+            {
+                Some(path) if !path.is_empty() => next = path.to_string(),
+                _ => return Ok(results),
+            }
+        }
+    }
+}
+
+// The next page links are full URLs, while requests are made with a path below the base
+// URL of the V3 API.
+pub(crate) fn relative_path(url: &str) -> Option<&str> {
+    url.find("/v3/").map(|i| &url[i + 3..])
+}
+
+#[test]
+fn single_send_stats() {
+    let page: Page<SingleSendStats> = ::serde_json::from_str(
+        r#"{"results":[{"id":"abc","ab_phase":"send","ab_variation":"v1","aggregation":"total","stats":{"delivered":200,"unique_opens":50,"unique_clicks":10}}],"_metadata":{"next":"https://api.sendgrid.com/v3/marketing/stats/singlesends?page_token=xyz"}}"#,
+    )
+    .unwrap();
+
+    let stats = &page.results[0].stats;
+    assert_eq!(stats.open_rate(), 0.25);
+    assert_eq!(stats.click_rate(), 0.05);
+    assert_eq!(
+        relative_path(page.metadata.next.as_ref().unwrap()),
+        Some("/marketing/stats/singlesends?page_token=xyz")
+    );
+}
+
+#[test]
+fn automation_step_query() {
+    let query = MarketingStatsQuery {
+        start_date: Some(String::from("2021-01-01")),
+        ..MarketingStatsQuery::default()
+    };
+    assert_eq!(
+        query.to_query_string("step_id"),
+        "start_date=2021-01-01&group_by=step_id"
+    );
+
+    let page: Page<AutomationStats> = ::serde_json::from_str(
+        r#"{"results":[{"id":"abc","step_id":"s1","aggregation":"total","stats":{"delivered":3}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(page.results[0].step_id, "s1");
+    assert!(page.metadata.next.is_none());
+}
@@ -0,0 +1,199 @@
+//! Metrics about sends in the Prometheus text exposition format, built from the events of
+//! senders. This doesn't use the prometheus crate, so the metrics aren't registered with
+//! a `prometheus::Registry`; serve `Metrics::render` from the scrape endpoint instead, or
+//! next to the output of the registry.
+
+use events::ClientEvent;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The upper bounds of the send duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counts what senders did, for a Prometheus scrape endpoint to serve. Clones share the
+/// same counts.
+///
+/// # Examples
+///
+/// ```ignore
+/// let metrics = Metrics::new();
+/// metrics.watch(sender.subscribe());
+/// // In the handler of GET /metrics:
+/// response.body(metrics.render());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    counts: Arc<Mutex<Counts>>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    sends: BTreeMap<u16, u64>,
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_sum: f64,
+    duration_count: u64,
+    retries: u64,
+    rate_limited: u64,
+    failovers: u64,
+    queue_depth: usize,
+}
+
+impl Metrics {
+    /// Construct metrics with every count at zero.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Count the events from a subscription, such as the one returned by
+    /// `V3Sender::subscribe`, on a new thread. The thread stops when the sender is
+    /// dropped.
+    pub fn watch(&self, events: Receiver<ClientEvent>) {
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for event in events {
+                metrics.record(&event);
+            }
+        });
+    }
+
+    /// Count a single event.
+    pub fn record(&self, event: &ClientEvent) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        match *event {
+            ClientEvent::Sent { status, elapsed } => {
+                *counts.sends.entry(status).or_default() += 1;
+                let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+                for (bucket, &bound) in DURATION_BUCKETS.iter().enumerate() {
+                    if seconds <= bound {
+                        counts.duration_buckets[bucket] += 1;
+                    }
+                }
+                counts.duration_sum += seconds;
+                counts.duration_count += 1;
+            }
+            ClientEvent::Retried { .. } => counts.retries += 1,
+            ClientEvent::RateLimited { .. } => counts.rate_limited += 1,
+            ClientEvent::FailedOver { .. } => counts.failovers += 1,
+            ClientEvent::QueueDepthChanged { depth } => counts.queue_depth = depth,
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "sendgrid_sends_total",
+            "counter",
+            "Messages that got a response from the mail send endpoint, by status.",
+        );
+        for (status, count) in &counts.sends {
+            let _ = writeln!(
+                out,
+                "sendgrid_sends_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        header(
+            &mut out,
+            "sendgrid_send_duration_seconds",
+            "histogram",
+            "How long requests to the mail send endpoint took.",
+        );
+        for (bucket, bound) in DURATION_BUCKETS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "sendgrid_send_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, counts.duration_buckets[bucket]
+            );
+        }
+        let _ = writeln!(
+            out,
+            "sendgrid_send_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            counts.duration_count
+        );
+        let _ = writeln!(
+            out,
+            "sendgrid_send_duration_seconds_sum {}",
+            counts.duration_sum
+        );
+        let _ = writeln!(
+            out,
+            "sendgrid_send_duration_seconds_count {}",
+            counts.duration_count
+        );
+
+        let simple = [
+            (
+                "sendgrid_retries_total",
+                "counter",
+                "Failed messages of bulk sends that were sent again.",
+                counts.retries as f64,
+            ),
+            (
+                "sendgrid_rate_limited_total",
+                "counter",
+                "Requests that got a 429 response.",
+                counts.rate_limited as f64,
+            ),
+            (
+                "sendgrid_failovers_total",
+                "counter",
+                "Moves to a fallback host.",
+                counts.failovers as f64,
+            ),
+            (
+                "sendgrid_queue_depth",
+                "gauge",
+                "Messages waiting in the background sender.",
+                counts.queue_depth as f64,
+            ),
+        ];
+        for &(name, kind, help, value) in &simple {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[test]
+fn render_metrics() {
+    use std::time::Duration;
+
+    let metrics = Metrics::new();
+    metrics.record(&ClientEvent::Sent {
+        status: 202,
+        elapsed: Duration::from_millis(80),
+    });
+    metrics.record(&ClientEvent::Sent {
+        status: 429,
+        elapsed: Duration::from_millis(20),
+    });
+    metrics.record(&ClientEvent::RateLimited {
+        path: String::from("/mail/send"),
+    });
+    metrics.record(&ClientEvent::QueueDepthChanged { depth: 7 });
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("sendgrid_sends_total{status=\"202\"} 1\n"));
+    assert!(rendered.contains("sendgrid_sends_total{status=\"429\"} 1\n"));
+    assert!(rendered.contains("sendgrid_send_duration_seconds_bucket{le=\"0.05\"} 1\n"));
+    assert!(rendered.contains("sendgrid_send_duration_seconds_bucket{le=\"0.1\"} 2\n"));
+    assert!(rendered.contains("sendgrid_send_duration_seconds_count 2\n"));
+    assert!(rendered.contains("sendgrid_rate_limited_total 1\n"));
+    assert!(rendered.contains("# TYPE sendgrid_queue_depth gauge\nsendgrid_queue_depth 7\n"));
+}
@@ -0,0 +1,333 @@
+//! Converting a V2 `Mail` into a V3 message, for moving existing code off the deprecated
+//! V2 endpoint without rewriting how its messages are built.
+
+use address;
+use mail::{EmailAddress, Mail};
+use v3::{Attachment, Content, Email, Personalization, SGMailV3};
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::path::Path;
+
+use serde_json::{self, Map, Value};
+
+/// A V3 message converted from a V2 `Mail`, with the parts of the `Mail` that have no V3
+/// equivalent and were left out.
+pub struct Migration {
+    /// The converted message.
+    pub message: SGMailV3,
+    /// A description of every part that was left out, such as an SMTPAPI filter.
+    pub unsupported: Vec<String>,
+}
+
+impl SGMailV3 {
+    /// Convert a V2 `Mail` into a V3 message. The addresses with their names, subject,
+    /// text and HTML content, attachments, inline content ids, custom headers and the
+    /// SMTPAPI categories, unique arguments, substitutions, unsubscribe group and send
+    /// times are mapped. Everything else is listed in `Migration::unsupported`, so it can
+    /// be checked before switching endpoints.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let migration = SGMailV3::from_v2(&mail);
+    /// for part in &migration.unsupported {
+    ///     warn!("not sent over V3: {}", part);
+    /// }
+    /// sender.send(&migration.message)?;
+    /// ```
+    pub fn from_v2(mail: &Mail) -> Migration {
+        let mut message = SGMailV3::new();
+        let mut unsupported = Vec::new();
+
+        message.set_from(email(&mail.from));
+        if !mail.reply_to.email.is_empty() {
+            message.set_reply_to(email(&mail.reply_to));
+        }
+        message.set_subject(&mail.subject);
+
+        // SendGrid requires the text part to come before the HTML part.
+        for &(content_type, ref value) in &[
+            ("text/plain", mail.make_text()),
+            ("text/html", Cow::Borrowed(&mail.html[..])),
+        ] {
+            if !value.is_empty() {
+                let mut c = Content::new();
+                c.set_content_type(content_type);
+                c.set_value(value);
+                message.add_content(c);
+            }
+        }
+
+        for (name, data) in &mail.attachments {
+            let mut a = Attachment::new();
+            a.set_content(data);
+            // V2 attachments are keyed by the path they were read from.
+            let filename = Path::new(name)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or(name);
+            a.set_filename(filename);
+            if let Some(content_id) = mail.content.get(&name[..]) {
+                a.set_disposition("inline");
+                a.set_content_id(content_id);
+            }
+            message.add_attachment(a);
+        }
+        for name in mail.content.keys() {
+            if !mail.attachments.contains_key(&name[..]) {
+                unsupported.push(format!("content id for {:?}, which is not attached", name));
+            }
+        }
+
+        for (name, value) in &mail.headers {
+            if message.add_header(name, value).is_err() {
+                unsupported.push(format!("the {} header", name));
+            }
+        }
+        if !mail.date.is_empty() {
+            unsupported.push(String::from(
+                "the date, which SendGrid sets for V3 messages",
+            ));
+        }
+
+        let smtpapi = if mail.x_smtpapi.is_empty() {
+            Map::new()
+        } else {
+            match serde_json::from_str(&mail.x_smtpapi) {
+                Ok(smtpapi) => smtpapi,
+                Err(_) => {
+                    unsupported.push(String::from(
+                        "the X-SMTPAPI header, which is not a JSON object",
+                    ));
+                    Map::new()
+                }
+            }
+        };
+        let personalizations = personalizations(mail, &smtpapi, &mut unsupported);
+        for p in personalizations {
+            message.add_personalization(p);
+        }
+        map_smtpapi(&mut message, smtpapi, &mut unsupported);
+
+        Migration {
+            message,
+            unsupported,
+        }
+    }
+}
+
+impl<'a> From<Mail<'a>> for SGMailV3 {
+    /// Convert a V2 `Mail` into a V3 message, leaving out the parts V3 has no equivalent
+    /// for. Use `SGMailV3::from_v2` to find out which parts those are.
+    fn from(mail: Mail<'a>) -> SGMailV3 {
+        SGMailV3::from_v2(&mail).message
+    }
+}
+
+fn email(address: &EmailAddress) -> Email {
+    let mut e = Email::new();
+    e.set_email(&address.email);
+    if let Some(ref name) = address.name {
+        e.set_name(name);
+    }
+    e
+}
+
+// A recipient of the SMTPAPI "to" list, which can have a display name like
+// `Jane <jane@example.com>`. The names of `Mail::to` belong to that list, not this one.
+fn smtpapi_recipient(addr: &str) -> Email {
+    match address::parse_list(addr) {
+        Ok(ref addresses) if addresses.len() == 1 => email(&addresses[0]),
+        _ => named(addr, &[], 0),
+    }
+}
+
+fn named(addr: &str, names: &[Cow<str>], i: usize) -> Email {
+    let mut e = Email::new();
+    e.set_email(addr);
+    if let Some(name) = names.get(i).filter(|n| !n.is_empty()) {
+        e.set_name(name);
+    }
+    e
+}
+
+// All the recipients share one personalization, like the V2 To header, unless SMTPAPI
+// sends every recipient their own message; then each gets a personalization with their
+// substitutions and send time, and the CC and BCC recipients go on the first one.
+fn personalizations(
+    mail: &Mail,
+    smtpapi: &Map<String, Value>,
+    unsupported: &mut Vec<String>,
+) -> Vec<Personalization> {
+    let smtpapi_to: Option<Vec<String>> = smtpapi
+        .get("to")
+        .and_then(|to| serde_json::from_value(to.clone()).ok());
+    let individual = smtpapi_to.is_some() || !mail.send_each_at.is_empty();
+
+    let mut ps = Vec::new();
+    if individual {
+        let to: Vec<Email> = match smtpapi_to {
+            Some(to) => to.iter().map(|addr| smtpapi_recipient(addr)).collect(),
+            None => mail
+                .to
+                .iter()
+                .enumerate()
+                .map(|(i, addr)| named(addr, &mail.to_names, i))
+                .collect(),
+        };
+        let subs = smtpapi.get("sub").and_then(Value::as_object);
+        let send_each_at = smtpapi
+            .get("send_each_at")
+            .and_then(|v| serde_json::from_value::<Vec<u64>>(v.clone()).ok())
+            .unwrap_or_else(|| mail.send_each_at.clone());
+        if !send_each_at.is_empty() && send_each_at.len() != to.len() {
+            unsupported.push(format!(
+                "send_each_at, which has {} times for {} recipients",
+                send_each_at.len(),
+                to.len()
+            ));
+        }
+
+        let recipients = to.len();
+        for (i, to) in to.into_iter().enumerate() {
+            let mut p = Personalization::new();
+            p.add_to(to);
+            for (tag, values) in subs.into_iter().flatten() {
+                if let Some(value) = values.get(i).and_then(Value::as_str) {
+                    p.add_substitution(tag, value);
+                }
+            }
+            if send_each_at.len() == recipients {
+                p.set_send_at(send_each_at[i]);
+            }
+            ps.push(p);
+        }
+    } else {
+        let mut p = Personalization::new();
+        for (i, addr) in mail.to.iter().enumerate() {
+            p.add_to(named(addr, &mail.to_names, i));
+        }
+        ps.push(p);
+    }
+
+    if let Some(first) = ps.first_mut() {
+        for (i, addr) in mail.cc.iter().enumerate() {
+            first.add_cc(named(addr, &mail.cc_names, i));
+        }
+        for (i, addr) in mail.bcc.iter().enumerate() {
+            first.add_bcc(named(addr, &mail.bcc_names, i));
+        }
+    }
+    ps
+}
+
+fn map_smtpapi(message: &mut SGMailV3, smtpapi: Map<String, Value>, unsupported: &mut Vec<String>) {
+    let groups_to_display: Vec<u32> = smtpapi
+        .get("asm_groups_to_display")
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default();
+
+    for (key, value) in smtpapi {
+        match (&key[..], value) {
+            // Mapped to the personalizations.
+            ("to", _) | ("sub", _) | ("send_each_at", _) | ("asm_groups_to_display", _) => {}
+            ("category", Value::String(category)) => message.add_category(&category),
+            ("category", Value::Array(categories)) => {
+                for category in categories.iter().filter_map(Value::as_str) {
+                    message.add_category(category);
+                }
+            }
+            ("unique_args", Value::Object(args)) => {
+                for (arg, value) in args {
+                    match value {
+                        Value::String(s) => message.add_custom_arg(&arg, s),
+                        other => message.add_custom_arg(&arg, other),
+                    }
+                }
+            }
+            ("send_at", Value::Number(ref n)) if n.is_u64() => {
+                message.set_send_at(n.as_u64().unwrap_or_default())
+            }
+            ("asm_group_id", Value::Number(ref n)) if n.is_u64() => {
+                match n.as_u64().map(u32::try_from) {
+                    Some(Ok(group_id)) => {
+                        message.set_unsubscribe_group(group_id, &groups_to_display)
+                    }
+                    _ => unsupported.push(format!(
+                        "the SMTPAPI asm_group_id {}, which is too large",
+                        n
+                    )),
+                }
+            }
+            (key, _) => unsupported.push(format!("the SMTPAPI {} field", key)),
+        }
+    }
+}
+
+#[test]
+fn migrate_v2_mail() {
+    let mut m = Mail::new().with_auto_text();
+    m.set_from(EmailAddress::with_name("me@example.com", "Me"));
+    m.add_subject("Hello -name-");
+    m.add_html("<p>Hi</p>");
+    m.add_to("a@example.com");
+    m.add_to_name("A");
+    m.add_to("b@example.com");
+    m.add_to_name("");
+    m.add_cc("c@example.com");
+    m.add_inline_attachment("/tmp/logo.png", vec![1, 2, 3], "logo");
+    m.add_header("X-Campaign", "spring").unwrap();
+    m.add_x_smtpapi(
+        r#"{"sub":{"-name-":["A","B"]},"category":["news"],"unique_args":{"user":7},"filters":{"clicktrack":{"settings":{"enable":1}}}}"#,
+    );
+    m.add_send_each_at(100);
+    m.add_send_each_at(200);
+
+    let migration = SGMailV3::from_v2(&m);
+    assert_eq!(migration.unsupported, vec!["the SMTPAPI filters field"]);
+
+    let json = serde_json::to_value(&migration.message).unwrap();
+    assert_eq!(json["from"]["name"], "Me");
+    assert_eq!(json["content"][0]["value"], "Hi");
+    assert_eq!(json["content"][1]["type"], "text/html");
+
+    let ps = json["personalizations"].as_array().unwrap();
+    assert_eq!(ps.len(), 2);
+    assert_eq!(ps[0]["to"][0]["name"], "A");
+    assert_eq!(ps[0]["cc"][0]["email"], "c@example.com");
+    assert!(ps[1]["to"][0].get("name").is_none());
+    assert_eq!(ps[1]["substitutions"]["-name-"], "B");
+    assert_eq!(ps[1]["send_at"], 200);
+    assert!(ps[1].get("cc").is_none());
+
+    assert_eq!(json["attachments"][0]["filename"], "logo.png");
+    assert_eq!(json["attachments"][0]["content_id"], "logo");
+    assert_eq!(json["headers"]["X-Campaign"], "spring");
+    assert_eq!(json["categories"][0], "news");
+    assert_eq!(json["custom_args"]["user"], "7");
+}
+
+#[test]
+fn migrate_smtpapi_recipients() {
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_to("list@example.com");
+    m.add_to_name("The List");
+    m.add_x_smtpapi(
+        r#"{"to":["Jane <jane@example.com>","bob@example.com"],"asm_group_id":4294967296}"#,
+    );
+
+    let migration = SGMailV3::from_v2(&m);
+    assert_eq!(
+        migration.unsupported,
+        vec!["the SMTPAPI asm_group_id 4294967296, which is too large"]
+    );
+    let json = serde_json::to_value(&migration.message).unwrap();
+    let ps = json["personalizations"].as_array().unwrap();
+    assert_eq!(ps[0]["to"][0]["email"], "jane@example.com");
+    assert_eq!(ps[0]["to"][0]["name"], "Jane");
+    assert_eq!(ps[1]["to"][0]["email"], "bob@example.com");
+    assert!(ps[1]["to"][0].get("name").is_none());
+    assert!(json.get("asm").is_none());
+}
@@ -0,0 +1,202 @@
+use errors::SendgridResult;
+use v3::{Attachment, Content, Email, Personalization, SGMailV3};
+
+use mailparse::{self, DispositionType, MailAddr, MailHeaderMap, ParsedMail};
+
+// Headers that are either mapped to dedicated fields, describe the MIME structure or are
+// added by the receiving side. SendGrid rejects most of these as custom headers.
+const SKIPPED_HEADERS: [&str; 16] = [
+    "bcc",
+    "cc",
+    "content-disposition",
+    "content-id",
+    "content-transfer-encoding",
+    "content-type",
+    "date",
+    "dkim-signature",
+    "from",
+    "mime-version",
+    "received",
+    "reply-to",
+    "return-path",
+    "subject",
+    "to",
+    "x-sg-id",
+];
+
+impl SGMailV3 {
+    /// Convert an existing RFC 5322 message into a V3 message. The addresses, subject,
+    /// text and HTML parts, attachments and custom headers are all mapped, which eases
+    /// migrating from pipelines that build messages for SMTP.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let raw = fs::read("message.eml")?;
+    /// let message = SGMailV3::from_eml(&raw)?;
+    /// sender.send(&message)?;
+    /// ```
+    pub fn from_eml(raw: &[u8]) -> SendgridResult<SGMailV3> {
+        let parsed = mailparse::parse_mail(raw)?;
+        let mut message = SGMailV3::new();
+
+        if let Some(from) = parse_addresses(&parsed, "From")?.into_iter().next() {
+            message.set_from(from);
+        }
+        if let Some(reply_to) = parse_addresses(&parsed, "Reply-To")?.into_iter().next() {
+            message.set_reply_to(reply_to);
+        }
+        if let Some(subject) = parsed.headers.get_first_value("Subject") {
+            message.set_subject(&subject);
+        }
+
+        let mut p = Personalization::new();
+        for to in parse_addresses(&parsed, "To")? {
+            p.add_to(to);
+        }
+        for cc in parse_addresses(&parsed, "Cc")? {
+            p.add_cc(cc);
+        }
+        for bcc in parse_addresses(&parsed, "Bcc")? {
+            p.add_bcc(bcc);
+        }
+        message.add_personalization(p);
+
+        for header in &parsed.headers {
+            let name = header.get_key();
+            if !SKIPPED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                message.add_header(&name, &header.get_value())?;
+            }
+        }
+
+        let mut parts = Parts::default();
+        collect_parts(&parsed, &mut parts)?;
+
+        // SendGrid requires the text part to come before the HTML part.
+        for (content_type, value) in [("text/plain", parts.text), ("text/html", parts.html)] {
+            if let Some(value) = value {
+                let mut c = Content::new();
+                c.set_content_type(content_type);
+                c.set_value(&value);
+                message.add_content(c);
+            }
+        }
+        for a in parts.attachments {
+            message.add_attachment(a);
+        }
+
+        Ok(message)
+    }
+}
+
+#[derive(Default)]
+struct Parts {
+    text: Option<String>,
+    html: Option<String>,
+    attachments: Vec<Attachment>,
+}
+
+// Walk the MIME tree, keeping the first text and HTML bodies and turning every other
+// leaf part into an attachment.
+fn collect_parts(part: &ParsedMail, parts: &mut Parts) -> SendgridResult<()> {
+    if !part.subparts.is_empty() {
+        for subpart in &part.subparts {
+            collect_parts(subpart, parts)?;
+        }
+        return Ok(());
+    }
+
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .cloned();
+    let mimetype = part.ctype.mimetype.to_ascii_lowercase();
+
+    if filename.is_none() && disposition.disposition != DispositionType::Attachment {
+        if mimetype == "text/plain" && parts.text.is_none() {
+            parts.text = Some(part.get_body()?);
+            return Ok(());
+        }
+        if mimetype == "text/html" && parts.html.is_none() {
+            parts.html = Some(part.get_body()?);
+            return Ok(());
+        }
+    }
+
+    let mut a = Attachment::new();
+    a.set_content(&part.get_body_raw()?);
+    a.set_filename(filename.as_ref().map_or("attachment", |f| f.as_str()));
+    a.set_mime_type(&mimetype);
+    if let Some(content_id) = part.headers.get_first_value("Content-ID") {
+        a.set_content_id(content_id.trim_matches(|c| c == '<' || c == '>'));
+        a.set_disposition("inline");
+    } else {
+        a.set_disposition("attachment");
+    }
+    parts.attachments.push(a);
+
+    Ok(())
+}
+
+// Parse every address in the given header, flattening groups.
+fn parse_addresses(parsed: &ParsedMail, header: &str) -> SendgridResult<Vec<Email>> {
+    let mut emails = Vec::new();
+
+    for value in parsed.headers.get_all_values(header) {
+        for addr in mailparse::addrparse(&value)?.iter() {
+            let singles = match *addr {
+                MailAddr::Single(ref info) => vec![info.clone()],
+                MailAddr::Group(ref group) => group.addrs.clone(),
+            };
+
+            for info in singles {
+                let mut email = Email::new();
+                email.set_email(&info.addr);
+                if let Some(ref name) = info.display_name {
+                    email.set_name(name);
+                }
+                emails.push(email);
+            }
+        }
+    }
+
+    Ok(emails)
+}
+
+#[test]
+fn import_eml() {
+    let raw = "From: Me <me@example.com>\r\n\
+               To: A <a@example.com>, b@example.com\r\n\
+               Subject: =?UTF-8?B?R3LDvMOfZQ==?=\r\n\
+               X-Cool: indeed\r\n\
+               MIME-Version: 1.0\r\n\
+               Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+               \r\n\
+               --b\r\n\
+               Content-Type: text/plain; charset=utf-8\r\n\
+               \r\n\
+               It works\r\n\
+               --b\r\n\
+               Content-Type: application/pdf\r\n\
+               Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+               Content-Transfer-Encoding: base64\r\n\
+               \r\n\
+               ZGF0YQ==\r\n\
+               --b--\r\n";
+
+    let message = SGMailV3::from_eml(raw.as_bytes()).unwrap();
+    let json = ::serde_json::to_string(&message).unwrap();
+
+    assert!(json.contains(r#""from":{"email":"me@example.com","name":"Me"}"#));
+    assert!(json.contains(r#""subject":"Grüße""#));
+    assert!(
+        json.contains(r#""to":[{"email":"a@example.com","name":"A"},{"email":"b@example.com"}]"#)
+    );
+    assert!(json.contains(r#""content":[{"type":"text/plain","value":"It works"#));
+    assert!(json.contains(
+        r#"{"content":"ZGF0YQ==","filename":"report.pdf","type":"application/pdf","disposition":"attachment"}"#
+    ));
+    assert!(json.contains(r#""headers":{"X-Cool":"indeed"}"#));
+}
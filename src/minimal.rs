@@ -0,0 +1,311 @@
+//! A small blocking client for the V3 mail send endpoint that only needs the standard
+//! library and native-tls, for command line tools and small binaries that don't want
+//! reqwest, hyper and tokio in their dependency tree.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use headers;
+use keys::{self, Key};
+use v3::SGMailV3;
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use native_tls::{HandshakeError, TlsConnector};
+
+/// The host of the V3 API.
+pub const API_HOST: &str = "api.sendgrid.com";
+
+// The largest response that is read, which is plenty for the small bodies the mail send
+// endpoint returns.
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+/// Sends V3 messages over a new HTTPS connection per message.
+///
+/// # Examples
+///
+/// ```ignore
+/// let sender = MinimalSender::new(api_key);
+/// let message_id = sender.send(&message)?;
+/// ```
+#[derive(Clone)]
+pub struct MinimalSender {
+    api_key: Key,
+    host: String,
+    port: u16,
+    timeout: Option<Duration>,
+}
+
+// The API key is left out, so it can't end up in logs.
+impl fmt::Debug for MinimalSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MinimalSender")
+            .field("api_key", &"[redacted]")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl MinimalSender {
+    /// Construct a sender that connects to the SendGrid API.
+    pub fn new(api_key: String) -> MinimalSender {
+        MinimalSender {
+            api_key: keys::to_key(api_key),
+            host: String::from(API_HOST),
+            port: 443,
+            timeout: None,
+        }
+    }
+
+    /// Connect to another host, e.g. a proxy in front of the API. It must also be
+    /// reachable over HTTPS.
+    pub fn set_host(&mut self, host: &str, port: u16) {
+        self.host = String::from(host);
+        self.port = port;
+    }
+
+    /// Set how long to wait for every read and write on the connection.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Send a message, returning the message ID SendGrid assigned to it. Responses with
+    /// a status other than 2xx are turned into an `Api` error.
+    pub fn send(&self, mail: &SGMailV3) -> SendgridResult<Option<String>> {
+        mail.check_attachments()?;
+        check_head(&self.host, keys::expose(&self.api_key))?;
+        let mut body = Vec::new();
+        mail.write_json(&mut body)?;
+
+        let stream = TcpStream::connect((&self.host[..], self.port))?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let connector = TlsConnector::builder()?.build()?;
+        let stream = match connector.connect(&self.host, stream) {
+            Ok(stream) => stream,
+            Err(HandshakeError::Failure(e)) => return Err(e.into()),
+            // The socket only stops blocking when a read or write timed out.
+            Err(HandshakeError::Interrupted(_)) => {
+                let timeout =
+                    io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out");
+                return Err(timeout.into());
+            }
+        };
+
+        post_mail(
+            stream,
+            &self.host,
+            self.port,
+            keys::expose(&self.api_key),
+            &body,
+        )
+    }
+}
+
+// Check the values that go into the request head for line breaks that would inject
+// other headers, before connecting.
+fn check_head(host: &str, api_key: &str) -> SendgridResult<()> {
+    headers::check_value(host)?;
+    headers::check_value(api_key)
+}
+
+// Post a message body on an established connection and read back the response.
+fn post_mail<S: Read + Write>(
+    mut stream: S,
+    host: &str,
+    port: u16,
+    api_key: &str,
+    body: &[u8],
+) -> SendgridResult<Option<String>> {
+    // The port is only left out of the Host header when it is the default one.
+    let host = if port == 443 {
+        String::from(host)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let head = format!(
+        "POST /v3/mail/send HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Authorization: Bearer {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         User-Agent: sendgrid-rs\r\n\
+         Connection: close\r\n\r\n",
+        host,
+        api_key,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream.take(MAX_RESPONSE_SIZE));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status: u16 = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(0);
+
+    let mut message_id = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("x-message-id") {
+                message_id = Some(String::from(value));
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    if (200..300).contains(&status) {
+        return Ok(message_id);
+    }
+
+    let body = if chunked {
+        read_chunked(&mut reader)?
+    } else {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        body
+    };
+    Err(SendgridErrorKind::Api(status, String::from_utf8_lossy(&body).into_owned()).into())
+}
+
+// Read a body sent with chunked transfer encoding.
+fn read_chunked<R: BufRead>(reader: &mut R) -> SendgridResult<Vec<u8>> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid chunk size {:?}", size),
+            )
+        })?;
+        if size == 0 {
+            break;
+        }
+        // The size comes from the server, so it is checked against the size limit before
+        // anything is allocated for it.
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end as u64 <= MAX_RESPONSE_SIZE)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("chunk of {} bytes is too large", size),
+                )
+            })?;
+        body.resize(end, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The line break after the chunk.
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+    Ok(body)
+}
+
+#[test]
+fn minimal_post() {
+    use std::io::Cursor;
+
+    struct Exchange {
+        response: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Exchange {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for Exchange {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut exchange = Exchange {
+        response: Cursor::new(
+            b"HTTP/1.1 202 Accepted\r\nX-Message-Id: abc123\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ),
+        sent: Vec::new(),
+    };
+    let message_id = post_mail(&mut exchange, API_HOST, 443, "key", b"{}").unwrap();
+    assert_eq!(message_id.as_deref(), Some("abc123"));
+    let sent = String::from_utf8(exchange.sent).unwrap();
+    assert!(sent.starts_with("POST /v3/mail/send HTTP/1.1\r\nHost: api.sendgrid.com\r\n"));
+    assert!(sent.contains("Authorization: Bearer key\r\n"));
+    assert!(sent
+        .ends_with("Content-Length: 2\r\nUser-Agent: sendgrid-rs\r\nConnection: close\r\n\r\n{}"));
+
+    let mut exchange = Exchange {
+        response: Cursor::new(
+            b"HTTP/1.1 400 Bad Request\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nerror\r\n0\r\n\r\n"
+                .to_vec(),
+        ),
+        sent: Vec::new(),
+    };
+    match *post_mail(&mut exchange, API_HOST, 443, "key", b"{}")
+        .unwrap_err()
+        .kind()
+    {
+        SendgridErrorKind::Api(400, ref body) => assert_eq!(body, "error"),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn minimal_rejects_bad_input() {
+    use std::io::Cursor;
+
+    match *check_head(API_HOST, "key\r\nX-Injected: 1")
+        .unwrap_err()
+        .kind()
+    {
+        SendgridErrorKind::InvalidHeaderValue(_) => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let sender = MinimalSender::new(String::from("SG.secret"));
+    assert!(!format!("{:?}", sender).contains("SG.secret"));
+
+    let mut chunks = Cursor::new(b"zz\r\nerror\r\n0\r\n\r\n".to_vec());
+    assert!(read_chunked(&mut chunks).is_err());
+    for size in ["ffffffffffffffff", "100001"] {
+        let mut chunks = Cursor::new(format!("{}\r\nerror\r\n0\r\n\r\n", size).into_bytes());
+        assert!(read_chunked(&mut chunks).is_err());
+    }
+}
+
+#[test]
+fn minimal_host_port() {
+    // Nothing is read back, as the cursor is at the end of what was written.
+    let mut stream = ::std::io::Cursor::new(Vec::new());
+    assert!(post_mail(&mut stream, "localhost", 8443, "key", b"{}").is_err());
+    let sent = String::from_utf8(stream.into_inner()).unwrap();
+    assert!(sent.contains("\r\nHost: localhost:8443\r\n"));
+}
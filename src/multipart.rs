@@ -0,0 +1,124 @@
+use errors::{SendgridError, SendgridErrorKind, SendgridResult};
+
+// A part of a multipart/form-data body.
+pub struct FormPart<'a> {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: &'a [u8],
+}
+
+// Split a multipart/form-data body into its parts, using the boundary from the value of
+// its Content-Type header.
+pub fn parse_form<'a>(content_type: &str, body: &'a [u8]) -> SendgridResult<Vec<FormPart<'a>>> {
+    let boundary = match param(content_type, "boundary") {
+        Some(boundary) => format!("--{}", boundary),
+        None => return Err(invalid("the content type has no boundary")),
+    };
+    let delimiter = format!("\r\n{}", boundary);
+
+    let start = match find(body, boundary.as_bytes()) {
+        Some(start) => start + boundary.len(),
+        None => return Err(invalid("the body does not contain the boundary")),
+    };
+    let mut rest = &body[start..];
+    let mut parts = Vec::new();
+
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err(invalid("a boundary is not followed by a line break"));
+        }
+        rest = &rest[2..];
+
+        let headers_end = match find(rest, b"\r\n\r\n") {
+            Some(end) => end,
+            None => return Err(invalid("a part has no end of its headers")),
+        };
+        let headers = String::from_utf8_lossy(&rest[..headers_end]).into_owned();
+        rest = &rest[headers_end + 4..];
+
+        let data_end = match find(rest, delimiter.as_bytes()) {
+            Some(end) => end,
+            None => return Err(invalid("a part is not closed by a boundary")),
+        };
+        let data = &rest[..data_end];
+        rest = &rest[data_end + delimiter.len()..];
+
+        let mut part = FormPart {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            data,
+        };
+        for line in headers.split("\r\n") {
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            let (name, value) = (&line[..colon], line[colon + 1..].trim());
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                part.name = param(value, "name").unwrap_or_default();
+                part.filename = param(value, "filename");
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+        parts.push(part);
+    }
+}
+
+// Get a parameter from a header value like `form-data; name="to"`.
+fn param(value: &str, name: &str) -> Option<String> {
+    for item in value.split(';').skip(1) {
+        let item = item.trim();
+        let eq = match item.find('=') {
+            Some(eq) => eq,
+            None => continue,
+        };
+        if !item[..eq].trim().eq_ignore_ascii_case(name) {
+            continue;
+        }
+
+        let value = item[eq + 1..].trim();
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            return Some(value[1..value.len() - 1].replace("\\\"", "\""));
+        }
+        return Some(value.to_string());
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn invalid(reason: &str) -> SendgridError {
+    SendgridErrorKind::InvalidMultipart(reason.to_string()).into()
+}
+
+#[test]
+fn form_parts() {
+    let body = b"--xyz\r\n\
+                 Content-Disposition: form-data; name=\"subject\"\r\n\
+                 \r\n\
+                 Hello\r\n\
+                 --xyz\r\n\
+                 Content-Disposition: form-data; name=\"attachment1\"; filename=\"a.bin\"\r\n\
+                 Content-Type: application/octet-stream\r\n\
+                 \r\n\
+                 \x00\xff\r\n\
+                 --xyz--\r\n";
+    let parts = parse_form("multipart/form-data; boundary=xyz", body).unwrap();
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].name, "subject");
+    assert_eq!(parts[0].data, b"Hello");
+    assert_eq!(parts[1].filename, Some(String::from("a.bin")));
+    assert_eq!(parts[1].data, b"\x00\xff");
+    assert!(parse_form("multipart/form-data", body).is_err());
+}
@@ -0,0 +1,103 @@
+//! Decoding newline delimited JSON, as SendGrid writes large exports, one record at a
+//! time as it is read, so exports with millions of rows don't have to fit in memory.
+
+use errors::SendgridResult;
+
+use libflate::gzip::MultiDecoder;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use std::io::{BufRead, BufReader, Read};
+use std::marker::PhantomData;
+
+// The first bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// An iterator over the records of a stream of newline delimited JSON, which may be
+/// compressed with gzip. Compressed streams are recognized by their first bytes and
+/// decompressed as they are read. Blank lines are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use sendgrid::ndjson::NdjsonRecords;
+/// use std::collections::HashMap;
+///
+/// let lines = "{\"email\":\"a@example.com\"}\n\n{\"email\":\"b@example.com\"}\n";
+/// let records: Vec<HashMap<String, String>> = NdjsonRecords::new(lines.as_bytes())
+///     .unwrap()
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(records[1]["email"], "b@example.com");
+/// ```
+pub struct NdjsonRecords<T> {
+    reader: Box<dyn BufRead + Send>,
+    line: String,
+    done: bool,
+    records: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> NdjsonRecords<T> {
+    /// Decode the records of a reader. Fails when the stream starts like gzip but its
+    /// header is invalid.
+    pub fn new<R: Read + Send + 'static>(reader: R) -> SendgridResult<NdjsonRecords<T>> {
+        let mut reader = BufReader::new(reader);
+        let gzipped = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+        let reader: Box<dyn BufRead + Send> = if gzipped {
+            Box::new(BufReader::new(MultiDecoder::new(reader)?))
+        } else {
+            Box::new(reader)
+        };
+        Ok(NdjsonRecords {
+            reader,
+            line: String::new(),
+            done: false,
+            records: PhantomData,
+        })
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for NdjsonRecords<T> {
+    type Item = SendgridResult<T>;
+
+    fn next(&mut self) -> Option<SendgridResult<T>> {
+        while !self.done {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => self.done = true,
+                Ok(_) if self.line.trim().is_empty() => {}
+                Ok(_) => return Some(serde_json::from_str(&self.line).map_err(Into::into)),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn gzipped_records() {
+    use libflate::gzip::Encoder;
+    use std::io::Write;
+
+    let mut encoder = Encoder::new(Vec::new()).unwrap();
+    for i in 0..1000 {
+        writeln!(encoder, "{{\"row\":{}}}", i).unwrap();
+    }
+    let gzipped = encoder.finish().into_result().unwrap();
+
+    let rows: Vec<serde_json::Value> = NdjsonRecords::new(::std::io::Cursor::new(gzipped))
+        .unwrap()
+        .collect::<SendgridResult<_>>()
+        .unwrap();
+    assert_eq!(rows.len(), 1000);
+    assert_eq!(rows[999]["row"], 999);
+
+    let mut records =
+        NdjsonRecords::<serde_json::Value>::new(&b"{\"a\":1}\nnot json\n"[..]).unwrap();
+    assert!(records.next().unwrap().is_ok());
+    assert!(records.next().unwrap().is_err());
+    assert!(records.next().is_none());
+}
@@ -0,0 +1,270 @@
+//! Sending messages through a transactional outbox. Messages are stored alongside the
+//! changes that caused them, in the same database transaction, and a driver sends them
+//! afterwards, so a message goes out once the transaction commits even if the process
+//! dies in between.
+
+#[cfg(feature = "client")]
+use bulk;
+use errors::{SendgridError, SendgridResult};
+use v3::SGMailV3;
+#[cfg(feature = "client")]
+use v3::V3Sender;
+
+#[cfg(feature = "client")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "client")]
+use std::thread;
+#[cfg(feature = "client")]
+use std::time::Duration;
+
+/// How urgent a message is. Messages with a higher priority are sent first, and bulk
+/// messages are throttled, so transactional mail never waits behind newsletters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Mail the recipient is waiting for, like password resets.
+    High,
+    /// Other transactional mail.
+    #[default]
+    Normal,
+    /// Newsletters and other mail that can wait.
+    Bulk,
+}
+
+impl Priority {
+    /// The rank of the priority for storing it, where lower ranks are sent first.
+    pub fn rank(self) -> u8 {
+        self as u8
+    }
+
+    /// The priority with the given rank.
+    pub fn from_rank(rank: u8) -> Option<Priority> {
+        match rank {
+            0 => Some(Priority::High),
+            1 => Some(Priority::Normal),
+            2 => Some(Priority::Bulk),
+            _ => None,
+        }
+    }
+}
+
+/// A stored message that is waiting to be sent.
+#[derive(Clone, Debug)]
+pub struct OutboxEntry {
+    /// The id the outbox gave the message when it was persisted.
+    pub id: String,
+    /// The JSON body of the message.
+    pub body: Vec<u8>,
+    /// The priority the message was stored with.
+    pub priority: Priority,
+    /// The number of times sending the message failed so far.
+    pub attempts: u32,
+}
+
+/// Storage for messages that are waiting to be sent, such as a table in Postgres or
+/// SQLite. Errors from the storage can be turned into a `SendgridError` with
+/// `.map_err(|e| e.to_string())?`.
+pub trait Outbox {
+    /// Store the JSON body of a message and return its id. Use the same transaction as
+    /// the changes the message is about.
+    fn persist(&self, body: &[u8], priority: Priority) -> SendgridResult<String>;
+
+    /// Up to `limit` messages that are waiting to be sent, by priority and then oldest
+    /// first. When several drivers share an outbox the messages should be claimed, for
+    /// example with `SELECT ... FOR UPDATE SKIP LOCKED`, so only one of them sends each
+    /// message.
+    fn pending(&self, limit: usize) -> SendgridResult<Vec<OutboxEntry>>;
+
+    /// Record that a message was accepted by SendGrid, with the id SendGrid gave it.
+    fn mark_sent(&self, id: &str, message_id: Option<&str>) -> SendgridResult<()>;
+
+    /// Record that sending a message failed. When `retry` is true the message should be
+    /// returned by `pending` again with its attempts increased, otherwise it has failed
+    /// for good.
+    fn mark_failed(&self, id: &str, error: &SendgridError, retry: bool) -> SendgridResult<()>;
+}
+
+/// Store a message in an outbox to be sent by an `OutboxDriver` with `Normal` priority.
+/// Messages with attachments over SendGrid's limits are rejected here rather than when
+/// they are sent.
+///
+/// # Examples
+///
+/// ```ignore
+/// let tx = db.transaction()?;
+/// tx.execute("INSERT INTO orders ...")?;
+/// outbox::enqueue(&PgOutbox::new(&tx), &confirmation)?;
+/// tx.commit()?;
+/// ```
+pub fn enqueue<O: Outbox>(outbox: &O, message: &SGMailV3) -> SendgridResult<String> {
+    enqueue_with_priority(outbox, message, Priority::Normal)
+}
+
+/// Store a message in an outbox like `enqueue`, with the given priority.
+pub fn enqueue_with_priority<O: Outbox>(
+    outbox: &O,
+    message: &SGMailV3,
+    priority: Priority,
+) -> SendgridResult<String> {
+    message.check_attachments()?;
+    let mut body = Vec::new();
+    message.write_json(&mut body)?;
+    outbox.persist(&body, priority)
+}
+
+/// Sends the messages waiting in an outbox and records the outcome of each.
+#[cfg(feature = "client")]
+pub struct OutboxDriver<'s, O> {
+    sender: &'s V3Sender,
+    outbox: O,
+    batch_size: usize,
+    max_attempts: u32,
+    poll_interval: Duration,
+    bulk_interval: Duration,
+}
+
+#[cfg(feature = "client")]
+impl<'s, O: Outbox> OutboxDriver<'s, O> {
+    /// Construct a new driver that sends 100 messages at a time, gives up on a message
+    /// after 5 failed attempts, checks for new messages every 5 seconds and sends at
+    /// most 10 bulk messages a second.
+    pub fn new(sender: &'s V3Sender, outbox: O) -> OutboxDriver<'s, O> {
+        OutboxDriver {
+            sender,
+            outbox,
+            batch_size: 100,
+            max_attempts: 5,
+            poll_interval: Duration::from_secs(5),
+            bulk_interval: Duration::from_millis(100),
+        }
+    }
+
+    /// Set how many messages are taken from the outbox at a time.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size;
+    }
+
+    /// Set how many times sending a message is attempted before giving up on it.
+    pub fn set_max_attempts(&mut self, max_attempts: u32) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Set how long `run` waits before checking an empty outbox again.
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    /// Set how long to wait after sending a bulk message before sending the next
+    /// message, which spreads newsletters out instead of sending them all at once. Bulk
+    /// messages are sent after the other messages of their batch, but urgent mail stored
+    /// while a batch is sent waits for it, for up to the batch size times this interval;
+    /// use a `BackgroundSender` for mail that can't wait that long.
+    pub fn set_bulk_interval(&mut self, bulk_interval: Duration) {
+        self.bulk_interval = bulk_interval;
+    }
+
+    /// Send one batch of waiting messages and return how many were taken from the
+    /// outbox. Only errors from the outbox itself are returned, failed sends are
+    /// recorded with `mark_failed`.
+    pub fn run_once(&self) -> SendgridResult<usize> {
+        let mut entries = self.outbox.pending(self.batch_size)?;
+        // Outboxes should already return them in this order, but a stable sort keeps
+        // mistakes there from holding up urgent mail.
+        entries.sort_by_key(|entry| entry.priority);
+
+        for entry in &entries {
+            let sent = self
+                .sender
+                .send_json(&entry.body)
+                .and_then(|res| self.sender.accepted(res));
+            match sent {
+                Ok(message_id) => self.outbox.mark_sent(&entry.id, message_id.as_deref())?,
+                Err(error) => {
+                    let retry = should_retry(&error, entry.attempts, self.max_attempts);
+                    self.outbox.mark_failed(&entry.id, &error, retry)?;
+                }
+            }
+            if entry.priority == Priority::Bulk {
+                thread::sleep(self.bulk_interval);
+            }
+        }
+
+        Ok(entries.len())
+    }
+
+    /// Keep sending messages until `stop` is set, waiting for the poll interval
+    /// whenever the outbox has run dry.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let driver = OutboxDriver::new(&sender, PgOutbox::new(pool));
+    /// driver.run(&stop)?;
+    /// ```
+    pub fn run(&self, stop: &AtomicBool) -> SendgridResult<()> {
+        while !stop.load(Ordering::Relaxed) {
+            if self.run_once()? < self.batch_size {
+                thread::sleep(self.poll_interval);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Whether a message should be tried again after a failed attempt.
+#[cfg(feature = "client")]
+fn should_retry(error: &SendgridError, attempts: u32, max_attempts: u32) -> bool {
+    bulk::is_retryable(error) && attempts + 1 < max_attempts
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn outbox_enqueue_and_retry() {
+    use errors::SendgridErrorKind;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryOutbox {
+        bodies: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl Outbox for MemoryOutbox {
+        fn persist(&self, body: &[u8], _: Priority) -> SendgridResult<String> {
+            let mut bodies = self.bodies.lock().unwrap();
+            bodies.push(body.to_vec());
+            Ok(bodies.len().to_string())
+        }
+
+        fn pending(&self, _: usize) -> SendgridResult<Vec<OutboxEntry>> {
+            Ok(Vec::new())
+        }
+
+        fn mark_sent(&self, _: &str, _: Option<&str>) -> SendgridResult<()> {
+            Ok(())
+        }
+
+        fn mark_failed(&self, _: &str, _: &SendgridError, _: bool) -> SendgridResult<()> {
+            Ok(())
+        }
+    }
+
+    let outbox = MemoryOutbox::default();
+    let mut message = SGMailV3::new();
+    message.set_subject("Your order");
+    assert_eq!(enqueue(&outbox, &message).unwrap(), "1");
+    assert_eq!(
+        outbox.bodies.lock().unwrap()[0],
+        ::serde_json::to_vec(&message).unwrap()
+    );
+
+    let unavailable = SendgridErrorKind::Api(503, String::new()).into();
+    assert!(should_retry(&unavailable, 0, 5));
+    assert!(!should_retry(&unavailable, 4, 5));
+    let rejected = SendgridErrorKind::Api(400, String::new()).into();
+    assert!(!should_retry(&rejected, 0, 5));
+
+    assert!(Priority::High < Priority::Normal && Priority::Normal < Priority::Bulk);
+    for &priority in &[Priority::High, Priority::Normal, Priority::Bulk] {
+        assert_eq!(Priority::from_rank(priority.rank()), Some(priority));
+    }
+}
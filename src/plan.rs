@@ -0,0 +1,117 @@
+//! The type and plan of the account, so applications can turn features off that the
+//! plan doesn't cover, like bulk sends on a free trial.
+
+use credits::CreditBalance;
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+/// Reading the plan of the account.
+pub struct Plan;
+
+impl Endpoint for Plan {
+    const SCOPES: &'static [&'static str] = &["user.account.read", "user.credits.read"];
+}
+
+/// The type of an account.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+    /// A free account, which includes the free trial.
+    Free,
+    /// An account on a paid plan.
+    Paid,
+    /// A type this crate doesn't know about yet.
+    #[serde(other)]
+    Other,
+}
+
+/// The type of the account and its sender reputation.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AccountInfo {
+    #[serde(rename = "type")]
+    pub account_type: AccountType,
+    /// The sender reputation of the account, from 0 to 100. It goes down with bounces
+    /// and spam reports, and SendGrid limits sending when it gets low.
+    #[serde(default)]
+    pub reputation: f64,
+    /// Other fields of the account, such as ones for new plan types.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The type of the account along with the credits of its plan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanInfo {
+    pub account: AccountInfo,
+    pub credits: CreditBalance,
+}
+
+impl PlanInfo {
+    /// Whether the account is on a free plan or trial.
+    pub fn is_trial(&self) -> bool {
+        self.account.account_type == AccountType::Free
+    }
+
+    /// The credits left in the current period, or 0 when they ran out and the account
+    /// is in overage.
+    pub fn credits_remaining(&self) -> u64 {
+        if self.credits.remain < 0 {
+            0
+        } else {
+            self.credits.remain as u64
+        }
+    }
+
+    /// Whether the plan allows sending a batch of messages that uses `credits` credits.
+    /// Paid plans can go into overage, so this is only false for free accounts.
+    pub fn allows(&self, credits: u64) -> bool {
+        !self.is_trial() || credits <= self.credits_remaining()
+    }
+}
+
+impl V3Sender {
+    /// Get the type and reputation of the account. Requires the `user.account.read`
+    /// scope.
+    pub fn account_info(&self) -> SendgridResult<AccountInfo> {
+        self.api_get("/user/account")
+    }
+
+    /// Get the type of the account and the credits of its plan.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let plan = sender.plan()?;
+    /// if plan.is_trial() {
+    ///     config.bulk_sends = false;
+    /// }
+    /// ```
+    pub fn plan(&self) -> SendgridResult<PlanInfo> {
+        Ok(PlanInfo {
+            account: self.account_info()?,
+            credits: self.credits()?,
+        })
+    }
+}
+
+#[test]
+fn trial_plan() {
+    let plan = PlanInfo {
+        account: ::serde_json::from_str(r#"{"type":"free","reputation":99.5}"#).unwrap(),
+        credits: ::serde_json::from_str(
+            r#"{"remain":-3,"total":100,"overage":3,"used":103,"last_reset":"2018-01-01","next_reset":"2018-02-01","reset_frequency":"daily"}"#,
+        )
+        .unwrap(),
+    };
+    assert!(plan.is_trial());
+    assert_eq!(plan.account.reputation, 99.5);
+    assert_eq!(plan.credits_remaining(), 0);
+    assert!(!plan.allows(1));
+    assert!(plan.allows(0));
+
+    let account: AccountInfo =
+        ::serde_json::from_str(r#"{"type":"paid","reputation":100}"#).unwrap();
+    assert_eq!(account.account_type, AccountType::Paid);
+}
@@ -0,0 +1,107 @@
+//! Preheaders, the snippet of text that inboxes show next to the subject of a message.
+//! Without one, clients show whatever text comes first in the HTML content, which is
+//! often a "View in browser" link.
+
+use mail::Mail;
+use v3::SGMailV3;
+
+// The comments around an inserted preheader, so setting it again replaces it.
+const START: &str = "<!--preheader-->";
+const END: &str = "<!--/preheader-->";
+
+// Clients fill the rest of the preview with the text that follows the preheader. A run of
+// zero width non-joiners and non-breaking spaces keeps that text out of the preview.
+const PADDING: &str = "&zwnj;&nbsp;";
+const PADDING_COUNT: usize = 100;
+
+/// Insert a hidden preheader at the start of the body of HTML content, replacing one
+/// that was inserted before. The text is HTML escaped.
+pub fn insert(html: &str, text: &str) -> String {
+    let html = remove(html);
+    let lower = html.to_ascii_lowercase();
+    let position = lower
+        .find("<body")
+        .and_then(|start| lower[start..].find('>').map(|end| start + end + 1))
+        .unwrap_or(0);
+
+    let mut out = String::with_capacity(html.len() + text.len() + 1600);
+    out.push_str(&html[..position]);
+    out.push_str(START);
+    out.push_str(
+        "<div style=\"display:none;font-size:1px;color:#ffffff;line-height:1px;\
+         max-height:0px;max-width:0px;opacity:0;overflow:hidden;mso-hide:all;\">",
+    );
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out.push_str(&PADDING.repeat(PADDING_COUNT));
+    out.push_str("</div>");
+    out.push_str(END);
+    out.push_str(&html[position..]);
+    out
+}
+
+// Remove a preheader inserted by `insert`.
+pub(crate) fn remove(html: &str) -> String {
+    match (html.find(START), html.find(END)) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{}", &html[..start], &html[end + END.len()..])
+        }
+        _ => String::from(html),
+    }
+}
+
+impl<'a> Mail<'a> {
+    /// Set the preheader, the preview text inboxes show next to the subject. It is added
+    /// to the HTML content hidden, with the padding that keeps the rest of the content
+    /// out of the preview, so set it after the HTML content.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// message.add_html(&html);
+    /// message.set_preheader("Your order has shipped");
+    /// ```
+    pub fn set_preheader(&mut self, text: &str) {
+        self.html = insert(&self.html, text).into();
+    }
+}
+
+impl SGMailV3 {
+    /// Set the preheader, the preview text inboxes show next to the subject. It is added
+    /// to the HTML content hidden, so set it after adding the HTML content. Messages
+    /// without HTML content are left alone.
+    pub fn set_preheader(&mut self, text: &str) {
+        if let Some(html) = self.html_mut() {
+            *html = insert(html, text);
+        }
+    }
+}
+
+#[test]
+fn insert_preheader() {
+    let mut m = Mail::new();
+    m.add_html("<html><BODY class=\"x\"><p>View in browser</p></BODY></html>");
+    m.set_preheader("Sale & more");
+    m.set_preheader("Shipped <today>");
+
+    let html = m.html.to_string();
+    assert!(html.starts_with("<html><BODY class=\"x\"><!--preheader--><div style=\"display:none;"));
+    assert!(html.contains(">Shipped &lt;today&gt;&zwnj;&nbsp;"));
+    assert!(!html.contains("Sale"));
+    assert!(html.ends_with("</div><!--/preheader--><p>View in browser</p></BODY></html>"));
+
+    assert!(insert("<p>Hi</p>", "Hello").starts_with("<!--preheader-->"));
+
+    // The padding stays out of the derived text part.
+    let mut m = Mail::new().with_auto_text();
+    m.add_html("<p>Hi</p>");
+    m.set_preheader("Hello");
+    assert_eq!(m.make_text(), "Hi");
+}
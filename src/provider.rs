@@ -0,0 +1,221 @@
+//! Sending through an ordered chain of providers, so mail still goes out through a
+//! fallback, like the SMTP relay or another service, while SendGrid's API is down.
+
+use bulk::{self, Backoff};
+use errors::{SendgridError, SendgridErrorKind, SendgridResult};
+use failover;
+use mail::Mail;
+#[cfg(feature = "smtp")]
+use smtp::SmtpTransport;
+use v3::{RequestOptions, SGMailV3, V3Sender};
+
+use std::thread;
+
+/// Something that sends a `Mail`: `SGClient`, `V3Sender`, `SmtpTransport`, or a closure
+/// with the same signature as `send` for another service.
+pub trait EmailProvider: Send + Sync {
+    /// Send a message. The errors `ProviderChain` fails over on are the ones from before
+    /// the message could have been accepted: `Api` errors with a 429 or 5xx status,
+    /// failures to connect, and SMTP replies in the 4xx range. Errors that leave it
+    /// open whether the message was accepted, like a timeout waiting for the response,
+    /// stop the chain so the message isn't sent twice.
+    fn send(&self, mail: &Mail) -> SendgridResult<()>;
+}
+
+impl<F> EmailProvider for F
+where
+    F: Fn(&Mail) -> SendgridResult<()> + Send + Sync,
+{
+    fn send(&self, mail: &Mail) -> SendgridResult<()> {
+        self(mail)
+    }
+}
+
+impl EmailProvider for V3Sender {
+    // The message is converted with `SGMailV3::from_v2`, leaving out what V3 can't send.
+    fn send(&self, mail: &Mail) -> SendgridResult<()> {
+        let message = SGMailV3::from_v2(mail).message;
+        self.send_chunk(&message, &RequestOptions::default())
+            .map(|_| ())
+    }
+}
+
+#[cfg(feature = "smtp")]
+impl EmailProvider for SmtpTransport {
+    fn send(&self, mail: &Mail) -> SendgridResult<()> {
+        SmtpTransport::send(self, mail)
+    }
+}
+
+/// Sends messages through the first of several providers that accepts them.
+///
+/// A message goes to the providers in the order they were added. When a provider fails
+/// with an error that sending again could fix, like a 5xx status, it is tried again as
+/// often as the retry backoff allows and then the next provider gets the message. Other errors are returned right away: a rejected message would fail with
+/// every provider, and after a timeout or a dropped connection the message may already
+/// have been accepted.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut chain = ProviderChain::new();
+/// chain.add("sendgrid", SGClient::new(api_key.clone()));
+/// chain.add("smtp", SmtpTransport::new(api_key));
+/// chain.add("backup", |mail: &Mail| backup_service.send(mail));
+///
+/// let provider = chain.send(&message)?;
+/// println!("sent through {}", provider);
+/// ```
+pub struct ProviderChain {
+    providers: Vec<(String, Box<dyn EmailProvider>)>,
+    retries: Backoff,
+}
+
+impl Default for ProviderChain {
+    fn default() -> ProviderChain {
+        ProviderChain::new()
+    }
+}
+
+impl ProviderChain {
+    /// Construct an empty chain that fails over without retrying a provider.
+    pub fn new() -> ProviderChain {
+        ProviderChain {
+            providers: Vec::new(),
+            retries: Backoff {
+                attempts: 0,
+                ..Backoff::default()
+            },
+        }
+    }
+
+    /// Add a provider after the ones already added. The name is returned by `send`
+    /// when the provider sent a message.
+    pub fn add<P: EmailProvider + 'static>(&mut self, name: &str, provider: P) {
+        self.providers
+            .push((String::from(name), Box::new(provider)));
+    }
+
+    /// Set how often and how long apart each provider is tried again before failing
+    /// over to the next one.
+    pub fn set_retries(&mut self, retries: Backoff) {
+        self.retries = retries;
+    }
+
+    /// The names of the providers, in order.
+    pub fn providers(&self) -> Vec<&str> {
+        self.providers.iter().map(|(name, _)| &name[..]).collect()
+    }
+
+    /// Send a message through the first provider that accepts it and return its name.
+    /// Fails with the error of the last provider when none accepted it, and with
+    /// `NoProviders` when the chain is empty.
+    pub fn send(&self, mail: &Mail) -> SendgridResult<&str> {
+        let mut last_error = None;
+        for (name, provider) in &self.providers {
+            match self.send_with(&**provider, mail) {
+                Ok(()) => return Ok(name),
+                Err(e) => {
+                    if !fails_over(&e) {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| SendgridErrorKind::NoProviders.into()))
+    }
+
+    // Send a message through one provider, retrying errors that could go away.
+    fn send_with(&self, provider: &dyn EmailProvider, mail: &Mail) -> SendgridResult<()> {
+        let mut attempt = 0;
+        loop {
+            match provider.send(mail) {
+                Err(ref e) if fails_over(e) && attempt < self.retries.attempts => {
+                    thread::sleep(self.retries.delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+// Whether another attempt or provider could succeed without delivering the message
+// twice: the errors that are retried, SMTP relays that couldn't be reached, and SMTP
+// replies in the 4xx range, which reject the message for now. A connection that ended
+// without a reply is `Smtp(0, _)` and isn't failed over on.
+fn fails_over(error: &SendgridError) -> bool {
+    match *error.kind() {
+        SendgridErrorKind::Smtp(code, _) => (400..500).contains(&code),
+        SendgridErrorKind::Io(ref e) => failover::is_unreachable(e),
+        _ => bulk::is_retryable(error),
+    }
+}
+
+#[test]
+fn fail_over_providers() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut mail = Mail::new();
+    mail.add_to("test@example.com");
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let primary_calls = Arc::clone(&calls);
+    let mut chain = ProviderChain::new();
+    chain.add("primary", move |_: &Mail| -> SendgridResult<()> {
+        primary_calls.fetch_add(1, Ordering::SeqCst);
+        Err(SendgridErrorKind::Api(503, String::new()).into())
+    });
+    chain.add("relay", |_: &Mail| -> SendgridResult<()> {
+        Err(SendgridErrorKind::Smtp(421, String::from("try again later")).into())
+    });
+    chain.add("backup", |_: &Mail| -> SendgridResult<()> { Ok(()) });
+    assert_eq!(chain.send(&mail).unwrap(), "backup");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let mut rejecting = ProviderChain::new();
+    rejecting.add("primary", |_: &Mail| -> SendgridResult<()> {
+        Err(SendgridErrorKind::Api(400, String::new()).into())
+    });
+    rejecting.add("backup", |_: &Mail| -> SendgridResult<()> { Ok(()) });
+    match *rejecting.send(&mail).unwrap_err().kind() {
+        SendgridErrorKind::Api(400, _) => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    match *ProviderChain::new().send(&mail).unwrap_err().kind() {
+        SendgridErrorKind::NoProviders => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn no_fail_over_once_sent() {
+    use std::io;
+
+    let mut mail = Mail::new();
+    mail.add_to("test@example.com");
+
+    let unconfirmed: Vec<fn() -> SendgridError> = vec![
+        || SendgridErrorKind::Unconfirmed(String::from("no reply to the data")).into(),
+        || SendgridErrorKind::Smtp(0, String::new()).into(),
+        || SendgridErrorKind::Io(io::Error::new(io::ErrorKind::TimedOut, "timed out")).into(),
+    ];
+    for error in unconfirmed {
+        let mut chain = ProviderChain::new();
+        chain.add("primary", move |_: &Mail| -> SendgridResult<()> {
+            Err(error())
+        });
+        chain.add("backup", |_: &Mail| -> SendgridResult<()> { Ok(()) });
+        assert!(chain.send(&mail).is_err());
+    }
+
+    let mut chain = ProviderChain::new();
+    chain.add("relay", |_: &Mail| -> SendgridResult<()> {
+        Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused").into())
+    });
+    chain.add("backup", |_: &Mail| -> SendgridResult<()> { Ok(()) });
+    assert_eq!(chain.send(&mail).unwrap(), "backup");
+}
@@ -0,0 +1,275 @@
+//! A builder for the query languages of the Email Activity feed and the contact search,
+//! which quotes and escapes values so they can't change the meaning of a query.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use std::fmt;
+
+/// The delivery status of a message in the Email Activity feed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Processed,
+    Delivered,
+    NotDelivered,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Processed => "processed",
+            Status::Delivered => "delivered",
+            Status::NotDelivered => "not_delivered",
+        }
+    }
+}
+
+/// A value compared against a field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(i64),
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::Text(String::from(s))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Text(s)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Number(n)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    NotEq,
+    Gt,
+    Lt,
+    Like,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Compare(String, Op, Value),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// A query for the Email Activity feed or the contact search.
+///
+/// # Examples
+///
+/// ```
+/// use sendgrid::query::{Query, Status};
+///
+/// let query = Query::to_email("you@example.com").and(Query::status(Status::Delivered));
+/// assert_eq!(
+///     query.to_activity_query(),
+///     r#"to_email="you@example.com" AND status="delivered""#
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    expr: Expr,
+}
+
+impl Query {
+    /// Match messages sent to an address.
+    pub fn to_email(email: &str) -> Query {
+        Query::compare("to_email", Op::Eq, email.into())
+    }
+
+    /// Match messages sent from an address.
+    pub fn from_email(email: &str) -> Query {
+        Query::compare("from_email", Op::Eq, email.into())
+    }
+
+    /// Match messages with a subject.
+    pub fn subject(subject: &str) -> Query {
+        Query::compare("subject", Op::Eq, subject.into())
+    }
+
+    /// Match a message by its ID.
+    pub fn msg_id(msg_id: &str) -> Query {
+        Query::compare("msg_id", Op::Eq, msg_id.into())
+    }
+
+    /// Match messages with a delivery status.
+    pub fn status(status: Status) -> Query {
+        Query::compare("status", Op::Eq, status.as_str().into())
+    }
+
+    /// Match contacts with an address.
+    pub fn email(email: &str) -> Query {
+        Query::compare("email", Op::Eq, email.into())
+    }
+
+    /// Match a field that equals a value. Field names may only contain ASCII letters,
+    /// digits, underscores and dots.
+    pub fn eq<V: Into<Value>>(field: &str, value: V) -> SendgridResult<Query> {
+        Query::checked(field, Op::Eq, value.into())
+    }
+
+    /// Match a field that doesn't equal a value.
+    pub fn not_eq<V: Into<Value>>(field: &str, value: V) -> SendgridResult<Query> {
+        Query::checked(field, Op::NotEq, value.into())
+    }
+
+    /// Match a field that is greater than a value.
+    pub fn gt<V: Into<Value>>(field: &str, value: V) -> SendgridResult<Query> {
+        Query::checked(field, Op::Gt, value.into())
+    }
+
+    /// Match a field that is less than a value.
+    pub fn lt<V: Into<Value>>(field: &str, value: V) -> SendgridResult<Query> {
+        Query::checked(field, Op::Lt, value.into())
+    }
+
+    /// Match a field against a pattern, where `%` matches any text.
+    pub fn like(field: &str, pattern: &str) -> SendgridResult<Query> {
+        Query::checked(field, Op::Like, pattern.into())
+    }
+
+    /// Match what both queries match.
+    pub fn and(self, other: Query) -> Query {
+        Query {
+            expr: Expr::And(Box::new(self.expr), Box::new(other.expr)),
+        }
+    }
+
+    /// Match what either query matches.
+    pub fn or(self, other: Query) -> Query {
+        Query {
+            expr: Expr::Or(Box::new(self.expr), Box::new(other.expr)),
+        }
+    }
+
+    /// Match what this query doesn't match.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Query {
+        Query {
+            expr: Expr::Not(Box::new(self.expr)),
+        }
+    }
+
+    /// Render the query for the Email Activity feed, which quotes text with double
+    /// quotes.
+    pub fn to_activity_query(&self) -> String {
+        let mut out = String::new();
+        render(&self.expr, '"', &mut out);
+        out
+    }
+
+    /// Render the query for the contact search, which quotes text with single quotes.
+    pub fn to_contact_query(&self) -> String {
+        let mut out = String::new();
+        render(&self.expr, '\'', &mut out);
+        out
+    }
+
+    fn compare(field: &str, op: Op, value: Value) -> Query {
+        Query {
+            expr: Expr::Compare(String::from(field), op, value),
+        }
+    }
+
+    fn checked(field: &str, op: Op, value: Value) -> SendgridResult<Query> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        if field.is_empty() || !field.chars().all(valid) {
+            return Err(SendgridErrorKind::InvalidFieldName(String::from(field)).into());
+        }
+        Ok(Query::compare(field, op, value))
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_activity_query())
+    }
+}
+
+fn render(expr: &Expr, quote: char, out: &mut String) {
+    match *expr {
+        Expr::Compare(ref field, op, ref value) => {
+            out.push_str(field);
+            out.push_str(match op {
+                Op::Eq => "=",
+                Op::NotEq => "!=",
+                Op::Gt => ">",
+                Op::Lt => "<",
+                Op::Like => " LIKE ",
+            });
+            match *value {
+                Value::Text(ref text) => push_quoted(out, text, quote),
+                Value::Number(n) => out.push_str(&n.to_string()),
+            }
+        }
+        Expr::And(ref a, ref b) => {
+            render_operand(a, quote, out);
+            out.push_str(" AND ");
+            render_operand(b, quote, out);
+        }
+        Expr::Or(ref a, ref b) => {
+            render_operand(a, quote, out);
+            out.push_str(" OR ");
+            render_operand(b, quote, out);
+        }
+        Expr::Not(ref a) => {
+            out.push_str("NOT ");
+            render_operand(a, quote, out);
+        }
+    }
+}
+
+// Render an operand of AND, OR or NOT, with parentheses around anything but a single
+// comparison so the precedence never depends on the query language.
+fn render_operand(expr: &Expr, quote: char, out: &mut String) {
+    if let Expr::Compare(..) = *expr {
+        render(expr, quote, out);
+    } else {
+        out.push('(');
+        render(expr, quote, out);
+        out.push(')');
+    }
+}
+
+fn push_quoted(out: &mut String, text: &str, quote: char) {
+    out.push(quote);
+    for c in text.chars() {
+        if c == quote || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push(quote);
+}
+
+#[test]
+fn render_queries() {
+    let query = Query::to_email("a@example.com")
+        .or(Query::to_email("b@example.com"))
+        .and(Query::status(Status::NotDelivered).not());
+    assert_eq!(
+        query.to_activity_query(),
+        r#"(to_email="a@example.com" OR to_email="b@example.com") AND (NOT status="not_delivered")"#
+    );
+
+    let query = Query::like("first_name", "J%")
+        .unwrap()
+        .and(Query::gt("age", 30).unwrap());
+    assert_eq!(query.to_contact_query(), "first_name LIKE 'J%' AND age>30");
+
+    let query = Query::subject(r#"Say "hi" \o/"#);
+    assert_eq!(query.to_activity_query(), r#"subject="Say \"hi\" \\o/""#);
+    assert!(Query::eq("x=1 OR y", "z").is_err());
+}
@@ -0,0 +1,155 @@
+//! Scheduling messages so they don't arrive during the night of their recipients.
+//! Recipients are given as their UTC offset in minutes, e.g. `-300` for New York in
+//! winter, since that is what most user profiles store and the crate has no time zone
+//! database; callers that store IANA zone names pass the offset currently in effect.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use mail::Mail;
+use v3::{Email, Personalization};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// A daily window in local time during which no mail should arrive, such as 22:00 to
+/// 08:00. Windows that end before they start wrap around midnight.
+///
+/// # Examples
+///
+/// ```ignore
+/// let quiet = QuietHours::new((22, 0), (8, 0))?;
+/// let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+/// for p in quiet.personalizations(now, users.iter().map(|u| (u.email(), u.utc_offset()))) {
+///     message.add_personalization(p);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuietHours {
+    start: i64,
+    end: i64,
+}
+
+impl QuietHours {
+    /// Construct quiet hours from the local hour and minute they start and end at.
+    /// Fails with `InvalidTimeOfDay` unless both are valid times of day.
+    pub fn new(start: (u32, u32), end: (u32, u32)) -> SendgridResult<QuietHours> {
+        let minutes = |(hour, minute): (u32, u32)| {
+            if hour < 24 && minute < 60 {
+                Ok(i64::from(hour * 60 + minute))
+            } else {
+                Err(SendgridErrorKind::InvalidTimeOfDay(hour, minute))
+            }
+        };
+        Ok(QuietHours {
+            start: minutes(start)?,
+            end: minutes(end)?,
+        })
+    }
+
+    /// Whether a Unix timestamp falls within the quiet hours at a UTC offset.
+    pub fn is_quiet(&self, timestamp: u64, utc_offset: i32) -> bool {
+        let minute = local_minute(timestamp, utc_offset);
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// The earliest Unix timestamp at or after `now` that is outside the quiet hours at
+    /// a UTC offset.
+    pub fn next_send_time(&self, now: u64, utc_offset: i32) -> u64 {
+        if !self.is_quiet(now, utc_offset) {
+            return now;
+        }
+        let minute = local_minute(now, utc_offset);
+        let wait = (self.end - minute).rem_euclid(MINUTES_PER_DAY) as u64;
+        let into_minute = (now as i64 + i64::from(utc_offset) * 60).rem_euclid(60) as u64;
+        now + wait * 60 - into_minute
+    }
+
+    /// Build a personalization for every recipient and their UTC offset, scheduled with
+    /// `send_at` for the end of the quiet hours when it is quiet for them at `now`.
+    /// Recipients outside their quiet hours are sent right away. SendGrid allows up to
+    /// `MAX_PERSONALIZATIONS` personalizations per message.
+    pub fn personalizations<I>(&self, now: u64, recipients: I) -> Vec<Personalization>
+    where
+        I: IntoIterator<Item = (Email, i32)>,
+    {
+        recipients
+            .into_iter()
+            .map(|(to, utc_offset)| {
+                let mut p = Personalization::new();
+                p.add_to(to);
+                let send_at = self.next_send_time(now, utc_offset);
+                if send_at > now {
+                    p.set_send_at(send_at);
+                }
+                p
+            })
+            .collect()
+    }
+
+    /// Schedule every "to" recipient of a V2 message through `send_each_at`, given
+    /// their UTC offsets in the same order. Fails with `InvalidSendEachAt` unless there
+    /// is an offset for every recipient.
+    pub fn schedule_v2(
+        &self,
+        mail: &mut Mail,
+        now: u64,
+        utc_offsets: &[i32],
+    ) -> SendgridResult<()> {
+        if utc_offsets.len() != mail.to.len() {
+            return Err(
+                SendgridErrorKind::InvalidSendEachAt(utc_offsets.len(), mail.to.len()).into(),
+            );
+        }
+        mail.send_each_at = utc_offsets
+            .iter()
+            .map(|&utc_offset| self.next_send_time(now, utc_offset))
+            .collect();
+        Ok(())
+    }
+}
+
+// The minute of the local day.
+fn local_minute(timestamp: u64, utc_offset: i32) -> i64 {
+    let local = timestamp as i64 + i64::from(utc_offset) * 60;
+    local.div_euclid(60).rem_euclid(MINUTES_PER_DAY)
+}
+
+#[test]
+fn quiet_hours_schedule() {
+    let quiet = QuietHours::new((22, 0), (8, 0)).unwrap();
+    assert!(QuietHours::new((24, 0), (8, 0)).is_err());
+
+    // 2018-01-01 23:30:15 UTC.
+    let now = 1_514_849_415;
+    assert!(quiet.is_quiet(now, 0));
+    // 08:00 UTC the next morning.
+    assert_eq!(quiet.next_send_time(now, 0), 1_514_880_000);
+    // 14:30 in Tokyo, so it can go right away.
+    assert_eq!(quiet.next_send_time(now, 9 * 60), now);
+    // 18:30 in New York.
+    assert!(!quiet.is_quiet(now, -5 * 60));
+    // 05:00 in Kolkata, three hours to wait.
+    assert_eq!(
+        quiet.next_send_time(now, 5 * 60 + 30),
+        1_514_849_400 + 3 * 3600
+    );
+
+    let daytime = QuietHours::new((12, 0), (13, 0)).unwrap();
+    assert!(!daytime.is_quiet(now, 0));
+
+    let mut to = Email::new();
+    to.set_email("a@example.com");
+    let ps = quiet.personalizations(now, vec![(to.clone(), 0), (to, 9 * 60)]);
+    let json = ::serde_json::to_value(&ps).unwrap();
+    assert_eq!(json[0]["send_at"], 1_514_880_000u64);
+    assert!(json[1].get("send_at").is_none());
+
+    let mut m = Mail::new();
+    m.add_to("a@example.com");
+    m.add_to("b@example.com");
+    assert!(quiet.schedule_v2(&mut m, now, &[0]).is_err());
+    quiet.schedule_v2(&mut m, now, &[0, 9 * 60]).unwrap();
+    assert_eq!(m.send_each_at, vec![1_514_880_000, now]);
+}
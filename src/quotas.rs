@@ -0,0 +1,129 @@
+//! Soft limits on how many messages are sent per category, kept by the client so a
+//! runaway job can't burn through the sending reputation of the account.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    quotas: Mutex<HashMap<String, Quota>>,
+}
+
+#[derive(Clone, Debug)]
+struct Quota {
+    limit: u64,
+    window: Duration,
+    used: u64,
+    // When the current window started, if anything was sent in it.
+    started: Option<Instant>,
+}
+
+// Copies keep the limits but start counting from zero.
+impl Clone for Quotas {
+    fn clone(&self) -> Quotas {
+        let quotas = self.quotas.lock().unwrap_or_else(|e| e.into_inner());
+        let quotas = quotas
+            .iter()
+            .map(|(category, quota)| {
+                let quota = Quota {
+                    used: 0,
+                    started: None,
+                    ..quota.clone()
+                };
+                (category.clone(), quota)
+            })
+            .collect();
+        Quotas {
+            quotas: Mutex::new(quotas),
+        }
+    }
+}
+
+impl Quotas {
+    pub fn set(&self, category: &str, limit: u64, window: Duration) {
+        let mut quotas = self.quotas.lock().unwrap_or_else(|e| e.into_inner());
+        quotas.insert(
+            String::from(category),
+            Quota {
+                limit,
+                window,
+                used: 0,
+                started: None,
+            },
+        );
+    }
+
+    // Count `sends` against the quota of every category, or fail without counting
+    // anything if one of them would go over its limit.
+    pub fn charge<'a, I>(&self, categories: I, sends: u64) -> SendgridResult<()>
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+    {
+        self.charge_at(categories, sends, Instant::now())
+    }
+
+    fn charge_at<'a, I>(&self, categories: I, sends: u64, now: Instant) -> SendgridResult<()>
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+    {
+        let mut quotas = self.quotas.lock().unwrap_or_else(|e| e.into_inner());
+        if quotas.is_empty() {
+            return Ok(());
+        }
+
+        for category in categories.clone() {
+            if let Some(quota) = quotas.get_mut(category) {
+                let expired = quota
+                    .started
+                    .is_some_and(|started| now.duration_since(started) >= quota.window);
+                if expired {
+                    quota.used = 0;
+                    quota.started = None;
+                }
+                if quota.used + sends > quota.limit {
+                    return Err(SendgridErrorKind::QuotaExceeded(
+                        String::from(category),
+                        quota.limit,
+                    )
+                    .into());
+                }
+            }
+        }
+
+        for category in categories {
+            if let Some(quota) = quotas.get_mut(category) {
+                quota.used += sends;
+                quota.started.get_or_insert(now);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn category_quotas() {
+    let quotas = Quotas::default();
+    quotas.set("marketing", 10, Duration::from_secs(60));
+    let start = Instant::now();
+
+    assert!(quotas.charge_at(vec!["marketing"], 6, start).is_ok());
+    assert!(quotas.charge_at(vec!["receipts"], 100, start).is_ok());
+    assert!(quotas
+        .charge_at(vec!["receipts", "marketing"], 5, start)
+        .is_err());
+    assert!(quotas.charge_at(vec!["marketing"], 4, start).is_ok());
+    assert!(quotas
+        .charge_at(vec!["marketing"], 1, start + Duration::from_secs(30))
+        .is_err());
+    assert!(quotas
+        .charge_at(vec!["marketing"], 10, start + Duration::from_secs(60))
+        .is_ok());
+
+    assert!(quotas
+        .clone()
+        .charge_at(vec!["marketing"], 10, start)
+        .is_ok());
+}
@@ -0,0 +1,191 @@
+//! Reports that combine the data of several endpoints.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use stats::{Metrics, StatsPeriod, StatsQuery};
+use suppressions::SuppressionEntry;
+use v3::V3Sender;
+
+use std::collections::HashMap;
+
+// The number of bounce reasons kept in a report.
+const TOP_REASONS: usize = 10;
+
+/// An overview of how well messages were delivered over a date range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliverabilityReport {
+    pub start_date: String,
+    pub end_date: String,
+    /// The sum of the metrics over the whole range.
+    pub totals: Metrics,
+    /// The share of requests that were delivered.
+    pub delivery_rate: f64,
+    /// The share of requests that bounced.
+    pub bounce_rate: f64,
+    /// The share of delivered messages that were reported as spam.
+    pub spam_report_rate: f64,
+    /// The share of delivered messages that were opened.
+    pub open_rate: f64,
+    /// The share of delivered messages with a clicked link.
+    pub click_rate: f64,
+    /// The most common bounce reasons and how often they happened, most common first.
+    pub top_bounce_reasons: Vec<(String, usize)>,
+    /// The addresses that reported messages as spam.
+    pub spam_reports: Vec<SuppressionEntry>,
+}
+
+impl DeliverabilityReport {
+    /// Build a report from the global statistics, bounces and spam reports of a date
+    /// range.
+    pub fn from_parts(
+        start_date: &str,
+        end_date: &str,
+        stats: &[StatsPeriod],
+        bounces: &[SuppressionEntry],
+        spam_reports: Vec<SuppressionEntry>,
+    ) -> DeliverabilityReport {
+        let mut totals = Metrics::default();
+        for entry in stats.iter().flat_map(|period| &period.stats) {
+            add_metrics(&mut totals, &entry.metrics);
+        }
+
+        let mut reasons = HashMap::new();
+        for bounce in bounces {
+            let reason = bounce.reason.clone().unwrap_or_default();
+            *reasons.entry(reason).or_insert(0) += 1;
+        }
+        let mut top_bounce_reasons: Vec<(String, usize)> = reasons.into_iter().collect();
+        top_bounce_reasons.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_bounce_reasons.truncate(TOP_REASONS);
+
+        DeliverabilityReport {
+            start_date: String::from(start_date),
+            end_date: String::from(end_date),
+            delivery_rate: rate(totals.delivered, totals.requests),
+            bounce_rate: rate(totals.bounces, totals.requests),
+            spam_report_rate: rate(totals.spam_reports, totals.delivered),
+            open_rate: rate(totals.unique_opens, totals.delivered),
+            click_rate: rate(totals.unique_clicks, totals.delivered),
+            totals,
+            top_bounce_reasons,
+            spam_reports,
+        }
+    }
+}
+
+impl V3Sender {
+    /// Build a deliverability report for a date range, with dates formatted as
+    /// `YYYY-MM-DD`. This needs the scopes of both `Stats` and `Suppressions`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let report = sender.deliverability_report("2018-01-01", "2018-01-31")?;
+    /// println!("{:.1}% bounced", report.bounce_rate * 100.0);
+    /// ```
+    pub fn deliverability_report(
+        &self,
+        start_date: &str,
+        end_date: &str,
+    ) -> SendgridResult<DeliverabilityReport> {
+        // The suppression lists take timestamps, and the end date is inclusive.
+        let start_time = timestamp(start_date)?;
+        let end_time = timestamp(end_date)? + 24 * 60 * 60 - 1;
+
+        let mut query = StatsQuery::new(start_date);
+        query.end_date = Some(String::from(end_date));
+        let stats = self.global_stats(&query)?;
+        let bounces = self.bounces_between(start_time, end_time)?;
+        let spam_reports = self.spam_reports_between(start_time, end_time)?;
+
+        Ok(DeliverabilityReport::from_parts(
+            start_date,
+            end_date,
+            &stats,
+            &bounces,
+            spam_reports,
+        ))
+    }
+}
+
+fn add_metrics(totals: &mut Metrics, metrics: &Metrics) {
+    totals.blocks += metrics.blocks;
+    totals.bounce_drops += metrics.bounce_drops;
+    totals.bounces += metrics.bounces;
+    totals.clicks += metrics.clicks;
+    totals.deferred += metrics.deferred;
+    totals.delivered += metrics.delivered;
+    totals.invalid_emails += metrics.invalid_emails;
+    totals.opens += metrics.opens;
+    totals.processed += metrics.processed;
+    totals.requests += metrics.requests;
+    totals.spam_report_drops += metrics.spam_report_drops;
+    totals.spam_reports += metrics.spam_reports;
+    totals.unique_clicks += metrics.unique_clicks;
+    totals.unique_opens += metrics.unique_opens;
+    totals.unsubscribe_drops += metrics.unsubscribe_drops;
+    totals.unsubscribes += metrics.unsubscribes;
+}
+
+fn rate(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+// The Unix timestamp of midnight UTC on a `YYYY-MM-DD` date.
+fn timestamp(date: &str) -> SendgridResult<i64> {
+    let parts: Vec<i64> = date.split('-').filter_map(|p| p.parse().ok()).collect();
+    let (year, month, day) = match parts[..] {
+        [year, month, day] if (1..=12).contains(&month) && (1..=31).contains(&day) => {
+            (year, month, day)
+        }
+        _ => return Err(SendgridErrorKind::InvalidDate(String::from(date)).into()),
+    };
+
+    // Count the days from the Unix epoch, with years starting in March so the leap day
+    // comes last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok(days * 24 * 60 * 60)
+}
+
+#[test]
+fn deliverability_report() {
+    let stats: Vec<StatsPeriod> = ::serde_json::from_str(
+        r#"[{"date":"2018-01-01","stats":[{"metrics":{"requests":100,"delivered":90,"bounces":8,"unique_opens":45,"spam_reports":1}}]},
+            {"date":"2018-01-02","stats":[{"metrics":{"requests":100,"delivered":100,"unique_clicks":19}}]}]"#,
+    )
+    .unwrap();
+    let bounces: Vec<SuppressionEntry> = ::serde_json::from_str(
+        r#"[{"created":1,"email":"a@example.com","reason":"mailbox full"},
+            {"created":2,"email":"b@example.com","reason":"unknown user"},
+            {"created":3,"email":"c@example.com","reason":"unknown user"}]"#,
+    )
+    .unwrap();
+
+    let report =
+        DeliverabilityReport::from_parts("2018-01-01", "2018-01-02", &stats, &bounces, Vec::new());
+    assert_eq!(report.totals.requests, 200);
+    assert_eq!(report.delivery_rate, 0.95);
+    assert_eq!(report.bounce_rate, 0.04);
+    assert_eq!(report.click_rate, 0.1);
+    assert_eq!(
+        report.top_bounce_reasons,
+        vec![
+            (String::from("unknown user"), 2),
+            (String::from("mailbox full"), 1)
+        ]
+    );
+
+    assert_eq!(timestamp("1970-01-01").unwrap(), 0);
+    assert_eq!(timestamp("2018-03-01").unwrap(), 1_519_862_400);
+    assert!(timestamp("2018-13-01").is_err());
+}
@@ -0,0 +1,90 @@
+//! Reading response bodies without buffering more than a set size, so a proxy or an
+//! error page that returns something huge can't exhaust the memory of the process.
+
+use errors::{SendgridErrorKind, SendgridResult};
+
+use std::io::{self, BufReader, Read};
+
+use serde::de::DeserializeOwned;
+use serde_json;
+
+/// The largest response body that is read by default, in bytes.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+// A reader that fails once more than `remaining` bytes have been read.
+struct Limited<R> {
+    inner: R,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Read one byte past the limit to tell a body of exactly the limit from a
+        // larger one.
+        let max = buf.len().min(self.remaining + 1);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("the response is too large"));
+        }
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+fn limited<R: Read>(reader: R, limit: usize) -> Limited<R> {
+    Limited {
+        inner: reader,
+        remaining: limit,
+        exceeded: false,
+    }
+}
+
+// Decode a JSON body while it is read, so only the decoded value is kept in memory.
+pub fn read_json<T: DeserializeOwned, R: Read>(reader: R, limit: usize) -> SendgridResult<T> {
+    let mut reader = BufReader::new(limited(reader, limit));
+    match serde_json::from_reader(&mut reader) {
+        Ok(value) => Ok(value),
+        Err(_) if reader.get_ref().exceeded => {
+            Err(SendgridErrorKind::ResponseTooLarge(limit).into())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Read a text body.
+pub fn read_text<R: Read>(reader: R, limit: usize) -> SendgridResult<String> {
+    let mut reader = limited(reader, limit);
+    let mut text = String::new();
+    match reader.read_to_string(&mut text) {
+        Ok(_) => Ok(text),
+        Err(_) if reader.exceeded => Err(SendgridErrorKind::ResponseTooLarge(limit).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Read the body of an error response for the error message. Bodies over the limit are
+// cut off rather than failing, as the status is what matters.
+pub fn error_text<R: Read>(reader: R, limit: usize) -> String {
+    let mut body = Vec::new();
+    let _ = reader.take(limit as u64).read_to_end(&mut body);
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+#[test]
+fn response_limits() {
+    let body = br#"{"id": 1}"#;
+    let value: serde_json::Value = read_json(&body[..], body.len()).unwrap();
+    assert_eq!(value["id"], 1);
+
+    match read_json::<serde_json::Value, _>(&body[..], 4) {
+        Err(e) => match *e.kind() {
+            SendgridErrorKind::ResponseTooLarge(4) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(_) => panic!("the body should be too large"),
+    }
+    assert!(read_text(&body[..], 4).is_err());
+    assert_eq!(error_text(&body[..], 4), r#"{"id"#);
+}
@@ -0,0 +1,207 @@
+//! Pausing, resuming and cancelling scheduled messages through their batch ID.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use suppressions::encode;
+use v3::{SGMailV3, V3Sender};
+
+use reqwest::Method;
+
+/// Scheduling messages and changing the status of scheduled messages.
+pub struct ScheduledSends;
+
+impl Endpoint for ScheduledSends {
+    const SCOPES: &'static [&'static str] = &[
+        "mail.batch.create",
+        "mail.send",
+        "user.scheduled_sends.create",
+        "user.scheduled_sends.delete",
+        "user.scheduled_sends.read",
+        "user.scheduled_sends.update",
+    ];
+}
+
+/// A handle for the messages scheduled with a batch ID.
+///
+/// # Examples
+///
+/// ```ignore
+/// let scheduled = sender.schedule(&mut message, send_at)?;
+/// scheduled.pause()?;
+/// scheduled.resume()?;
+/// ```
+pub struct ScheduledSend<'a> {
+    sender: &'a V3Sender,
+    batch_id: String,
+}
+
+/// A batch of scheduled messages that was paused or cancelled.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ScheduledBatch {
+    pub batch_id: String,
+    /// Either `pause` or `cancel`.
+    pub status: String,
+    /// Other fields SendGrid lists with the batch.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    batch_id: String,
+}
+
+#[derive(Serialize)]
+struct StatusRequest<'a> {
+    batch_id: &'a str,
+    status: &'a str,
+}
+
+impl V3Sender {
+    /// Create a new batch ID for scheduled messages.
+    pub fn create_batch_id(&self) -> SendgridResult<String> {
+        let res = self.api_request(Method::Post, "/mail/batch", None)?;
+        let batch: BatchResponse = self.read_json(res)?;
+        Ok(batch.batch_id)
+    }
+
+    /// Schedule a message to be sent at a Unix timestamp under a new batch ID, and return
+    /// a handle to pause, resume or cancel it.
+    pub fn schedule(&self, mail: &mut SGMailV3, send_at: u64) -> SendgridResult<ScheduledSend<'_>> {
+        let batch_id = self.create_batch_id()?;
+        mail.set_batch_id(&batch_id);
+        mail.set_send_at(send_at);
+
+        let mut res = self.send(mail)?;
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = self.error_text(&mut res);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
+        Ok(self.scheduled_send(&batch_id))
+    }
+
+    /// Get the batches of scheduled messages that were paused or cancelled.
+    pub fn scheduled_batches(&self) -> SendgridResult<Vec<ScheduledBatch>> {
+        self.api_get("/user/scheduled_sends")
+    }
+
+    /// Get a handle for the messages scheduled with an existing batch ID.
+    pub fn scheduled_send(&self, batch_id: &str) -> ScheduledSend<'_> {
+        ScheduledSend {
+            sender: self,
+            batch_id: String::from(batch_id),
+        }
+    }
+}
+
+impl<'a> ScheduledSend<'a> {
+    /// The batch ID of the scheduled messages.
+    pub fn batch_id(&self) -> &str {
+        &self.batch_id
+    }
+
+    /// Pause the messages until they are resumed. Messages paused for more than 72 hours
+    /// are discarded.
+    pub fn pause(&self) -> SendgridResult<()> {
+        self.set_status("pause")
+    }
+
+    /// Cancel the messages so they are never sent.
+    pub fn cancel(&self) -> SendgridResult<()> {
+        self.set_status("cancel")
+    }
+
+    /// Resume paused or cancelled messages.
+    pub fn resume(&self) -> SendgridResult<()> {
+        self.sender
+            .api_request(Method::Delete, &status_path(&self.batch_id), None)?;
+        Ok(())
+    }
+
+    fn set_status(&self, status: &str) -> SendgridResult<()> {
+        let body = StatusRequest {
+            batch_id: &self.batch_id,
+            status,
+        };
+        set_status_with(&self.batch_id, |method, path| {
+            self.sender.api_send_json(method, path, &body).map(|_| ())
+        })
+    }
+}
+
+// A batch gets a status with a POST, after which it can only be changed with a PATCH.
+fn set_status_with<F>(batch_id: &str, mut send: F) -> SendgridResult<()>
+where
+    F: FnMut(Method, &str) -> SendgridResult<()>,
+{
+    match send(Method::Post, "/user/scheduled_sends") {
+        Err(ref e) if is_bad_request(e.kind()) => send(Method::Patch, &status_path(batch_id)),
+        result => result,
+    }
+}
+
+fn status_path(batch_id: &str) -> String {
+    format!("/user/scheduled_sends/{}", encode(batch_id))
+}
+
+fn is_bad_request(kind: &SendgridErrorKind) -> bool {
+    matches!(*kind, SendgridErrorKind::Api(400, _))
+}
+
+#[test]
+fn status_changes() {
+    let body = StatusRequest {
+        batch_id: "abc",
+        status: "pause",
+    };
+    assert_eq!(
+        ::serde_json::to_string(&body).unwrap(),
+        r#"{"batch_id":"abc","status":"pause"}"#
+    );
+
+    let mut calls = Vec::new();
+    set_status_with("a/b", |method, path| {
+        calls.push((method, String::from(path)));
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        calls,
+        vec![(Method::Post, String::from("/user/scheduled_sends"))]
+    );
+
+    // A batch that already has a status is changed with a PATCH.
+    let mut calls = Vec::new();
+    set_status_with("a/b", |method, path| {
+        calls.push((method.clone(), String::from(path)));
+        match method {
+            Method::Post => Err(SendgridErrorKind::Api(400, String::new()).into()),
+            _ => Ok(()),
+        }
+    })
+    .unwrap();
+    assert_eq!(
+        calls[1],
+        (Method::Patch, String::from("/user/scheduled_sends/a%2Fb"))
+    );
+
+    let mut calls = 0;
+    let result = set_status_with("abc", |_, _| {
+        calls += 1;
+        Err(SendgridErrorKind::Api(403, String::new()).into())
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+}
+
+#[cfg(feature = "extra-fields")]
+#[test]
+fn batch_extra_fields() {
+    let batches: Vec<ScheduledBatch> =
+        ::serde_json::from_str(r#"[{"batch_id":"abc","status":"pause","paused_at":1700000000}]"#)
+            .unwrap();
+    assert_eq!(batches[0].status, "pause");
+    assert_eq!(batches[0].extra["paused_at"], 1700000000);
+}
@@ -0,0 +1,173 @@
+//! Senders that fill in defaults on every message they send, e.g. to give every tenant
+//! of an application a preconfigured sender.
+
+use errors::SendgridResult;
+use headers;
+use v3::{Email, RequestOptions, SGMailV3, SGMap, V3Sender};
+
+use std::borrow::Cow;
+use std::fmt;
+
+use reqwest::Response;
+
+#[derive(Clone, Default)]
+pub(crate) struct MessageDefaults {
+    pub from: Option<Email>,
+    pub reply_to: Option<Email>,
+    pub categories: Vec<String>,
+    pub custom_args: SGMap,
+    pub headers: SGMap,
+}
+
+/// A sender that fills in defaults on every message it sends. Messages keep what they
+/// set themselves, and categories are added to their own. Scoped senders borrow the
+/// `V3Sender` they were made from, so they share its connections and are cheap to make
+/// and clone.
+///
+/// # Examples
+///
+/// ```ignore
+/// let tenant = sender
+///     .with_default_from(tenant_address)
+///     .with_default_categories(&["tenant-x", "transactional"]);
+/// tenant.send(&mut message)?;
+/// ```
+#[derive(Clone)]
+pub struct ScopedSender<'a> {
+    sender: &'a V3Sender,
+    defaults: MessageDefaults,
+}
+
+impl V3Sender {
+    /// Make a scoped sender with a default from address.
+    pub fn with_default_from(&self, from: Email) -> ScopedSender<'_> {
+        self.scoped().with_default_from(from)
+    }
+
+    /// Make a scoped sender with a default reply to address.
+    pub fn with_default_reply_to(&self, reply_to: Email) -> ScopedSender<'_> {
+        self.scoped().with_default_reply_to(reply_to)
+    }
+
+    /// Make a scoped sender that adds these categories to every message.
+    pub fn with_default_categories(&self, categories: &[&str]) -> ScopedSender<'_> {
+        self.scoped().with_default_categories(categories)
+    }
+
+    /// Make a scoped sender with a default custom argument.
+    pub fn with_default_custom_arg<V: fmt::Display>(
+        &self,
+        key: &str,
+        value: V,
+    ) -> ScopedSender<'_> {
+        self.scoped().with_default_custom_arg(key, value)
+    }
+
+    /// Make a scoped sender with a default header.
+    pub fn with_default_header(&self, name: &str, value: &str) -> SendgridResult<ScopedSender<'_>> {
+        self.scoped().with_default_header(name, value)
+    }
+
+    fn scoped(&self) -> ScopedSender<'_> {
+        ScopedSender {
+            sender: self,
+            defaults: MessageDefaults::default(),
+        }
+    }
+}
+
+impl<'a> ScopedSender<'a> {
+    /// Set the default from address.
+    pub fn with_default_from(mut self, from: Email) -> ScopedSender<'a> {
+        self.defaults.from = Some(from);
+        self
+    }
+
+    /// Set the default reply to address.
+    pub fn with_default_reply_to(mut self, reply_to: Email) -> ScopedSender<'a> {
+        self.defaults.reply_to = Some(reply_to);
+        self
+    }
+
+    /// Add categories to every message.
+    pub fn with_default_categories(mut self, categories: &[&str]) -> ScopedSender<'a> {
+        self.defaults
+            .categories
+            .extend(categories.iter().map(|c| String::from(*c)));
+        self
+    }
+
+    /// Set a default custom argument.
+    pub fn with_default_custom_arg<V: fmt::Display>(
+        mut self,
+        key: &str,
+        value: V,
+    ) -> ScopedSender<'a> {
+        self.defaults
+            .custom_args
+            .insert(String::from(key), value.to_string());
+        self
+    }
+
+    /// Set a default header, which is checked like `SGMailV3::add_header`.
+    pub fn with_default_header(
+        mut self,
+        name: &str,
+        value: &str,
+    ) -> SendgridResult<ScopedSender<'a>> {
+        headers::validate_name(name)?;
+        let value = headers::encode_value(Cow::Borrowed(value))?.into_owned();
+        self.defaults.headers.insert(String::from(name), value);
+        Ok(self)
+    }
+
+    /// The sender this scoped sender was made from.
+    pub fn sender(&self) -> &'a V3Sender {
+        self.sender
+    }
+
+    /// Fill in the defaults on a message and send it.
+    pub fn send(&self, mail: &mut SGMailV3) -> SendgridResult<Response> {
+        self.send_with(mail, &RequestOptions::default())
+    }
+
+    /// Fill in the defaults on a message and send it with options for just this
+    /// request.
+    pub fn send_with(
+        &self,
+        mail: &mut SGMailV3,
+        options: &RequestOptions,
+    ) -> SendgridResult<Response> {
+        mail.apply_defaults(&self.defaults);
+        self.sender.send_with(mail, options)
+    }
+}
+
+#[test]
+fn apply_defaults() {
+    let mut from = Email::new();
+    from.set_email("tenant@example.com");
+    let defaults = MessageDefaults {
+        from: Some(from),
+        categories: vec![String::from("tenant-x")],
+        custom_args: vec![
+            (String::from("tenant"), String::from("x")),
+            (String::from("job"), String::from("default")),
+        ]
+        .into_iter()
+        .collect(),
+        ..Default::default()
+    };
+
+    let mut m = SGMailV3::new();
+    m.add_category("welcome");
+    m.add_category("tenant-x");
+    m.add_custom_arg("job", "42");
+    m.apply_defaults(&defaults);
+
+    let json = ::serde_json::to_string(&m).unwrap();
+    assert!(json.contains(r#""from":{"email":"tenant@example.com"}"#));
+    assert!(json.contains(r#""categories":["welcome","tenant-x"]"#));
+    assert!(json.contains(r#""job":"42""#));
+    assert!(json.contains(r#""tenant":"x""#));
+}
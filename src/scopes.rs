@@ -0,0 +1,93 @@
+//! Checking the API key and the permissions it was granted.
+//!
+//! Every group of endpoints has a marker type implementing `Endpoint`, which lists the
+//! scopes the API key needs to use it. `V3Sender::check_scopes_for` compares them with the
+//! scopes of the key.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use v3::V3Sender;
+
+/// A group of endpoints and the scopes an API key needs to use them.
+pub trait Endpoint {
+    /// The scopes needed by the endpoints.
+    const SCOPES: &'static [&'static str];
+}
+
+/// Sending mail with `V3Sender::send` and its variants.
+pub struct MailSend;
+
+impl Endpoint for MailSend {
+    const SCOPES: &'static [&'static str] = &["mail.send"];
+}
+
+/// The answer of `/v3/scopes`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct KeyScopes {
+    /// The scopes granted to the API key.
+    pub scopes: Vec<String>,
+    /// Other fields SendGrid answers with besides the scopes.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl V3Sender {
+    /// Get the scopes granted to the API key from `/v3/scopes`. Any valid API key can
+    /// read its own scopes.
+    pub fn scopes(&self) -> SendgridResult<Vec<String>> {
+        Ok(self.key_scopes()?.scopes)
+    }
+
+    /// Get the whole answer of `/v3/scopes`, for the fields other than the scopes.
+    pub fn key_scopes(&self) -> SendgridResult<KeyScopes> {
+        self.api_get("/scopes")
+    }
+
+    /// Check that the API key is valid and return the scopes it was granted. This only
+    /// reads `/v3/scopes`, so it is cheap enough to call when a service starts to fail
+    /// fast on a wrong key.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sender = V3Sender::new(api_key);
+    /// let scopes = sender.verify_credentials()?;
+    /// ```
+    pub fn verify_credentials(&self) -> SendgridResult<Vec<String>> {
+        self.scopes()
+    }
+
+    /// Check that the API key has all the scopes needed by a group of endpoints, failing
+    /// with a `MissingScopes` error that lists the ones it lacks.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.check_scopes_for::<MailSend>()?;
+    /// ```
+    pub fn check_scopes_for<E: Endpoint>(&self) -> SendgridResult<()> {
+        let missing = missing_scopes(&self.scopes()?, E::SCOPES);
+        if !missing.is_empty() {
+            return Err(SendgridErrorKind::MissingScopes(missing).into());
+        }
+        Ok(())
+    }
+}
+
+fn missing_scopes(granted: &[String], required: &[&str]) -> Vec<String> {
+    required
+        .iter()
+        .filter(|scope| !granted.iter().any(|g| g == *scope))
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+#[test]
+fn scope_check() {
+    let granted = vec![String::from("mail.send"), String::from("scopes.read")];
+    assert!(missing_scopes(&granted, MailSend::SCOPES).is_empty());
+    assert_eq!(
+        missing_scopes(&granted, &["mail.send", "suppression.read"]),
+        vec!["suppression.read"]
+    );
+}
@@ -0,0 +1,163 @@
+//! The sender identities of Marketing Campaigns, which Single Sends are sent from.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use reqwest::Method;
+
+/// Creating, reading, updating and deleting sender identities.
+pub struct SenderIdentities;
+
+impl Endpoint for SenderIdentities {
+    const SCOPES: &'static [&'static str] = &["marketing.read", "marketing.write"];
+}
+
+/// An address with an optional display name.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SenderEmail {
+    pub email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl SenderEmail {
+    /// Construct an address with a display name.
+    pub fn new(email: &str, name: &str) -> SenderEmail {
+        SenderEmail {
+            email: String::from(email),
+            name: Some(String::from(name)),
+        }
+    }
+}
+
+/// The details of a sender identity. The postal address is required by anti-spam laws
+/// and shows up in the footer of the messages.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SenderIdentity {
+    /// The name the identity is listed under, which is never shown to recipients.
+    pub nickname: String,
+    pub from: SenderEmail,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<SenderEmail>,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_2: Option<String>,
+    pub city: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zip: Option<String>,
+    pub country: String,
+}
+
+/// A sender identity as stored by SendGrid.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StoredSender {
+    /// The ID that Single Sends refer to as `sender_id`.
+    pub id: u64,
+    #[serde(flatten)]
+    pub identity: SenderIdentity,
+    #[serde(default)]
+    pub verified: Verification,
+    /// Whether the identity is used by a Single Send that is scheduled or sending, in
+    /// which case it can't be changed.
+    #[serde(default)]
+    pub locked: bool,
+    /// Other fields of the sender identity, such as when it was created or updated.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// Whether the from address of an identity was verified.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Verification {
+    pub status: bool,
+    pub reason: Option<String>,
+    /// Other details SendGrid gives about the verification.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl V3Sender {
+    /// Create a sender identity. SendGrid emails the from address to verify it unless
+    /// its domain is authenticated.
+    pub fn create_sender(&self, identity: &SenderIdentity) -> SendgridResult<StoredSender> {
+        let res = self.api_send_json(Method::Post, "/marketing/senders", identity)?;
+        self.read_json(res)
+    }
+
+    /// Get all the sender identities.
+    pub fn senders(&self) -> SendgridResult<Vec<StoredSender>> {
+        self.api_get("/marketing/senders")
+    }
+
+    /// Get a sender identity.
+    pub fn sender(&self, id: u64) -> SendgridResult<StoredSender> {
+        self.api_get(&format!("/marketing/senders/{}", id))
+    }
+
+    /// Find a sender identity by its nickname, to get the `sender_id` of a Single Send
+    /// without looking it up by hand.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sender_id = sender.sender_by_nickname("Newsletter")?.map(|s| s.id);
+    /// ```
+    pub fn sender_by_nickname(&self, nickname: &str) -> SendgridResult<Option<StoredSender>> {
+        Ok(self
+            .senders()?
+            .into_iter()
+            .find(|s| s.identity.nickname == nickname))
+    }
+
+    /// Replace the details of a sender identity. Changing the from address needs it to
+    /// be verified again.
+    pub fn update_sender(
+        &self,
+        id: u64,
+        identity: &SenderIdentity,
+    ) -> SendgridResult<StoredSender> {
+        let path = format!("/marketing/senders/{}", id);
+        let res = self.api_send_json(Method::Patch, &path, identity)?;
+        self.read_json(res)
+    }
+
+    /// Delete a sender identity.
+    pub fn delete_sender(&self, id: u64) -> SendgridResult<()> {
+        self.api_request(Method::Delete, &format!("/marketing/senders/{}", id), None)?;
+        Ok(())
+    }
+
+    /// Send the verification email for a sender identity again.
+    pub fn resend_sender_verification(&self, id: u64) -> SendgridResult<()> {
+        let path = format!("/marketing/senders/{}/resend_verification", id);
+        self.api_request(Method::Post, &path, None)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn stored_sender() {
+    let sender: StoredSender = ::serde_json::from_str(
+        r#"{"id":3,"nickname":"News","from":{"email":"news@example.com","name":"News"},"address":"1 Main St","city":"Denver","country":"USA","verified":{"status":false,"reason":"pending"},"locked":false,"created_at":1}"#,
+    )
+    .unwrap();
+    assert_eq!(sender.id, 3);
+    assert_eq!(
+        sender.identity.from,
+        SenderEmail::new("news@example.com", "News")
+    );
+    assert!(!sender.verified.status);
+
+    let body = ::serde_json::to_string(&sender.identity).unwrap();
+    assert_eq!(
+        body,
+        r#"{"nickname":"News","from":{"email":"news@example.com","name":"News"},"address":"1 Main St","city":"Denver","country":"USA"}"#
+    );
+}
@@ -1,122 +1,751 @@
-use errors::SendgridResult;
-
+use address;
+use attachments;
+#[cfg(feature = "client")]
+use client_stats::{ClientStats, Counters};
+#[cfg(feature = "client")]
+use connection::Connection;
+use errors::{SendgridErrorKind, SendgridResult};
+#[cfg(feature = "client")]
+use keys::{self, Key};
 use mail::Mail;
+#[cfg(feature = "client")]
+use provider::EmailProvider;
+#[cfg(feature = "client")]
+use response;
 
-use std::io::Read;
+#[cfg(feature = "client")]
+use std::io::Cursor;
 
-use reqwest::header::{Authorization, Bearer, ContentType, Headers, UserAgent};
-use reqwest::Client;
+#[cfg(feature = "client")]
+use reqwest::header::{ContentType, Headers, UserAgent};
+#[cfg(feature = "client")]
+use reqwest::multipart::{Form, Part};
+#[cfg(feature = "client")]
+use reqwest::{Client, Response};
 
-use url::form_urlencoded::Serializer;
+#[cfg(all(feature = "client", feature = "secrets"))]
+use secrecy::SecretString;
+use url::form_urlencoded::{byte_serialize, Serializer};
 
-static API_URL: &'static str = "https://api.sendgrid.com/api/mail.send.json?";
+#[cfg(feature = "client")]
+static API_HOST: &str = "https://api.sendgrid.com";
+#[cfg(feature = "client")]
+static MAIL_SEND_PATH: &str = "/api/mail.send.json?";
 
 /// This is the struct that allows you to authenticate to the SendGrid API.
-/// It's only field is the API key which allows you to send messages.
+/// It holds the API key which allows you to send messages, and a connection pool that
+/// is shared by all the requests.
+#[cfg(feature = "client")]
 pub struct SGClient {
-    api_key: String,
+    api_key: Key,
+    client: Client,
+    connection: Connection,
+    counters: Counters,
+    host: String,
+    max_response_size: usize,
 }
 
-// Given a form value and a key, generate the correct key.
-fn make_form_key(form: &str, key: &str) -> String {
-    let mut value = String::new();
+// Given a form value and a key, generate the correct key. The key ends up between
+// brackets, so keys with brackets or control characters would be parsed as a different
+// field by SendGrid and are rejected. Everything else is percent-encoded along with the
+// rest of the body.
+fn make_form_key(form: &str, key: &str) -> SendgridResult<String> {
+    if key.is_empty() || key.chars().any(|c| c == '[' || c == ']' || c.is_control()) {
+        return Err(SendgridErrorKind::InvalidFormKey(key.to_string()).into());
+    }
+
+    let mut value = String::with_capacity(form.len() + key.len() + 2);
     value.push_str(form);
     value.push('[');
     value.push_str(key);
     value.push(']');
 
-    value
+    Ok(value)
+}
+
+// A guess at the size of the encoded body, so the buffer doesn't have to grow while the
+// body is encoded. Text mostly encodes to about its own size, while markup and binary
+// attachments grow a lot more, so those get extra room.
+fn estimated_body_size(mail_info: &Mail) -> usize {
+    let lists = [
+        &mail_info.to,
+        &mail_info.to_names,
+        &mail_info.cc,
+        &mail_info.cc_names,
+        &mail_info.bcc,
+        &mail_info.bcc_names,
+    ];
+    let recipients: usize = lists
+        .iter()
+        .flat_map(|list| list.iter())
+        .map(|value| value.len() + 16)
+        .sum();
+    let attachments: usize = mail_info
+        .attachments
+        .iter()
+        .map(|(name, contents)| name.len() + 16 + contents.len() * 2)
+        .sum();
+    let content: usize = mail_info
+        .content
+        .iter()
+        .chain(&mail_info.headers)
+        .map(|(key, value)| key.len() + value.len() + 16)
+        .sum();
+    let fields = mail_info.subject.len()
+        + mail_info.html.len() * 2
+        + mail_info.text.len()
+        + mail_info.x_smtpapi.len()
+        + 128;
+
+    (recipients + content + fields) * 5 / 4 + attachments
 }
 
-// Use the URL form encoder to properly generate the body used in the mail send request.
-fn make_post_body(mut mail_info: Mail) -> SendgridResult<String> {
-    let body = String::new();
+/// Encode a message as the URL encoded form that `SGClient::send` posts to the V2 API.
+/// Messages over SendGrid's attachment limits are rejected.
+pub fn make_post_body(mail_info: &Mail) -> SendgridResult<String> {
+    attachments::check(
+        mail_info
+            .attachments
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.len())),
+    )?;
+
+    let body = String::with_capacity(estimated_body_size(mail_info));
     let mut encoder = Serializer::new(body);
 
     for to in mail_info.to.iter() {
-        encoder.append_pair("to[]", &to);
+        encoder.append_pair("to[]", &address::encode(to));
     }
 
     for to_name in mail_info.to_names.iter() {
-        encoder.append_pair("toname[]", &to_name);
+        encoder.append_pair("toname[]", to_name);
     }
 
     for cc in mail_info.cc.iter() {
-        encoder.append_pair("cc[]", &cc);
+        encoder.append_pair("cc[]", &address::encode(cc));
+    }
+
+    for cc_name in mail_info.cc_names.iter() {
+        encoder.append_pair("ccname[]", cc_name);
     }
 
     for bcc in mail_info.bcc.iter() {
-        encoder.append_pair("bcc[]", &bcc);
+        encoder.append_pair("bcc[]", &address::encode(bcc));
     }
 
+    for bcc_name in mail_info.bcc_names.iter() {
+        encoder.append_pair("bccname[]", bcc_name);
+    }
+
+    // The encoder only takes strings, so the binary attachments are encoded by hand.
+    let mut body = encoder.finish();
     for (attachment, contents) in &mail_info.attachments {
-        encoder.append_pair(&make_form_key("files", attachment), contents);
+        if !body.is_empty() {
+            body.push('&');
+        }
+        body.extend(byte_serialize(
+            make_form_key("files", attachment)?.as_bytes(),
+        ));
+        body.push('=');
+        body.extend(byte_serialize(contents));
     }
+    let mut encoder = Serializer::for_suffix(body, 0);
 
     for (id, value) in &mail_info.content {
-        encoder.append_pair(&make_form_key("content", id), value);
+        encoder.append_pair(&make_form_key("content", id)?, value);
     }
 
-    encoder.append_pair("from", &mail_info.from);
+    encoder.append_pair("from", &address::encode(&mail_info.from.email));
     encoder.append_pair("subject", &mail_info.subject);
     encoder.append_pair("html", &mail_info.html);
-    encoder.append_pair("text", &mail_info.text);
-    encoder.append_pair("fromname", &mail_info.from_name);
-    encoder.append_pair("replyto", &mail_info.reply_to);
-    encoder.append_pair("date", &mail_info.date);
+    encoder.append_pair("text", &mail_info.make_text());
+    encoder.append_pair("fromname", mail_info.from.name.as_ref().map_or("", |n| n));
+    encoder.append_pair("replyto", &address::encode(&mail_info.reply_to.email));
+    encoder.append_pair("date", &mail_info.make_date());
     encoder.append_pair("headers", &mail_info.make_header_string()?);
-    encoder.append_pair("x-smtpapi", &mail_info.x_smtpapi);
+    encoder.append_pair("x-smtpapi", &mail_info.make_x_smtpapi_string()?);
 
     Ok(encoder.finish())
 }
 
+// Build a multipart/form-data body with the same fields as `make_post_body`, but with
+// the attachments uploaded as binary file parts instead of being URL encoded. Returns the
+// form and the size of its contents.
+#[cfg(feature = "client")]
+fn make_multipart_form(mail_info: &Mail) -> SendgridResult<(Form, usize)> {
+    attachments::check(
+        mail_info
+            .attachments
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.len())),
+    )?;
+
+    let mut fields = Vec::new();
+    let lists = [
+        ("to[]", &mail_info.to),
+        ("toname[]", &mail_info.to_names),
+        ("cc[]", &mail_info.cc),
+        ("ccname[]", &mail_info.cc_names),
+        ("bcc[]", &mail_info.bcc),
+        ("bccname[]", &mail_info.bcc_names),
+    ];
+    for &(key, values) in &lists {
+        for value in values {
+            let value = if key.ends_with("name[]") {
+                value.to_string()
+            } else {
+                address::encode(value).into_owned()
+            };
+            fields.push((key.to_string(), value));
+        }
+    }
+    for (id, value) in &mail_info.content {
+        fields.push((make_form_key("content", id)?, value.to_string()));
+    }
+    let from_name = mail_info.from.name.as_ref().map_or("", |n| n);
+    fields.extend(vec![
+        (
+            "from".to_string(),
+            address::encode(&mail_info.from.email).into_owned(),
+        ),
+        ("subject".to_string(), mail_info.subject.to_string()),
+        ("html".to_string(), mail_info.html.to_string()),
+        ("text".to_string(), mail_info.make_text().into_owned()),
+        ("fromname".to_string(), from_name.to_string()),
+        (
+            "replyto".to_string(),
+            address::encode(&mail_info.reply_to.email).into_owned(),
+        ),
+        ("date".to_string(), mail_info.make_date().into_owned()),
+        ("headers".to_string(), mail_info.make_header_string()?),
+        (
+            "x-smtpapi".to_string(),
+            mail_info.make_x_smtpapi_string()?.into_owned(),
+        ),
+    ]);
+
+    let mut form = Form::new();
+    let mut size = 0;
+    for (attachment, contents) in &mail_info.attachments {
+        size += contents.len();
+        let part = Part::reader_with_length(Cursor::new(contents.clone()), contents.len() as u64)
+            .file_name(attachment.to_string());
+        form = form.part(make_form_key("files", attachment)?, part);
+    }
+    for (key, value) in fields {
+        size += value.len();
+        form = form.text(key, value);
+    }
+
+    Ok((form, size))
+}
+
+#[cfg(feature = "client")]
 impl SGClient {
     /// Makes a new SendGrid cient with the specified API key.
     pub fn new(key: String) -> SGClient {
-        SGClient { api_key: key }
+        SGClient::with_key(keys::to_key(key))
+    }
+
+    /// Makes a new SendGrid client from a key that is already kept secret.
+    #[cfg(feature = "secrets")]
+    pub fn from_secret(key: SecretString) -> SGClient {
+        SGClient::with_key(key)
     }
 
-    /// Sends a messages through the SendGrid API. It takes a Mail struct as an
-    /// argument. It returns the string response from the API as JSON.
-    /// It sets the Content-Type to be application/x-www-form-urlencoded.
-    pub fn send(self, mail_info: Mail) -> SendgridResult<String> {
-        let client = Client::new();
+    fn with_key(key: Key) -> SGClient {
+        SGClient {
+            api_key: key,
+            client: Client::new(),
+            connection: Connection::default(),
+            counters: Counters::default(),
+            host: String::from(API_HOST),
+            max_response_size: response::DEFAULT_MAX_RESPONSE_SIZE,
+        }
+    }
+
+    /// Make requests to another host than `https://api.sendgrid.com`, such as a proxy or
+    /// a fake server in tests.
+    pub fn set_host(&mut self, host: &str) {
+        self.host = String::from(host.trim_end_matches('/'));
+    }
+
+    /// Send every request through an HTTP proxy. Proxies that need authentication are
+    /// not supported, as HTTPS requests are tunneled through the proxy without
+    /// credentials; a local proxy that adds them, such as cntlm, can be used instead.
+    pub fn set_proxy(&mut self, url: &str) -> SendgridResult<()> {
+        self.connection.set_proxy(url)?;
+        self.client = self.connection.client(None)?;
+        Ok(())
+    }
+
+    /// Trust the certificates in a PEM bundle on top of the system's root certificates.
+    pub fn add_root_certificates(&mut self, pem: &str) -> SendgridResult<()> {
+        self.connection.add_root_certificates(pem)?;
+        self.client = self.connection.client(None)?;
+        Ok(())
+    }
+
+    /// Set the largest response body that is read, in bytes. Larger responses fail with
+    /// `ResponseTooLarge`. The limit is 10 MiB by default.
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.max_response_size = max_response_size;
+    }
+
+    /// Connect to the API ahead of time, so the first send doesn't have to wait for
+    /// DNS resolution and the TLS handshake. The status of the request is ignored.
+    pub fn warm_up(&self) -> SendgridResult<()> {
         let mut headers = Headers::new();
-        headers.set(Authorization(Bearer {
-            token: self.api_key.to_owned(),
-        }));
+        headers.set(UserAgent::new("sendgrid-rs"));
+        self.client
+            .head(&format!("{}/", self.host)[..])
+            .headers(headers)
+            .send()?;
+        Ok(())
+    }
+
+    /// A snapshot of the requests this client has made so far.
+    pub fn stats(&self) -> ClientStats {
+        self.counters.snapshot()
+    }
+
+    /// Sends a messages through the SendGrid API. It takes a reference to a Mail
+    /// struct as an argument. It returns the string response from the API as JSON.
+    /// It sets the Content-Type to be application/x-www-form-urlencoded. Messages with
+    /// attachments over SendGrid's size or count limits are rejected before sending.
+    pub fn send(&self, mail_info: &Mail) -> SendgridResult<String> {
+        let res = self.post_form(mail_info)?;
+        response::read_text(res, self.max_response_size)
+    }
+
+    // Post a message URL encoded, whatever the status of the response.
+    fn post_form(&self, mail_info: &Mail) -> SendgridResult<Response> {
+        let mut headers = Headers::new();
+        headers.set(keys::authorization(&self.api_key));
         headers.set(ContentType::form_url_encoded());
         headers.set(UserAgent::new("sendgrid-rs"));
 
         let post_body = make_post_body(mail_info)?;
-        let mut res = client
-            .post(API_URL)
+        let _in_flight = self.counters.start(post_body.len());
+        let res = self
+            .client
+            .post(&format!("{}{}", self.host, MAIL_SEND_PATH)[..])
             .headers(headers)
             .body(post_body)
             .send()?;
-        let mut body = String::new();
-        res.read_to_string(&mut body)?;
-        Ok(body)
+        Ok(res)
+    }
+
+    /// Sends a message like `send`, but as multipart/form-data. The attachments are
+    /// uploaded as binary file parts, which is much smaller than URL encoding them and
+    /// better suited for large attachments.
+    pub fn send_multipart(&self, mail_info: &Mail) -> SendgridResult<String> {
+        let mut headers = Headers::new();
+        headers.set(keys::authorization(&self.api_key));
+        headers.set(UserAgent::new("sendgrid-rs"));
+
+        let (form, size) = make_multipart_form(mail_info)?;
+        let _in_flight = self.counters.start(size);
+        let res = self
+            .client
+            .post(&format!("{}{}", self.host, MAIL_SEND_PATH)[..])
+            .headers(headers)
+            .multipart(form)
+            .send()?;
+        response::read_text(res, self.max_response_size)
+    }
+}
+
+#[cfg(feature = "client")]
+impl EmailProvider for SGClient {
+    // Statuses other than 2xx fail with `Api`, so a chain can tell when to fail over.
+    fn send(&self, mail: &Mail) -> SendgridResult<()> {
+        let res = self.post_form(mail)?;
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = response::error_text(res, self.max_response_size);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
+        Ok(())
     }
 }
 
 #[test]
 fn basic_message_body() {
-    let mut m = Mail::new();
+    let mut m = Mail::new().without_auto_date();
     m.add_to("test@example.com");
-    m.add_from("me@example.com");
+    m.set_from("me@example.com");
     m.add_subject("Test");
     m.add_text("It works");
 
-    let body = make_post_body(m);
+    let body = make_post_body(&m);
     let want = "to%5B%5D=test%40example.com&from=me%40example.com&subject=Test&\
                 html=&text=It+works&fromname=&replyto=&date=&headers=%7B%7D&x-smtpapi=";
     assert_eq!(body.unwrap(), want);
 }
 
+#[test]
+fn date_body() {
+    let mut m = Mail::new();
+    let body = make_post_body(&m).unwrap();
+    assert!(body.contains("&date=") && !body.contains("&date=&"));
+
+    m.add_date_timestamp(0);
+    let body = make_post_body(&m).unwrap();
+    assert!(body.contains("&date=Thu%2C+01+Jan+1970+00%3A00%3A00+%2B0000&"));
+}
+
+#[test]
+fn presized_body() {
+    let mut m = Mail::new();
+    m.add_to("test@example.com");
+    m.add_html("<p>Report</p>".repeat(100));
+    m.attachments
+        .insert("report.bin".to_string(), vec![7; 1000]);
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.len() <= estimated_body_size(&m));
+}
+
 #[test]
 fn test_proper_key() {
     let want = "files[test.jpg]";
-    let got = make_form_key("files", "test.jpg");
+    let got = make_form_key("files", "test.jpg").unwrap();
     assert_eq!(want, got);
 }
+
+#[test]
+fn hostile_form_keys() {
+    for key in ["", "a]b.txt", "a[b].txt", "a\r\nb.txt"] {
+        assert!(make_form_key("files", key).is_err());
+    }
+
+    let mut m = Mail::new();
+    m.add_content("logo&to=evil@example.com", "data");
+    let body = make_post_body(&m).unwrap();
+    assert!(body.starts_with("content%5Blogo%26to%3Devil%40example.com%5D=data&"));
+
+    let mut m = Mail::new();
+    m.add_content("a]b", "data");
+    assert!(make_post_body(&m).is_err());
+    #[cfg(feature = "client")]
+    assert!(make_multipart_form(&m).is_err());
+}
+
+#[test]
+fn non_ascii_attachment_key() {
+    let mut m = Mail::new();
+    m.add_to("test@example.com");
+    m.attachments
+        .insert("Rechnungsübersicht.pdf".to_string(), b"data".to_vec());
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.contains("&files%5BRechnungs%C3%BCbersicht.pdf%5D=data&"));
+}
+
+#[test]
+fn inline_attachment_body() {
+    let mut m = Mail::new();
+    m.add_html("<img src=\"cid:logo\">");
+    m.add_inline_attachment("logo.png", b"png".to_vec(), "logo");
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.starts_with("files%5Blogo.png%5D=png&content%5Blogo.png%5D=logo&"));
+    assert!(m.to_eml().contains("Content-ID: <logo>\r\n"));
+}
+
+#[test]
+fn send_each_at_body() {
+    let mut m = Mail::new();
+    m.add_to("a@example.com");
+    m.add_to("b@example.com");
+    m.add_send_each_at(1409348513);
+    m.add_send_each_at(1409348514);
+
+    let smtpapi = m.make_x_smtpapi_string().unwrap();
+    let want = r#"{"send_each_at":[1409348513,1409348514],"to":["a@example.com","b@example.com"]}"#;
+    assert_eq!(smtpapi, want);
+}
+
+#[test]
+fn send_each_at_length_mismatch() {
+    let mut m = Mail::new();
+    m.add_to("a@example.com");
+    m.add_to("b@example.com");
+    m.add_send_each_at(1409348513);
+
+    assert!(make_post_body(&m).is_err());
+
+    // A "to" list of the SMTPAPI header is what the timestamps are paired with.
+    let mut m = Mail::new();
+    m.add_to("a@example.com");
+    m.add_x_smtpapi(r#"{"to":["a@example.com","b@example.com"]}"#);
+    m.add_send_each_at(1409348513);
+    assert!(m.make_x_smtpapi_string().is_err());
+    m.add_send_each_at(1409348514);
+    assert!(m.make_x_smtpapi_string().is_ok());
+}
+
+#[test]
+fn send_each_at_encoded_recipients() {
+    let mut m = Mail::new();
+    m.add_to("jane@bücher.example");
+    m.add_send_each_at(1409348513);
+
+    let smtpapi = m.make_x_smtpapi_string().unwrap();
+    assert!(smtpapi.contains(r#""to":["jane@xn--bcher-kva.example"]"#));
+}
+
+#[test]
+fn from_name_body() {
+    use mail::EmailAddress;
+
+    let mut m = Mail::new();
+    m.add_to("test@example.com");
+    m.set_from(EmailAddress::with_name("me@example.com", "Me"));
+    m.set_reply_to(EmailAddress::with_name("reply@example.com", "Reply"));
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.contains("from=me%40example.com&"));
+    assert!(body.contains("&fromname=Me&replyto=reply%40example.com&"));
+}
+
+#[test]
+fn bulk_recipients_keep_names_aligned() {
+    let mut m = Mail::new();
+    m.add_to("first@example.com");
+    m.add_tos(vec![("a@example.com", None), ("b@example.com", Some("B"))]);
+    m.add_ccs(vec![("c@example.com", None)]);
+
+    assert_eq!(m.to.len(), 3);
+    assert_eq!(m.to_names, vec!["", "", "B"]);
+    assert!(m.cc_names.is_empty());
+}
+
+#[test]
+fn address_list_recipients() {
+    let mut m = Mail::new();
+    m.add_to("first@example.com");
+    m.add_to_list(r#""Doe, John" <j@example.com>, b@example.com"#)
+        .unwrap();
+
+    assert_eq!(
+        m.to,
+        vec!["first@example.com", "j@example.com", "b@example.com"]
+    );
+    assert_eq!(m.to_names, vec!["", "Doe, John", ""]);
+    assert!(m.add_cc_list("Alice <a@example.com").is_err());
+}
+
+#[test]
+fn auto_text_body() {
+    let mut m = Mail::new().with_auto_text();
+    m.add_html("<p>It works</p>");
+
+    assert!(make_post_body(&m).unwrap().contains("&text=It+works&"));
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn markdown_body() {
+    let mut m = Mail::new();
+    m.add_markdown("# Hello\n\nIt *works*");
+
+    assert_eq!(m.html, "<h1>Hello</h1>\n<p>It <em>works</em></p>\n");
+    assert_eq!(m.text, "# Hello\n\nIt *works*");
+}
+
+#[cfg(feature = "templates-local")]
+#[test]
+fn handlebars_body() {
+    use handlebars::Handlebars;
+    use std::collections::HashMap;
+
+    let mut registry = Handlebars::new();
+    registry
+        .register_template_string("html", "<p>Hello {{name}}!</p>")
+        .unwrap();
+    registry
+        .register_template_string("text", "Hello {{name}}!")
+        .unwrap();
+    let mut data = HashMap::new();
+    data.insert("name", "Ferris");
+
+    let mut m = Mail::new();
+    m.render_html_template(&registry, "html", &data).unwrap();
+    m.render_text_template(&registry, "text", &data).unwrap();
+
+    assert_eq!(m.html, "<p>Hello Ferris!</p>");
+    assert_eq!(m.text, "Hello Ferris!");
+}
+
+#[cfg(feature = "tera")]
+#[test]
+fn tera_body() {
+    use tera::{Context, Tera};
+
+    let mut tera = Tera::default();
+    tera.add_raw_template("welcome.html", "<p>Hello {{ name }}!</p>")
+        .unwrap();
+    let mut context = Context::new();
+    context.insert("name", "Ferris");
+
+    let m = Mail::from_tera(&tera, "welcome.html", &context).unwrap();
+    assert_eq!(m.html, "<p>Hello Ferris!</p>");
+}
+
+#[test]
+fn header_injection() {
+    let mut m = Mail::new();
+    assert!(m
+        .add_header("X-Cool", "indeed\r\nBcc: evil@example.com")
+        .is_err());
+    assert!(m.headers.is_empty());
+}
+
+#[test]
+fn list_unsubscribe_headers() {
+    let mut m = Mail::new();
+    m.set_list_unsubscribe(Some("unsub@example.com"), Some("https://example.com/u"))
+        .unwrap();
+
+    assert_eq!(
+        m.headers["List-Unsubscribe"],
+        "<mailto:unsub@example.com>, <https://example.com/u>"
+    );
+    assert_eq!(
+        m.headers["List-Unsubscribe-Post"],
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[test]
+fn reply_chain_headers() {
+    let mut m = Mail::new();
+    m.add_reference("first@example.com").unwrap();
+    m.set_in_reply_to("<second@example.com>").unwrap();
+    m.add_reference("<second@example.com>").unwrap();
+
+    assert_eq!(m.headers["In-Reply-To"], "<second@example.com>");
+    assert_eq!(
+        m.headers["References"],
+        "<first@example.com> <second@example.com>"
+    );
+    assert!(m.set_in_reply_to("a@example.com>\r\nBcc: b").is_err());
+}
+
+#[test]
+fn priority_headers() {
+    let mut m = Mail::new();
+    m.set_priority(::mail::Priority::High);
+
+    assert_eq!(m.headers["X-Priority"], "1 (Highest)");
+    assert_eq!(m.headers["Importance"], "High");
+    assert_eq!(m.headers["X-MSMail-Priority"], "High");
+}
+
+#[test]
+fn hostile_values_round_trip() {
+    use url::form_urlencoded;
+
+    let values = [
+        "a&to[]=evil@example.com",
+        "x=y+z%20",
+        "+++",
+        "Grüße 👋",
+        "line\r\nbreak",
+        "nul\u{0}byte",
+        "[brackets]",
+        "",
+    ];
+    for value in &values {
+        let mut m = Mail::new().without_auto_date();
+        m.add_to("you@example.com");
+        m.add_to_name(*value);
+        m.add_subject(*value);
+        m.add_content("id", *value);
+
+        let body = make_post_body(&m).unwrap();
+        let decoded: Vec<(String, String)> = form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect();
+        let get = |key: &str| decoded.iter().find(|(k, _)| k == key).map(|(_, v)| &v[..]);
+        assert_eq!(get("toname[]"), Some(*value));
+        assert_eq!(get("subject"), Some(*value));
+        assert_eq!(get("content[id]"), Some(*value));
+        assert_eq!(decoded.iter().filter(|(k, _)| k == "to[]").count(), 1);
+    }
+}
+
+#[test]
+fn sanitize_fields() {
+    let mut m = Mail::new();
+    m.add_subject("Hello\r\nBcc: evil@example.com");
+    m.add_tos(vec![
+        ("a@example.com", Some("A\u{0}")),
+        ("b@example.com", Some("B")),
+    ]);
+    m.add_header("X-Campaign", "spring\u{1b}").unwrap();
+
+    assert_eq!(
+        m.sanitize(),
+        vec!["subject", "to name of a@example.com", "header X-Campaign"]
+    );
+    assert_eq!(m.subject, "Hello  Bcc: evil@example.com");
+    assert_eq!(m.to_names, vec!["A", "B"]);
+    assert!(m.sanitize().is_empty());
+}
+
+#[test]
+fn body_keeps_insertion_order() {
+    let mut m = Mail::new();
+    for header in &["X-Zulu", "X-Alpha", "X-Mike"] {
+        m.add_header(*header, "1").unwrap();
+    }
+    m.add_content("zulu", "z");
+    m.add_content("alpha", "a");
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.contains("content%5Bzulu%5D=z&content%5Balpha%5D=a"));
+    assert!(body.contains(
+        "headers=%7B%22X-Zulu%22%3A%221%22%2C%22X-Alpha%22%3A%221%22%2C%22X-Mike%22%3A%221%22%7D"
+    ));
+}
+
+#[test]
+fn binary_attachment_body() {
+    let mut m = Mail::new();
+    m.attachments.insert(
+        "logo.png".to_string(),
+        vec![0x89, b'P', b'N', b'G', 0xff, 0x00],
+    );
+
+    let body = make_post_body(&m).unwrap();
+    assert!(body.starts_with("files%5Blogo.png%5D=%89PNG%FF%00&from="));
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn multipart_form_parts() {
+    let mut m = Mail::new().without_auto_date();
+    m.add_to("test@example.com");
+    m.add_subject("Report");
+    m.attachments
+        .insert("report.bin".to_string(), vec![0xff, 0x00, 0xfe]);
+
+    let (form, size) = make_multipart_form(&m).unwrap();
+    let form = format!("{:?}", form);
+    assert!(form.contains(r#""files[report.bin]""#));
+    assert!(form.contains(r#"file_name: Some("report.bin")"#));
+    assert!(form.contains(r#""to[]""#));
+    // The attachment counts as its three raw bytes, not URL encoded.
+    let fields = "test@example.com".len()
+        + "Report".len()
+        + m.make_header_string().unwrap().len()
+        + m.make_x_smtpapi_string().unwrap().len();
+    assert_eq!(size, 3 + fields);
+
+    m.attachments
+        .insert("huge.bin".to_string(), vec![0; 31 * 1024 * 1024]);
+    assert!(make_multipart_form(&m).is_err());
+}
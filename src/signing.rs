@@ -0,0 +1,99 @@
+//! Adding headers computed from each request before it is sent, for deployments that
+//! route SendGrid traffic through an egress gateway that checks an HMAC or similar
+//! signature of the requests.
+
+use errors::SendgridResult;
+use headers;
+
+use reqwest::header::Headers;
+
+use std::borrow::Cow;
+
+/// The parts of a request a signer can sign.
+#[derive(Clone, Copy, Debug)]
+pub struct SignableRequest<'a> {
+    /// The method, e.g. `POST`.
+    pub method: &'a str,
+    /// The path below the base URL of the V3 API, with its query, e.g. `/mail/send`.
+    pub path: &'a str,
+    /// The body as it is sent, compressed if the sender compresses it. Empty for
+    /// requests without a body.
+    pub body: &'a [u8],
+}
+
+/// Computes extra headers for requests to the V3 API. The headers are computed once
+/// for every call, before the first attempt, and sent with every attempt of it,
+/// including retries with another API key or on a fallback host.
+///
+/// Closures with the same signature as `sign` are signers too.
+///
+/// # Examples
+///
+/// ```ignore
+/// sender.set_request_signer(Arc::new(move |request: &SignableRequest| {
+///     let timestamp = unix_time().to_string();
+///     let signature = hmac_sha256(&gateway_key, &[request.method, request.path, &timestamp], request.body);
+///     Ok(vec![
+///         (String::from("X-Gateway-Timestamp"), timestamp),
+///         (String::from("X-Gateway-Signature"), hex(&signature)),
+///     ])
+/// }));
+/// ```
+pub trait RequestSigner: Send + Sync {
+    /// The names and values of the headers to add to a request. An error fails the
+    /// request before it is sent.
+    fn sign(&self, request: &SignableRequest) -> SendgridResult<Vec<(String, String)>>;
+}
+
+impl<F> RequestSigner for F
+where
+    F: Fn(&SignableRequest) -> SendgridResult<Vec<(String, String)>> + Send + Sync,
+{
+    fn sign(&self, request: &SignableRequest) -> SendgridResult<Vec<(String, String)>> {
+        self(request)
+    }
+}
+
+// Add the headers of a signer to a request. Fails with `InvalidHeaderName` or
+// `InvalidHeaderValue` when a header could break the request.
+pub(crate) fn sign(
+    signer: &dyn RequestSigner,
+    request: &SignableRequest,
+    headers: &mut Headers,
+) -> SendgridResult<()> {
+    for (name, value) in signer.sign(request)? {
+        headers::validate_name(&name)?;
+        let value = headers::encode_value(Cow::Owned(value))?;
+        headers.set_raw(name, value.into_owned());
+    }
+    Ok(())
+}
+
+#[test]
+fn sign_requests() {
+    let signer = |request: &SignableRequest| -> SendgridResult<Vec<(String, String)>> {
+        let digest = format!("{} {} {}", request.method, request.path, request.body.len());
+        Ok(vec![(String::from("X-Gateway-Signature"), digest)])
+    };
+    let request = SignableRequest {
+        method: "POST",
+        path: "/mail/send",
+        body: b"{}",
+    };
+    let mut headers = Headers::new();
+    sign(&signer, &request, &mut headers).unwrap();
+    let value = headers
+        .get_raw("X-Gateway-Signature")
+        .unwrap()
+        .one()
+        .unwrap();
+    assert_eq!(value, b"POST /mail/send 2");
+
+    let injecting = |_: &SignableRequest| -> SendgridResult<Vec<(String, String)>> {
+        Ok(vec![(
+            String::from("X-Gateway"),
+            String::from("a\r\nX-Other: b"),
+        )])
+    };
+    assert!(sign(&injecting, &request, &mut headers).is_err());
+}
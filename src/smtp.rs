@@ -0,0 +1,418 @@
+//! Sending messages through SendGrid's SMTP relay instead of the web API.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use keys::{self, Key};
+use mail::Mail;
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use data_encoding::BASE64;
+use native_tls::{HandshakeError, TlsConnector};
+
+/// The host of the SMTP relay.
+pub const SMTP_HOST: &str = "smtp.sendgrid.net";
+
+/// The submission port of the SMTP relay, which is upgraded to TLS with STARTTLS.
+pub const SMTP_PORT: u16 = 587;
+
+// The line length that folded headers are kept within.
+const MAX_LINE_LENGTH: usize = 78;
+
+/// Sends messages through the SMTP relay, using the API key as the password. This is
+/// useful where only outgoing connections on port 587 are allowed.
+///
+/// # Examples
+///
+/// ```ignore
+/// let transport = SmtpTransport::new(api_key);
+/// transport.send(&message)?;
+/// ```
+#[derive(Clone)]
+pub struct SmtpTransport {
+    api_key: Key,
+    host: String,
+    port: u16,
+    timeout: Option<Duration>,
+}
+
+// The API key is left out, so it can't end up in logs.
+impl fmt::Debug for SmtpTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SmtpTransport")
+            .field("api_key", &"[redacted]")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl SmtpTransport {
+    /// Construct a transport that connects to the SendGrid relay.
+    pub fn new(api_key: String) -> SmtpTransport {
+        SmtpTransport {
+            api_key: keys::to_key(api_key),
+            host: String::from(SMTP_HOST),
+            port: SMTP_PORT,
+            timeout: None,
+        }
+    }
+
+    /// Connect to another host, e.g. a proxy in front of the relay.
+    pub fn set_host(&mut self, host: &str, port: u16) {
+        self.host = String::from(host);
+        self.port = port;
+    }
+
+    /// Set how long to wait for every read and write on the connection.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Send a message over a new connection. BCC recipients get the message without
+    /// being listed in its headers.
+    pub fn send(&self, mail: &Mail) -> SendgridResult<()> {
+        let stream = TcpStream::connect((&self.host[..], self.port))?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut session = Session::new(stream);
+        session.expect(220)?;
+        session.command("EHLO localhost", 250)?;
+        session.command("STARTTLS", 220)?;
+
+        let connector = TlsConnector::builder()?.build()?;
+        let stream = match connector.connect(&self.host, session.into_inner()) {
+            Ok(stream) => stream,
+            Err(HandshakeError::Failure(e)) => return Err(e.into()),
+            // The socket only stops blocking when a read or write timed out.
+            Err(HandshakeError::Interrupted(_)) => {
+                let timeout =
+                    io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out");
+                return Err(timeout.into());
+            }
+        };
+
+        let mut session = Session::new(stream);
+        session.command("EHLO localhost", 250)?;
+        session.deliver(keys::expose(&self.api_key), mail)
+    }
+}
+
+struct Session<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Session<S> {
+    fn new(stream: S) -> Session<S> {
+        Session {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    // Authenticate and send a message on an established connection.
+    fn deliver(&mut self, api_key: &str, mail: &Mail) -> SendgridResult<()> {
+        let recipients = mail.to.iter().chain(&mail.cc).chain(&mail.bcc);
+        for address in ::std::iter::once(&mail.from.email).chain(recipients.clone()) {
+            check_path(address)?;
+        }
+        let smtpapi = mail.make_x_smtpapi_string()?;
+
+        let credentials = BASE64.encode(format!("\0apikey\0{}", api_key).as_bytes());
+        self.command(&format!("AUTH PLAIN {}", credentials), 235)?;
+
+        self.command(&format!("MAIL FROM:<{}>", mail.from.email), 250)?;
+        for rcpt in recipients {
+            self.command(&format!("RCPT TO:<{}>", rcpt), 250)?;
+        }
+
+        self.command("DATA", 354)?;
+        let mut message = String::new();
+        if !smtpapi.is_empty() {
+            message.push_str(&fold_json_header("X-SMTPAPI", &smtpapi));
+        }
+        message.push_str(&mail.to_eml());
+
+        // Once the data is on its way, the relay may have accepted the message even if
+        // no reply makes it back. Only a reply that rejects the data is a clear failure.
+        let sent = self
+            .write(&dot_stuff(&message))
+            .and_then(|()| self.expect(250));
+        if let Err(e) = sent {
+            return match *e.kind() {
+                SendgridErrorKind::Smtp(code, _) if code != 0 => Err(e),
+                _ => Err(SendgridErrorKind::Unconfirmed(e.to_string()).into()),
+            };
+        }
+
+        // The message was accepted with the 250, so a connection that ends without
+        // saying goodbye doesn't matter.
+        let _ = self.command("QUIT", 221);
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str, code: u16) -> SendgridResult<()> {
+        self.write(&format!("{}\r\n", command))?;
+        self.expect(code)
+    }
+
+    fn write(&mut self, data: &str) -> SendgridResult<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(data.as_bytes())?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    // Read a reply, which can span several lines, and check its code.
+    fn expect(&mut self, code: u16) -> SendgridResult<()> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                return Err(SendgridErrorKind::Smtp(0, reply).into());
+            }
+            reply.push_str(&line);
+            // A dash after the code means more lines follow.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+
+        let got = reply.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+        if got != code {
+            return Err(SendgridErrorKind::Smtp(got, String::from(reply.trim_end())).into());
+        }
+        Ok(())
+    }
+}
+
+// Check that an address can go between the angle brackets of a command, without line
+// breaks that would inject other commands.
+fn check_path(address: &str) -> SendgridResult<()> {
+    if address
+        .chars()
+        .any(|c| c.is_control() || c == '<' || c == '>')
+    {
+        return Err(SendgridErrorKind::InvalidAddress(String::from(address)).into());
+    }
+    Ok(())
+}
+
+// Write a header with a JSON value, folding it after the commas between items so lines
+// stay within 78 characters unless a single item is longer. Commas inside of strings are
+// left alone, as folding there would change the value.
+fn fold_json_header(name: &str, json: &str) -> String {
+    let mut header = format!("{}: ", name);
+    let mut line_length = header.len();
+    let mut line_items = 0;
+    let mut push_item = |header: &mut String, item: &str| {
+        if line_items > 0 && line_length + item.len() > MAX_LINE_LENGTH {
+            header.push_str("\r\n ");
+            line_length = 1;
+            line_items = 0;
+        }
+        header.push_str(item);
+        line_length += item.len();
+        line_items += 1;
+    };
+
+    let (mut start, mut in_string, mut escaped) = (0, false, false);
+    for (i, c) in json.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                push_item(&mut header, &json[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    push_item(&mut header, &json[start..]);
+    header.push_str("\r\n");
+    header
+}
+
+// Escape lines starting with a dot and end the data with a lone dot.
+fn dot_stuff(message: &str) -> String {
+    let mut out = String::with_capacity(message.len() + 5);
+    for line in message.split("\r\n") {
+        if line.starts_with('.') {
+            out.push('.');
+        }
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+    // Splitting a message that ends with a line break leaves an empty last line.
+    if message.ends_with("\r\n") {
+        out.truncate(out.len() - 2);
+    }
+    out.push_str(".\r\n");
+    out
+}
+
+#[test]
+fn smtp_dialogue() {
+    use std::io::Cursor;
+
+    struct Script {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let replies = "235 2.7.0 Authentication successful\r\n\
+                   250 Sender address accepted\r\n\
+                   250 Recipient address accepted\r\n\
+                   250 Recipient address accepted\r\n\
+                   354 Continue\r\n\
+                   250 Ok: queued as abc\r\n\
+                   221 See you later\r\n";
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_to("you@example.com");
+    m.add_bcc("hidden@example.com");
+    m.add_text(".hidden?");
+
+    let mut session = Session::new(Script {
+        replies: Cursor::new(replies.as_bytes().to_vec()),
+        sent: Vec::new(),
+    });
+    session.deliver("SG.key", &m).unwrap();
+
+    let sent = String::from_utf8(session.into_inner().sent).unwrap();
+    assert!(sent.starts_with("AUTH PLAIN AGFwaWtleQBTRy5rZXk=\r\nMAIL FROM:<me@example.com>\r\n"));
+    assert!(sent.contains("RCPT TO:<you@example.com>\r\nRCPT TO:<hidden@example.com>\r\nDATA\r\n"));
+    assert!(!sent.contains("Bcc:"));
+    assert!(sent.contains("\r\n..hidden?\r\n.\r\nQUIT\r\n"));
+
+    let mut session = Session::new(Script {
+        replies: Cursor::new(b"535-Authentication\r\n535 failed\r\n".to_vec()),
+        sent: Vec::new(),
+    });
+    match *session.deliver("SG.key", &m).unwrap_err().kind() {
+        SendgridErrorKind::Smtp(535, _) => {}
+        ref e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn smtp_api_header() {
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_x_smtpapi(r#"{"category":["newsletter, weekly"],"unique_args":{"campaign":"spring"}}"#);
+    for (i, to) in [
+        "first@example.com",
+        "second@example.com",
+        "third@example.com",
+    ]
+    .iter()
+    .enumerate()
+    {
+        m.add_to(*to);
+        m.add_send_each_at(1_500_000_000 + i as u64);
+    }
+    let smtpapi = m.make_x_smtpapi_string().unwrap();
+
+    let header = fold_json_header("X-SMTPAPI", &smtpapi);
+    assert!(header.contains("send_each_at"));
+    assert!(header.contains(r#""newsletter, weekly""#));
+    assert!(header.lines().count() > 1);
+    assert!(header.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+    let unfolded = header.replace("\r\n ", "");
+    assert_eq!(unfolded, format!("X-SMTPAPI: {}\r\n", smtpapi));
+}
+
+#[test]
+fn smtp_debug_redacts_key() {
+    let transport = SmtpTransport::new(String::from("SG.secret"));
+    assert!(!format!("{:?}", transport).contains("SG.secret"));
+}
+
+#[test]
+fn smtp_rejects_line_breaks() {
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_to("you@example.com>\r\nRCPT TO:<other@example.com");
+
+    let mut session = Session::new(::std::io::Cursor::new(Vec::new()));
+    match *session.deliver("SG.key", &m).unwrap_err().kind() {
+        SendgridErrorKind::InvalidAddress(_) => {}
+        ref e => panic!("unexpected error: {}", e),
+    }
+    // Nothing was sent.
+    assert!(session.into_inner().into_inner().is_empty());
+}
+
+#[test]
+fn smtp_disconnects() {
+    use std::io::Cursor;
+
+    // A relay that closes the connection once all its replies are read.
+    struct Script(Cursor<Vec<u8>>);
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let before_data = "235 2.7.0 Authentication successful\r\n\
+                       250 Sender address accepted\r\n\
+                       250 Recipient address accepted\r\n\
+                       354 Continue\r\n";
+    let mut m = Mail::new();
+    m.set_from("me@example.com");
+    m.add_to("you@example.com");
+    m.add_text("Hi");
+
+    let accepted = format!("{}250 Ok: queued as abc\r\n", before_data);
+    let mut session = Session::new(Script(Cursor::new(accepted.into_bytes())));
+    session.deliver("SG.key", &m).unwrap();
+
+    let mut session = Session::new(Script(Cursor::new(before_data.as_bytes().to_vec())));
+    match *session.deliver("SG.key", &m).unwrap_err().kind() {
+        SendgridErrorKind::Unconfirmed(_) => {}
+        ref e => panic!("unexpected error: {}", e),
+    }
+
+    let rejected = format!("{}451 Try again later\r\n", before_data);
+    let mut session = Session::new(Script(Cursor::new(rejected.into_bytes())));
+    match *session.deliver("SG.key", &m).unwrap_err().kind() {
+        SendgridErrorKind::Smtp(451, _) => {}
+        ref e => panic!("unexpected error: {}", e),
+    }
+}
@@ -0,0 +1,219 @@
+//! Simple A/B tests of subjects and content, where every recipient is assigned one of
+//! several variants and the webhook events tell which variant did better.
+
+use v3::{Content, Email, Personalization, SGMailV3, MAX_PERSONALIZATIONS};
+
+/// The custom argument with the name of the variant a recipient got, which comes back in
+/// every webhook event of their message.
+pub const VARIANT_ARG: &str = "split_variant";
+
+/// The custom argument with the name of the test.
+pub const TEST_ARG: &str = "split_test";
+
+/// The subject and either the content or the template of one variant.
+#[derive(Clone, Default)]
+pub struct Variant {
+    name: String,
+    subject: String,
+    html: Option<String>,
+    text: Option<String>,
+    template_id: Option<String>,
+}
+
+impl Variant {
+    /// Construct a new variant with the given name, which is stamped on its messages,
+    /// and subject.
+    pub fn new(name: &str, subject: &str) -> Variant {
+        Variant {
+            name: String::from(name),
+            subject: String::from(subject),
+            ..Variant::default()
+        }
+    }
+
+    /// Set the HTML content.
+    pub fn set_html(&mut self, html: &str) {
+        self.html = Some(String::from(html));
+    }
+
+    /// Set the text content.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = Some(String::from(text));
+    }
+
+    /// Use a template instead of the HTML and text content.
+    pub fn set_template_id(&mut self, template_id: &str) {
+        self.template_id = Some(String::from(template_id));
+    }
+
+    /// The name of the variant.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Splits recipients across variants and builds a message per variant. Recipients are
+/// assigned by a hash of their address and the name of the test, so the same recipient
+/// always gets the same variant of a test, even across runs and processes.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut test = SplitTest::new(from, "spring-sale");
+/// let mut a = Variant::new("a", "Spring sale: 20% off");
+/// a.set_html(&html);
+/// let mut b = Variant::new("b", "Our spring sale starts today");
+/// b.set_html(&html);
+/// test.add_variant(a);
+/// test.add_variant(b);
+/// let report = sender.send_all(test.build(users.iter().map(|u| u.email())));
+/// ```
+pub struct SplitTest {
+    from: Email,
+    name: String,
+    variants: Vec<Variant>,
+}
+
+impl SplitTest {
+    /// Construct a new test with the given name, sent from the given address.
+    pub fn new(from: Email, name: &str) -> SplitTest {
+        SplitTest {
+            from,
+            name: String::from(name),
+            variants: Vec::new(),
+        }
+    }
+
+    /// Add a variant. Recipients are split evenly across the variants.
+    pub fn add_variant(&mut self, variant: Variant) {
+        self.variants.push(variant);
+    }
+
+    /// The variant a recipient gets, or `None` when the test has no variants.
+    pub fn variant_for(&self, address: &str) -> Option<&Variant> {
+        self.variant_index(address).map(|i| &self.variants[i])
+    }
+
+    fn variant_index(&self, address: &str) -> Option<usize> {
+        if self.variants.is_empty() {
+            return None;
+        }
+        let address = address.trim().bytes().map(|b| b.to_ascii_lowercase());
+        let hash = fnv1a(self.name.bytes().chain(Some(0)).chain(address));
+        Some((hash % self.variants.len() as u64) as usize)
+    }
+
+    /// Build the messages for a list of recipients. Every variant gets its own messages,
+    /// with a personalization per recipient and the `VARIANT_ARG` and `TEST_ARG` custom
+    /// arguments set.
+    pub fn build<I: IntoIterator<Item = Email>>(&self, recipients: I) -> Vec<SGMailV3> {
+        let mut groups: Vec<Vec<Vec<Personalization>>> =
+            self.variants.iter().map(|_| Vec::new()).collect();
+
+        for to in recipients {
+            let index = match self.variant_index(to.email()) {
+                Some(index) => index,
+                None => break,
+            };
+
+            let mut p = Personalization::new();
+            p.add_to(to);
+            let chunks = &mut groups[index];
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() < MAX_PERSONALIZATIONS => chunk.push(p),
+                _ => chunks.push(vec![p]),
+            }
+        }
+
+        let mut messages = Vec::new();
+        for (variant, chunks) in self.variants.iter().zip(groups) {
+            for chunk in chunks {
+                let mut message = self.new_message(variant);
+                for p in chunk {
+                    message.add_personalization(p);
+                }
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    fn new_message(&self, variant: &Variant) -> SGMailV3 {
+        let mut message = SGMailV3::new();
+        message.set_from(self.from.clone());
+        message.set_subject(&variant.subject);
+        message.add_custom_arg(VARIANT_ARG, &variant.name);
+        message.add_custom_arg(TEST_ARG, &self.name);
+
+        match variant.template_id {
+            Some(ref template_id) => message.set_template_id(template_id),
+            None => {
+                // SendGrid requires the text part to come before the HTML part.
+                for &(content_type, value) in
+                    &[("text/plain", &variant.text), ("text/html", &variant.html)]
+                {
+                    if let Some(ref value) = *value {
+                        let mut c = Content::new();
+                        c.set_content_type(content_type);
+                        c.set_value(value);
+                        message.add_content(c);
+                    }
+                }
+            }
+        }
+
+        message
+    }
+}
+
+// 64-bit FNV-1a, which unlike the standard library's hasher is guaranteed to give the
+// same assignment in every Rust version.
+fn fnv1a<I: Iterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn split_recipients() {
+    let mut from = Email::new();
+    from.set_email("me@example.com");
+    let mut test = SplitTest::new(from, "subjects");
+    assert!(test.variant_for("a@example.com").is_none());
+    for name in &["a", "b", "c"] {
+        let mut v = Variant::new(name, &format!("Subject {}", name));
+        v.set_text("Hello");
+        test.add_variant(v);
+    }
+
+    let addresses: Vec<String> = (0..300).map(|i| format!("user{}@example.com", i)).collect();
+    let recipients = addresses.iter().map(|a| {
+        let mut to = Email::new();
+        to.set_email(a);
+        to
+    });
+    let messages = test.build(recipients);
+    assert_eq!(messages.len(), 3);
+
+    let mut total = 0;
+    for (message, name) in messages.iter().zip(&["a", "b", "c"]) {
+        let json = ::serde_json::to_value(message).unwrap();
+        assert_eq!(json["custom_args"][VARIANT_ARG], *name);
+        assert_eq!(json["subject"], format!("Subject {}", name));
+        let ps = json["personalizations"].as_array().unwrap();
+        // The split is roughly even.
+        assert!(ps.len() > 60, "{} got {} recipients", name, ps.len());
+        for p in ps {
+            let address = p["to"][0]["email"].as_str().unwrap();
+            assert_eq!(test.variant_for(address).unwrap().name(), *name);
+        }
+        total += ps.len();
+    }
+    assert_eq!(total, 300);
+
+    // Assignments don't depend on case.
+    assert_eq!(
+        test.variant_for("User7@Example.com").unwrap().name(),
+        test.variant_for("user7@example.com").unwrap().name()
+    );
+}
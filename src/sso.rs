@@ -0,0 +1,273 @@
+//! The single sign-on settings of enterprise accounts: the SAML integrations with an
+//! identity provider, their certificates, and the teammates that sign in through them.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use reqwest::Method;
+
+/// The SSO settings endpoints.
+pub struct SsoSettings;
+
+impl Endpoint for SsoSettings {
+    const SCOPES: &'static [&'static str] = &[
+        "sso.settings.create",
+        "sso.settings.delete",
+        "sso.settings.read",
+        "sso.settings.update",
+        "sso.teammates.create",
+        "sso.teammates.update",
+    ];
+}
+
+/// The settings of a SAML integration, as sent to create or update one.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SsoIntegrationSettings {
+    pub name: String,
+    pub enabled: bool,
+    /// The URL of the identity provider that teammates are sent to to sign in.
+    pub signin_url: String,
+    /// The URL of the identity provider that teammates are sent to when they sign out.
+    pub signout_url: String,
+    /// The entity ID of the identity provider.
+    pub entity_id: String,
+    /// Whether the integration was set up on both sides. Integrations can't be enabled
+    /// before this is set.
+    pub completed_integration: bool,
+}
+
+/// A SAML integration as stored by SendGrid.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SsoIntegration {
+    pub id: String,
+    #[serde(flatten)]
+    pub settings: SsoIntegrationSettings,
+    /// The URL to enter as the single sign-on URL in the identity provider.
+    #[serde(default)]
+    pub single_signon_url: String,
+    /// The URL to enter as the audience URI in the identity provider.
+    #[serde(default)]
+    pub audience_url: String,
+    /// When the integration was last changed, as a Unix timestamp.
+    #[serde(default)]
+    pub last_updated: u64,
+    /// The certificates of the integration, only set by `sso_integrations_with_certificates`.
+    #[serde(default)]
+    pub certificates: Vec<SsoCertificate>,
+    /// Other settings of the integration that don't have a field here.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A certificate the identity provider signs SAML responses with.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SsoCertificate {
+    pub id: u64,
+    /// The certificate in PEM format.
+    pub public_certificate: String,
+    /// The start of the validity of the certificate, as a Unix timestamp.
+    pub not_before: u64,
+    /// The end of the validity of the certificate, as a Unix timestamp.
+    pub not_after: u64,
+    // SendGrid spells this `intergration_id` in its responses.
+    #[serde(alias = "intergration_id")]
+    pub integration_id: String,
+    /// Other fields of the certificate, such as the integrations using it.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The details of a teammate that signs in through SSO. Teammates are either admins or
+/// get the listed scopes.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SsoTeammate {
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub is_admin: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct NewCertificate<'a> {
+    public_certificate: &'a str,
+    enabled: bool,
+    integration_id: &'a str,
+}
+
+#[derive(Serialize)]
+struct TeammateUpdate<'a> {
+    first_name: &'a str,
+    last_name: &'a str,
+    is_admin: bool,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    scopes: &'a [String],
+}
+
+impl V3Sender {
+    /// Get all the SAML integrations of the account, without their certificates.
+    pub fn sso_integrations(&self) -> SendgridResult<Vec<SsoIntegration>> {
+        self.api_get("/sso/integrations")
+    }
+
+    /// Get all the SAML integrations of the account along with their certificates.
+    pub fn sso_integrations_with_certificates(&self) -> SendgridResult<Vec<SsoIntegration>> {
+        self.api_get("/sso/integrations?si=true")
+    }
+
+    /// Get a SAML integration.
+    pub fn sso_integration(&self, id: &str) -> SendgridResult<SsoIntegration> {
+        self.api_get(&format!("/sso/integrations/{}", id))
+    }
+
+    /// Create a SAML integration. Enter its `single_signon_url` and `audience_url` in
+    /// the identity provider, then add the certificate of the identity provider with
+    /// `create_sso_certificate` and update the integration as completed.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let integration = sender.create_sso_integration(&SsoIntegrationSettings {
+    ///     name: String::from("Okta"),
+    ///     signin_url: String::from("https://example.okta.com/app/sso/saml"),
+    ///     signout_url: String::from("https://example.okta.com/login/signout"),
+    ///     entity_id: String::from("http://www.okta.com/exk1"),
+    ///     ..Default::default()
+    /// })?;
+    /// println!("ACS URL: {}", integration.single_signon_url);
+    /// ```
+    pub fn create_sso_integration(
+        &self,
+        settings: &SsoIntegrationSettings,
+    ) -> SendgridResult<SsoIntegration> {
+        let res = self.api_send_json(Method::Post, "/sso/integrations", settings)?;
+        self.read_json(res)
+    }
+
+    /// Replace the settings of a SAML integration.
+    pub fn update_sso_integration(
+        &self,
+        id: &str,
+        settings: &SsoIntegrationSettings,
+    ) -> SendgridResult<SsoIntegration> {
+        let path = format!("/sso/integrations/{}", id);
+        let res = self.api_send_json(Method::Patch, &path, settings)?;
+        self.read_json(res)
+    }
+
+    /// Delete a SAML integration.
+    pub fn delete_sso_integration(&self, id: &str) -> SendgridResult<()> {
+        self.api_request(Method::Delete, &format!("/sso/integrations/{}", id), None)?;
+        Ok(())
+    }
+
+    /// Get the certificates of a SAML integration.
+    pub fn sso_certificates(&self, integration_id: &str) -> SendgridResult<Vec<SsoCertificate>> {
+        self.api_get(&format!(
+            "/sso/integrations/{}/certificates",
+            integration_id
+        ))
+    }
+
+    /// Get an SSO certificate.
+    pub fn sso_certificate(&self, id: u64) -> SendgridResult<SsoCertificate> {
+        self.api_get(&format!("/sso/certificates/{}", id))
+    }
+
+    /// Add the certificate of the identity provider to a SAML integration.
+    pub fn create_sso_certificate(
+        &self,
+        integration_id: &str,
+        public_certificate: &str,
+        enabled: bool,
+    ) -> SendgridResult<SsoCertificate> {
+        let body = NewCertificate {
+            public_certificate,
+            enabled,
+            integration_id,
+        };
+        let res = self.api_send_json(Method::Post, "/sso/certificates", &body)?;
+        self.read_json(res)
+    }
+
+    /// Replace the PEM of an SSO certificate, e.g. when the identity provider rotates
+    /// its signing certificate.
+    pub fn update_sso_certificate(
+        &self,
+        id: u64,
+        integration_id: &str,
+        public_certificate: &str,
+        enabled: bool,
+    ) -> SendgridResult<SsoCertificate> {
+        let body = NewCertificate {
+            public_certificate,
+            enabled,
+            integration_id,
+        };
+        let path = format!("/sso/certificates/{}", id);
+        let res = self.api_send_json(Method::Patch, &path, &body)?;
+        self.read_json(res)
+    }
+
+    /// Delete an SSO certificate.
+    pub fn delete_sso_certificate(&self, id: u64) -> SendgridResult<()> {
+        self.api_request(Method::Delete, &format!("/sso/certificates/{}", id), None)?;
+        Ok(())
+    }
+
+    /// Add a teammate that signs in through SSO.
+    pub fn create_sso_teammate(&self, teammate: &SsoTeammate) -> SendgridResult<SsoTeammate> {
+        let res = self.api_send_json(Method::Post, "/sso/teammates", teammate)?;
+        self.read_json(res)
+    }
+
+    /// Change the name, admin flag or scopes of an SSO teammate. Teammates are
+    /// identified by their username, which is their email address.
+    pub fn update_sso_teammate(
+        &self,
+        username: &str,
+        teammate: &SsoTeammate,
+    ) -> SendgridResult<SsoTeammate> {
+        let body = TeammateUpdate {
+            first_name: &teammate.first_name,
+            last_name: &teammate.last_name,
+            is_admin: teammate.is_admin,
+            scopes: &teammate.scopes,
+        };
+        let path = format!("/sso/teammates/{}", username);
+        let res = self.api_send_json(Method::Patch, &path, &body)?;
+        self.read_json(res)
+    }
+}
+
+#[test]
+fn sso_integration() {
+    let integration: SsoIntegration = ::serde_json::from_str(
+        r#"{"id":"b0b98502","name":"Okta","enabled":false,"signin_url":"https://idp/sso","signout_url":"https://idp/out","entity_id":"urn:idp","completed_integration":false,"last_updated":1621188382,"single_signon_url":"https://api.sendgrid.com/v3/public/sso/saml/response/id/b0b98502","audience_url":"https://api.sendgrid.com/v3/public/sso/saml/response/id/b0b98502","certificates":[{"id":66,"public_certificate":"-----BEGIN CERTIFICATE-----","not_before":1,"not_after":2,"intergration_id":"b0b98502"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(integration.settings.name, "Okta");
+    assert_eq!(integration.certificates[0].integration_id, "b0b98502");
+
+    let body = ::serde_json::to_value(&integration.settings).unwrap();
+    assert_eq!(body["entity_id"], "urn:idp");
+    assert!(body.get("id").is_none());
+
+    let teammate = SsoTeammate {
+        email: String::from("ada@example.com"),
+        is_admin: true,
+        ..SsoTeammate::default()
+    };
+    let body = ::serde_json::to_string(&teammate).unwrap();
+    assert_eq!(
+        body,
+        r#"{"email":"ada@example.com","first_name":"","last_name":"","is_admin":true}"#
+    );
+}
@@ -0,0 +1,441 @@
+//! The statistics endpoints, which aggregate the events of sent messages by day, week or
+//! month.
+
+use cancel::{self, CancellationToken};
+use errors::SendgridResult;
+use headers;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "csv")]
+use csv;
+#[cfg(feature = "csv")]
+use std::io::Write;
+use url::form_urlencoded::Serializer;
+
+/// Reading global, category and subuser statistics.
+pub struct Stats;
+
+impl Endpoint for Stats {
+    const SCOPES: &'static [&'static str] =
+        &["categories.stats.read", "stats.read", "subusers.stats.read"];
+}
+
+/// The date range and aggregation of a statistics request. Dates are formatted as
+/// `YYYY-MM-DD`.
+#[derive(Clone, Debug, Default)]
+pub struct StatsQuery {
+    pub start_date: String,
+    pub end_date: Option<String>,
+    /// Either `day`, `week` or `month`. Defaults to `day`.
+    pub aggregated_by: Option<String>,
+}
+
+impl StatsQuery {
+    /// Construct a new query starting at the given date.
+    pub fn new(start_date: &str) -> StatsQuery {
+        StatsQuery {
+            start_date: String::from(start_date),
+            ..Default::default()
+        }
+    }
+
+    /// Construct a query for the statistics of the current month so far, in UTC, as a
+    /// single period.
+    pub fn month_to_date() -> StatsQuery {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let (year, month, _) = headers::civil_date(now.as_secs());
+        StatsQuery {
+            start_date: format!("{:04}-{:02}-01", year, month),
+            end_date: None,
+            aggregated_by: Some(String::from("month")),
+        }
+    }
+
+    fn to_query_string(&self, extra: &[(&str, &str)]) -> String {
+        let mut query = Serializer::new(String::new());
+        query.append_pair("start_date", &self.start_date);
+        if let Some(ref end_date) = self.end_date {
+            query.append_pair("end_date", end_date);
+        }
+        if let Some(ref aggregated_by) = self.aggregated_by {
+            query.append_pair("aggregated_by", aggregated_by);
+        }
+        for &(key, value) in extra {
+            query.append_pair(key, value);
+        }
+        query.finish()
+    }
+}
+
+/// The statistics for one day, week or month.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StatsPeriod {
+    /// The first day of the period.
+    pub date: String,
+    pub stats: Vec<StatsEntry>,
+    /// Other fields of the period, such as ones SendGrid added after this release.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The statistics of everything, or of one category or subuser.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct StatsEntry {
+    /// Either `category` or `subuser`, and empty for global statistics.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// The name of the category or subuser.
+    pub name: Option<String>,
+    pub metrics: Metrics,
+    /// Other fields of the entry, for groupings other than categories and subusers.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The number of events of every type.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Metrics {
+    pub blocks: u64,
+    pub bounce_drops: u64,
+    pub bounces: u64,
+    pub clicks: u64,
+    pub deferred: u64,
+    pub delivered: u64,
+    pub invalid_emails: u64,
+    pub opens: u64,
+    pub processed: u64,
+    pub requests: u64,
+    pub spam_report_drops: u64,
+    pub spam_reports: u64,
+    pub unique_clicks: u64,
+    pub unique_opens: u64,
+    pub unsubscribe_drops: u64,
+    pub unsubscribes: u64,
+    /// Metrics SendGrid added that don't have a field here yet.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+// The names of the metrics, in the order `Metrics::values` returns them.
+#[cfg(feature = "csv")]
+const METRIC_NAMES: [&str; 16] = [
+    "blocks",
+    "bounce_drops",
+    "bounces",
+    "clicks",
+    "deferred",
+    "delivered",
+    "invalid_emails",
+    "opens",
+    "processed",
+    "requests",
+    "spam_report_drops",
+    "spam_reports",
+    "unique_clicks",
+    "unique_opens",
+    "unsubscribe_drops",
+    "unsubscribes",
+];
+
+impl Metrics {
+    /// The values of all the metrics, in alphabetical order of their names.
+    pub fn values(&self) -> [u64; 16] {
+        [
+            self.blocks,
+            self.bounce_drops,
+            self.bounces,
+            self.clicks,
+            self.deferred,
+            self.delivered,
+            self.invalid_emails,
+            self.opens,
+            self.processed,
+            self.requests,
+            self.spam_report_drops,
+            self.spam_reports,
+            self.unique_clicks,
+            self.unique_opens,
+            self.unsubscribe_drops,
+            self.unsubscribes,
+        ]
+    }
+}
+
+/// Write the statistics from any of the statistics endpoints as CSV, with one row for
+/// every period and category or subuser.
+///
+/// # Examples
+///
+/// ```ignore
+/// let stats = sender.category_stats(&["newsletter"], &StatsQuery::new("2018-01-01"))?;
+/// write_csv(&stats, File::create("stats.csv")?)?;
+/// ```
+#[cfg(feature = "csv")]
+pub fn write_csv<W: Write>(periods: &[StatsPeriod], writer: W) -> SendgridResult<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    let mut header = vec!["date", "type", "name"];
+    header.extend_from_slice(&METRIC_NAMES);
+    writer.write_record(&header)?;
+
+    for period in periods {
+        for entry in &period.stats {
+            let mut record = vec![
+                period.date.clone(),
+                entry.kind.clone().unwrap_or_default(),
+                entry.name.clone().unwrap_or_default(),
+            ];
+            record.extend(entry.metrics.values().iter().map(|v| v.to_string()));
+            writer.write_record(&record)?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+impl V3Sender {
+    /// Get the statistics of all messages.
+    pub fn global_stats(&self, query: &StatsQuery) -> SendgridResult<Vec<StatsPeriod>> {
+        self.api_get(&format!("/stats?{}", query.to_query_string(&[])))
+    }
+
+    /// Get the statistics of the messages in the given categories.
+    pub fn category_stats(
+        &self,
+        categories: &[&str],
+        query: &StatsQuery,
+    ) -> SendgridResult<Vec<StatsPeriod>> {
+        let extra: Vec<_> = categories.iter().map(|c| ("categories", *c)).collect();
+        self.api_get(&format!(
+            "/categories/stats?{}",
+            query.to_query_string(&extra)
+        ))
+    }
+
+    /// Get the statistics of the messages sent by the given subusers.
+    pub fn subuser_stats(
+        &self,
+        subusers: &[&str],
+        query: &StatsQuery,
+    ) -> SendgridResult<Vec<StatsPeriod>> {
+        let extra: Vec<_> = subusers.iter().map(|s| ("subusers", *s)).collect();
+        self.api_get(&format!(
+            "/subusers/stats?{}",
+            query.to_query_string(&extra)
+        ))
+    }
+}
+
+/// The statistics fetched by a `StatsWatcher`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsSnapshot {
+    /// The statistics of all messages.
+    pub global: Vec<StatsPeriod>,
+    /// The statistics of the watched categories, if any were given.
+    pub categories: Vec<StatsPeriod>,
+    /// When the statistics were fetched.
+    pub fetched_at: SystemTime,
+}
+
+/// Fetches statistics on a background thread at a fixed interval, keeping the latest
+/// snapshot around for dashboards. The thread stops when the watcher is dropped.
+///
+/// # Examples
+///
+/// ```ignore
+/// let watcher = StatsWatcher::spawn(
+///     Arc::new(sender),
+///     Duration::from_secs(60),
+///     StatsQuery::new("2018-01-01"),
+///     &["newsletter"],
+/// );
+/// for snapshot in watcher.changes() {
+///     dashboard.update(&snapshot.global);
+/// }
+/// ```
+pub struct StatsWatcher {
+    shared: Arc<Watched>,
+    stop: CancellationToken,
+}
+
+#[derive(Default)]
+struct Watched {
+    latest: Mutex<Option<StatsSnapshot>>,
+    last_error: Mutex<Option<String>>,
+    subscribers: Mutex<Vec<Sender<StatsSnapshot>>>,
+}
+
+impl StatsWatcher {
+    /// Start fetching the global statistics, and those of the categories when any are
+    /// given, every `interval`. The first fetch happens right away. Requires the
+    /// `stats.read` scope, and `categories.stats.read` for categories.
+    pub fn spawn(
+        sender: Arc<V3Sender>,
+        interval: Duration,
+        query: StatsQuery,
+        categories: &[&str],
+    ) -> StatsWatcher {
+        let categories: Vec<String> = categories.iter().map(|c| String::from(*c)).collect();
+        StatsWatcher::spawn_with(interval, move || {
+            let global = sender.global_stats(&query)?;
+            let categories = if categories.is_empty() {
+                Vec::new()
+            } else {
+                let categories: Vec<&str> = categories.iter().map(|c| c.as_str()).collect();
+                sender.category_stats(&categories, &query)?
+            };
+            Ok((global, categories))
+        })
+    }
+
+    fn spawn_with<F>(interval: Duration, mut fetch: F) -> StatsWatcher
+    where
+        F: FnMut() -> SendgridResult<(Vec<StatsPeriod>, Vec<StatsPeriod>)> + Send + 'static,
+    {
+        let shared = Arc::new(Watched::default());
+        let stop = CancellationToken::new();
+
+        let watched = Arc::clone(&shared);
+        let stopped = stop.clone();
+        thread::spawn(move || loop {
+            match fetch() {
+                Ok((global, categories)) => {
+                    *watched.last_error.lock().unwrap() = None;
+                    watched.update(StatsSnapshot {
+                        global,
+                        categories,
+                        fetched_at: SystemTime::now(),
+                    });
+                }
+                Err(e) => *watched.last_error.lock().unwrap() = Some(e.to_string()),
+            }
+            if cancel::sleep(interval, Some(&stopped), None).is_err() {
+                return;
+            }
+        });
+
+        StatsWatcher { shared, stop }
+    }
+
+    /// The statistics from the last successful fetch.
+    pub fn latest(&self) -> Option<StatsSnapshot> {
+        self.shared.latest.lock().unwrap().clone()
+    }
+
+    /// Why the last fetch failed, if it did. The latest snapshot is kept when a fetch
+    /// fails.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared.last_error.lock().unwrap().clone()
+    }
+
+    /// Subscribe to new snapshots. A snapshot is only sent when the statistics differ
+    /// from the ones fetched before it.
+    pub fn changes(&self) -> Receiver<StatsSnapshot> {
+        let (tx, rx) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+impl Drop for StatsWatcher {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+impl Watched {
+    fn update(&self, snapshot: StatsSnapshot) {
+        let mut latest = self.latest.lock().unwrap();
+        let changed = latest.as_ref().is_none_or(|previous| {
+            previous.global != snapshot.global || previous.categories != snapshot.categories
+        });
+        if changed {
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|tx| tx.send(snapshot.clone()).is_ok());
+        }
+        *latest = Some(snapshot);
+    }
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn stats_csv() {
+    let periods: Vec<StatsPeriod> = ::serde_json::from_str(
+        r#"[{"date":"2018-01-01","stats":[{"type":"category","name":"news","metrics":{"delivered":10,"opens":4}}]}]"#,
+    )
+    .unwrap();
+
+    let mut out = Vec::new();
+    write_csv(&periods, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let mut lines = out.lines();
+    assert!(lines.next().unwrap().starts_with("date,type,name,blocks,"));
+    assert_eq!(
+        lines.next().unwrap(),
+        "2018-01-01,category,news,0,0,0,0,0,10,0,4,0,0,0,0,0,0,0,0"
+    );
+}
+
+#[test]
+fn watch_stats() {
+    use errors::SendgridErrorKind;
+
+    let period = |delivered: u64| StatsPeriod {
+        date: String::from("2018-01-01"),
+        stats: vec![StatsEntry {
+            kind: None,
+            name: None,
+            metrics: Metrics {
+                delivered,
+                ..Default::default()
+            },
+            #[cfg(feature = "extra-fields")]
+            extra: Default::default(),
+        }],
+        #[cfg(feature = "extra-fields")]
+        extra: Default::default(),
+    };
+
+    // Every fetch waits for the test to hand it a result.
+    let (results, next) = mpsc::channel::<SendgridResult<StatsPeriod>>();
+    let watcher = StatsWatcher::spawn_with(Duration::from_millis(1), move || {
+        let result = next
+            .recv()
+            .unwrap_or_else(|_| Err(SendgridErrorKind::Api(500, String::new()).into()));
+        result.map(|global| (vec![global], Vec::new()))
+    });
+    let changes = watcher.changes();
+
+    results.send(Ok(period(1))).unwrap();
+    assert_eq!(changes.recv().unwrap().global, vec![period(1)]);
+    results.send(Ok(period(1))).unwrap();
+    results.send(Ok(period(2))).unwrap();
+    assert_eq!(changes.recv().unwrap().global, vec![period(2)]);
+
+    results
+        .send(Err(SendgridErrorKind::Api(500, String::new()).into()))
+        .unwrap();
+    for _ in 0..100 {
+        if watcher.last_error().is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(watcher.last_error().is_some());
+    assert_eq!(watcher.latest().unwrap().global, vec![period(2)]);
+}
@@ -0,0 +1,357 @@
+//! Sunsetting addresses that stopped engaging: suppressing them in an unsubscribe group
+//! and taking them off lists, so mail to them stops hurting the sender's reputation.
+
+use engagement::{EngagementScore, EngagementScorer};
+use errors::SendgridResult;
+use suppressions::SuppressReport;
+use v3::V3Sender;
+use webhooks::Event;
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Which addresses a sunset policy sunsets.
+#[derive(Clone, Debug)]
+pub struct SunsetCriteria {
+    /// Addresses scoring below this are sunset.
+    pub min_score: f64,
+    /// Addresses are only sunset after this many messages were delivered to them, so
+    /// new addresses get a chance first.
+    pub min_delivered: u32,
+    /// Addresses that opened or clicked within this many days are kept whatever their
+    /// score.
+    pub grace_days: i64,
+}
+
+impl Default for SunsetCriteria {
+    fn default() -> SunsetCriteria {
+        SunsetCriteria {
+            min_score: 0.5,
+            min_delivered: 5,
+            grace_days: 90,
+        }
+    }
+}
+
+impl SunsetCriteria {
+    /// Whether an address with a score is sunset at the Unix timestamp `now`.
+    pub fn matches(&self, score: &EngagementScore, now: i64) -> bool {
+        score.delivered >= self.min_delivered
+            && score.score < self.min_score
+            && score
+                .days_since_engaged(now)
+                .is_none_or(|days| days >= self.grace_days)
+    }
+}
+
+/// An address a sunset policy picked, with the score it was picked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SunsetCandidate {
+    /// The lowercase address.
+    pub email: String,
+    pub score: EngagementScore,
+}
+
+/// What a run of a sunset policy changed, or would have changed in a dry run.
+#[derive(Debug, Default)]
+pub struct SunsetReport {
+    /// Whether this was a dry run, which changed nothing.
+    pub dry_run: bool,
+    /// The addresses that were sunset, or would have been, sorted by address.
+    pub sunset: Vec<SunsetCandidate>,
+    /// The number of addresses that matched but already were in the group.
+    pub already_suppressed: usize,
+    /// The outcome of adding the addresses to the group, unless this was a dry run.
+    pub suppressed: Option<SuppressReport>,
+    /// The number of contacts removed from each list, or that would have been. Addresses
+    /// that failed to be added to the group stay on the lists.
+    pub removed_from_lists: Vec<(String, usize)>,
+}
+
+/// Moves addresses that stopped engaging into an unsubscribe group, and optionally
+/// off some lists, from their webhook event history.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut policy = SunsetPolicy::new(&sender, SUNSET_GROUP_ID);
+/// policy.add_list(NEWSLETTER_LIST_ID);
+/// policy.set_dry_run(true);
+///
+/// let report = policy.run(&history, now)?;
+/// for candidate in &report.sunset {
+///     println!("{} would be sunset with a score of {:.2}", candidate.email, candidate.score.score);
+/// }
+/// ```
+pub struct SunsetPolicy<'a> {
+    sender: &'a V3Sender,
+    group_id: u32,
+    scorer: EngagementScorer,
+    criteria: SunsetCriteria,
+    list_ids: Vec<String>,
+    dry_run: bool,
+}
+
+// The longest `run_every` sleeps before checking whether it should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<'a> SunsetPolicy<'a> {
+    /// Construct a policy that suppresses addresses in an unsubscribe group, with the
+    /// default scorer and criteria.
+    pub fn new(sender: &'a V3Sender, group_id: u32) -> SunsetPolicy<'a> {
+        SunsetPolicy {
+            sender,
+            group_id,
+            scorer: EngagementScorer::new(),
+            criteria: SunsetCriteria::default(),
+            list_ids: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    /// Set how addresses are scored.
+    pub fn set_scorer(&mut self, scorer: EngagementScorer) {
+        self.scorer = scorer;
+    }
+
+    /// Set which addresses are sunset.
+    pub fn set_criteria(&mut self, criteria: SunsetCriteria) {
+        self.criteria = criteria;
+    }
+
+    /// Also remove the contacts of sunset addresses from a list.
+    pub fn add_list(&mut self, list_id: &str) {
+        self.list_ids.push(String::from(list_id));
+    }
+
+    /// Set whether runs only report what they would change instead of changing it.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// The addresses of an event history that match the criteria at the Unix timestamp
+    /// `now`, sorted by address.
+    pub fn candidates<'e, I>(&self, events: I, now: i64) -> Vec<SunsetCandidate>
+    where
+        I: IntoIterator<Item = &'e Event>,
+    {
+        candidates(&self.scorer, &self.criteria, events, now)
+    }
+
+    /// Sunset the matching addresses of an event history at the Unix timestamp `now`.
+    /// Addresses already in the group are left alone, and only addresses that were added
+    /// to it are taken off the lists, so a failed chunk can be sunset again by the next
+    /// run. In a dry run, SendGrid is only read from.
+    pub fn run<'e, I>(&self, events: I, now: i64) -> SendgridResult<SunsetReport>
+    where
+        I: IntoIterator<Item = &'e Event>,
+    {
+        let group = self.sender.asm_group(self.group_id);
+        let suppressed: HashSet<String> = group
+            .suppressions()?
+            .into_iter()
+            .map(|email| email.to_lowercase())
+            .collect();
+
+        let mut report = SunsetReport {
+            dry_run: self.dry_run,
+            ..SunsetReport::default()
+        };
+        for candidate in self.candidates(events, now) {
+            if suppressed.contains(&candidate.email) {
+                report.already_suppressed += 1;
+            } else {
+                report.sunset.push(candidate);
+            }
+        }
+        if report.sunset.is_empty() {
+            return Ok(report);
+        }
+
+        if !self.dry_run {
+            report.suppressed = Some(group.suppress_all(report.sunset.iter().map(|c| &c.email)));
+        }
+        if !self.list_ids.is_empty() {
+            let emails = suppressed_emails(&report.sunset, report.suppressed.as_ref());
+            report.removed_from_lists = self.remove_from_lists(&emails)?;
+        }
+        Ok(report)
+    }
+
+    /// Run the policy every `interval` until `stop` is set, with the event history
+    /// `history` returns each time, and hand each report to `on_report`. Errors of a
+    /// run are returned and end the schedule.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// policy.run_every(Duration::from_secs(24 * 60 * 60), &stop, || events.last_days(180), |report| {
+    ///     log::info!("sunset {} addresses", report.sunset.len());
+    /// })?;
+    /// ```
+    pub fn run_every<H, R>(
+        &self,
+        interval: Duration,
+        stop: &AtomicBool,
+        mut history: H,
+        mut on_report: R,
+    ) -> SendgridResult<()>
+    where
+        H: FnMut() -> SendgridResult<Vec<Event>>,
+        R: FnMut(SunsetReport),
+    {
+        while !stop.load(Ordering::Relaxed) {
+            let started = Instant::now();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            let events = history()?;
+            on_report(self.run(&events, now)?);
+
+            while !stop.load(Ordering::Relaxed) && started.elapsed() < interval {
+                thread::sleep(STOP_CHECK_INTERVAL.min(interval.saturating_sub(started.elapsed())));
+            }
+        }
+        Ok(())
+    }
+
+    // Remove the contacts of the sunset addresses from the lists of the policy they are
+    // on, and count them by list.
+    fn remove_from_lists(&self, emails: &[&str]) -> SendgridResult<Vec<(String, usize)>> {
+        let contacts = self.sender.contacts();
+        let found = contacts.get_by_emails(emails)?;
+
+        let mut removed = Vec::new();
+        for list_id in &self.list_ids {
+            let ids: Vec<&str> = found
+                .iter()
+                .filter(|c| c.list_ids.contains(list_id))
+                .map(|c| &c.id[..])
+                .collect();
+            if !self.dry_run && !ids.is_empty() {
+                contacts.remove_from_list(list_id, &ids)?;
+            }
+            removed.push((list_id.clone(), ids.len()));
+        }
+        Ok(removed)
+    }
+}
+
+// The sunset addresses that are in the group now: all of them in a dry run, and the
+// ones outside the failed chunks and the skipped addresses otherwise.
+fn suppressed_emails<'a>(
+    sunset: &'a [SunsetCandidate],
+    suppressed: Option<&SuppressReport>,
+) -> Vec<&'a str> {
+    let failed: HashSet<&str> = suppressed
+        .map(|report| report.failed())
+        .unwrap_or(&[])
+        .iter()
+        .flat_map(|chunk| chunk.addresses.iter().map(|a| &a[..]))
+        .chain(
+            suppressed
+                .iter()
+                .flat_map(|report| report.skipped.iter().map(|a| &a[..])),
+        )
+        .collect();
+    sunset
+        .iter()
+        .map(|c| &c.email[..])
+        .filter(|email| !failed.contains(email))
+        .collect()
+}
+
+fn candidates<'e, I>(
+    scorer: &EngagementScorer,
+    criteria: &SunsetCriteria,
+    events: I,
+    now: i64,
+) -> Vec<SunsetCandidate>
+where
+    I: IntoIterator<Item = &'e Event>,
+{
+    let mut candidates: Vec<SunsetCandidate> = scorer
+        .score_addresses(events, now)
+        .into_iter()
+        .filter(|(_, score)| criteria.matches(score, now))
+        .map(|(email, score)| SunsetCandidate { email, score })
+        .collect();
+    candidates.sort_by(|a, b| a.email.cmp(&b.email));
+    candidates
+}
+
+#[test]
+fn sunset_candidates() {
+    let day = 24 * 60 * 60;
+    let now = 400 * day;
+    let mut events = Vec::new();
+    for i in 0..6 {
+        events.push(format!(
+            r#"{{"email":"idle@example.com","timestamp":{},"sg_event_id":"i{}","event":"delivered"}}"#,
+            now - i * 30 * day,
+            i
+        ));
+        events.push(format!(
+            r#"{{"email":"reader@example.com","timestamp":{},"sg_event_id":"r{}","event":"delivered"}}"#,
+            now - i * 30 * day,
+            i
+        ));
+    }
+    // A reader that opened a month ago, and one that only opened long ago.
+    events.push(format!(
+        r#"{{"email":"reader@example.com","timestamp":{},"sg_event_id":"o1","event":"open"}}"#,
+        now - 30 * day
+    ));
+    events.push(format!(
+        r#"{{"email":"idle@example.com","timestamp":{},"sg_event_id":"o2","event":"open"}}"#,
+        now - 300 * day
+    ));
+    events.push(String::from(
+        r#"{"email":"new@example.com","timestamp":1,"sg_event_id":"n1","event":"delivered"}"#,
+    ));
+    let body = format!("[{}]", events.join(","));
+    let (events, errors) = ::webhooks::parse_events(body.as_bytes());
+    assert!(errors.is_empty());
+
+    let criteria = SunsetCriteria::default();
+    let picked = candidates(&EngagementScorer::new(), &criteria, &events, now);
+    let emails: Vec<&str> = picked.iter().map(|c| &c.email[..]).collect();
+    assert_eq!(emails, vec!["idle@example.com"]);
+    assert_eq!(picked[0].score.opens, 1);
+
+    let lenient = SunsetCriteria {
+        grace_days: 30,
+        min_score: 1.0,
+        ..SunsetCriteria::default()
+    };
+    let picked = candidates(&EngagementScorer::new(), &lenient, &events, now);
+    assert_eq!(picked.len(), 2);
+}
+
+#[test]
+fn failed_suppressions_stay_on_lists() {
+    let candidate = |email: &str| SunsetCandidate {
+        email: String::from(email),
+        score: EngagementScore::default(),
+    };
+    let sunset = vec![candidate("a@example.com"), candidate("b@example.com")];
+    assert_eq!(suppressed_emails(&sunset, None).len(), 2);
+
+    let report = SuppressReport {
+        added: 1,
+        failures: vec![::suppressions::FailedSuppressChunk {
+            offset: 1,
+            addresses: vec![String::from("b@example.com")],
+            error: "the request timed out".into(),
+        }],
+        skipped: Vec::new(),
+    };
+    assert_eq!(
+        suppressed_emails(&sunset, Some(&report)),
+        vec!["a@example.com"]
+    );
+}
@@ -0,0 +1,1205 @@
+//! The suppression lists, which hold the addresses SendGrid won't deliver to.
+
+use bounces::BounceClass;
+use bulk;
+use cursor::Cursor;
+use errors::{SendgridError, SendgridErrorKind, SendgridResult};
+use scopes::Endpoint;
+use throttle::PageThrottle;
+use v3::V3Sender;
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Mutex, TryLockError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::Method;
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, TimeZone};
+
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+/// Reading the bounce, block, spam report, invalid email and global unsubscribe
+/// suppression lists.
+pub struct Suppressions;
+
+impl Endpoint for Suppressions {
+    const SCOPES: &'static [&'static str] = &[
+        "asm.suppressions.global.read",
+        "suppression.blocks.read",
+        "suppression.bounces.read",
+        "suppression.invalid_emails.read",
+        "suppression.spam_reports.read",
+        "suppression.unsubscribes.read",
+    ];
+}
+
+/// Removing addresses from the bounce, block, spam report and invalid email suppression
+/// lists.
+pub struct SuppressionDeletes;
+
+impl Endpoint for SuppressionDeletes {
+    const SCOPES: &'static [&'static str] = &[
+        "suppression.blocks.delete",
+        "suppression.bounces.delete",
+        "suppression.invalid_emails.delete",
+        "suppression.spam_reports.delete",
+    ];
+}
+
+/// Reading and changing the suppressions of unsubscribe groups.
+pub struct GroupSuppressions;
+
+impl Endpoint for GroupSuppressions {
+    const SCOPES: &'static [&'static str] = &[
+        "asm.groups.suppressions.create",
+        "asm.groups.suppressions.delete",
+        "asm.groups.suppressions.read",
+    ];
+}
+
+// The number of entries requested per page from the suppression lists, which is the
+// most the API returns at once.
+const PAGE_SIZE: usize = 500;
+
+// The number of addresses added to a group per request.
+const GROUP_BATCH_SIZE: usize = 1000;
+
+// The kind of cursor of `SuppressionPages`.
+const PAGES_CURSOR: &str = "suppressions";
+
+// The number of addresses removed from a suppression list per request.
+const DELETE_BATCH_SIZE: usize = 500;
+
+/// A suppression list that addresses can be removed from in bulk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuppressionList {
+    Bounces,
+    Blocks,
+    SpamReports,
+    InvalidEmails,
+}
+
+impl SuppressionList {
+    fn path(self) -> &'static str {
+        match self {
+            SuppressionList::Bounces => "/suppression/bounces",
+            SuppressionList::Blocks => "/suppression/blocks",
+            SuppressionList::SpamReports => "/suppression/spam_reports",
+            SuppressionList::InvalidEmails => "/suppression/invalid_emails",
+        }
+    }
+}
+
+impl fmt::Display for SuppressionList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SuppressionList::Bounces => "bounce",
+            SuppressionList::Blocks => "block",
+            SuppressionList::SpamReports => "spam report",
+            SuppressionList::InvalidEmails => "invalid email",
+        })
+    }
+}
+
+// The body of a bulk deletion from a suppression list.
+#[derive(Debug, PartialEq, Serialize)]
+struct DeleteSuppressions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delete_all: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    emails: Option<&'a [&'a str]>,
+}
+
+/// Which entries of a suppression list to get: the entries created in a time range,
+/// and how many of them to skip and return. Without options, every entry is returned.
+///
+/// # Examples
+///
+/// ```
+/// use sendgrid::suppressions::ListOptions;
+///
+/// let options = ListOptions::new()
+///     .with_start_time(1_500_000_000)
+///     .with_limit(2000);
+/// assert_eq!(options.limit(), Some(2000));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ListOptions {
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+impl ListOptions {
+    /// Construct options that return every entry.
+    pub fn new() -> ListOptions {
+        ListOptions::default()
+    }
+
+    /// Only return the entries created at or after a Unix timestamp. Timestamps before
+    /// the epoch are treated as the epoch.
+    pub fn with_start_time(mut self, start_time: i64) -> ListOptions {
+        self.start_time = Some(cmp::max(start_time, 0));
+        self
+    }
+
+    /// Only return the entries created at or before a Unix timestamp.
+    pub fn with_end_time(mut self, end_time: i64) -> ListOptions {
+        self.end_time = Some(cmp::max(end_time, 0));
+        self
+    }
+
+    /// Only return the entries created at or after a date.
+    #[cfg(feature = "chrono")]
+    pub fn with_start<Tz: TimeZone>(self, start: &DateTime<Tz>) -> ListOptions {
+        self.with_start_time(start.timestamp())
+    }
+
+    /// Only return the entries created at or before a date.
+    #[cfg(feature = "chrono")]
+    pub fn with_end<Tz: TimeZone>(self, end: &DateTime<Tz>) -> ListOptions {
+        self.with_end_time(end.timestamp())
+    }
+
+    /// Return at most this many entries. Limits over the page size of the API are
+    /// fetched over several requests.
+    pub fn with_limit(mut self, limit: usize) -> ListOptions {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip this many entries, newest first.
+    pub fn with_offset(mut self, offset: usize) -> ListOptions {
+        self.offset = offset;
+        self
+    }
+
+    /// The most entries to return, if limited.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    // The options with an end at the Unix timestamp `now`, unless they have one.
+    fn pinned(mut self, now: i64) -> ListOptions {
+        if self.end_time.is_none() {
+            self.end_time = Some(now);
+        }
+        self
+    }
+
+    // The query of the request for the page that starts `fetched` entries in, or `None`
+    // when the limit was reached. The size of every page is clamped to the maximum of
+    // the API, and an end before the start is moved up to the start.
+    fn page_query(&self, fetched: usize) -> Option<String> {
+        let size = match self.limit {
+            Some(limit) if fetched >= limit => return None,
+            Some(limit) => cmp::min(limit - fetched, PAGE_SIZE),
+            None => PAGE_SIZE,
+        };
+        let mut query = format!("limit={}&offset={}", size, self.offset + fetched);
+        if let Some(start) = self.start_time {
+            query.push_str(&format!("&start_time={}", start));
+        }
+        if let Some(end) = self.end_time {
+            let end = self.start_time.map_or(end, |start| cmp::max(start, end));
+            query.push_str(&format!("&end_time={}", end));
+        }
+        Some(query)
+    }
+}
+
+/// An iterator over the entries of a suppression list, which gets a page at a time as it
+/// is read. Its `cursor` can be saved to resume from the same entry later, with
+/// `V3Sender::resume_suppression_pages`. Without an end time, the listing ends when it
+/// started, so entries added while it is read, or before it is resumed, don't shift the
+/// offsets of the pages.
+pub struct SuppressionPages<'a> {
+    sender: &'a V3Sender,
+    path: String,
+    options: ListOptions,
+    // The number of entries fetched so far, some of which may not be read yet.
+    fetched: usize,
+    page: VecDeque<SuppressionEntry>,
+    done: bool,
+    throttle: PageThrottle,
+}
+
+// Where a `SuppressionPages` is, as saved in its cursor.
+#[derive(Deserialize, Serialize)]
+struct PagesPosition {
+    path: String,
+    start_time: Option<i64>,
+    end_time: Option<i64>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+impl<'a> SuppressionPages<'a> {
+    fn new(sender: &'a V3Sender, path: &str, options: ListOptions) -> SuppressionPages<'a> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        SuppressionPages {
+            sender,
+            path: String::from(path),
+            options: options.pinned(now),
+            fetched: 0,
+            page: VecDeque::new(),
+            done: false,
+            throttle: PageThrottle::new(),
+        }
+    }
+
+    /// The position of the next entry. Resuming from it skips every entry read so far,
+    /// even those of a page that was only partly read.
+    pub fn cursor(&self) -> Cursor {
+        let read = self.fetched - self.page.len();
+        let position = PagesPosition {
+            path: self.path.clone(),
+            start_time: self.options.start_time,
+            end_time: self.options.end_time,
+            limit: self.options.limit.map(|limit| limit.saturating_sub(read)),
+            offset: self.options.offset + read,
+        };
+        Cursor::encode(PAGES_CURSOR, &position).expect("positions always serialize")
+    }
+}
+
+impl<'a> Iterator for SuppressionPages<'a> {
+    type Item = SendgridResult<SuppressionEntry>;
+
+    fn next(&mut self) -> Option<SendgridResult<SuppressionEntry>> {
+        if self.page.is_empty() && !self.done {
+            let query = self.options.page_query(self.fetched)?;
+            let path = format!("{}?{}", self.path, query);
+            let page: Vec<SuppressionEntry> = match self.sender.get_page(&path, &mut self.throttle)
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            self.done = page.len() < PAGE_SIZE;
+            self.fetched += page.len();
+            self.page.extend(page);
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+/// An entry of the bounce or block suppression lists.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SuppressionEntry {
+    /// The Unix timestamp of when the address was suppressed.
+    pub created: i64,
+    pub email: String,
+    /// The reason given by the receiving server.
+    pub reason: Option<String>,
+    /// The SMTP status code, for bounces.
+    pub status: Option<String>,
+    /// Other fields SendGrid lists with the suppressed address.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl SuppressionEntry {
+    /// Classify the bounce or block by its reason and status.
+    pub fn classification(&self) -> BounceClass {
+        let reason = self.reason.as_deref().unwrap_or("");
+        BounceClass::classify(reason, self.status.as_deref())
+    }
+}
+
+/// An unsubscribe group, which recipients can unsubscribe from on its own.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SuppressionGroup {
+    pub id: u32,
+    pub name: String,
+    pub description: String,
+    /// Whether messages without a group are sent with this one.
+    pub is_default: bool,
+    /// Other fields of the group, such as the number of unsubscribes.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The list an address is suppressed by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuppressionKind {
+    Bounce,
+    Block,
+    GlobalUnsubscribe,
+    SpamReport,
+    InvalidEmail,
+}
+
+impl fmt::Display for SuppressionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SuppressionKind::Bounce => "the address bounced",
+            SuppressionKind::Block => "the receiving server blocked a message",
+            SuppressionKind::GlobalUnsubscribe => "the recipient unsubscribed from all messages",
+            SuppressionKind::SpamReport => "the recipient reported a message as spam",
+            SuppressionKind::InvalidEmail => "the address is malformed or doesn't exist",
+        })
+    }
+}
+
+/// An address that SendGrid would not deliver to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuppressedAddress {
+    pub email: String,
+    pub kind: SuppressionKind,
+}
+
+/// A list of recipients split into the ones that can be sent to and the suppressed
+/// ones, with the reason for each.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecipientPartition {
+    /// The addresses that can be sent to, as given.
+    pub sendable: Vec<String>,
+    /// The suppressed addresses, as given.
+    pub suppressed: Vec<SuppressedAddress>,
+}
+
+/// The changes that make the suppressions of a group match a local list. Both lists
+/// hold lowercase addresses.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GroupSync {
+    /// Addresses that are unsubscribed locally but not in the group.
+    pub added: Vec<String>,
+    /// Addresses that are in the group but no longer unsubscribed locally.
+    pub removed: Vec<String>,
+}
+
+impl GroupSync {
+    /// Compare the local list of unsubscribed addresses with the suppressions of a
+    /// group. Addresses are compared without regard to case.
+    pub fn diff<'l, 'r, L, R>(local: L, remote: R) -> GroupSync
+    where
+        L: IntoIterator<Item = &'l str>,
+        R: IntoIterator<Item = &'r str>,
+    {
+        let local = lowercase_set(local);
+        let remote = lowercase_set(remote);
+
+        let mut sync = GroupSync {
+            added: local.difference(&remote).cloned().collect(),
+            removed: remote.difference(&local).cloned().collect(),
+        };
+        sync.added.sort();
+        sync.removed.sort();
+        sync
+    }
+}
+
+/// A handle for an unsubscribe group, returned by `V3Sender::asm_group`.
+///
+/// # Examples
+///
+/// ```ignore
+/// let report = sender.asm_group(1234).suppress_all(old_provider.unsubscribes());
+/// for chunk in report.failed() {
+///     eprintln!("{} addresses failed: {}", chunk.addresses.len(), chunk.error);
+/// }
+/// ```
+pub struct AsmGroup<'a> {
+    sender: &'a V3Sender,
+    group_id: u32,
+}
+
+/// The outcome of adding a list of addresses to an unsubscribe group, in chunks of
+/// the most addresses the API takes at once.
+#[derive(Debug, Default)]
+pub struct SuppressReport {
+    /// The number of addresses added.
+    pub added: usize,
+    /// The chunks SendGrid didn't accept, which can be added again.
+    pub failures: Vec<FailedSuppressChunk>,
+    /// The lowercase addresses that were never sent, because a chunk before them failed
+    /// with an error that the rest would fail with too, like a revoked API key.
+    pub skipped: Vec<String>,
+}
+
+impl SuppressReport {
+    /// The chunks that failed.
+    pub fn failed(&self) -> &[FailedSuppressChunk] {
+        &self.failures
+    }
+
+    /// Whether every chunk was added.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// A chunk of addresses that could not be added to an unsubscribe group.
+#[derive(Debug)]
+pub struct FailedSuppressChunk {
+    /// The position of the first address of the chunk in the input.
+    pub offset: usize,
+    /// The lowercase addresses of the chunk.
+    pub addresses: Vec<String>,
+    /// Why it failed.
+    pub error: SendgridError,
+}
+
+#[derive(Serialize)]
+struct RecipientEmails<'a> {
+    recipient_emails: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct GlobalUnsubscribe {
+    recipient_email: Option<String>,
+}
+
+impl V3Sender {
+    /// Get the bounce suppressions of an address.
+    pub fn bounce(&self, email: &str) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.api_get(&format!("/suppression/bounces/{}", encode(email)))
+    }
+
+    /// Get the block suppressions of an address.
+    pub fn block(&self, email: &str) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.api_get(&format!("/suppression/blocks/{}", encode(email)))
+    }
+
+    /// Check whether an address unsubscribed from all messages.
+    pub fn is_globally_unsubscribed(&self, email: &str) -> SendgridResult<bool> {
+        let res: GlobalUnsubscribe =
+            self.api_get(&format!("/asm/suppressions/global/{}", encode(email)))?;
+        Ok(res.recipient_email.is_some())
+    }
+
+    /// Get all the bounce suppressions.
+    pub fn bounces(&self) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.bounces_with(&ListOptions::new())
+    }
+
+    /// Get the bounce suppressions created between two Unix timestamps.
+    pub fn bounces_between(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.bounces_with(&between(start_time, end_time))
+    }
+
+    /// Get the bounce suppressions selected by list options.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let recent = sender.bounces_with(&ListOptions::new().with_start(&last_run).with_limit(100))?;
+    /// ```
+    pub fn bounces_with(&self, options: &ListOptions) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.list_all("/suppression/bounces", options)
+    }
+
+    /// Get all the block suppressions.
+    pub fn blocks(&self) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.blocks_with(&ListOptions::new())
+    }
+
+    /// Get the block suppressions selected by list options.
+    pub fn blocks_with(&self, options: &ListOptions) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.list_all("/suppression/blocks", options)
+    }
+
+    /// Get all the addresses that unsubscribed from all messages.
+    pub fn global_unsubscribes(&self) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.global_unsubscribes_with(&ListOptions::new())
+    }
+
+    /// Get the addresses that unsubscribed from all messages, selected by list options.
+    pub fn global_unsubscribes_with(
+        &self,
+        options: &ListOptions,
+    ) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.list_all("/suppression/unsubscribes", options)
+    }
+
+    /// Get the spam reports created between two Unix timestamps.
+    pub fn spam_reports_between(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.spam_reports_with(&between(start_time, end_time))
+    }
+
+    /// Get the spam reports selected by list options.
+    pub fn spam_reports_with(
+        &self,
+        options: &ListOptions,
+    ) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.list_all("/suppression/spam_reports", options)
+    }
+
+    /// Get the addresses that were rejected as malformed or nonexistent, selected by
+    /// list options.
+    pub fn invalid_emails_with(
+        &self,
+        options: &ListOptions,
+    ) -> SendgridResult<Vec<SuppressionEntry>> {
+        self.list_all("/suppression/invalid_emails", options)
+    }
+
+    /// Get all the unsubscribe groups.
+    pub fn suppression_groups(&self) -> SendgridResult<Vec<SuppressionGroup>> {
+        self.api_get("/asm/groups")
+    }
+
+    /// Get the addresses that unsubscribed from an unsubscribe group.
+    pub fn group_suppressions(&self, group_id: u32) -> SendgridResult<Vec<String>> {
+        self.api_get(&format!("/asm/groups/{}/suppressions", group_id))
+    }
+
+    /// Get a handle for an unsubscribe group.
+    pub fn asm_group(&self, group_id: u32) -> AsmGroup<'_> {
+        AsmGroup {
+            sender: self,
+            group_id,
+        }
+    }
+
+    /// Make the suppressions of an unsubscribe group match a local list of
+    /// unsubscribed addresses, for applications whose own database is the source of
+    /// truth. Missing addresses are added in bulk, while addresses that are no longer
+    /// unsubscribed locally are removed one request at a time, as the API has no bulk
+    /// removal. Returns the changes that were made.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let unsubscribed = db.newsletter_unsubscribes()?;
+    /// let sync = sender.sync_group_suppressions(1234, unsubscribed.iter().map(|s| s.as_str()))?;
+    /// println!("added {}, removed {}", sync.added.len(), sync.removed.len());
+    /// ```
+    pub fn sync_group_suppressions<'a, I>(
+        &self,
+        group_id: u32,
+        unsubscribed: I,
+    ) -> SendgridResult<GroupSync>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let remote = self.group_suppressions(group_id)?;
+        let sync = GroupSync::diff(unsubscribed, remote.iter().map(|e| e.as_str()));
+
+        let path = format!("/asm/groups/{}/suppressions", group_id);
+        for batch in sync.added.chunks(GROUP_BATCH_SIZE) {
+            let body = RecipientEmails {
+                recipient_emails: batch,
+            };
+            self.api_send_json(Method::Post, &path, &body)?;
+        }
+        for email in &sync.removed {
+            self.api_request(Method::Delete, &format!("{}/{}", path, encode(email)), None)?;
+        }
+
+        Ok(sync)
+    }
+
+    /// Remove addresses from a suppression list, so SendGrid delivers to them again.
+    /// Large lists are removed over several requests.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let fixed: Vec<&str> = mailboxes.iter().map(|m| m.as_str()).collect();
+    /// sender.delete_suppressions(SuppressionList::Bounces, &fixed)?;
+    /// ```
+    pub fn delete_suppressions(
+        &self,
+        list: SuppressionList,
+        emails: &[&str],
+    ) -> SendgridResult<()> {
+        for batch in emails.chunks(DELETE_BATCH_SIZE) {
+            let body = DeleteSuppressions {
+                delete_all: None,
+                emails: Some(batch),
+            };
+            self.api_send_json(Method::Delete, list.path(), &body)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every address from a suppression list. This can't be undone, and sending
+    /// to addresses that bounced before hurts the sender reputation, so it fails with
+    /// `UnconfirmedDeleteAll` unless `confirm` is set, e.g. from a `--yes` flag.
+    pub fn delete_all_suppressions(
+        &self,
+        list: SuppressionList,
+        confirm: bool,
+    ) -> SendgridResult<()> {
+        if !confirm {
+            return Err(SendgridErrorKind::UnconfirmedDeleteAll(list.to_string()).into());
+        }
+        let body = DeleteSuppressions {
+            delete_all: Some(true),
+            emails: None,
+        };
+        self.api_send_json(Method::Delete, list.path(), &body)?;
+        Ok(())
+    }
+
+    /// Read the entries of a suppression list a page at a time, for lists too large
+    /// to hold in memory or to sync in one go.
+    pub fn suppression_pages(
+        &self,
+        list: SuppressionList,
+        options: &ListOptions,
+    ) -> SuppressionPages<'_> {
+        SuppressionPages::new(self, list.path(), *options)
+    }
+
+    /// Resume reading a suppression list from a cursor saved from `SuppressionPages`.
+    /// Fails with `InvalidCursor` when the cursor is for another listing.
+    pub fn resume_suppression_pages(
+        &self,
+        cursor: &Cursor,
+    ) -> SendgridResult<SuppressionPages<'_>> {
+        let position: PagesPosition = cursor.decode(PAGES_CURSOR)?;
+        let lists = [
+            "/suppression/bounces",
+            "/suppression/blocks",
+            "/suppression/invalid_emails",
+            "/suppression/spam_reports",
+            "/suppression/unsubscribes",
+        ];
+        if !lists.contains(&&position.path[..]) {
+            return Err(SendgridErrorKind::InvalidCursor(cursor.to_string()).into());
+        }
+        let options = ListOptions {
+            start_time: position.start_time,
+            end_time: position.end_time,
+            limit: position.limit,
+            offset: position.offset,
+        };
+        Ok(SuppressionPages::new(self, &position.path, options))
+    }
+
+    // Get the pages of a suppression list selected by list options.
+    fn list_all(&self, path: &str, options: &ListOptions) -> SendgridResult<Vec<SuppressionEntry>> {
+        SuppressionPages::new(self, path, *options).collect()
+    }
+
+    /// Find the addresses that SendGrid would not deliver to because they bounced, were
+    /// blocked or unsubscribed from all messages. Skipping them keeps the bounce rate
+    /// down. This makes up to three requests for every address, so use a
+    /// `SuppressionCache` on hot paths.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let suppressed = sender.filter_suppressed(vec!["a@example.com", "b@example.com"])?;
+    /// ```
+    pub fn filter_suppressed<'a, I>(&self, addresses: I) -> SendgridResult<Vec<SuppressedAddress>>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut suppressed = Vec::new();
+
+        for email in addresses {
+            let kind = if !self.bounce(email)?.is_empty() {
+                SuppressionKind::Bounce
+            } else if !self.block(email)?.is_empty() {
+                SuppressionKind::Block
+            } else if self.is_globally_unsubscribed(email)? {
+                SuppressionKind::GlobalUnsubscribe
+            } else {
+                continue;
+            };
+            suppressed.push(SuppressedAddress {
+                email: String::from(email),
+                kind,
+            });
+        }
+
+        Ok(suppressed)
+    }
+}
+
+/// Storage for the suppressed addresses synced by a `SuppressionCache`. Implement this
+/// on top of a shared cache such as Redis to share the suppressions across processes.
+pub trait SuppressionStore {
+    /// Replace all the stored addresses with the list they are suppressed by. The
+    /// addresses are lowercase.
+    fn replace(&self, addresses: HashMap<String, SuppressionKind>);
+
+    /// The list a lowercase address is suppressed by, if it is stored.
+    fn kind(&self, address: &str) -> Option<SuppressionKind>;
+
+    /// Check whether a lowercase address is stored.
+    fn contains(&self, address: &str) -> bool {
+        self.kind(address).is_some()
+    }
+}
+
+/// A suppression store that keeps the addresses in memory.
+#[derive(Debug, Default)]
+pub struct MemorySuppressionStore {
+    addresses: Mutex<HashMap<String, SuppressionKind>>,
+}
+
+impl SuppressionStore for MemorySuppressionStore {
+    fn replace(&self, addresses: HashMap<String, SuppressionKind>) {
+        *self.addresses.lock().unwrap_or_else(|e| e.into_inner()) = addresses;
+    }
+
+    fn kind(&self, address: &str) -> Option<SuppressionKind> {
+        self.addresses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(address)
+            .cloned()
+    }
+}
+
+/// A local copy of the bounce, block, global unsubscribe, spam report and invalid email
+/// lists, for checking recipients on hot send paths without a request per address. The
+/// lists are synced again once they are older than the time to live, by the first
+/// caller to find them stale; the others keep using the old lists in the meantime.
+///
+/// # Examples
+///
+/// ```ignore
+/// let cache = SuppressionCache::new(&sender, Duration::from_secs(3600));
+/// if !cache.is_suppressed("a@example.com")? {
+///     sender.send(&message)?;
+/// }
+/// ```
+pub struct SuppressionCache<'a, S: SuppressionStore = MemorySuppressionStore> {
+    sender: &'a V3Sender,
+    ttl: Duration,
+    store: S,
+    synced_at: Mutex<Option<Instant>>,
+    syncing: Mutex<()>,
+}
+
+impl<'a> SuppressionCache<'a, MemorySuppressionStore> {
+    /// Construct a cache that keeps the suppressions in memory.
+    pub fn new(sender: &'a V3Sender, ttl: Duration) -> SuppressionCache<'a> {
+        SuppressionCache::with_store(sender, ttl, MemorySuppressionStore::default())
+    }
+}
+
+impl<'a, S: SuppressionStore> SuppressionCache<'a, S> {
+    /// Construct a cache that keeps the suppressions in the given store.
+    pub fn with_store(sender: &'a V3Sender, ttl: Duration, store: S) -> SuppressionCache<'a, S> {
+        SuppressionCache {
+            sender,
+            ttl,
+            store,
+            synced_at: Mutex::new(None),
+            syncing: Mutex::new(()),
+        }
+    }
+
+    /// Check whether an address is suppressed, syncing the lists first if they are
+    /// stale.
+    pub fn is_suppressed(&self, email: &str) -> SendgridResult<bool> {
+        self.sync_if_stale()?;
+        Ok(self.store.contains(&email.to_lowercase()))
+    }
+
+    /// Split a list of recipients into the ones that can be sent to and the suppressed
+    /// ones, syncing the lists first if they are stale. The reasons can be shown to
+    /// users to tell them why some recipients were skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let partition = cache.partition(invitees.iter().map(|s| s.as_str()))?;
+    /// for skipped in &partition.suppressed {
+    ///     println!("not invited {}: {}", skipped.email, skipped.kind);
+    /// }
+    /// ```
+    pub fn partition<'b, I>(&self, addresses: I) -> SendgridResult<RecipientPartition>
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        self.sync_if_stale()?;
+        Ok(partition(&self.store, addresses))
+    }
+
+    fn sync_if_stale(&self) -> SendgridResult<()> {
+        sync_once(&self.synced_at, &self.syncing, self.ttl, || self.sync())
+    }
+
+    /// Sync the lists now. Addresses on several lists are stored with the first of
+    /// bounces, blocks, global unsubscribes, spam reports and invalid emails.
+    pub fn sync(&self) -> SendgridResult<()> {
+        let mut addresses = HashMap::new();
+        for (entries, kind) in [
+            (self.sender.bounces()?, SuppressionKind::Bounce),
+            (self.sender.blocks()?, SuppressionKind::Block),
+            (
+                self.sender.global_unsubscribes()?,
+                SuppressionKind::GlobalUnsubscribe,
+            ),
+            (
+                self.sender.spam_reports_with(&ListOptions::new())?,
+                SuppressionKind::SpamReport,
+            ),
+            (
+                self.sender.invalid_emails_with(&ListOptions::new())?,
+                SuppressionKind::InvalidEmail,
+            ),
+        ] {
+            for entry in entries {
+                addresses.entry(entry.email.to_lowercase()).or_insert(kind);
+            }
+        }
+
+        self.store.replace(addresses);
+        *self.synced_at.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl<'a> AsmGroup<'a> {
+    /// The ID of the group.
+    pub fn id(&self) -> u32 {
+        self.group_id
+    }
+
+    /// Get the addresses that unsubscribed from the group.
+    pub fn suppressions(&self) -> SendgridResult<Vec<String>> {
+        self.sender.group_suppressions(self.group_id)
+    }
+
+    /// Add every address of an iterator to the group, e.g. to migrate the unsubscribe
+    /// list of another provider. Addresses are trimmed and lowercased, empty ones are
+    /// skipped, and they are sent in chunks of 1000. A chunk that fails with an error
+    /// that could go away, like a rate limit, doesn't stop the ones after it; it is kept
+    /// in the report instead, so only the failed chunks have to be added again. Any
+    /// other error stops the upload, and the addresses after the chunk are kept in
+    /// `skipped`.
+    pub fn suppress_all<I>(&self, addresses: I) -> SuppressReport
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let path = format!("/asm/groups/{}/suppressions", self.group_id);
+        suppress_chunks(addresses, GROUP_BATCH_SIZE, |chunk| {
+            let body = RecipientEmails {
+                recipient_emails: chunk,
+            };
+            self.sender
+                .api_send_json(Method::Post, &path, &body)
+                .map(|_| ())
+        })
+    }
+}
+
+// Send the addresses in chunks of `size`, recording where each failed chunk starts in
+// the input, until a chunk fails with an error that isn't retryable.
+fn suppress_chunks<I, F>(addresses: I, size: usize, mut send: F) -> SuppressReport
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+    F: FnMut(&[String]) -> SendgridResult<()>,
+{
+    let mut report = SuppressReport::default();
+    let mut chunk = Vec::with_capacity(size);
+    let mut offset = 0;
+    let mut position = 0;
+    let mut stopped = false;
+
+    let mut flush = |chunk: Vec<String>, offset: usize, report: &mut SuppressReport| {
+        match send(&chunk) {
+            Ok(()) => report.added += chunk.len(),
+            Err(error) => {
+                let retryable = bulk::is_retryable(&error);
+                report.failures.push(FailedSuppressChunk {
+                    offset,
+                    addresses: chunk,
+                    error,
+                });
+                return retryable;
+            }
+        }
+        true
+    };
+
+    for address in addresses {
+        let address = address.as_ref().trim().to_lowercase();
+        position += 1;
+        if address.is_empty() {
+            continue;
+        }
+        if stopped {
+            report.skipped.push(address);
+            continue;
+        }
+        if chunk.is_empty() {
+            offset = position - 1;
+        }
+        chunk.push(address);
+        if chunk.len() == size {
+            let full = ::std::mem::replace(&mut chunk, Vec::with_capacity(size));
+            stopped = !flush(full, offset, &mut report);
+        }
+    }
+    if !chunk.is_empty() {
+        flush(chunk, offset, &mut report);
+    }
+    report
+}
+
+// Sync when the lists are older than the time to live. While one caller syncs, the
+// others go on with the stale lists instead of syncing too; only the first sync is
+// waited for, as there are no lists to use before it.
+fn sync_once<F>(
+    synced_at: &Mutex<Option<Instant>>,
+    syncing: &Mutex<()>,
+    ttl: Duration,
+    sync: F,
+) -> SendgridResult<()>
+where
+    F: FnOnce() -> SendgridResult<()>,
+{
+    let stale = || {
+        synced_at
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .map(|synced_at| synced_at.elapsed() >= ttl)
+    };
+    let _syncing = match stale() {
+        Some(false) => return Ok(()),
+        Some(true) => match syncing.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(()),
+        },
+        None => syncing.lock().unwrap_or_else(|e| e.into_inner()),
+    };
+    // Another caller may have synced while this one waited for the first sync.
+    if stale() == Some(false) {
+        return Ok(());
+    }
+    sync()
+}
+
+fn partition<'b, S, I>(store: &S, addresses: I) -> RecipientPartition
+where
+    S: SuppressionStore,
+    I: IntoIterator<Item = &'b str>,
+{
+    let mut partition = RecipientPartition::default();
+    for email in addresses {
+        match store.kind(&email.to_lowercase()) {
+            Some(kind) => partition.suppressed.push(SuppressedAddress {
+                email: String::from(email),
+                kind,
+            }),
+            None => partition.sendable.push(String::from(email)),
+        }
+    }
+    partition
+}
+
+fn lowercase_set<'a, I: IntoIterator<Item = &'a str>>(addresses: I) -> HashSet<String> {
+    addresses.into_iter().map(|e| e.to_lowercase()).collect()
+}
+
+fn between(start_time: i64, end_time: i64) -> ListOptions {
+    ListOptions::new()
+        .with_start_time(start_time)
+        .with_end_time(end_time)
+}
+
+// Encode an address or an ID for use as a path segment.
+pub(crate) fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+#[test]
+fn suppression_entries() {
+    let entries: Vec<SuppressionEntry> = ::serde_json::from_str(
+        r#"[{"created":1443651125,"email":"a@example.com","reason":"550 unknown user","status":"5.1.1"}]"#,
+    )
+    .unwrap();
+    assert_eq!(entries[0].status, Some(String::from("5.1.1")));
+    assert_eq!(encode("a b/c@example.com"), "a%20b%2Fc@example.com");
+}
+
+#[test]
+fn suppress_chunk_offsets() {
+    let addresses = vec!["A@example.com", " ", "b@example.com", "c@example.com", ""];
+    let mut sent = Vec::new();
+    let report = suppress_chunks(addresses.clone(), 2, |chunk| {
+        sent.push(chunk.to_vec());
+        if sent.len() == 2 {
+            return Err(SendgridErrorKind::Api(500, String::new()).into());
+        }
+        Ok(())
+    });
+    assert_eq!(
+        sent,
+        vec![
+            vec![String::from("a@example.com"), String::from("b@example.com")],
+            vec![String::from("c@example.com")],
+        ]
+    );
+    assert_eq!(report.added, 2);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].offset, 3);
+    assert!(report.skipped.is_empty());
+
+    let mut calls = 0;
+    let report = suppress_chunks(addresses, 1, |_| {
+        calls += 1;
+        Err(SendgridErrorKind::Api(401, String::new()).into())
+    });
+    assert_eq!(calls, 1);
+    assert_eq!(report.failures[0].offset, 0);
+    assert_eq!(report.skipped, vec!["b@example.com", "c@example.com"]);
+    assert!(!report.is_complete());
+}
+
+#[test]
+fn group_sync_diff() {
+    let sync = GroupSync::diff(
+        vec!["A@example.com", "b@example.com", "c@example.com"],
+        vec!["a@example.com", "d@example.com"],
+    );
+    assert_eq!(sync.added, vec!["b@example.com", "c@example.com"]);
+    assert_eq!(sync.removed, vec!["d@example.com"]);
+}
+
+#[test]
+fn memory_store_kinds() {
+    let store = MemorySuppressionStore::default();
+    let mut addresses = HashMap::new();
+    addresses.insert(String::from("a@example.com"), SuppressionKind::Block);
+    store.replace(addresses);
+    assert_eq!(store.kind("a@example.com"), Some(SuppressionKind::Block));
+    assert!(store.contains("a@example.com"));
+    assert!(!store.contains("b@example.com"));
+    assert_eq!(
+        SuppressionKind::Block.to_string(),
+        "the receiving server blocked a message"
+    );
+}
+
+#[test]
+fn single_sync() {
+    let synced_at = Mutex::new(None);
+    let syncing = Mutex::new(());
+    let ttl = Duration::from_millis(10);
+    let syncs = ::std::cell::Cell::new(0);
+    let sync = || {
+        syncs.set(syncs.get() + 1);
+        *synced_at.lock().unwrap() = Some(Instant::now());
+        Ok(())
+    };
+
+    sync_once(&synced_at, &syncing, ttl, sync).unwrap();
+    sync_once(&synced_at, &syncing, ttl, sync).unwrap();
+    assert_eq!(syncs.get(), 1);
+
+    ::std::thread::sleep(ttl * 2);
+    {
+        let _other_caller = syncing.lock().unwrap();
+        sync_once(&synced_at, &syncing, ttl, sync).unwrap();
+        assert_eq!(syncs.get(), 1);
+    }
+    sync_once(&synced_at, &syncing, ttl, sync).unwrap();
+    assert_eq!(syncs.get(), 2);
+}
+
+#[test]
+fn partition_reasons() {
+    let store = MemorySuppressionStore::default();
+    let mut addresses = HashMap::new();
+    addresses.insert(String::from("b@example.com"), SuppressionKind::SpamReport);
+    addresses.insert(String::from("c@example.com"), SuppressionKind::InvalidEmail);
+    store.replace(addresses);
+    assert_eq!(
+        partition(
+            &store,
+            vec!["a@example.com", "B@example.com", "c@example.com"]
+        ),
+        RecipientPartition {
+            sendable: vec![String::from("a@example.com")],
+            suppressed: vec![
+                SuppressedAddress {
+                    email: String::from("B@example.com"),
+                    kind: SuppressionKind::SpamReport,
+                },
+                SuppressedAddress {
+                    email: String::from("c@example.com"),
+                    kind: SuppressionKind::InvalidEmail,
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn list_option_pages() {
+    let options = ListOptions::new()
+        .with_start_time(200)
+        .with_end_time(100)
+        .with_offset(10)
+        .with_limit(1200);
+    assert_eq!(
+        options.page_query(0).unwrap(),
+        "limit=500&offset=10&start_time=200&end_time=200"
+    );
+    assert!(options
+        .page_query(1000)
+        .unwrap()
+        .starts_with("limit=200&offset=1010&"));
+    assert!(options.page_query(1200).is_none());
+    assert_eq!(options.pinned(300), options);
+    assert_eq!(
+        ListOptions::new().pinned(300),
+        ListOptions::new().with_end_time(300)
+    );
+    assert_eq!(
+        ListOptions::new()
+            .with_start_time(-5)
+            .page_query(0)
+            .unwrap(),
+        "limit=500&offset=0&start_time=0"
+    );
+}
+
+#[test]
+fn delete_bodies() {
+    let emails = ["a@example.com", "b@example.com"];
+    let body = DeleteSuppressions {
+        delete_all: None,
+        emails: Some(&emails),
+    };
+    assert_eq!(
+        ::serde_json::to_string(&body).unwrap(),
+        r#"{"emails":["a@example.com","b@example.com"]}"#
+    );
+    let body = DeleteSuppressions {
+        delete_all: Some(true),
+        emails: None,
+    };
+    assert_eq!(
+        ::serde_json::to_string(&body).unwrap(),
+        r#"{"delete_all":true}"#
+    );
+    assert_eq!(
+        SuppressionList::SpamReports.path(),
+        "/suppression/spam_reports"
+    );
+}
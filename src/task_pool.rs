@@ -0,0 +1,373 @@
+//! Running the API calls of several parts of an application on shared threads, with a
+//! budget of concurrent calls and calls per second for all of them, so a suppression
+//! sync or a stats pull can't take up every request an API key is allowed and hold up
+//! sends.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use v3::V3Sender;
+
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The budgets of a `TaskPool`.
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    /// The most tasks running at once, which is also the number of threads.
+    pub max_concurrency: usize,
+    /// The most tasks started per window of time, e.g. `(100, 1s)`. Tasks wait for the
+    /// budget to allow them before they start.
+    pub rate_limit: Option<(usize, Duration)>,
+    /// The most tasks of a lane running at once, by lane. Lanes without a limit can use
+    /// every thread.
+    pub lane_limits: HashMap<String, usize>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> PoolOptions {
+        PoolOptions {
+            max_concurrency: 4,
+            rate_limit: None,
+            lane_limits: HashMap::new(),
+        }
+    }
+}
+
+/// What a task pool did by the time it shut down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolReport {
+    /// The number of tasks that ran to the end.
+    pub completed: usize,
+    /// The number of tasks that panicked.
+    pub panicked: usize,
+    /// The number of tasks that had not started when the timeout ran out.
+    pub unstarted: usize,
+}
+
+/// Runs tasks for the API calls of different parts of an application, e.g. sends, stats
+/// pulls and suppression syncs, on a fixed number of threads that share one sender.
+///
+/// Every task belongs to a lane, usually named after the part of the application it
+/// comes from. Free threads take the next task from each lane in turn, so a lane with
+/// thousands of queued tasks only delays the others by one task each, and lanes can be
+/// capped to a number of threads. Tasks of one lane start in the order they were
+/// submitted.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut options = PoolOptions::default();
+/// options.rate_limit = Some((100, Duration::from_secs(1)));
+/// options.lane_limits.insert(String::from("sync"), 1);
+/// let pool = TaskPool::spawn(V3Sender::new(api_key), options);
+///
+/// let bounces = pool.submit("sync", |sender| sender.bounces())?;
+/// let sent = pool.submit("send", move |sender| sender.send(&message))?;
+/// sent.wait()??;
+/// ```
+pub struct TaskPool<C = V3Sender> {
+    shared: Arc<Shared<C>>,
+}
+
+/// The result of a submitted task, once it ran.
+pub struct TaskHandle<T> {
+    result: Receiver<T>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Wait for the task to run and return its result. Fails with `PoolClosed` when the
+    /// pool shut down before the task ran, or the task panicked.
+    pub fn wait(self) -> SendgridResult<T> {
+        self.result
+            .recv()
+            .map_err(|_| SendgridErrorKind::PoolClosed.into())
+    }
+}
+
+type Task<C> = Box<dyn FnOnce(&C) + Send>;
+
+struct Shared<C> {
+    context: C,
+    state: Mutex<State<C>>,
+    // Notified when a task is submitted or finishes, and when the pool is closed.
+    changed: Condvar,
+    options: PoolOptions,
+}
+
+struct State<C> {
+    lanes: Vec<Lane<C>>,
+    // The lane to look at first for the next task, counting past the last lane.
+    next_lane: usize,
+    // When the tasks in the current rate limit window started.
+    started: VecDeque<Instant>,
+    closed: bool,
+    abandoned: bool,
+    // The number of worker threads that stopped.
+    stopped: usize,
+    report: PoolReport,
+}
+
+struct Lane<C> {
+    name: String,
+    queue: VecDeque<Task<C>>,
+    running: usize,
+}
+
+impl<C: Send + Sync + 'static> TaskPool<C> {
+    /// Start the threads of a pool that run tasks with a shared context, usually a
+    /// `V3Sender`.
+    pub fn spawn(context: C, options: PoolOptions) -> TaskPool<C> {
+        let threads = options.max_concurrency.max(1);
+        let shared = Arc::new(Shared {
+            context,
+            state: Mutex::new(State {
+                lanes: Vec::new(),
+                next_lane: 0,
+                started: VecDeque::new(),
+                closed: false,
+                abandoned: false,
+                stopped: 0,
+                report: PoolReport::default(),
+            }),
+            changed: Condvar::new(),
+            options,
+        });
+
+        for _ in 0..threads {
+            let worker = Arc::clone(&shared);
+            thread::spawn(move || {
+                while let Some((lane, task)) = worker.next_task() {
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| task(&worker.context)));
+                    worker.finish_task(lane, outcome.is_ok());
+                }
+            });
+        }
+
+        TaskPool { shared }
+    }
+
+    /// Queue a task in a lane. Fails with `PoolClosed` once the pool is shut down.
+    pub fn submit<F, T>(&self, lane: &str, task: F) -> SendgridResult<TaskHandle<T>>
+    where
+        F: FnOnce(&C) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let task: Task<C> = Box::new(move |context| {
+            // The handle may have been dropped by a caller that doesn't need the result.
+            let _ = tx.send(task(context));
+        });
+
+        let mut state = self.shared.lock();
+        if state.closed {
+            return Err(SendgridErrorKind::PoolClosed.into());
+        }
+        let index = match state.lanes.iter().position(|l| l.name == lane) {
+            Some(index) => index,
+            None => {
+                state.lanes.push(Lane {
+                    name: String::from(lane),
+                    queue: VecDeque::new(),
+                    running: 0,
+                });
+                state.lanes.len() - 1
+            }
+        };
+        state.lanes[index].queue.push_back(task);
+        self.shared.changed.notify_all();
+        Ok(TaskHandle { result: rx })
+    }
+
+    /// The number of tasks waiting to start, by lane.
+    pub fn queued(&self) -> HashMap<String, usize> {
+        let state = self.shared.lock();
+        state
+            .lanes
+            .iter()
+            .map(|l| (l.name.clone(), l.queue.len()))
+            .collect()
+    }
+
+    /// Stop taking new tasks and wait up to `timeout` for the queued ones to run. Later
+    /// calls to `submit` fail with `PoolClosed`. Tasks that have not started when the
+    /// timeout runs out are dropped, and tasks that are running are left to finish on
+    /// their own.
+    pub fn shutdown(&self, timeout: Duration) -> PoolReport {
+        let deadline = Instant::now() + timeout;
+        let threads = self.shared.options.max_concurrency.max(1);
+        let mut state = self.shared.lock();
+        state.closed = true;
+        self.shared.changed.notify_all();
+
+        while state.stopped < threads {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        let mut report = state.report;
+        if state.stopped < threads {
+            state.abandoned = true;
+            for lane in &mut state.lanes {
+                report.unstarted += lane.queue.len();
+                lane.queue.clear();
+            }
+            self.shared.changed.notify_all();
+        }
+        report
+    }
+}
+
+impl<C> Shared<C> {
+    fn lock(&self) -> MutexGuard<'_, State<C>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Wait for a task that fits the budgets and take it, with the index of its lane.
+    // Returns `None` once the pool is closed and every lane is empty, or abandoned.
+    fn next_task(&self) -> Option<(usize, Task<C>)> {
+        let mut state = self.lock();
+        loop {
+            let empty = state.lanes.iter().all(|l| l.queue.is_empty());
+            if state.abandoned || state.closed && empty {
+                state.stopped += 1;
+                self.changed.notify_all();
+                return None;
+            }
+
+            let now = Instant::now();
+            let wait = self.rate_wait(&mut state, now);
+            let lane = if wait.is_none() {
+                self.pick_lane(&state)
+            } else {
+                None
+            };
+            if let Some(index) = lane {
+                state.next_lane = index + 1;
+                state.lanes[index].running += 1;
+                if self.options.rate_limit.is_some() {
+                    state.started.push_back(now);
+                }
+                let task = state.lanes[index].queue.pop_front();
+                return Some((index, task.expect("the picked lane has a task")));
+            }
+
+            state = match wait {
+                Some(wait) if !empty => {
+                    self.changed
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                _ => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    // How long until the rate limit allows another task, or `None` if it does now.
+    fn rate_wait(&self, state: &mut State<C>, now: Instant) -> Option<Duration> {
+        let (limit, window) = self.options.rate_limit?;
+        while state
+            .started
+            .front()
+            .is_some_and(|&started| now.duration_since(started) >= window)
+        {
+            state.started.pop_front();
+        }
+        if state.started.len() < limit.max(1) {
+            return None;
+        }
+        state
+            .started
+            .front()
+            .map(|&oldest| (oldest + window).saturating_duration_since(now))
+    }
+
+    // The next lane in turn with a queued task and room under its limit.
+    fn pick_lane(&self, state: &State<C>) -> Option<usize> {
+        let count = state.lanes.len();
+        (0..count)
+            .map(|offset| (state.next_lane + offset) % count)
+            .find(|&index| {
+                let lane = &state.lanes[index];
+                let limit = self.options.lane_limits.get(&lane.name);
+                !lane.queue.is_empty() && limit.is_none_or(|&limit| lane.running < limit)
+            })
+    }
+
+    fn finish_task(&self, lane: usize, completed: bool) {
+        let mut state = self.lock();
+        state.lanes[lane].running -= 1;
+        if completed {
+            state.report.completed += 1;
+        } else {
+            state.report.panicked += 1;
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl<C> Drop for TaskPool<C> {
+    // Without a shutdown the threads carry on until every lane is empty.
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[test]
+fn fair_lanes() {
+    let mut options = PoolOptions {
+        max_concurrency: 1,
+        ..PoolOptions::default()
+    };
+    options.lane_limits.insert(String::from("sync"), 1);
+    let pool: TaskPool<Mutex<Vec<&str>>> = TaskPool::spawn(Mutex::new(Vec::new()), options);
+
+    // Hold the only thread until every task is queued.
+    let (release, held) = mpsc::channel::<()>();
+    let first = pool
+        .submit("sync", move |order| {
+            held.recv().unwrap();
+            order.lock().unwrap().push("sync");
+        })
+        .unwrap();
+    for _ in 0..3 {
+        pool.submit("sync", |order| order.lock().unwrap().push("sync"))
+            .unwrap();
+    }
+    let send = pool
+        .submit("send", |order| {
+            order.lock().unwrap().push("send");
+            42
+        })
+        .unwrap();
+    pool.submit("send", |_| panic!("the task failed")).unwrap();
+    release.send(()).unwrap();
+
+    first.wait().unwrap();
+    assert_eq!(send.wait().unwrap(), 42);
+    let report = pool.shutdown(Duration::from_secs(5));
+    assert_eq!(
+        report,
+        PoolReport {
+            completed: 5,
+            panicked: 1,
+            unstarted: 0,
+        }
+    );
+    let order = pool.shared.context.lock().unwrap().clone();
+    assert_eq!(order, vec!["sync", "send", "sync", "sync", "sync"]);
+    assert!(pool.submit("send", |_| ()).is_err());
+}
@@ -0,0 +1,808 @@
+//! Reading transactional templates, and rendering them locally to preview them.
+
+use errors::{SendgridErrorKind, SendgridResult};
+use marketing_stats::relative_path;
+use scopes::Endpoint;
+use throttle::PageThrottle;
+use v3::{Content, Email, Personalization, SGMailV3, V3Sender};
+
+#[cfg(feature = "templates-local")]
+use handlebars::{self, Handlebars};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use std::cmp;
+use std::fmt;
+
+/// Reading transactional templates and their versions.
+pub struct Templates;
+
+impl Endpoint for Templates {
+    const SCOPES: &'static [&'static str] = &["templates.read", "templates.versions.read"];
+}
+
+/// Creating and activating template versions.
+pub struct TemplateVersions;
+
+impl Endpoint for TemplateVersions {
+    const SCOPES: &'static [&'static str] = &[
+        "mail.send",
+        "templates.versions.activate.create",
+        "templates.versions.create",
+        "templates.versions.read",
+    ];
+}
+
+// The number of templates requested per page.
+const TEMPLATE_PAGE_SIZE: usize = 200;
+
+// The category of the test messages sent to a seed list during a rollout.
+const ROLLOUT_CATEGORY: &str = "template-rollout";
+
+/// A transactional template with all of its versions.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Template {
+    pub id: String,
+    pub name: String,
+    /// Either `legacy` or `dynamic`.
+    #[serde(default)]
+    pub generation: String,
+    #[serde(default)]
+    pub versions: Vec<TemplateVersion>,
+    /// Other fields of the template, such as its update time.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A version of a template. Only the active version is used when sending.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TemplateVersion {
+    pub id: String,
+    pub name: String,
+    /// 1 for the active version, 0 for all others.
+    pub active: u8,
+    pub subject: String,
+    pub html_content: String,
+    pub plain_content: String,
+    /// Other fields of the version, such as its editor or thumbnail URL.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// The addresses a new template version is sent to before it is activated.
+#[derive(Clone)]
+pub struct SeedList {
+    pub from: Email,
+    pub addresses: Vec<String>,
+}
+
+/// The outcome of `V3Sender::roll_out_template_version`.
+#[derive(Clone, Debug)]
+pub struct Rollout {
+    /// The uploaded version.
+    pub version: TemplateVersion,
+    /// Whether the version was confirmed and activated. Versions that weren't stay on
+    /// the template inactive.
+    pub activated: bool,
+}
+
+/// A line of a `TemplateDiff`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffLine {
+    /// A line both versions have.
+    Same(String),
+    /// A line only the newer version has.
+    Added(String),
+    /// A line only the older version has.
+    Removed(String),
+}
+
+// The number of unchanged lines shown around the changes in a hunk.
+const DIFF_CONTEXT: usize = 3;
+
+/// The line by line differences between the subject, HTML and text content of two
+/// template versions. `Display` prints them as a unified diff with three lines of
+/// context, for CI logs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TemplateDiff {
+    /// Every line of the subjects, changed or not.
+    pub subject: Vec<DiffLine>,
+    /// Every line of the HTML content.
+    pub html: Vec<DiffLine>,
+    /// Every line of the plain text content.
+    pub plain: Vec<DiffLine>,
+}
+
+impl TemplateDiff {
+    /// Whether the versions have the same subject and content.
+    pub fn is_empty(&self) -> bool {
+        [&self.subject, &self.html, &self.plain]
+            .iter()
+            .all(|lines| lines.iter().all(|l| matches!(*l, DiffLine::Same(_))))
+    }
+}
+
+impl fmt::Display for TemplateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(name, lines) in &[
+            ("subject", &self.subject),
+            ("html_content", &self.html),
+            ("plain_content", &self.plain),
+        ] {
+            if lines.iter().all(|l| matches!(*l, DiffLine::Same(_))) {
+                continue;
+            }
+            writeln!(f, "--- {}\n+++ {}", name, name)?;
+            for (start, end) in hunks(lines) {
+                let before = &lines[..start];
+                let hunk = &lines[start..end];
+                let old = |l: &&DiffLine| !matches!(**l, DiffLine::Added(_));
+                let new = |l: &&DiffLine| !matches!(**l, DiffLine::Removed(_));
+                writeln!(
+                    f,
+                    "@@ -{} +{} @@",
+                    hunk_range(
+                        before.iter().filter(old).count(),
+                        hunk.iter().filter(old).count()
+                    ),
+                    hunk_range(
+                        before.iter().filter(new).count(),
+                        hunk.iter().filter(new).count()
+                    ),
+                )?;
+                for line in hunk {
+                    match *line {
+                        DiffLine::Same(ref l) => writeln!(f, " {}", l)?,
+                        DiffLine::Added(ref l) => writeln!(f, "+{}", l)?,
+                        DiffLine::Removed(ref l) => writeln!(f, "-{}", l)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The ranges of the lines of a diff that are shown: every change with the context
+// around it, where changes whose context overlaps share a hunk.
+fn hunks(lines: &[DiffLine]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let DiffLine::Same(_) = *line {
+            continue;
+        }
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = cmp::min(i + 1 + DIFF_CONTEXT, lines.len());
+        match hunks.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+// The range of a hunk header for a hunk of `count` lines after `before` lines, which
+// names the line before the hunk when it is empty, like diff does.
+fn hunk_range(before: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", before)
+    } else {
+        format!("{},{}", before + 1, count)
+    }
+}
+
+#[derive(Deserialize)]
+struct TemplatePage {
+    result: Vec<Template>,
+    #[serde(rename = "_metadata", default)]
+    metadata: PageMetadata,
+}
+
+#[derive(Default, Deserialize)]
+struct PageMetadata {
+    next: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NewVersion<'a> {
+    name: &'a str,
+    subject: &'a str,
+    html_content: &'a str,
+    plain_content: &'a str,
+    active: u8,
+}
+
+/// A template rendered with a set of dynamic data.
+#[cfg(feature = "templates-local")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preview {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// The differences between the placeholders of a template and the data for it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TemplateLint {
+    /// Placeholders that the data has no value for, which would render as empty.
+    pub missing: Vec<String>,
+    /// Top level keys of the data that the template never uses.
+    pub unused: Vec<String>,
+}
+
+impl TemplateLint {
+    /// Whether the data matches the template.
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.unused.is_empty()
+    }
+}
+
+impl Template {
+    /// The version of the template that is used when sending.
+    pub fn active_version(&self) -> Option<&TemplateVersion> {
+        self.versions.iter().find(|v| v.active == 1)
+    }
+}
+
+impl TemplateVersion {
+    /// Render the version with the given dynamic data. Values are HTML escaped in the
+    /// HTML content only, and triple braces skip the escaping like they do on SendGrid.
+    ///
+    /// Only the built-in Handlebars helpers are available, so templates that use
+    /// SendGrid's own helpers such as `formatDate` or `equals` can't be rendered.
+    #[cfg(feature = "templates-local")]
+    pub fn render<T: Serialize>(&self, data: &T) -> SendgridResult<Preview> {
+        let mut registry = Handlebars::new();
+        registry.register_template_string("html", &self.html_content)?;
+
+        let mut text_registry = Handlebars::new();
+        text_registry.register_escape_fn(handlebars::no_escape);
+        text_registry.register_template_string("subject", &self.subject)?;
+        text_registry.register_template_string("text", &self.plain_content)?;
+
+        Ok(Preview {
+            subject: text_registry.render("subject", data)?,
+            html: registry.render("html", data)?,
+            text: text_registry.render("text", data)?,
+        })
+    }
+
+    /// Compare the subject and content of the version with a newer one.
+    pub fn diff(&self, newer: &TemplateVersion) -> TemplateDiff {
+        TemplateDiff {
+            subject: diff_lines(&self.subject, &newer.subject),
+            html: diff_lines(&self.html_content, &newer.html_content),
+            plain: diff_lines(&self.plain_content, &newer.plain_content),
+        }
+    }
+
+    /// Compare the placeholders of the version with the given dynamic data. Paths like
+    /// `{{user.first_name}}` are checked all the way down, while placeholders inside of
+    /// `#each` and `#with` blocks are relative to the block and only count as a use of
+    /// the block's value.
+    pub fn lint<T: Serialize>(&self, data: &T) -> SendgridResult<TemplateLint> {
+        let data = serde_json::to_value(data)?;
+        let mut paths = Vec::new();
+        for content in &[&self.subject, &self.html_content, &self.plain_content] {
+            collect_placeholders(content, &mut paths);
+        }
+
+        let mut lint = TemplateLint::default();
+        for path in &paths {
+            if lookup(&data, path).is_none() && !lint.missing.contains(path) {
+                lint.missing.push(path.clone());
+            }
+        }
+        if let Value::Object(ref map) = data {
+            for key in map.keys() {
+                if !paths.iter().any(|path| root(path) == key) {
+                    lint.unused.push(key.clone());
+                }
+            }
+        }
+        Ok(lint)
+    }
+}
+
+// Diff two texts line by line through their longest common subsequence. The lines both
+// start and end with are left out of it, so the table only covers the part that changed,
+// which for an edit of a large template is a few lines.
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut lines: Vec<DiffLine> = old[..prefix]
+        .iter()
+        .map(|l| DiffLine::Same(String::from(*l)))
+        .collect();
+    lines.extend(diff_changed(
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    ));
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|l| DiffLine::Same(String::from(*l))),
+    );
+    lines
+}
+
+fn diff_changed(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    // lengths[i][j] is the length of the common subsequence of old[i..] and new[j..].
+    let mut lengths = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(old.len().max(new.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(String::from(old[i])));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(DiffLine::Removed(String::from(old[i])));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(String::from(new[j])));
+            j += 1;
+        }
+    }
+    lines
+}
+
+// Find the data paths used by the expressions of a Handlebars template.
+fn collect_placeholders(template: &str, paths: &mut Vec<String>) {
+    let mut rest = template;
+    // How many `#each` or `#with` blocks we are in, where paths are relative.
+    let mut scoped = 0;
+
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let end = match rest.find("}}") {
+            Some(end) => end,
+            None => return,
+        };
+        let expression = rest[..end].trim_matches(&['{', '~', '&'][..]);
+        rest = &rest[end + 2..];
+        let expression = expression.trim();
+
+        if expression.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = expression.strip_prefix('/') {
+            if (name.trim() == "each" || name.trim() == "with") && scoped > 0 {
+                scoped -= 1;
+            }
+            continue;
+        }
+
+        let block = expression.starts_with('#') || expression.starts_with('^');
+        let mut tokens = expression
+            .trim_start_matches(&['#', '^'][..])
+            .split_whitespace();
+        let first = match tokens.next() {
+            Some(first) => first,
+            None => continue,
+        };
+        let is_helper = block || tokens.clone().next().is_some();
+
+        let mut arguments: Vec<&str> = if is_helper {
+            tokens.collect()
+        } else {
+            vec![first]
+        };
+        if first == "else" {
+            arguments.clear();
+        }
+        for argument in arguments {
+            let argument = argument.rsplit('=').next().unwrap_or(argument);
+            if let Some(path) = data_path(argument, scoped > 0) {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+
+        if block && (first == "each" || first == "with") {
+            scoped += 1;
+        }
+    }
+}
+
+// The data path of a helper argument or expression, or `None` for literals, special
+// variables and paths relative to a block.
+fn data_path(argument: &str, scoped: bool) -> Option<String> {
+    let literal = argument.starts_with('"')
+        || argument.starts_with('\'')
+        || argument.starts_with('(')
+        || argument.parse::<f64>().is_ok()
+        || ["true", "false", "null", "undefined", "else"].contains(&argument);
+    if literal || argument.starts_with('@') && !argument.starts_with("@root.") {
+        return None;
+    }
+
+    let path = argument.trim_start_matches("@root.");
+    let relative = path == "this" || path.starts_with("this.") || path.starts_with("../");
+    if relative || scoped && !argument.starts_with("@root.") {
+        return None;
+    }
+    Some(path.to_string())
+}
+
+// The top level key of a data path.
+fn root(path: &str) -> &str {
+    path.split('.').next().unwrap_or(path)
+}
+
+// Follow a dotted path through nested objects.
+fn lookup<'a>(data: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(data, |value, key| value.as_object()?.get(key))
+}
+
+impl V3Sender {
+    /// Get a transactional template with all of its versions.
+    pub fn template(&self, template_id: &str) -> SendgridResult<Template> {
+        self.api_get(&format!("/templates/{}", template_id))
+    }
+
+    /// Get all the legacy and dynamic transactional templates. The versions only have
+    /// their names and whether they are active; get a template to read its content.
+    pub fn templates(&self) -> SendgridResult<Vec<Template>> {
+        let mut throttle = PageThrottle::new();
+        let mut next = format!(
+            "/templates?generations=legacy,dynamic&page_size={}",
+            TEMPLATE_PAGE_SIZE
+        );
+        let mut templates = Vec::new();
+        loop {
+            let page: TemplatePage = self.get_page(&next, &mut throttle)?;
+            templates.extend(page.result);
+            match page
+                .metadata
+                .next
+                .as_ref()
+                .and_then(|url| relative_path(url))
+            {
+                Some(path) if !path.is_empty() => next = path.to_string(),
+                _ => return Ok(templates),
+            }
+        }
+    }
+
+    /// Get a version of a template.
+    pub fn template_version(
+        &self,
+        template_id: &str,
+        version_id: &str,
+    ) -> SendgridResult<TemplateVersion> {
+        self.api_get(&format!(
+            "/templates/{}/versions/{}",
+            template_id, version_id
+        ))
+    }
+
+    /// Download two versions of a template and compare their subject and content, so
+    /// reviewers can see what changes before a version is activated.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let diff = sender.diff_template_versions("d-123", &active.id, &draft.id)?;
+    /// print!("{}", diff);
+    /// ```
+    pub fn diff_template_versions(
+        &self,
+        template_id: &str,
+        old_version_id: &str,
+        new_version_id: &str,
+    ) -> SendgridResult<TemplateDiff> {
+        let old = self.template_version(template_id, old_version_id)?;
+        let new = self.template_version(template_id, new_version_id)?;
+        Ok(old.diff(&new))
+    }
+
+    /// Upload a new version of a template with the name, subject and content of the
+    /// given version. The version is not activated.
+    pub fn create_template_version(
+        &self,
+        template_id: &str,
+        version: &TemplateVersion,
+    ) -> SendgridResult<TemplateVersion> {
+        let body = NewVersion {
+            name: &version.name,
+            subject: &version.subject,
+            html_content: &version.html_content,
+            plain_content: &version.plain_content,
+            active: 0,
+        };
+        let path = format!("/templates/{}/versions", template_id);
+        let res = self.api_send_json(Method::Post, &path, &body)?;
+        self.read_json(res)
+    }
+
+    /// Make a version the one used when sending with the template.
+    pub fn activate_template_version(
+        &self,
+        template_id: &str,
+        version_id: &str,
+    ) -> SendgridResult<TemplateVersion> {
+        let path = format!(
+            "/templates/{}/versions/{}/activate",
+            template_id, version_id
+        );
+        let res = self.api_request(Method::Post, &path, None)?;
+        self.read_json(res)
+    }
+
+    /// Upload a new version of a template, optionally send it to a seed list, and only
+    /// activate it once `confirm` returns `true` for the uploaded version. Sends keep
+    /// using the current version until then, and a version that isn't confirmed stays on
+    /// the template inactive.
+    ///
+    /// SendGrid only sends the active version of a template, so the seed list gets a
+    /// message with the subject and content of the new version instead, with its
+    /// placeholders left as they are and the `template-rollout` category.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let seeds = SeedList { from, addresses: vec![String::from("qa@example.com")] };
+    /// let rollout = sender.roll_out_template_version("d-123", &version, Some(&seeds), |v| {
+    ///     prompt(&format!("Activate {} after checking the seed inboxes?", v.name))
+    /// })?;
+    /// ```
+    pub fn roll_out_template_version<F>(
+        &self,
+        template_id: &str,
+        version: &TemplateVersion,
+        seeds: Option<&SeedList>,
+        confirm: F,
+    ) -> SendgridResult<Rollout>
+    where
+        F: FnOnce(&TemplateVersion) -> bool,
+    {
+        let mut version = self.create_template_version(template_id, version)?;
+        if let Some(seeds) = seeds.filter(|s| !s.addresses.is_empty()) {
+            self.send(&seed_message(&version, seeds))?;
+        }
+
+        let activated = confirm(&version);
+        if activated {
+            version = self.activate_template_version(template_id, &version.id)?;
+        }
+        Ok(Rollout { version, activated })
+    }
+
+    /// Render the active version of a template with the given dynamic data, without
+    /// sending anything. This is handy for preview screens and for tests of the data an
+    /// application passes to its templates.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let preview = sender.preview_template("d-123", &json!({"first_name": "Ferris"}))?;
+    /// println!("{}", preview.html);
+    /// ```
+    #[cfg(feature = "templates-local")]
+    pub fn preview_template<T: Serialize>(
+        &self,
+        template_id: &str,
+        data: &T,
+    ) -> SendgridResult<Preview> {
+        let template = self.template(template_id)?;
+        match template.active_version() {
+            Some(version) => version.render(data),
+            None => Err(SendgridErrorKind::InactiveTemplate(template.id.clone()).into()),
+        }
+    }
+
+    /// Download a template and compare the placeholders of its active version with the
+    /// given dynamic data, to catch data that drifted from the template before
+    /// recipients see an empty greeting.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let lint = sender.lint_template_data("d-123", &welcome_data)?;
+    /// assert!(lint.is_clean(), "{:?}", lint);
+    /// ```
+    pub fn lint_template_data<T: Serialize>(
+        &self,
+        template_id: &str,
+        data: &T,
+    ) -> SendgridResult<TemplateLint> {
+        let template = self.template(template_id)?;
+        match template.active_version() {
+            Some(version) => version.lint(data),
+            None => Err(SendgridErrorKind::InactiveTemplate(template.id.clone()).into()),
+        }
+    }
+}
+
+// A message with the subject and content of a version for every seed address.
+fn seed_message(version: &TemplateVersion, seeds: &SeedList) -> SGMailV3 {
+    let mut message = SGMailV3::new();
+    message.set_from(seeds.from.clone());
+    message.set_subject(&version.subject);
+    message.add_category(ROLLOUT_CATEGORY);
+    // SendGrid requires the text part to come before the HTML part.
+    for &(content_type, value) in &[
+        ("text/plain", &version.plain_content),
+        ("text/html", &version.html_content),
+    ] {
+        if !value.is_empty() {
+            let mut c = Content::new();
+            c.set_content_type(content_type);
+            c.set_value(value);
+            message.add_content(c);
+        }
+    }
+    for address in &seeds.addresses {
+        let mut to = Email::new();
+        to.set_email(address);
+        let mut p = Personalization::new();
+        p.add_to(to);
+        message.add_personalization(p);
+    }
+    message
+}
+
+#[test]
+fn lint_placeholders() {
+    let version = TemplateVersion {
+        subject: "Hi {{ first_name }}".to_string(),
+        html_content: "{{! a comment }}<p>{{{user.signature}}}</p>\
+                       {{#if premium}}<b>Thanks!</b>{{else}}{{formatDate renewal \"DD\"}}{{/if}}\
+                       {{#each items}}{{this.name}} {{price}}{{/each}}{{@root.total}}"
+            .to_string(),
+        ..TemplateVersion::default()
+    };
+    let data: Value = serde_json::from_str(
+        r#"{"first_name": "Ferris", "user": {}, "items": [], "total": 3, "coupon": "X"}"#,
+    )
+    .unwrap();
+
+    let lint = version.lint(&data).unwrap();
+    assert_eq!(lint.missing, vec!["user.signature", "premium", "renewal"]);
+    assert_eq!(lint.unused, vec!["coupon"]);
+    assert!(!lint.is_clean());
+}
+
+#[cfg(feature = "templates-local")]
+#[test]
+fn render_active_version() {
+    let template: Template = ::serde_json::from_str(
+        r#"{
+            "id": "d-123",
+            "name": "Welcome",
+            "generation": "dynamic",
+            "versions": [
+                {"id": "v1", "active": 0, "subject": "Old"},
+                {
+                    "id": "v2",
+                    "active": 1,
+                    "subject": "Hi {{name}}",
+                    "html_content": "<p>Hi {{name}}, {{{signature}}}</p>",
+                    "plain_content": "Hi {{name}}"
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let mut data = ::std::collections::HashMap::new();
+    data.insert("name", "Tom & Jerry");
+    data.insert("signature", "<b>Us</b>");
+    let preview = template.active_version().unwrap().render(&data).unwrap();
+    assert_eq!(preview.subject, "Hi Tom & Jerry");
+    assert_eq!(preview.html, "<p>Hi Tom &amp; Jerry, <b>Us</b></p>");
+    assert_eq!(preview.text, "Hi Tom & Jerry");
+}
+
+#[test]
+fn rollout_bodies() {
+    let version = TemplateVersion {
+        name: String::from("v2"),
+        active: 1,
+        subject: String::from("Hi {{name}}"),
+        html_content: String::from("<p>Hi</p>"),
+        ..TemplateVersion::default()
+    };
+    let body = NewVersion {
+        name: &version.name,
+        subject: &version.subject,
+        html_content: &version.html_content,
+        plain_content: &version.plain_content,
+        active: 0,
+    };
+    assert_eq!(
+        serde_json::to_string(&body).unwrap(),
+        r#"{"name":"v2","subject":"Hi {{name}}","html_content":"<p>Hi</p>","plain_content":"","active":0}"#
+    );
+
+    let mut from = Email::new();
+    from.set_email("me@example.com");
+    let seeds = SeedList {
+        from,
+        addresses: vec![String::from("a@example.com"), String::from("b@example.com")],
+    };
+    let json = serde_json::to_value(seed_message(&version, &seeds)).unwrap();
+    assert_eq!(json["categories"][0], ROLLOUT_CATEGORY);
+    assert_eq!(json["content"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        json["personalizations"][1]["to"][0]["email"],
+        "b@example.com"
+    );
+}
+
+#[test]
+fn diff_versions() {
+    let old = TemplateVersion {
+        subject: String::from("Hi"),
+        html_content: String::from("<p>Hi {{name}}</p>\n<p>Thanks</p>\n<footer>Us</footer>"),
+        ..TemplateVersion::default()
+    };
+    let new = TemplateVersion {
+        subject: String::from("Hi"),
+        html_content: String::from(
+            "<p>Hello {{name}}</p>\n<p>Thanks</p>\n<footer>Us</footer>\n<p>PS</p>",
+        ),
+        ..TemplateVersion::default()
+    };
+
+    let diff = old.diff(&new);
+    assert!(!diff.is_empty());
+    assert_eq!(diff.subject, vec![DiffLine::Same(String::from("Hi"))]);
+    assert_eq!(
+        diff.to_string(),
+        "--- html_content\n+++ html_content\n@@ -1,3 +1,4 @@\n-<p>Hi {{name}}</p>\n\
+         +<p>Hello {{name}}</p>\n <p>Thanks</p>\n <footer>Us</footer>\n+<p>PS</p>\n"
+    );
+    assert!(old.diff(&old).is_empty());
+}
+
+#[test]
+fn diff_hunks() {
+    let lines: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+    let old = TemplateVersion {
+        plain_content: lines.join("\n"),
+        ..TemplateVersion::default()
+    };
+    let mut changed = lines.clone();
+    changed[1] = String::from("second");
+    changed.remove(15);
+    let new = TemplateVersion {
+        plain_content: changed.join("\n"),
+        ..TemplateVersion::default()
+    };
+
+    let diff = old.diff(&new);
+    assert_eq!(diff.plain.len(), 21);
+    assert_eq!(
+        diff.to_string(),
+        "--- plain_content\n+++ plain_content\n\
+         @@ -1,5 +1,5 @@\n line 1\n-line 2\n+second\n line 3\n line 4\n line 5\n\
+         @@ -13,7 +13,6 @@\n line 13\n line 14\n line 15\n-line 16\n line 17\n line 18\n line 19\n"
+    );
+    assert_eq!(hunk_range(4, 0), "4,0");
+}
@@ -0,0 +1,332 @@
+//! Helpers for deriving the plain text part of a message.
+
+// Tags whose content never makes it into the text version.
+const SKIPPED_TAGS: [&str; 4] = ["head", "script", "style", "template"];
+
+// Tags that start a new paragraph.
+const PARAGRAPH_TAGS: [&str; 9] = ["p", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre"];
+
+// Tags that start a new line.
+const LINE_TAGS: [&str; 12] = [
+    "div", "tr", "ul", "ol", "table", "section", "article", "header", "footer", "hr", "dl", "dt",
+];
+
+/// Convert an HTML document into a readable plain text version. Markup is removed,
+/// block elements are turned into line breaks, list items are bulleted, links keep
+/// their target in parentheses and common character references are decoded.
+///
+/// This is not a full HTML renderer, but it produces a reasonable text/plain
+/// alternative for typical email templates.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut link: Option<(String, usize)> = None;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let start = match rest.find('<') {
+            Some(i) => i,
+            None => {
+                push_text(&mut out, rest);
+                break;
+            }
+        };
+        push_text(&mut out, &rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("<!--") {
+            rest = match rest.find("-->") {
+                Some(end) => &rest[end + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => {
+                push_text(&mut out, rest);
+                break;
+            }
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag_name(tag);
+
+        if !closing && SKIPPED_TAGS.contains(&name.as_str()) {
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(i) => match rest[i..].find('>') {
+                    Some(j) => &rest[i + j + 1..],
+                    None => "",
+                },
+                None => "",
+            };
+            continue;
+        }
+
+        match name.as_str() {
+            "br" => push_break(&mut out, 1),
+            "li" if !closing => {
+                push_break(&mut out, 1);
+                out.push_str("* ");
+            }
+            "td" | "th" if closing => out.push(' '),
+            "a" if !closing => link = attribute(tag, "href").map(|href| (href, out.len())),
+            "a" => {
+                if let Some((href, start)) = link.take() {
+                    let text = out[start..].trim().to_string();
+                    if !href.is_empty() && !href.starts_with('#') && text != href {
+                        out.push_str(" (");
+                        out.push_str(&href);
+                        out.push(')');
+                    }
+                }
+            }
+            n if PARAGRAPH_TAGS.contains(&n) => push_break(&mut out, 2),
+            n if LINE_TAGS.contains(&n) => push_break(&mut out, 1),
+            _ => {}
+        }
+    }
+
+    tidy(&out)
+}
+
+// The lowercase name of a tag, without the leading slash or any attributes.
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+// Find the value of an attribute inside of a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut offset = 0;
+
+    while let Some(i) = lower[offset..].find(name) {
+        let pos = offset + i;
+        offset = pos + name.len();
+
+        let preceded_by_space = lower[..pos].ends_with(char::is_whitespace);
+        let value = tag[offset..].trim_start();
+        if !preceded_by_space || !value.starts_with('=') {
+            continue;
+        }
+
+        let value = value[1..].trim_start();
+        let value = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let value = &value[1..];
+                &value[..value.find(quote).unwrap_or(value.len())]
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                &value[..end]
+            }
+        };
+
+        let mut decoded = String::new();
+        push_text(&mut decoded, value);
+        return Some(decoded);
+    }
+
+    None
+}
+
+// Make sure the output ends with at least `count` line breaks.
+fn push_break(out: &mut String, count: usize) {
+    while out.ends_with(' ') {
+        out.pop();
+    }
+    if out.is_empty() {
+        return;
+    }
+    let existing = out.len() - out.trim_end_matches('\n').len();
+    for _ in existing..count {
+        out.push('\n');
+    }
+}
+
+// Append a run of text, decoding character references and collapsing whitespace the
+// way a browser would.
+fn push_text(out: &mut String, text: &str) {
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '&' {
+            if let Some((decoded, len)) = decode_reference(rest) {
+                push_char(out, decoded);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+
+        push_char(out, c);
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+fn push_char(out: &mut String, c: char) {
+    if c.is_whitespace() {
+        if !out.is_empty() && !out.ends_with(' ') && !out.ends_with('\n') {
+            out.push(' ');
+        }
+    } else {
+        out.push(c);
+    }
+}
+
+// Decode the character reference at the start of `text`, returning the character and
+// how many bytes it used.
+fn decode_reference(text: &str) -> Option<(char, usize)> {
+    let end = text.char_indices().take(12).find(|&(_, c)| c == ';')?.0;
+    let name = &text[1..end];
+
+    let decoded = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        _ if name.starts_with("#x") || name.starts_with("#X") => {
+            u32::from_str_radix(&name[2..], 16)
+                .ok()
+                .and_then(::std::char::from_u32)?
+        }
+        _ if name.starts_with('#') => name[1..].parse().ok().and_then(::std::char::from_u32)?,
+        _ => return None,
+    };
+
+    Some((decoded, end + 1))
+}
+
+// Trim every line and collapse runs of blank lines into a single one.
+fn tidy(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank = false;
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(line);
+    }
+
+    out
+}
+
+/// The column that `normalize_text` wraps lines at, as recommended by RFC 5322.
+pub const TEXT_WIDTH: usize = 78;
+
+/// Normalize a plain text part for strict text clients. Line endings become CRLF,
+/// control characters other than tabs are removed, trailing whitespace is trimmed
+/// except on the `-- ` signature separator, and lines are wrapped at `TEXT_WIDTH`
+/// columns. Words longer than a line, such as URLs, are never broken.
+pub fn normalize_text(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut out = String::with_capacity(text.len() + text.len() / 16);
+
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push_str("\r\n");
+        }
+        let line: String = line
+            .chars()
+            .filter(|&c| !c.is_control() || c == '\t')
+            .collect();
+        let line = if line == "-- " {
+            &line[..]
+        } else {
+            line.trim_end()
+        };
+        wrap_line(&mut out, line);
+    }
+
+    out
+}
+
+// Append a line, broken at the last space before the width wherever it is too long.
+fn wrap_line(out: &mut String, mut line: &str) {
+    while line.chars().count() > TEXT_WIDTH {
+        let (limit, ch) = line
+            .char_indices()
+            .nth(TEXT_WIDTH)
+            .expect("the line is longer than the width");
+        let split = match line[..limit + ch.len_utf8()].rfind(' ') {
+            Some(split) if !line[..split].trim().is_empty() => split,
+            // No space to break at in time, so break after the long word instead.
+            _ => match line[limit..].find(' ') {
+                Some(split) => limit + split,
+                None => break,
+            },
+        };
+        out.push_str(line[..split].trim_end());
+        out.push_str("\r\n");
+        line = line[split..].trim_start_matches(' ');
+    }
+    out.push_str(line);
+}
+
+#[test]
+fn html_to_text_blocks() {
+    let html = "<html><head><title>Hi</title><style>p { color: red; }</style></head>\
+                <body><h1>Welcome</h1><p>Thanks for   signing up.<br>See you soon.</p>\
+                <ul><li>One</li><li>Two</li></ul></body></html>";
+    let want = "Welcome\n\nThanks for signing up.\nSee you soon.\n\n* One\n* Two";
+    assert_eq!(html_to_text(html), want);
+}
+
+#[test]
+fn html_to_text_links_and_entities() {
+    let html = r#"<p>Visit <a href="https://example.com/?a=1&amp;b=2">our site</a> &amp; <a href='https://example.com'>https://example.com</a>&#33;</p>"#;
+    let want = "Visit our site (https://example.com/?a=1&b=2) & https://example.com!";
+    assert_eq!(html_to_text(html), want);
+}
+
+#[test]
+fn normalize_text_lines() {
+    let long = "word ".repeat(20);
+    let url = format!("https://example.com/{}", "x".repeat(80));
+    let text = format!("Hi\u{7}  \r\n\r{}\nsee {} now\n-- \nMe", long, url);
+    let want = format!(
+        "Hi\r\n\r\n{}word\r\n{}\r\nsee\r\n{}\r\nnow\r\n-- \r\nMe",
+        "word ".repeat(14),
+        "word ".repeat(5).trim_end(),
+        url
+    );
+    assert_eq!(normalize_text(&text), want);
+    assert_eq!(normalize_text("short\tline"), "short\tline");
+}
+
+#[test]
+fn wrap_non_ascii_lines() {
+    let line = format!("{} ééé end", "é".repeat(76));
+    let want = format!("{}\r\nééé end", "é".repeat(76));
+    assert_eq!(normalize_text(&line), want);
+
+    let word = "é".repeat(100);
+    assert_eq!(normalize_text(&word), word);
+}
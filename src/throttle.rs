@@ -0,0 +1,150 @@
+//! Spacing out the requests of paginated listings by the rate limit SendGrid reports in
+//! its responses, so a sync of a whole account slows down instead of running into 429
+//! responses and failing halfway through.
+
+use errors::SendgridResult;
+use v3::V3Sender;
+
+use reqwest::header::Headers;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Requests are spread out over what is left of the window once less than this fraction
+// of the limit remains.
+const SPREAD_BELOW: u64 = 4;
+
+// The longest wait before a request, in case the reset is far off or wrong.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+// How many 429 responses in a row are waited out before the error is returned.
+const MAX_RATE_LIMITED: u32 = 5;
+
+// How long to wait after a 429 response without rate limit headers, multiplied by the
+// number of the attempt.
+const RATE_LIMITED_DELAY: Duration = Duration::from_secs(1);
+
+// The rate limit of an endpoint, as reported by the `X-RateLimit-*` headers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RateLimit {
+    limit: u64,
+    remaining: u64,
+    // The Unix timestamp of when the limit resets.
+    reset: u64,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &Headers) -> Option<RateLimit> {
+        let number = |name: &str| -> Option<u64> {
+            let raw = headers.get_raw(name)?.one()?;
+            String::from_utf8_lossy(raw).trim().parse().ok()
+        };
+        Some(RateLimit {
+            limit: number("X-RateLimit-Limit")?,
+            remaining: number("X-RateLimit-Remaining")?,
+            reset: number("X-RateLimit-Reset")?,
+        })
+    }
+
+    // How long to wait before the next request at the Unix timestamp `now`: nothing
+    // while plenty of the limit remains, then an even share of the rest of the window
+    // for every remaining request, and the whole rest of it once none remain.
+    fn delay(&self, now: u64) -> Duration {
+        let window = Duration::from_secs(self.reset.saturating_sub(now));
+        let delay = if self.remaining == 0 {
+            window
+        } else if self.remaining * SPREAD_BELOW < self.limit {
+            window / cmp::min(self.remaining, u64::from(u32::MAX)) as u32
+        } else {
+            Duration::from_secs(0)
+        };
+        cmp::min(delay, MAX_WAIT)
+    }
+}
+
+// Paces the requests of one listing.
+#[derive(Debug, Default)]
+pub(crate) struct PageThrottle {
+    // When the next request may be made.
+    next: Option<Instant>,
+}
+
+impl PageThrottle {
+    pub fn new() -> PageThrottle {
+        PageThrottle::default()
+    }
+
+    fn wait(&self) {
+        if let Some(next) = self.next {
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+    }
+
+    // Update the pace from the headers of a response. `attempt` is the number of 429
+    // responses in a row, for when there are no headers to go by.
+    fn observe(&mut self, headers: &Headers, attempt: u32) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let delay = match RateLimit::from_headers(headers) {
+            Some(limit) if attempt > 0 => limit.delay(now).max(RATE_LIMITED_DELAY),
+            Some(limit) => limit.delay(now),
+            None => RATE_LIMITED_DELAY * attempt,
+        };
+        self.next = Some(Instant::now() + delay);
+    }
+}
+
+impl V3Sender {
+    // Get and decode a page of a listing, after waiting as long as the throttle says.
+    // 429 responses are waited out and the page is requested again.
+    pub(crate) fn get_page<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        throttle: &mut PageThrottle,
+    ) -> SendgridResult<T> {
+        let mut attempt = 0;
+        loop {
+            throttle.wait();
+            let res = self.api_response(Method::Get, path, None)?;
+            if res.status().as_u16() != 429 || attempt == MAX_RATE_LIMITED {
+                throttle.observe(res.headers(), 0);
+                return self.read_json(self.api_success(path, res)?);
+            }
+            attempt += 1;
+            throttle.observe(res.headers(), attempt);
+        }
+    }
+}
+
+#[test]
+fn rate_limit_delays() {
+    let mut headers = Headers::new();
+    headers.set_raw("X-RateLimit-Limit", "600");
+    headers.set_raw("X-RateLimit-Remaining", "500");
+    headers.set_raw("X-RateLimit-Reset", "1060");
+    let limit = RateLimit::from_headers(&headers).unwrap();
+    assert_eq!(limit.delay(1000), Duration::from_secs(0));
+
+    let low = RateLimit {
+        remaining: 30,
+        ..limit
+    };
+    assert_eq!(low.delay(1000), Duration::from_secs(2));
+    let none = RateLimit {
+        remaining: 0,
+        ..limit
+    };
+    assert_eq!(none.delay(1030), Duration::from_secs(30));
+    assert_eq!(none.delay(2000), Duration::from_secs(0));
+
+    headers.remove_raw("X-RateLimit-Reset");
+    assert_eq!(RateLimit::from_headers(&headers), None);
+}
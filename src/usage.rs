@@ -0,0 +1,129 @@
+//! Keeping large sends under a monthly cap of requests, checked against the statistics
+//! of the current month.
+
+use bulk::SendReport;
+use credits;
+use errors::{SendgridErrorKind, SendgridResult};
+use stats::StatsQuery;
+use v3::{SGMailV3, V3Sender};
+
+/// Refuses sends that would take the requests of the current month over a cap. A request
+/// is one message to one recipient, as counted by the `requests` statistic.
+///
+/// # Examples
+///
+/// ```ignore
+/// let guard = UsageGuard::new(100_000);
+/// let report = guard.send_all(&sender, merge.build(recipients)?)?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct UsageGuard {
+    monthly_cap: u64,
+    allow_over_cap: bool,
+}
+
+/// The requests of the current month next to the cap, before a send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UsageCheck {
+    /// The requests made this month so far.
+    pub used: u64,
+    /// The requests the messages would make.
+    pub needed: u64,
+    /// The monthly cap of the guard.
+    pub cap: u64,
+}
+
+impl UsageCheck {
+    /// Whether sending the messages would take the requests of the month over the cap.
+    pub fn exceeds_cap(&self) -> bool {
+        self.used.saturating_add(self.needed) > self.cap
+    }
+}
+
+impl UsageGuard {
+    /// Construct a guard with a cap on the requests of a month.
+    pub fn new(monthly_cap: u64) -> UsageGuard {
+        UsageGuard {
+            monthly_cap,
+            allow_over_cap: false,
+        }
+    }
+
+    /// Let sends through even when they go over the cap, e.g. for an on-call engineer
+    /// who made sure the overage is wanted.
+    pub fn with_override(mut self) -> UsageGuard {
+        self.allow_over_cap = true;
+        self
+    }
+
+    /// Compare the requests of the current month and the ones the messages would make
+    /// with the cap. Requires the `stats.read` scope.
+    pub fn check<'a, I>(&self, sender: &V3Sender, messages: I) -> SendgridResult<UsageCheck>
+    where
+        I: IntoIterator<Item = &'a SGMailV3>,
+    {
+        let needed = credits::credits_needed(messages);
+        let used = sender
+            .global_stats(&StatsQuery::month_to_date())?
+            .iter()
+            .flat_map(|period| &period.stats)
+            .map(|entry| entry.metrics.requests)
+            .sum();
+        Ok(UsageCheck {
+            used,
+            needed,
+            cap: self.monthly_cap,
+        })
+    }
+
+    /// Send every message like `V3Sender::send_all`, unless they would take the requests
+    /// of the month over the cap, which fails with `UsageCapExceeded` without sending
+    /// anything. Guards made with `with_override` send anyway. Requires the `mail.send`
+    /// and `stats.read` scopes.
+    pub fn send_all(
+        &self,
+        sender: &V3Sender,
+        messages: Vec<SGMailV3>,
+    ) -> SendgridResult<SendReport> {
+        let check = self.check(sender, &messages)?;
+        self.allow(&check)?;
+        Ok(sender.send_all(messages))
+    }
+
+    fn allow(&self, check: &UsageCheck) -> SendgridResult<()> {
+        if !check.exceeds_cap() {
+            return Ok(());
+        }
+        if self.allow_over_cap {
+            #[cfg(feature = "log")]
+            warn!(
+                "sending {} requests goes over the monthly cap of {}, allowed by an override",
+                check.needed, check.cap
+            );
+            return Ok(());
+        }
+        Err(SendgridErrorKind::UsageCapExceeded(check.used, check.needed, check.cap).into())
+    }
+}
+
+#[test]
+fn usage_cap() {
+    let check = UsageCheck {
+        used: 900,
+        needed: 200,
+        cap: 1000,
+    };
+    assert!(check.exceeds_cap());
+    match *UsageGuard::new(1000).allow(&check).unwrap_err().kind() {
+        SendgridErrorKind::UsageCapExceeded(900, 200, 1000) => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(UsageGuard::new(1000).with_override().allow(&check).is_ok());
+
+    let check = UsageCheck {
+        needed: 100,
+        ..check
+    };
+    assert!(!check.exceeds_cap());
+    assert!(UsageGuard::new(1000).allow(&check).is_ok());
+}
@@ -0,0 +1,10 @@
+//! The legacy V2 API, which sends a `Mail` as a URL encoded form to `/api/mail.send.json`.
+//! SendGrid deprecated the endpoint, so new code should build an `SGMailV3` and send it
+//! with `v3::V3Sender` instead. This module is behind the `legacy-v2` feature, which is
+//! enabled by default so existing code keeps compiling.
+
+pub use sg_client::make_post_body;
+#[cfg(feature = "client")]
+pub use sg_client::SGClient;
+
+pub use mail::{EmailAddress, Mail, Priority};
@@ -1,24 +1,269 @@
-use errors::SendgridResult;
+use address;
+use attachments;
+#[cfg(feature = "client")]
+use audit::{self, AuditOutcome, AuditSink};
+#[cfg(feature = "client")]
+use cancel::{self, CancellationToken};
+#[cfg(feature = "client")]
+use client_stats::{ClientStats, Counters};
+#[cfg(feature = "client")]
+use connection::Connection;
+#[cfg(feature = "client")]
+use domains::DomainPolicy;
+#[cfg(feature = "client")]
+use download::ResumableDownload;
+use eml::{EmlAttachment, EmlBody};
+#[cfg(feature = "client")]
+use errors::SendgridError;
+use errors::{SendgridErrorKind, SendgridResult};
+#[cfg(feature = "client")]
+use events::{ClientEvent, Events};
+#[cfg(feature = "client")]
+use failover::{self, Hosts};
+use headers;
+#[cfg(feature = "client")]
+use idempotency::IdempotencyStore;
+#[cfg(feature = "client")]
+use keys::{self, ApiKeys, Key, KeyRejected};
+#[cfg(all(feature = "client", feature = "log"))]
+use logging::{self, AddressRedaction, RedactionPolicy};
+use mail::{EmailAddress, Mail};
+#[cfg(feature = "client")]
+use quotas::Quotas;
+#[cfg(feature = "client")]
+use response;
+#[cfg(feature = "client")]
+use scoped::MessageDefaults;
+#[cfg(feature = "client")]
+use signing::{self, RequestSigner, SignableRequest};
+use text;
 
+use std::borrow::Cow;
+#[cfg(feature = "client")]
+use std::cell::RefCell;
+#[cfg(feature = "client")]
+use std::cmp;
 use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "client")]
+use std::io::{self, Write};
+use std::str;
+#[cfg(feature = "client")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "client")]
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+#[cfg(feature = "client")]
+use std::sync::Mutex;
+#[cfg(feature = "client")]
+use std::time::Duration;
+#[cfg(feature = "client")]
+use std::time::Instant;
+#[cfg(feature = "client")]
+use std::time::SystemTime;
 
-use reqwest::header::{Authorization, Bearer, ContentType, Headers, UserAgent};
-use reqwest::Client;
+#[cfg(feature = "client")]
+use reqwest::header::{ContentEncoding, ContentType, Encoding, Headers, UserAgent};
+#[cfg(feature = "client")]
+use reqwest::{Body, Client, Method};
 
+#[cfg(feature = "csv")]
+use csv;
 use data_encoding::BASE64;
+#[cfg(feature = "client")]
+use libflate::gzip;
 
-use serde_json;
+#[cfg(all(feature = "client", feature = "secrets"))]
+use secrecy::SecretString;
+#[cfg(feature = "client")]
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Serializer};
+use serde_json::{self, Value};
+#[cfg(all(feature = "client", feature = "uuid"))]
+use uuid::Uuid;
 
+#[cfg(feature = "client")]
 pub use reqwest::Response;
 
-const V3_API_URL: &'static str = "https://api.sendgrid.com/v3/mail/send";
+// The base URL of the V3 API.
+#[cfg(feature = "client")]
+const V3_API_BASE: &str = "https://api.sendgrid.com/v3";
+
+// The timeout of reqwest clients built without one.
+#[cfg(feature = "client")]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The most clients with a timeout of their own a sender keeps.
+#[cfg(feature = "client")]
+const MAX_TIMEOUT_CLIENTS: usize = 16;
+
+// The MIME type used for calendar invites. Outlook needs the method parameter to treat
+// the part as an invite instead of a plain attachment.
+const CALENDAR_INVITE_TYPE: &str = "text/calendar; method=REQUEST; charset=UTF-8";
+
+/// The custom argument that `V3Sender::send_tracked` stores the correlation ID in.
+pub const CORRELATION_ID_ARG: &str = "correlation_id";
+
+/// The custom argument that `SGMailV3::set_trace_context` stores the trace ID in.
+pub const TRACE_ID_ARG: &str = "trace_id";
+
+/// The custom argument that `SGMailV3::set_trace_context` stores the span ID in.
+pub const SPAN_ID_ARG: &str = "span_id";
+
+/// The category that `V3Sender::test_send` adds to test messages.
+pub const TEST_CATEGORY: &str = "test";
+
+/// The maximum number of categories SendGrid accepts in a single message.
+pub const MAX_CATEGORIES: usize = 10;
+
+/// The maximum number of personalizations SendGrid accepts in a single message.
+pub const MAX_PERSONALIZATIONS: usize = 1000;
+
+/// The maximum number of attachments accepted in a single message.
+pub const MAX_ATTACHMENTS: usize = 100;
+
+/// The maximum size of a single attachment as sent to SendGrid, in bytes.
+pub const MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// The maximum size of all the attachments of a message as sent to SendGrid, in bytes.
+pub const MAX_ATTACHMENTS_SIZE: usize = 30 * 1024 * 1024;
+
+// Scratch buffers above this size are dropped after use rather than kept around for
+// the next message.
+#[cfg(feature = "client")]
+const MAX_POOLED_BUFFER: usize = 4 * 1024 * 1024;
+
+#[cfg(feature = "client")]
+thread_local! {
+    // The buffer used to encode bodies that get compressed, shared by all the senders
+    // on a thread.
+    static JSON_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
 
 /// Just a redefinition of a map to store string keys and values.
 pub type SGMap = HashMap<String, String>;
 
-/// Used to send a V3 message body.
+/// Used to send a V3 message body. The connection pool is shared by all the requests
+/// made with the same sender. Requests are made over HTTP/1.1, so concurrent sends each
+/// use their own pooled keep-alive connection rather than being multiplexed over one.
+#[cfg(feature = "client")]
 pub struct V3Sender {
-    api_key: String,
+    api_keys: ApiKeys,
+    audit: Option<Arc<dyn AuditSink>>,
+    client: Client,
+    connection: Connection,
+    counters: Counters,
+    body_capacity: AtomicUsize,
+    domain_policy: Option<DomainPolicy>,
+    events: Arc<Events>,
+    gzip_threshold: Option<usize>,
+    hosts: Hosts,
+    max_response_size: usize,
+    on_behalf_of: Option<String>,
+    quotas: Quotas,
+    #[cfg(feature = "log")]
+    redaction: RedactionPolicy,
+    signer: Option<Arc<dyn RequestSigner>>,
+    timeouts: TimeoutProfile,
+    timeout_clients: Mutex<HashMap<Duration, Client>>,
+}
+
+/// Options for a single request that differ from the defaults of the sender.
+#[cfg(feature = "client")]
+#[derive(Clone, Debug, Default)]
+pub struct RequestOptions {
+    /// The timeout for the request. Requests with a timeout use a separate connection
+    /// pool for every distinct timeout.
+    pub timeout: Option<Duration>,
+    /// Extra headers to send with the request.
+    pub extra_headers: Vec<(String, String)>,
+    /// The subuser to make the request on behalf of, which sets the `on-behalf-of`
+    /// header.
+    pub on_behalf_of: Option<String>,
+    /// When the caller stops waiting for the request. The request fails with
+    /// `DeadlineExceeded` if it passed before it was made, and otherwise its timeout is
+    /// cut down to the time that is left.
+    pub deadline: Option<Instant>,
+    /// A token to cancel the request before it is made, and the retry waits of bulk
+    /// sends made with these options.
+    pub cancellation: Option<CancellationToken>,
+}
+
+#[cfg(feature = "client")]
+impl RequestOptions {
+    // The timeout of a request made now, failing if it was cancelled or the deadline
+    // passed. `default` is the timeout of the sender for this kind of request.
+    pub(crate) fn timeout_now(
+        &self,
+        default: Option<Duration>,
+    ) -> SendgridResult<Option<Duration>> {
+        let left = cancel::check(self.cancellation.as_ref(), self.deadline)?.map(round_down);
+        Ok(match (self.timeout.or(default), left) {
+            (Some(timeout), Some(left)) => Some(cmp::min(timeout, left)),
+            (timeout, left) => timeout.or(left),
+        })
+    }
+}
+
+// Round the time left until a deadline down to a power of two milliseconds. Every
+// distinct timeout needs a client of its own, so timeouts cut down to a deadline would
+// otherwise build a new client for almost every request.
+#[cfg(feature = "client")]
+fn round_down(left: Duration) -> Duration {
+    let millis = left.as_millis().max(1);
+    Duration::from_millis(1 << (127 - millis.leading_zeros()))
+}
+
+/// The timeouts of the kinds of requests a sender makes, since a timeout that suits
+/// sends is too short for downloading a large export. Kinds without a timeout use the
+/// default of 30 seconds, and the timeout of `RequestOptions` takes precedence. Every
+/// timeout other than the default keeps a connection pool of its own.
+///
+/// # Examples
+///
+/// ```ignore
+/// sender.set_timeout_profile(
+///     TimeoutProfile::new()
+///         .with_mail_send(Duration::from_secs(5))
+///         .with_transfers(Duration::from_secs(30 * 60)),
+/// );
+/// ```
+#[cfg(feature = "client")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeoutProfile {
+    /// The timeout of sends to the mail send endpoint.
+    pub mail_send: Option<Duration>,
+    /// The timeout of the other requests to the V3 API, like listings and settings.
+    pub api: Option<Duration>,
+    /// The timeout of downloads of exports and uploads of imports, which covers reading
+    /// the whole body.
+    pub transfers: Option<Duration>,
+}
+
+#[cfg(feature = "client")]
+impl TimeoutProfile {
+    /// Construct a profile that leaves every kind of request at the default timeout.
+    pub fn new() -> TimeoutProfile {
+        TimeoutProfile::default()
+    }
+
+    /// Set the timeout of sends.
+    pub fn with_mail_send(mut self, timeout: Duration) -> TimeoutProfile {
+        self.mail_send = Some(timeout);
+        self
+    }
+
+    /// Set the timeout of other API requests.
+    pub fn with_api(mut self, timeout: Duration) -> TimeoutProfile {
+        self.api = Some(timeout);
+        self
+    }
+
+    /// Set the timeout of downloads and uploads.
+    pub fn with_transfers(mut self, timeout: Duration) -> TimeoutProfile {
+        self.transfers = Some(timeout);
+        self
+    }
 }
 
 /// The main structure for a V3 API mail send call. This is composed of many other smaller
@@ -32,6 +277,61 @@ pub struct SGMailV3 {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     attachments: Option<Vec<Attachment>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<Email>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    headers: Option<SGMap>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    categories: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    custom_args: Option<SGMap>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    send_at: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batch_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asm: Option<Asm>,
+}
+
+// The unsubscribe group of a message, which SendGrid's unsubscribe links refer to.
+#[derive(Serialize)]
+struct Asm {
+    group_id: u32,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups_to_display: Vec<u32>,
+}
+
+/// The unsubscribe links SendGrid fills in for messages with an unsubscribe group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnsubscribeLink {
+    /// Unsubscribe from the group of the message.
+    Group,
+    /// Unsubscribe from every message of the account.
+    Global,
+    /// Open the page where recipients pick the groups they want to be in.
+    Preferences,
+}
+
+impl UnsubscribeLink {
+    /// The substitution tag SendGrid replaces with the URL of the link.
+    pub fn tag(self) -> &'static str {
+        match self {
+            UnsubscribeLink::Group => "<%asm_group_unsubscribe_raw_url%>",
+            UnsubscribeLink::Global => "<%asm_global_unsubscribe_raw_url%>",
+            UnsubscribeLink::Preferences => "<%asm_preferences_raw_url%>",
+        }
+    }
 }
 
 /// An email with a required address and an optional name field.
@@ -77,14 +377,18 @@ pub struct Personalization {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     send_at: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dynamic_template_data: Option<Value>,
 }
 
 /// An attachment block for a V3 message. Content and filename are required. If the
 /// mime_type is unspecified, the email will use Sendgrid's default for attachments
 /// which is 'application/octet-stream'.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Attachment {
-    content: String,
+    #[serde(serialize_with = "serialize_base64")]
+    content: AttachmentContent,
 
     filename: String,
 
@@ -98,26 +402,1018 @@ pub struct Attachment {
     content_id: Option<String>,
 }
 
+/// Attachment contents that are base64 encoded once and then shared, for attaching the
+/// same file to many messages of a bulk send without encoding or copying it for every
+/// message. Clones share the encoded contents.
+///
+/// # Examples
+///
+/// ```ignore
+/// let report = SharedContent::new(&fs::read("report.pdf")?);
+/// for message in &mut messages {
+///     let mut a = Attachment::new();
+///     a.set_shared_content(&report);
+///     a.set_filename("report.pdf");
+///     message.add_attachment(a);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedContent {
+    encoded: Arc<str>,
+}
+
+impl SharedContent {
+    /// Encode the raw body of an attachment.
+    pub fn new(data: &[u8]) -> SharedContent {
+        SharedContent {
+            encoded: Arc::from(BASE64.encode(data)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum AttachmentContent {
+    Raw(Vec<u8>),
+    Shared(SharedContent),
+}
+
+impl AttachmentContent {
+    fn encoded_len(&self) -> usize {
+        match *self {
+            AttachmentContent::Raw(ref data) => BASE64.encode_len(data.len()),
+            AttachmentContent::Shared(ref shared) => shared.encoded.len(),
+        }
+    }
+}
+
+#[cfg(feature = "client")]
 impl V3Sender {
     /// Construct a new V3 message sender.
     pub fn new(api_key: String) -> V3Sender {
-        V3Sender { api_key: api_key }
+        V3Sender::with_key(keys::to_key(api_key))
+    }
+
+    /// Construct a new V3 message sender from a key that is already kept secret.
+    #[cfg(feature = "secrets")]
+    pub fn from_secret(api_key: SecretString) -> V3Sender {
+        V3Sender::with_key(api_key)
+    }
+
+    fn with_key(api_key: Key) -> V3Sender {
+        V3Sender {
+            api_keys: ApiKeys::new(api_key),
+            client: Client::new(),
+            connection: Connection::default(),
+            counters: Counters::default(),
+            audit: None,
+            body_capacity: AtomicUsize::new(0),
+            domain_policy: None,
+            events: Arc::default(),
+            gzip_threshold: None,
+            hosts: Hosts::new(V3_API_BASE),
+            max_response_size: response::DEFAULT_MAX_RESPONSE_SIZE,
+            on_behalf_of: None,
+            quotas: Quotas::default(),
+            #[cfg(feature = "log")]
+            redaction: RedactionPolicy::default(),
+            signer: None,
+            timeouts: TimeoutProfile::default(),
+            timeout_clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Compress request bodies larger than `threshold` bytes with gzip, which cuts the
+    /// bandwidth used by messages with large attachments. Bodies are sent uncompressed
+    /// by default.
+    pub fn set_gzip_threshold(&mut self, threshold: usize) {
+        self.gzip_threshold = Some(threshold);
+    }
+
+    /// Set the timeouts of the kinds of requests the sender makes.
+    pub fn set_timeout_profile(&mut self, timeouts: TimeoutProfile) {
+        self.timeouts = timeouts;
+    }
+
+    /// Set the largest response body that is read, in bytes. Larger responses fail with
+    /// `ResponseTooLarge`, and the bodies of error responses are cut off. The limit is
+    /// 10 MiB by default.
+    pub fn set_max_response_size(&mut self, max_response_size: usize) {
+        self.max_response_size = max_response_size;
+    }
+
+    /// Send every request through an HTTP proxy, such as `http://proxy.example.com:3128`.
+    /// Proxies that need authentication are not supported, as HTTPS requests are
+    /// tunneled through the proxy without credentials; a local proxy that adds them,
+    /// such as cntlm, can be used instead.
+    pub fn set_proxy(&mut self, url: &str) -> SendgridResult<()> {
+        self.connection.set_proxy(url)?;
+        self.rebuild_clients()
+    }
+
+    /// Trust the certificates in a PEM bundle on top of the system's root certificates,
+    /// for networks that intercept TLS with their own certificate authority.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.add_root_certificates(&fs::read_to_string("/etc/ssl/corporate-ca.pem")?)?;
+    /// ```
+    pub fn add_root_certificates(&mut self, pem: &str) -> SendgridResult<()> {
+        self.connection.add_root_certificates(pem)?;
+        self.rebuild_clients()
+    }
+
+    // Replace the connection pools after the connection settings changed.
+    fn rebuild_clients(&mut self) -> SendgridResult<()> {
+        self.client = self.connection.client(None)?;
+        self.timeout_clients
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        Ok(())
+    }
+
+    /// Only send messages from the valid authenticated domains of the account, or their
+    /// subdomains. Messages from any other domain fail with `UnauthenticatedDomain`
+    /// before they are sent, rather than being sent unsigned. The domains are fetched
+    /// on the first send and again once they are older than `refresh_after`, which
+    /// needs the `whitelabel.read` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.require_authenticated_domains(Duration::from_secs(60 * 60));
+    /// ```
+    pub fn require_authenticated_domains(&mut self, refresh_after: Duration) {
+        self.domain_policy = Some(DomainPolicy::new(refresh_after));
+    }
+
+    /// Limit how many sends messages in a category can make within a window of time,
+    /// such as 10,000 a day for "marketing". Every recipient counts as a send, and
+    /// messages that would go over the limit fail with `QuotaExceeded` before they are
+    /// sent. The counts are kept by this sender only, so they start from zero in every
+    /// process.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.set_category_quota("marketing", 10_000, Duration::from_secs(24 * 60 * 60));
+    /// ```
+    pub fn set_category_quota(&mut self, category: &str, limit: u64, window: Duration) {
+        self.quotas.set(category, limit, window);
+    }
+
+    /// Make requests to another base URL, such as `https://api.eu.sendgrid.com/v3` or a
+    /// proxy, and fall back to the other base URLs in order when it can't be reached.
+    /// After falling back, requests stay on the fallback for a minute before the
+    /// primary base URL is tried again, so an unstable primary isn't hit with every
+    /// request.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.set_hosts("https://proxy.example.com/v3", &["https://api.sendgrid.com/v3"]);
+    /// ```
+    pub fn set_hosts(&mut self, primary: &str, fallbacks: &[&str]) {
+        self.hosts = Hosts::with_fallbacks(primary, fallbacks);
+    }
+
+    /// Set how long to stay on a fallback base URL before trying the primary one again.
+    pub fn set_failback_after(&mut self, failback_after: Duration) {
+        self.hosts.set_failback_after(failback_after);
+    }
+
+    /// Add an API key to fall back to when SendGrid rejects the current key with 401
+    /// Unauthorized. Keys are tried in the order they were added, and the last key that
+    /// was accepted is used first afterwards, so keys can be rotated without downtime.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut sender = V3Sender::new(new_key);
+    /// sender.add_fallback_key(old_key);
+    /// sender.on_key_rejected(|e| println!("API key {} was rejected", e.rejected));
+    /// ```
+    pub fn add_fallback_key(&mut self, api_key: String) {
+        self.api_keys.push(keys::to_key(api_key));
+    }
+
+    /// Call a function whenever SendGrid rejects an API key and the next one is tried.
+    pub fn on_key_rejected<F: Fn(&KeyRejected) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.api_keys.set_observer(observer);
+    }
+
+    /// Write a redacted record of every request that sends mail or changes the account
+    /// to an audit sink. Senders made with `for_subuser` write to the same sink.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let audit = Arc::new(MemoryAuditSink::new());
+    /// sender.set_audit_sink(audit.clone());
+    /// sender.send(&message)?;
+    /// println!("{}", audit.records()[0]);
+    /// ```
+    pub fn set_audit_sink<S: AuditSink + 'static>(&mut self, sink: Arc<S>) {
+        self.audit = Some(sink);
+    }
+
+    /// Add the headers a signer computes from the method, path and body to every request
+    /// to the V3 API, e.g. for an egress gateway that checks a signature. Downloads and
+    /// uploads of export and import files go to other hosts and are not signed. Senders
+    /// made with `for_subuser` use the same signer.
+    pub fn set_request_signer<S: RequestSigner + 'static>(&mut self, signer: Arc<S>) {
+        self.signer = Some(signer);
+    }
+
+    /// Set how recipient addresses are written to the debug logs. They are masked by
+    /// default.
+    #[cfg(feature = "log")]
+    pub fn set_address_redaction(&mut self, redaction: AddressRedaction) {
+        self.redaction.addresses = redaction;
+    }
+
+    /// Set what gets logged of request bodies and how it is redacted. Bodies are not
+    /// logged by default.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.set_redaction_policy(RedactionPolicy {
+    ///     log_bodies: true,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    #[cfg(feature = "log")]
+    pub fn set_redaction_policy(&mut self, policy: RedactionPolicy) {
+        self.redaction = policy;
+    }
+
+    /// Make a sender that makes every request on behalf of a subuser, so its sends,
+    /// statistics and suppression lists are the subuser's. This is meant for platforms
+    /// that give every tenant its own subuser. The new sender shares the connection
+    /// pool and settings of this one, but keeps its own `stats` and quota counts.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let tenant = sender.for_subuser("tenant-x");
+    /// tenant.send(&message)?;
+    /// let bounces = tenant.bounces()?;
+    /// ```
+    pub fn for_subuser(&self, subuser: &str) -> V3Sender {
+        V3Sender {
+            api_keys: self.api_keys.clone(),
+            audit: self.audit.clone(),
+            client: self.client.clone(),
+            connection: self.connection.clone(),
+            counters: Counters::default(),
+            body_capacity: AtomicUsize::new(self.body_capacity.load(Ordering::Relaxed)),
+            domain_policy: self.domain_policy.clone(),
+            events: Arc::clone(&self.events),
+            gzip_threshold: self.gzip_threshold,
+            hosts: self.hosts.clone(),
+            max_response_size: self.max_response_size,
+            on_behalf_of: Some(String::from(subuser)),
+            quotas: self.quotas.clone(),
+            #[cfg(feature = "log")]
+            redaction: self.redaction.clone(),
+            signer: self.signer.clone(),
+            timeouts: self.timeouts,
+            timeout_clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Subscribe to the events of the sender, such as sends, rate limited requests and
+    /// moves to a fallback host. Senders made with `for_subuser` share the events of the
+    /// sender they were made from. Events are dropped for a subscriber that is
+    /// `EVENT_CAPACITY` events behind instead of holding up sends.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let events = sender.subscribe();
+    /// thread::spawn(move || {
+    ///     for event in events {
+    ///         println!("{:?}", event);
+    ///     }
+    /// });
+    /// ```
+    pub fn subscribe(&self) -> Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    // The events of the sender, for the helpers that send on its behalf.
+    pub(crate) fn events(&self) -> &Arc<Events> {
+        &self.events
+    }
+
+    // The request counters of the sender, for the helpers that retry on its behalf.
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Connect to the API ahead of time, so the first send doesn't have to wait for
+    /// DNS resolution and the TLS handshake. This makes a HEAD request without the API
+    /// key, whose status is ignored, and leaves the connection in the pool that sends
+    /// use, the one of the `mail_send` timeout. Call it at startup from services where the first send is latency
+    /// sensitive.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let sender = V3Sender::new(api_key);
+    /// sender.warm_up()?;
+    /// ```
+    pub fn warm_up(&self) -> SendgridResult<()> {
+        let mut headers = self.default_headers()?;
+        self.sign("HEAD", "/", &[], &mut headers)?;
+        let client = self.client_for(self.timeouts.mail_send)?;
+        self.hosts.request(None, |base, _| {
+            client
+                .head(&format!("{}/", base)[..])
+                .headers(headers.clone())
+                .send()
+        })?;
+        Ok(())
+    }
+
+    /// A snapshot of the requests this sender has made so far.
+    pub fn stats(&self) -> ClientStats {
+        self.counters.snapshot()
     }
 
     /// Send a V3 message and return the status code or an error from the request.
+    /// Messages with attachments over SendGrid's size or count limits are rejected
+    /// before sending. Requires the `mail.send` scope.
     pub fn send(&self, mail: &SGMailV3) -> SendgridResult<Response> {
-        let client = Client::new();
-        let mut headers = Headers::new();
-        headers.set(Authorization(Bearer {
-            token: self.api_key.to_owned(),
-        }));
-        headers.set(ContentType::json());
-        headers.set(UserAgent::new("sendgrid-rs"));
-
-        let body = mail.gen_json();
-        let res = client.post(V3_API_URL).headers(headers).body(body).send()?;
+        self.send_with(mail, &RequestOptions::default())
+    }
+
+    /// Send a V3 message like `send`, but with options for just this request. Requires
+    /// the `mail.send` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let options = RequestOptions {
+    ///     timeout: Some(Duration::from_secs(5)),
+    ///     on_behalf_of: Some(String::from("subuser")),
+    ///     ..Default::default()
+    /// };
+    /// sender.send_with(&message, &options)?;
+    /// ```
+    pub fn send_with(&self, mail: &SGMailV3, options: &RequestOptions) -> SendgridResult<Response> {
+        let timeout = options.timeout_now(self.timeouts.mail_send)?;
+        let mut headers = self.default_headers()?;
+        for (name, value) in &options.extra_headers {
+            headers::validate_name(name)?;
+            let value = headers::encode_value(Cow::Borrowed(value))?;
+            headers.set_raw(name.clone(), value.into_owned());
+        }
+        if let Some(ref subuser) = options.on_behalf_of {
+            let subuser = headers::encode_value(Cow::Borrowed(subuser))?;
+            headers.set_raw("on-behalf-of", subuser.into_owned());
+        }
+
+        mail.check_attachments()?;
+        self.check_sender(
+            &mail.from.email,
+            mail.categories.as_deref(),
+            mail.recipients().count(),
+        )?;
+
+        let body = match self.gzip_threshold {
+            // Only the compressed body is sent, so the JSON is written to the buffer of
+            // the thread instead of a new one.
+            Some(threshold) => JSON_BUFFER.with(|buffer| -> SendgridResult<Vec<u8>> {
+                let mut buffer = buffer.borrow_mut();
+                mail.write_json(&mut buffer)?;
+                #[cfg(feature = "log")]
+                logging::body("POST", "/mail/send", &buffer, &self.redaction);
+                let body = if buffer.len() > threshold {
+                    headers.set(ContentEncoding(vec![Encoding::Gzip]));
+                    gzip(&buffer)?
+                } else {
+                    buffer.to_vec()
+                };
+                if buffer.capacity() > MAX_POOLED_BUFFER {
+                    *buffer = Vec::new();
+                }
+                Ok(body)
+            })?,
+            None => {
+                // Messages from the same sender tend to be the same size, so start from
+                // the size of the last body.
+                let body = mail.gen_json(self.body_capacity.load(Ordering::Relaxed))?;
+                self.body_capacity.store(body.len(), Ordering::Relaxed);
+                #[cfg(feature = "log")]
+                logging::body("POST", "/mail/send", &body, &self.redaction);
+                body
+            }
+        };
+        self.post_mail(headers, body, timeout, || mail.recipients())
+    }
+
+    /// Send a message from its JSON body, such as one written by `SGMailV3::write_json`
+    /// and stored to be sent later. The body is checked like the messages of `send`,
+    /// against the attachment limits, the authenticated domains and the quotas.
+    /// Requires the `mail.send` scope.
+    pub fn send_json(&self, body: &[u8]) -> SendgridResult<Response> {
+        let stored = StoredMail::check(body)?;
+        self.check_sender(
+            &stored.from.email,
+            stored.categories.as_deref(),
+            stored.recipients(),
+        )?;
+
+        let mut headers = self.default_headers()?;
+        #[cfg(feature = "log")]
+        logging::body("POST", "/mail/send", body, &self.redaction);
+        let body = match self.gzip_threshold {
+            Some(threshold) if body.len() > threshold => {
+                headers.set(ContentEncoding(vec![Encoding::Gzip]));
+                gzip(body)?
+            }
+            _ => body.to_vec(),
+        };
+        self.post_mail(headers, body, self.timeouts.mail_send, ::std::iter::empty)
+    }
+
+    /// Send a copy of a message to a seed list instead of its recipients, for checking
+    /// how it renders across mailbox providers before a launch. Every seed address gets
+    /// its own copy of the first personalization, so substitutions and dynamic template
+    /// data still apply, without the CC and BCC recipients. The copy is sent right away
+    /// with sandbox mode off and the `TEST_CATEGORY` category, so its events can be
+    /// told apart. Requires the `mail.send` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.test_send(&newsletter, &["seed@gmail.com", "seed@outlook.com"])?;
+    /// ```
+    pub fn test_send(&self, mail: &SGMailV3, seed_addresses: &[&str]) -> SendgridResult<Response> {
+        mail.check_attachments()?;
+        let body = test_body(mail, seed_addresses)?;
+        self.send_json(&body)
+    }
+
+    // Post an encoded body to the mail send endpoint. The recipients are only used for
+    // logging.
+    fn post_mail<'a, F, I>(
+        &self,
+        mut headers: Headers,
+        body: Vec<u8>,
+        timeout: Option<Duration>,
+        recipients: F,
+    ) -> SendgridResult<Response>
+    where
+        F: FnOnce() -> I,
+        I: Iterator<Item = &'a str>,
+    {
+        #[cfg(not(feature = "log"))]
+        let _ = recipients;
+        let client = self.client_for(timeout)?;
+        self.sign("POST", "/mail/send", &body, &mut headers)?;
+        let _in_flight = self.counters.start(body.len());
+        let summary = self.audit.as_ref().map(|_| audit::summarize(Some(&body)));
+        let fallback = self.hosts.fallback().map(String::from);
+        let started = Instant::now();
+        let timestamp = SystemTime::now();
+        let mut attempted = false;
+        let res = self.api_keys.request(Some(body), |api_key, body| {
+            let mut headers = headers.clone();
+            headers.set(keys::authorization(api_key));
+            let res = self.hosts.request(body, |base, body| {
+                self.counters.attempt(&mut attempted);
+                let mut request = client.post(&format!("{}/mail/send", base)[..]);
+                request.headers(headers.clone());
+                if let Some(body) = body {
+                    request.body(body);
+                }
+                request.send()
+            })?;
+            Ok(res)
+        });
+        self.emit_events("/mail/send", fallback, started, &res);
+        self.audit("POST", "/mail/send", summary, timestamp, &res);
+        #[cfg(feature = "log")]
+        self.log_result("POST", "/mail/send", started, &res, recipients);
+        res
+    }
+
+    // Tell subscribers about the outcome of a request. `fallback` is the host requests
+    // were made to before it.
+    fn emit_events(
+        &self,
+        path: &str,
+        fallback: Option<String>,
+        started: Instant,
+        res: &SendgridResult<Response>,
+    ) {
+        if let Some(host) = self.hosts.fallback() {
+            if fallback.as_deref() != Some(host) {
+                self.events.emit(|| ClientEvent::FailedOver {
+                    host: String::from(host),
+                });
+            }
+        }
+
+        if let Ok(ref res) = *res {
+            let status = res.status().as_u16();
+            if status == 429 {
+                self.events.emit(|| ClientEvent::RateLimited {
+                    path: String::from(path.split('?').next().unwrap_or_default()),
+                });
+            }
+            if path == "/mail/send" {
+                self.events.emit(|| ClientEvent::Sent {
+                    status,
+                    elapsed: started.elapsed(),
+                });
+            }
+        }
+    }
+
+    // Write the record of a request to the audit sink, if the sender has one. The summary
+    // is only built when it does.
+    fn audit(
+        &self,
+        method: &str,
+        path: &str,
+        summary: Option<String>,
+        timestamp: SystemTime,
+        res: &SendgridResult<Response>,
+    ) {
+        let (sink, summary) = match (self.audit.as_ref(), summary) {
+            (Some(sink), Some(summary)) => (sink, summary),
+            _ => return,
+        };
+        let outcome = match *res {
+            Ok(ref res) => AuditOutcome::Status(res.status().as_u16()),
+            Err(ref e) => audit::failure(e),
+        };
+        sink.record(&audit::record(method, path, summary, outcome, timestamp));
+    }
+
+    #[cfg(feature = "log")]
+    fn log_result<'a, F, I>(
+        &self,
+        method: &str,
+        path: &str,
+        started: Instant,
+        res: &SendgridResult<Response>,
+        recipients: F,
+    ) where
+        F: FnOnce() -> I,
+        I: Iterator<Item = &'a str>,
+    {
+        match *res {
+            Ok(ref res) => logging::request(
+                method,
+                path,
+                res.status().as_u16(),
+                started.elapsed(),
+                self.redaction.addresses,
+                recipients,
+            ),
+            Err(ref e) => {
+                logging::failure(method, path, started.elapsed(), self.redaction.addresses, e)
+            }
+        }
+    }
+
+    // Add the headers of the signer, if the sender has one, to a request.
+    fn sign(
+        &self,
+        method: &str,
+        path: &str,
+        body: &[u8],
+        headers: &mut Headers,
+    ) -> SendgridResult<()> {
+        match self.signer {
+            Some(ref signer) => {
+                let request = SignableRequest { method, path, body };
+                signing::sign(&**signer, &request, headers)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Check a message about to be sent against the domain policy, and charge its
+    // recipients to the quotas of its categories.
+    fn check_sender(
+        &self,
+        from: &str,
+        categories: Option<&[String]>,
+        recipients: usize,
+    ) -> SendgridResult<()> {
+        if let Some(ref policy) = self.domain_policy {
+            policy.check(self, from)?;
+        }
+        if let Some(categories) = categories {
+            self.quotas
+                .charge(categories.iter().map(|c| c.as_str()), recipients as u64)?;
+        }
+        Ok(())
+    }
+
+    // The headers sent with every request to the V3 API, except for the API key.
+    fn default_headers(&self) -> SendgridResult<Headers> {
+        api_headers(self.on_behalf_of.as_deref())
+    }
+
+    // Make a request to another endpoint of the V3 API, with an optional JSON body.
+    // Responses with a status other than 2xx are turned into an `Api` error.
+    pub(crate) fn api_request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> SendgridResult<Response> {
+        let res = self.api_response(method, path, body)?;
+        self.api_success(path, res)
+    }
+
+    // Make a request to another endpoint of the V3 API and return its response, whatever
+    // its status.
+    pub(crate) fn api_response(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> SendgridResult<Response> {
+        let mut headers = self.default_headers()?;
+        self.sign(
+            method.as_ref(),
+            path,
+            body.as_deref().unwrap_or(&[]),
+            &mut headers,
+        )?;
+        #[cfg(feature = "log")]
+        {
+            if let Some(ref body) = body {
+                logging::body(method.as_ref(), path, body, &self.redaction);
+            }
+        }
+        let size = body.as_ref().map_or(0, |b| b.len());
+        let _in_flight = self.counters.start(size);
+        let summary = match self.audit {
+            Some(_) if audit::is_mutating(method.as_ref()) => {
+                Some(audit::summarize(body.as_ref().map(|b| &b[..])))
+            }
+            _ => None,
+        };
+        let client = self.client_for(self.timeouts.api)?;
+        let fallback = self.hosts.fallback().map(String::from);
+        let started = Instant::now();
+        let timestamp = SystemTime::now();
+        let mut attempted = false;
+        let res = self.api_keys.request(body, |api_key, body| {
+            let res = self.hosts.request(body, |base, body| {
+                self.counters.attempt(&mut attempted);
+                let mut request = client.request(method.clone(), &format!("{}{}", base, path)[..]);
+                request.headers(headers.clone());
+                request.header(keys::authorization(api_key));
+                if let Some(body) = body {
+                    request.body(body);
+                }
+                request.send()
+            })?;
+            Ok(res)
+        });
+        self.emit_events(path, fallback, started, &res);
+        self.audit(method.as_ref(), path, summary, timestamp, &res);
+        #[cfg(feature = "log")]
+        self.log_result(method.as_ref(), path, started, &res, ::std::iter::empty);
+        res
+    }
+
+    // Turn a response of the V3 API with a status other than 2xx into an `Api` error.
+    // The path is only needed to log the response.
+    #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+    pub(crate) fn api_success(&self, path: &str, mut res: Response) -> SendgridResult<Response> {
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = self.error_text(&mut res);
+            #[cfg(feature = "log")]
+            logging::body("response to", path, body.as_bytes(), &self.redaction);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
         Ok(res)
     }
+
+    // Get and decode a JSON resource from the V3 API.
+    pub(crate) fn api_get<T: DeserializeOwned>(&self, path: &str) -> SendgridResult<T> {
+        let res = self.api_request(Method::Get, path, None)?;
+        self.read_json(res)
+    }
+
+    // Decode a JSON response body as it is read, failing if it is over the size limit.
+    pub(crate) fn read_json<T: DeserializeOwned>(&self, res: Response) -> SendgridResult<T> {
+        response::read_json(res, self.max_response_size)
+    }
+
+    // Read a text response body, failing if it is over the size limit.
+    pub(crate) fn read_text(&self, res: Response) -> SendgridResult<String> {
+        response::read_text(res, self.max_response_size)
+    }
+
+    // Read the body of an error response, cut off at the size limit.
+    pub(crate) fn error_text(&self, res: &mut Response) -> String {
+        response::error_text(res, self.max_response_size)
+    }
+
+    // Send a JSON body to an endpoint of the V3 API.
+    pub(crate) fn api_send_json<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &B,
+    ) -> SendgridResult<Response> {
+        self.api_request(method, path, Some(serde_json::to_vec(body)?))
+    }
+
+    // Download a file from a presigned URL, which needs no API key.
+    pub(crate) fn download(&self, url: &str) -> SendgridResult<Response> {
+        let _in_flight = self.counters.start(0);
+        let mut res = self.client_for(self.timeouts.transfers)?.get(url).send()?;
+
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = self.error_text(&mut res);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
+        Ok(res)
+    }
+
+    // Download a file from a presigned URL like `download`, resuming with range requests
+    // when reading the body fails.
+    pub(crate) fn download_resumable(&self, url: &str) -> SendgridResult<ResumableDownload> {
+        let res = self.download(url)?;
+        let client = self.client_for(self.timeouts.transfers)?;
+        Ok(ResumableDownload::new(client, url, res))
+    }
+
+    // Upload a file to a presigned URL with the headers that came with the URL.
+    pub(crate) fn upload(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        body: Body,
+    ) -> SendgridResult<()> {
+        let _in_flight = self.counters.start(0);
+        let mut raw = Headers::new();
+        for (name, value) in headers {
+            headers::validate_name(name)?;
+            let value = headers::encode_value(Cow::Borrowed(value))?;
+            raw.set_raw(name.clone(), value.into_owned());
+        }
+        let client = self.client_for(self.timeouts.transfers)?;
+        let mut res = client.put(url).headers(raw).body(body).send()?;
+
+        if !res.status().is_success() {
+            let status = res.status().as_u16();
+            let body = self.error_text(&mut res);
+            return Err(SendgridErrorKind::Api(status, body).into());
+        }
+        Ok(())
+    }
+
+    // The client to use for a request with the given timeout. Clients with a timeout are
+    // kept around so later requests with the same timeout can reuse their connections,
+    // up to `MAX_TIMEOUT_CLIENTS` of them, since every client has a thread of its own.
+    fn client_for(&self, timeout: Option<Duration>) -> SendgridResult<Client> {
+        let timeout = match timeout {
+            Some(timeout) if timeout != DEFAULT_TIMEOUT => timeout,
+            _ => return Ok(self.client.clone()),
+        };
+
+        let mut clients = self
+            .timeout_clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get(&timeout) {
+            return Ok(client.clone());
+        }
+        if clients.len() >= MAX_TIMEOUT_CLIENTS {
+            clients.clear();
+        }
+        let client = self.connection.client(Some(timeout))?;
+        clients.insert(timeout, client.clone());
+        Ok(client)
+    }
+
+    /// Stamp a message with a newly generated correlation ID in the `correlation_id`
+    /// custom argument and send it. The ID is returned with the response, and shows up
+    /// in every webhook event of the message so events can be matched to what was sent.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let (correlation_id, res) = sender.send_tracked(&mut message)?;
+    /// db.save_sent_message(user.id, correlation_id)?;
+    /// ```
+    #[cfg(feature = "uuid")]
+    pub fn send_tracked(&self, mail: &mut SGMailV3) -> SendgridResult<(Uuid, Response)> {
+        let correlation_id = Uuid::new_v4();
+        mail.add_custom_arg(CORRELATION_ID_ARG, correlation_id);
+        let res = self.send(mail)?;
+        Ok((correlation_id, res))
+    }
+
+    /// Send a V3 message with a `Message-ID` header at `domain`, generating one unless
+    /// the message already has it. The id is returned with the response, so it is known
+    /// before any webhook events for the message arrive.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let (message_id, res) = sender.send_with_message_id(&mut message, "mail.example.com")?;
+    /// db.save_sent_message(user.id, &message_id)?;
+    /// ```
+    pub fn send_with_message_id(
+        &self,
+        mail: &mut SGMailV3,
+        domain: &str,
+    ) -> SendgridResult<(String, Response)> {
+        let message_id = mail.generate_message_id(domain)?;
+        let res = self.send(mail)?;
+        Ok((message_id, res))
+    }
+
+    /// Send a V3 message unless a message with the same idempotency key was already
+    /// sent, in which case `None` is returned. The key is forgotten again if SendGrid
+    /// answered with an error status or the request failed before reaching it, so retried
+    /// jobs or at-least-once queues deliver every message exactly once. It is kept when
+    /// SendGrid may have accepted the message, like after a timeout, so such a message is
+    /// sent at most once. Requires the `mail.send` scope.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let store = MemoryStore::new();
+    /// let key = format!("welcome-{}", user.id);
+    /// sender.send_once(&message, &key, &store)?;
+    /// ```
+    pub fn send_once<S: IdempotencyStore>(
+        &self,
+        mail: &SGMailV3,
+        key: &str,
+        store: &S,
+    ) -> SendgridResult<Option<Response>> {
+        send_once_with(
+            key,
+            store,
+            || self.send(mail),
+            |res| res.status().is_success(),
+        )
+    }
+}
+
+// Send a message once for an idempotency key, forgetting the key when the message is
+// known not to have been accepted.
+#[cfg(feature = "client")]
+fn send_once_with<S, T, F, A>(
+    key: &str,
+    store: &S,
+    send: F,
+    accepted: A,
+) -> SendgridResult<Option<T>>
+where
+    S: IdempotencyStore,
+    F: FnOnce() -> SendgridResult<T>,
+    A: FnOnce(&T) -> bool,
+{
+    if !store.insert(key) {
+        return Ok(None);
+    }
+
+    let res = send();
+    let sent = match res {
+        Ok(ref res) => accepted(res),
+        Err(ref e) => may_have_reached(e),
+    };
+    if !sent {
+        store.remove(key);
+    }
+    res.map(Some)
+}
+
+// Whether a failed send may still have reached SendGrid. Only the errors of the request
+// itself can happen after SendGrid got the message, and of those only connect errors are
+// known to happen before, the same as for `bulk::is_retryable`.
+#[cfg(feature = "client")]
+fn may_have_reached(error: &SendgridError) -> bool {
+    match *error.kind() {
+        SendgridErrorKind::ReqwestError(ref e) => !failover::is_connect_error(e),
+        _ => false,
+    }
+}
+
+// The parts of a stored JSON body that are checked before it is sent.
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+struct StoredMail {
+    from: StoredEmail,
+    #[serde(default)]
+    personalizations: Vec<StoredPersonalization>,
+    categories: Option<Vec<String>>,
+    #[serde(default)]
+    attachments: Vec<StoredAttachment>,
+}
+
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+struct StoredEmail {
+    email: String,
+}
+
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+struct StoredPersonalization {
+    #[serde(default)]
+    to: Vec<StoredEmail>,
+    #[serde(default)]
+    cc: Vec<StoredEmail>,
+    #[serde(default)]
+    bcc: Vec<StoredEmail>,
+}
+
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+struct StoredAttachment {
+    filename: String,
+    // The base64 encoded content.
+    content: String,
+}
+
+#[cfg(feature = "client")]
+impl StoredMail {
+    // Parse a stored body and check its attachments against the limits.
+    fn check(body: &[u8]) -> SendgridResult<StoredMail> {
+        let stored: StoredMail = serde_json::from_slice(body)?;
+        attachments::check(
+            stored
+                .attachments
+                .iter()
+                .map(|a| (a.filename.as_str(), a.content.len())),
+        )?;
+        Ok(stored)
+    }
+
+    fn recipients(&self) -> usize {
+        self.personalizations
+            .iter()
+            .map(|p| p.to.len() + p.cc.len() + p.bcc.len())
+            .sum()
+    }
+}
+
+// The headers of `V3Sender::default_headers`, with the subuser requests are made on
+// behalf of.
+#[cfg(feature = "client")]
+fn api_headers(on_behalf_of: Option<&str>) -> SendgridResult<Headers> {
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    headers.set(UserAgent::new("sendgrid-rs"));
+    if let Some(subuser) = on_behalf_of {
+        let subuser = headers::encode_value(Cow::Borrowed(subuser))?;
+        headers.set_raw("on-behalf-of", subuser.into_owned());
+    }
+    Ok(headers)
+}
+
+// Rebuild a JSON value with the keys of every object in sorted order, whichever map
+// type serde_json was built with.
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, sort_keys(v)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+#[cfg(feature = "client")]
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = gzip::Encoder::new(Vec::with_capacity(data.len() / 2))?;
+    encoder.write_all(data)?;
+    encoder.finish().into_result()
+}
+
+impl Default for SGMailV3 {
+    fn default() -> SGMailV3 {
+        SGMailV3::new()
+    }
 }
 
 impl SGMailV3 {
@@ -129,6 +1425,14 @@ impl SGMailV3 {
             content: Vec::new(),
             personalizations: Vec::new(),
             attachments: None,
+            template_id: None,
+            reply_to: None,
+            headers: None,
+            categories: None,
+            custom_args: None,
+            send_at: None,
+            batch_id: None,
+            asm: None,
         }
     }
 
@@ -137,6 +1441,92 @@ impl SGMailV3 {
         self.from = from;
     }
 
+    /// Set the reply to address.
+    pub fn set_reply_to(&mut self, reply_to: Email) {
+        self.reply_to = Some(reply_to);
+    }
+
+    /// Add a custom header to every personalization of the message. The name must be
+    /// printable ASCII without a colon and the value can not contain line breaks.
+    /// Values that are not ASCII are encoded per RFC 2047.
+    pub fn add_header(&mut self, name: &str, value: &str) -> SendgridResult<()> {
+        headers::validate_name(name)?;
+        let value = headers::encode_value(Cow::Borrowed(value))?.into_owned();
+
+        self.headers
+            .get_or_insert_with(SGMap::new)
+            .insert(String::from(name), value);
+        Ok(())
+    }
+
+    /// Make sure the message has a `Message-ID` header and return it. A new id at
+    /// `domain` is generated unless the header was already set, so the id stays the
+    /// same when a send is retried.
+    pub fn generate_message_id(&mut self, domain: &str) -> SendgridResult<String> {
+        if let Some(message_id) = self.headers.as_ref().and_then(|h| h.get("Message-ID")) {
+            return Ok(message_id.clone());
+        }
+
+        let message_id = headers::message_id(domain)?;
+        self.add_header("Message-ID", &message_id)?;
+        Ok(message_id)
+    }
+
+    /// Stamp the message with the trace and span IDs of a W3C `traceparent` value, such
+    /// as the one an OpenTelemetry propagator injects for the current span. They are
+    /// stored as custom arguments, so the webhook events of the message carry them in
+    /// `EventInfo::trace_id` and `EventInfo::span_id` and can be joined back to the trace
+    /// that sent it.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut carrier = HashMap::new();
+    /// propagator.inject_context(&Context::current(), &mut carrier);
+    /// message.set_trace_context(&carrier["traceparent"])?;
+    /// ```
+    pub fn set_trace_context(&mut self, traceparent: &str) -> SendgridResult<()> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        let is_hex = |part: &str, len: usize| {
+            part.len() == len
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        };
+        let is_id = |part: &str, len: usize| is_hex(part, len) && part.bytes().any(|b| b != b'0');
+        // Version ff is invalid, so a traceparent can't start with it.
+        if parts.len() < 4
+            || !is_hex(parts[0], 2)
+            || parts[0] == "ff"
+            || !is_id(parts[1], 32)
+            || !is_id(parts[2], 16)
+        {
+            return Err(SendgridErrorKind::InvalidTraceparent(String::from(traceparent)).into());
+        }
+
+        self.add_custom_arg(TRACE_ID_ARG, parts[1]);
+        self.add_custom_arg(SPAN_ID_ARG, parts[2]);
+        Ok(())
+    }
+
+    /// Add a category to the message, which groups it in the statistics. Categories that
+    /// were already added are skipped.
+    pub fn add_category(&mut self, category: &str) {
+        let categories = self.categories.get_or_insert_with(Vec::new);
+        if !categories.iter().any(|c| c == category) {
+            categories.push(String::from(category));
+        }
+    }
+
+    /// Add a custom argument to every personalization of the message. Custom arguments
+    /// are passed back in the events of the Event Webhook. Any displayable value works,
+    /// including a `uuid::Uuid`.
+    pub fn add_custom_arg<V: fmt::Display>(&mut self, key: &str, value: V) {
+        self.custom_args
+            .get_or_insert_with(SGMap::new)
+            .insert(String::from(key), value.to_string());
+    }
+
     /// Set the subject.
     pub fn set_subject(&mut self, subject: &str) {
         self.subject = String::from(subject);
@@ -147,6 +1537,17 @@ impl SGMailV3 {
         self.content.push(content);
     }
 
+    /// Normalize the text content with `text::normalize_text`, which wraps it at 78
+    /// columns, uses CRLF line endings and removes control characters, for strict text
+    /// clients.
+    pub fn normalize_text(&mut self) {
+        for c in &mut self.content {
+            if c.content_type.starts_with("text/plain") {
+                c.value = text::normalize_text(&c.value);
+            }
+        }
+    }
+
     /// Add a personalization to the message.
     pub fn add_personalization(&mut self, p: Personalization) {
         self.personalizations.push(p);
@@ -155,17 +1556,271 @@ impl SGMailV3 {
     /// Add an attachment to the message.
     pub fn add_attachment(&mut self, a: Attachment) {
         match self.attachments {
-            None => {
-                let mut attachments = Vec::new();
-                attachments.push(a);
-                self.attachments = Some(attachments);
-            }
+            None => self.attachments = Some(vec![a]),
             Some(ref mut attachments) => attachments.push(a),
         };
     }
 
-    fn gen_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    /// Add a calendar invite from the contents of an ICS file. The invite is added
+    /// both as a `text/calendar; method=REQUEST` content part, which Outlook and Gmail
+    /// render natively, and as an `invite.ics` attachment for other clients. The ICS
+    /// data should use `METHOD:REQUEST` to match.
+    pub fn add_calendar_invite(&mut self, ics: &str) {
+        let mut c = Content::new();
+        c.set_content_type(CALENDAR_INVITE_TYPE);
+        c.set_value(ics);
+        self.add_content(c);
+
+        let mut a = Attachment::new();
+        a.set_content(ics.as_bytes());
+        a.set_filename("invite.ics");
+        a.set_mime_type(CALENDAR_INVITE_TYPE);
+        a.set_disposition("attachment");
+        self.add_attachment(a);
+    }
+
+    /// Set the ID of a dynamic template to use for this message.
+    pub fn set_template_id(&mut self, template_id: &str) {
+        self.template_id = Some(String::from(template_id));
+    }
+
+    /// Schedule the message to be sent at a Unix timestamp, up to 72 hours ahead.
+    pub fn set_send_at(&mut self, send_at: u64) {
+        self.send_at = Some(send_at);
+    }
+
+    /// Set the batch ID of the message, which lets scheduled messages be paused or
+    /// cancelled together.
+    pub fn set_batch_id(&mut self, batch_id: &str) {
+        self.batch_id = Some(String::from(batch_id));
+    }
+
+    /// Set the unsubscribe group of the message, which is needed for the
+    /// `UnsubscribeLink` tags to be filled in. The preferences page lists the groups in
+    /// `groups_to_display`, up to 25 of them.
+    pub fn set_unsubscribe_group(&mut self, group_id: u32, groups_to_display: &[u32]) {
+        self.asm = Some(Asm {
+            group_id,
+            groups_to_display: groups_to_display.to_vec(),
+        });
+    }
+
+    // Fill in the defaults of a scoped sender for everything the message doesn't set
+    // itself.
+    #[cfg(feature = "client")]
+    pub(crate) fn apply_defaults(&mut self, defaults: &MessageDefaults) {
+        if self.from.email.is_empty() {
+            if let Some(ref from) = defaults.from {
+                self.from = from.clone();
+            }
+        }
+        if self.reply_to.is_none() {
+            self.reply_to = defaults.reply_to.clone();
+        }
+        for category in &defaults.categories {
+            self.add_category(category);
+        }
+        for (key, value) in &defaults.custom_args {
+            let custom_args = self.custom_args.get_or_insert_with(SGMap::new);
+            custom_args
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for (name, value) in &defaults.headers {
+            let headers = self.headers.get_or_insert_with(SGMap::new);
+            headers.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    // Check the attachments against the limits SendGrid enforces.
+    pub(crate) fn check_attachments(&self) -> SendgridResult<()> {
+        match self.attachments {
+            Some(ref attachments) => attachments::check(
+                attachments
+                    .iter()
+                    .map(|a| (a.filename.as_str(), a.content.encoded_len())),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn send_at(&self) -> Option<u64> {
+        self.send_at
+    }
+
+    pub(crate) fn personalizations_mut(&mut self) -> &mut Vec<Personalization> {
+        &mut self.personalizations
+    }
+
+    // The value of the HTML content, if the message has one.
+    pub(crate) fn html(&self) -> Option<&str> {
+        self.content
+            .iter()
+            .find(|c| c.content_type.starts_with("text/html"))
+            .map(|c| c.value.as_str())
+    }
+
+    pub(crate) fn html_mut(&mut self) -> Option<&mut String> {
+        self.content
+            .iter_mut()
+            .find(|c| c.content_type.starts_with("text/html"))
+            .map(|c| &mut c.value)
+    }
+
+    // The addresses of every recipient of the message.
+    #[cfg(feature = "client")]
+    pub(crate) fn recipients(&self) -> impl Iterator<Item = &str> {
+        self.personalizations
+            .iter()
+            .flat_map(Personalization::recipients)
+    }
+
+    /// The JSON body of the message, pretty-printed with the keys of every object in
+    /// sorted order. Headers and custom arguments are kept in hash maps, so the body that
+    /// is sent can list them in any order; this gives snapshot tests of outgoing mail a
+    /// stable text to compare against.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// assert_eq!(message.canonical_json()?, fs::read_to_string("tests/welcome.json")?);
+    /// ```
+    pub fn canonical_json(&self) -> SendgridResult<String> {
+        let value = sort_keys(serde_json::to_value(self)?);
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Render the message as RFC 5322 MIME documents, one for each personalization, to
+    /// preview what every recipient gets in a mail client or to archive a copy of what
+    /// was sent. Substitutions and templates are left for SendGrid to fill in, and BCC
+    /// recipients are left out like a mail client would. The `Date` header is the
+    /// `send_at` time, or the current time when the message isn't scheduled. Content
+    /// parts are alternatives of each other in the order they were added, and
+    /// attachments keep their type, disposition and content ID.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for (i, eml) in message.to_eml().iter().enumerate() {
+    ///     fs::write(format!("preview-{}.eml", i), eml)?;
+    /// }
+    /// ```
+    pub fn to_eml(&self) -> Vec<String> {
+        let bodies: Vec<_> = self
+            .content
+            .iter()
+            .map(|content| EmlBody {
+                mime_type: &content.content_type,
+                text: &content.value,
+            })
+            .collect();
+        let attachments: Vec<_> = self
+            .attachments
+            .iter()
+            .flatten()
+            .map(|attachment| EmlAttachment {
+                filename: &attachment.filename,
+                mime_type: attachment.mime_type.as_deref(),
+                disposition: attachment.disposition.as_deref(),
+                content_id: attachment.content_id.as_deref(),
+                contents: match attachment.content {
+                    AttachmentContent::Raw(ref data) => Cow::Borrowed(&data[..]),
+                    AttachmentContent::Shared(ref shared) => {
+                        Cow::Owned(BASE64.decode(shared.encoded.as_bytes()).unwrap_or_default())
+                    }
+                },
+            })
+            .collect();
+
+        self.personalizations
+            .iter()
+            .map(|p| self.personalized_mail(p).render_eml(&bodies, &attachments))
+            .collect()
+    }
+
+    // The headers of the message one personalization gets, as a v2 message without a
+    // body.
+    fn personalized_mail<'a>(&'a self, personalization: &'a Personalization) -> Mail<'a> {
+        let mut mail = Mail::new();
+        mail.from = self.from.to_address();
+        if let Some(ref reply_to) = self.reply_to {
+            mail.reply_to = reply_to.to_address();
+        }
+        for (emails, names, list) in [
+            (&mut mail.to, &mut mail.to_names, Some(&personalization.to)),
+            (
+                &mut mail.cc,
+                &mut mail.cc_names,
+                personalization.cc.as_ref(),
+            ),
+        ] {
+            for email in list.into_iter().flatten() {
+                emails.push(Cow::Borrowed(&email.email[..]));
+                names.push(Cow::Borrowed(email.name.as_ref().map_or("", |n| &n[..])));
+            }
+        }
+        mail.subject = Cow::Borrowed(personalization.subject.as_ref().unwrap_or(&self.subject));
+
+        // Personalization headers replace the message headers of the same name. Headers
+        // are kept in hash maps, so they are sorted to render the same every time.
+        let mut headers: Vec<(&String, &String)> = self
+            .headers
+            .iter()
+            .chain(personalization.headers.iter())
+            .flatten()
+            .collect::<HashMap<_, _>>()
+            .into_iter()
+            .collect();
+        headers.sort();
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case("date") {
+                mail.date = Cow::Borrowed(value);
+            } else {
+                mail.headers
+                    .insert(Cow::Borrowed(name), Cow::Borrowed(value));
+            }
+        }
+        if mail.date.is_empty() {
+            if let Some(send_at) = personalization.send_at.or(self.send_at) {
+                mail.add_date_timestamp(send_at);
+            }
+        }
+        mail
+    }
+
+    /// Write the JSON body of the message into `buffer`, replacing what was in it. This
+    /// lets services that encode many messages, for example to queue them, reuse one
+    /// buffer instead of allocating a new one for every message.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut buffer = Vec::new();
+    /// for message in messages {
+    ///     message.write_json(&mut buffer)?;
+    ///     queue.push(&buffer)?;
+    /// }
+    /// ```
+    pub fn write_json(&self, buffer: &mut Vec<u8>) -> SendgridResult<()> {
+        buffer.clear();
+        serde_json::to_writer(buffer, self)?;
+        Ok(())
+    }
+
+    // Serialize the message straight into the buffer that is handed to the request, so
+    // the body isn't copied again. The capacity is a guess at the size of the body to
+    // avoid growing the buffer while writing.
+    #[cfg(feature = "client")]
+    fn gen_json(&self, capacity: usize) -> SendgridResult<Vec<u8>> {
+        let mut body = Vec::with_capacity(capacity);
+        serde_json::to_writer(&mut body, self)?;
+        Ok(body)
+    }
+}
+
+impl Default for Email {
+    fn default() -> Email {
+        Email::new()
     }
 }
 
@@ -180,13 +1835,32 @@ impl Email {
 
     /// Set the address for this email.
     pub fn set_email(&mut self, email: &str) {
-        self.email = String::from(email);
+        self.email = address::encode(email).into_owned();
     }
 
     /// Set an optional name.
     pub fn set_name(&mut self, name: &str) {
         self.name = Some(String::from(name));
     }
+
+    // The address as a v2 address, for rendering.
+    fn to_address(&self) -> EmailAddress<'_> {
+        EmailAddress {
+            email: Cow::Borrowed(&self.email),
+            name: self.name.as_ref().map(|n| Cow::Borrowed(&n[..])),
+        }
+    }
+
+    /// The address of this email.
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+}
+
+impl Default for Content {
+    fn default() -> Content {
+        Content::new()
+    }
 }
 
 impl Content {
@@ -207,6 +1881,40 @@ impl Content {
     pub fn set_value(&mut self, value: &str) {
         self.value = String::from(value);
     }
+
+    /// Append an unsubscribe link to the content. HTML content gets a paragraph with
+    /// an anchor around `text`, other content gets `text` followed by the URL. The link
+    /// only works when the message has an unsubscribe group set with
+    /// `SGMailV3::set_unsubscribe_group`.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut c = Content::new();
+    /// c.set_content_type("text/html");
+    /// c.set_value("<p>Our newsletter</p>");
+    /// message.add_content(c.with_unsubscribe_link(UnsubscribeLink::Group, "Unsubscribe"));
+    /// message.set_unsubscribe_group(1234, &[]);
+    /// ```
+    pub fn with_unsubscribe_link(mut self, link: UnsubscribeLink, text: &str) -> Content {
+        if self.content_type.starts_with("text/html") {
+            self.value
+                .push_str(&format!("<p><a href=\"{}\">{}</a></p>", link.tag(), text));
+        } else {
+            if !self.value.is_empty() && !self.value.ends_with('\n') {
+                self.value.push('\n');
+            }
+            self.value
+                .push_str(&format!("\n{}: {}\n", text, link.tag()));
+        }
+        self
+    }
+}
+
+impl Default for Personalization {
+    fn default() -> Personalization {
+        Personalization::new()
+    }
 }
 
 impl Personalization {
@@ -221,6 +1929,7 @@ impl Personalization {
             substitutions: None,
             custom_args: None,
             send_at: None,
+            dynamic_template_data: None,
         }
     }
 
@@ -229,14 +1938,71 @@ impl Personalization {
         self.to.push(to);
     }
 
+    /// Set the data used to fill in a dynamic template for this personalization. Any
+    /// serializable type works, so a struct gets its fields checked at compile time.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// #[derive(Serialize)]
+    /// struct Welcome<'a> {
+    ///     first_name: &'a str,
+    /// }
+    ///
+    /// let mut p = Personalization::new();
+    /// p.set_dynamic_data(&Welcome { first_name: "Ferris" })?;
+    /// ```
+    pub fn set_dynamic_data<T: Serialize>(&mut self, data: &T) -> SendgridResult<()> {
+        self.dynamic_template_data = Some(serde_json::to_value(data)?);
+        Ok(())
+    }
+
+    /// Set a subject for this personalization, which overrides the subject of the
+    /// message.
+    pub fn set_subject(&mut self, subject: &str) {
+        self.subject = Some(String::from(subject));
+    }
+
+    /// Schedule this personalization to be sent at a Unix timestamp, which overrides the
+    /// `send_at` of the message.
+    pub fn set_send_at(&mut self, send_at: u64) {
+        self.send_at = Some(send_at);
+    }
+
+    pub(crate) fn send_at(&self) -> Option<u64> {
+        self.send_at
+    }
+
+    // Every recipient of the personalization, including CC and BCC.
+    pub(crate) fn recipients(&self) -> impl Iterator<Item = &str> {
+        self.to
+            .iter()
+            .chain(self.cc.iter().flatten())
+            .chain(self.bcc.iter().flatten())
+            .map(|e| e.email.as_str())
+    }
+
+    /// Add a substitution for this personalization. Every occurrence of `tag` in the
+    /// subject and content of the message is replaced with `value`. Substitutions don't
+    /// work with dynamic templates, which use `set_dynamic_data` instead.
+    pub fn add_substitution(&mut self, tag: &str, value: &str) {
+        self.substitutions
+            .get_or_insert_with(SGMap::new)
+            .insert(String::from(tag), String::from(value));
+    }
+
+    /// Add a custom argument for this personalization, which overrides the custom
+    /// argument of the message with the same key.
+    pub fn add_custom_arg<V: fmt::Display>(&mut self, key: &str, value: V) {
+        self.custom_args
+            .get_or_insert_with(SGMap::new)
+            .insert(String::from(key), value.to_string());
+    }
+
     /// Add a CC field.
     pub fn add_cc(&mut self, cc: Email) {
         match self.cc {
-            None => {
-                let mut ccs = Vec::new();
-                ccs.push(cc);
-                self.cc = Some(ccs);
-            }
+            None => self.cc = Some(vec![cc]),
             Some(ref mut c) => {
                 c.push(cc);
             }
@@ -246,11 +2012,7 @@ impl Personalization {
     /// Add a BCC field.
     pub fn add_bcc(&mut self, bcc: Email) {
         match self.bcc {
-            None => {
-                let mut bccs = Vec::new();
-                bccs.push(bcc);
-                self.bcc = Some(bccs);
-            }
+            None => self.bcc = Some(vec![bcc]),
             Some(ref mut b) => {
                 b.push(bcc);
             }
@@ -258,11 +2020,17 @@ impl Personalization {
     }
 }
 
+impl Default for Attachment {
+    fn default() -> Attachment {
+        Attachment::new()
+    }
+}
+
 impl Attachment {
     /// Construct a new attachment for this message.
     pub fn new() -> Attachment {
         Attachment {
-            content: String::new(),
+            content: AttachmentContent::Raw(Vec::new()),
             filename: String::new(),
             mime_type: None,
             disposition: None,
@@ -272,7 +2040,12 @@ impl Attachment {
 
     /// The raw body of the attachment.
     pub fn set_content(&mut self, c: &[u8]) {
-        self.content = BASE64.encode(c);
+        self.content = AttachmentContent::Raw(c.to_vec());
+    }
+
+    /// Use contents that were already encoded, and are shared with other attachments.
+    pub fn set_shared_content(&mut self, content: &SharedContent) {
+        self.content = AttachmentContent::Shared(content.clone());
     }
 
     /// Sets the filename for the attachment.
@@ -285,4 +2058,649 @@ impl Attachment {
     pub fn set_mime_type(&mut self, mime: &str) {
         self.mime_type = Some(String::from(mime));
     }
+
+    /// Set the content disposition, either "attachment" or "inline".
+    pub fn set_disposition(&mut self, disposition: &str) {
+        self.disposition = Some(String::from(disposition));
+    }
+
+    /// Set the content ID used to reference an inline attachment from the HTML content.
+    pub fn set_content_id(&mut self, content_id: &str) {
+        self.content_id = Some(String::from(content_id));
+    }
+
+    /// Serialize a list of records into a CSV attachment with the given filename. The
+    /// header row is taken from the field names of the records.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// #[derive(Serialize)]
+    /// struct Row { day: String, signups: u32 }
+    ///
+    /// let report = Attachment::from_csv_records("signups.csv", rows)?;
+    /// message.add_attachment(report);
+    /// ```
+    #[cfg(feature = "csv")]
+    pub fn from_csv_records<I, T>(name: &str, records: I) -> SendgridResult<Attachment>
+    where
+        I: IntoIterator<Item = T>,
+        T: Serialize,
+    {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for record in records {
+            writer.serialize(record)?;
+        }
+        let data = writer.into_inner().map_err(|e| e.into_error())?;
+
+        let mut a = Attachment::new();
+        a.content = AttachmentContent::Raw(data);
+        a.set_filename(name);
+        a.set_mime_type("text/csv");
+        Ok(a)
+    }
+}
+
+// The number of bytes encoded at a time when writing attachments. It is a multiple of 3
+// so the chunks can be encoded separately without padding in between.
+const BASE64_CHUNK: usize = 3 * 1024;
+
+// Displays bytes as base64, encoding them in chunks so the attachment is written into
+// the body without making a full encoded copy of it first.
+struct Base64Display<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Base64Display<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut buf = [0; BASE64_CHUNK / 3 * 4];
+        for chunk in self.0.chunks(BASE64_CHUNK) {
+            let encoded = &mut buf[..BASE64.encode_len(chunk.len())];
+            BASE64.encode_mut(chunk, encoded);
+            f.write_str(str::from_utf8(encoded).map_err(|_| fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
+
+fn serialize_base64<S: Serializer>(
+    content: &AttachmentContent,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match *content {
+        AttachmentContent::Raw(ref data) => serializer.collect_str(&Base64Display(data)),
+        AttachmentContent::Shared(ref shared) => serializer.serialize_str(&shared.encoded),
+    }
+}
+
+/// Sends one dynamic template to many recipients. Every recipient gets their own
+/// personalization with their own template data, and the personalizations are split into
+/// as many messages as needed to stay under the `MAX_PERSONALIZATIONS` limit.
+pub struct MailMerge {
+    from: Email,
+    template_id: String,
+}
+
+impl MailMerge {
+    /// Construct a new mail merge for a template sent from the given address.
+    pub fn new(from: Email, template_id: &str) -> MailMerge {
+        MailMerge {
+            from,
+            template_id: String::from(template_id),
+        }
+    }
+
+    /// Build the messages for a list of recipients and their template data.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let merge = MailMerge::new(from, "d-123");
+    /// for message in merge.build(users.iter().map(|u| (u.email(), u.template_data())))? {
+    ///     sender.send(&message)?;
+    /// }
+    /// ```
+    pub fn build<I, T>(&self, recipients: I) -> SendgridResult<Vec<SGMailV3>>
+    where
+        I: IntoIterator<Item = (Email, T)>,
+        T: Serialize,
+    {
+        let mut messages = Vec::new();
+        let mut current = self.new_message();
+
+        for (to, data) in recipients {
+            if current.personalizations.len() == MAX_PERSONALIZATIONS {
+                messages.push(current);
+                current = self.new_message();
+            }
+
+            let mut p = Personalization::new();
+            p.add_to(to);
+            p.set_dynamic_data(&data)?;
+            current.add_personalization(p);
+        }
+
+        if !current.personalizations.is_empty() {
+            messages.push(current);
+        }
+
+        Ok(messages)
+    }
+
+    fn new_message(&self) -> SGMailV3 {
+        let mut message = SGMailV3::new();
+        message.set_from(self.from.clone());
+        message.set_template_id(&self.template_id);
+        message
+    }
+}
+
+// The JSON body of a copy of a message for `V3Sender::test_send`.
+#[cfg(feature = "client")]
+fn test_body(mail: &SGMailV3, seed_addresses: &[&str]) -> SendgridResult<Vec<u8>> {
+    if seed_addresses.is_empty() {
+        return Err(SendgridErrorKind::NoSeedAddresses.into());
+    }
+    for address in seed_addresses {
+        address::validate(address)?;
+    }
+
+    let mut body = serde_json::to_value(mail)?;
+    let personalization = body["personalizations"]
+        .get(0)
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let seeds = seed_addresses
+        .iter()
+        .map(|address| {
+            let mut p = personalization.clone();
+            if let Some(p) = p.as_object_mut() {
+                p.remove("cc");
+                p.remove("bcc");
+                p.remove("send_at");
+                let mut to = serde_json::Map::new();
+                to.insert(String::from("email"), Value::from(*address));
+                p.insert(String::from("to"), Value::Array(vec![Value::Object(to)]));
+            }
+            p
+        })
+        .collect();
+
+    if let Some(message) = body.as_object_mut() {
+        message.insert(String::from("personalizations"), Value::Array(seeds));
+        message.remove("send_at");
+        message.remove("batch_id");
+        let categories = message
+            .entry("categories")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Some(categories) = categories.as_array_mut() {
+            if !categories.iter().any(|c| c == TEST_CATEGORY) {
+                categories.push(Value::from(TEST_CATEGORY));
+            }
+            if categories.len() > MAX_CATEGORIES {
+                return Err(SendgridErrorKind::TooManyCategories(categories.len()).into());
+            }
+        }
+        disable_sandbox(message);
+    }
+    Ok(serde_json::to_vec(&body)?)
+}
+
+// Turn off sandbox mode and nothing else, so a test send keeps the footer and the
+// other mail settings of the real one.
+#[cfg(feature = "client")]
+fn disable_sandbox(message: &mut serde_json::Map<String, Value>) {
+    let settings = message
+        .entry("mail_settings")
+        .or_insert_with(|| Value::Object(Default::default()));
+    if let Some(settings) = settings.as_object_mut() {
+        let sandbox = settings
+            .entry("sandbox_mode")
+            .or_insert_with(|| Value::Object(Default::default()));
+        if let Some(sandbox) = sandbox.as_object_mut() {
+            sandbox.insert(String::from("enable"), Value::Bool(false));
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn timeout_precedence() {
+    let profile = TimeoutProfile::new().with_mail_send(Duration::from_secs(5));
+    let options = RequestOptions::default();
+    assert_eq!(
+        options.timeout_now(profile.mail_send).unwrap(),
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(options.timeout_now(profile.transfers).unwrap(), None);
+
+    let options = RequestOptions {
+        timeout: Some(Duration::from_secs(60)),
+        deadline: Some(Instant::now() + Duration::from_secs(10)),
+        ..RequestOptions::default()
+    };
+    let timeout = options.timeout_now(profile.mail_send).unwrap().unwrap();
+    assert!(timeout <= Duration::from_secs(10));
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn send_once_keys() {
+    use idempotency::MemoryStore;
+
+    let store = MemoryStore::new();
+    let accepted = |status: &u16| *status == 202;
+    let sent = send_once_with("welcome", &store, || Ok(202), accepted).unwrap();
+    assert_eq!(sent, Some(202));
+    let sent = send_once_with("welcome", &store, || Ok(202), accepted).unwrap();
+    assert_eq!(sent, None);
+
+    // Rejected messages and errors before the request can be sent again.
+    let sent = send_once_with("invite", &store, || Ok(400), accepted).unwrap();
+    assert_eq!(sent, Some(400));
+    let unavailable = || Err(SendgridErrorKind::Api(503, String::new()).into());
+    assert!(send_once_with("invite", &store, unavailable, accepted).is_err());
+    let invalid = || Err(SendgridErrorKind::InvalidHeaderName(String::from("X:")).into());
+    assert!(send_once_with("invite", &store, invalid, accepted).is_err());
+    let sent = send_once_with("invite", &store, || Ok(202), accepted).unwrap();
+    assert_eq!(sent, Some(202));
+    let sent = send_once_with("invite", &store, || Ok(202), accepted).unwrap();
+    assert_eq!(sent, None);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn deadline_timeouts_round_down() {
+    assert_eq!(
+        round_down(Duration::from_millis(9_999)),
+        Duration::from_millis(8_192)
+    );
+    assert_eq!(
+        round_down(Duration::from_millis(8_192)),
+        Duration::from_millis(8_192)
+    );
+    assert_eq!(
+        round_down(Duration::from_micros(10)),
+        Duration::from_millis(1)
+    );
+
+    // Requests a moment apart get the same timeout, and so the same client.
+    let deadline = Instant::now() + Duration::from_millis(9_900);
+    let options = RequestOptions {
+        deadline: Some(deadline),
+        ..RequestOptions::default()
+    };
+    let first = options.timeout_now(None).unwrap();
+    let second = options.timeout_now(None).unwrap();
+    assert_eq!(first, Some(Duration::from_millis(8_192)));
+    assert_eq!(first, second);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn stored_body_checks() {
+    let email = |address: &str| {
+        let mut email = Email::new();
+        email.set_email(address);
+        email
+    };
+    let mut mail = SGMailV3::new();
+    mail.set_from(email("sender@example.com"));
+    let mut p = Personalization::new();
+    p.add_to(email("a@example.com"));
+    p.add_cc(email("b@example.com"));
+    mail.add_personalization(p);
+    mail.add_category("welcome");
+    let body = serde_json::to_vec(&mail).unwrap();
+
+    let stored = StoredMail::check(&body).unwrap();
+    assert_eq!(stored.from.email, "sender@example.com");
+    assert_eq!(stored.recipients(), 2);
+    assert_eq!(stored.categories, Some(vec![String::from("welcome")]));
+
+    let big = format!(
+        r#"{{"from":{{"email":"sender@example.com"}},"attachments":[{{"filename":"big.bin","content":"{}"}}]}}"#,
+        "A".repeat(MAX_ATTACHMENT_SIZE + 4)
+    );
+    match StoredMail::check(big.as_bytes()) {
+        Err(ref e) => match *e.kind() {
+            SendgridErrorKind::InvalidAttachments(_) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(_) => panic!("the attachment is over the limit"),
+    }
+}
+
+#[test]
+fn dynamic_template_data() {
+    #[derive(Serialize)]
+    struct Welcome<'a> {
+        first_name: &'a str,
+    }
+
+    let mut to = Email::new();
+    to.set_email("test@example.com");
+    let mut p = Personalization::new();
+    p.add_to(to);
+    p.set_dynamic_data(&Welcome {
+        first_name: "Ferris",
+    })
+    .unwrap();
+
+    let mut m = SGMailV3::new();
+    m.set_template_id("d-123");
+    m.add_personalization(p);
+
+    let want = r#"{"from":{"email":""},"subject":"","content":[],"personalizations":[{"to":[{"email":"test@example.com"}],"dynamic_template_data":{"first_name":"Ferris"}}],"template_id":"d-123"}"#;
+    assert_eq!(serde_json::to_string(&m).unwrap(), want);
+}
+
+#[test]
+fn mail_merge_chunks() {
+    let mut from = Email::new();
+    from.set_email("me@example.com");
+    let merge = MailMerge::new(from, "d-123");
+
+    let recipients = (0..2501).map(|i| {
+        let mut to = Email::new();
+        to.set_email(&format!("user{}@example.com", i));
+        (to, i)
+    });
+    let messages = merge.build(recipients).unwrap();
+
+    let sizes: Vec<usize> = messages.iter().map(|m| m.personalizations.len()).collect();
+    assert_eq!(sizes, vec![1000, 1000, 501]);
+    assert_eq!(messages[2].template_id, Some(String::from("d-123")));
+}
+
+#[test]
+fn calendar_invite() {
+    let mut m = SGMailV3::new();
+    m.add_calendar_invite("BEGIN:VCALENDAR\r\nMETHOD:REQUEST\r\nEND:VCALENDAR\r\n");
+
+    let json = serde_json::to_string(&m).unwrap();
+    assert!(json.contains(
+        r#"{"type":"text/calendar; method=REQUEST; charset=UTF-8","value":"BEGIN:VCALENDAR"#
+    ));
+    assert!(json.contains(r#""filename":"invite.ics","type":"text/calendar; method=REQUEST; charset=UTF-8","disposition":"attachment""#));
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn csv_attachment() {
+    #[derive(Serialize)]
+    struct Row {
+        day: &'static str,
+        signups: u32,
+    }
+
+    let rows = vec![
+        Row {
+            day: "monday",
+            signups: 3,
+        },
+        Row {
+            day: "tuesday",
+            signups: 5,
+        },
+    ];
+    let a = Attachment::from_csv_records("signups.csv", rows).unwrap();
+
+    assert_eq!(a.filename, "signups.csv");
+    assert_eq!(a.mime_type, Some(String::from("text/csv")));
+    assert_eq!(
+        a.content,
+        AttachmentContent::Raw(b"day,signups\nmonday,3\ntuesday,5\n".to_vec())
+    );
+}
+
+#[test]
+fn write_json_replaces_buffer() {
+    let mut m = SGMailV3::new();
+    m.set_subject("Hello");
+
+    let mut buffer = b"stale".to_vec();
+    m.write_json(&mut buffer).unwrap();
+    assert_eq!(buffer, serde_json::to_vec(&m).unwrap());
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn subuser_headers() {
+    let headers = api_headers(Some("tenant-x")).unwrap();
+    assert_eq!(
+        headers.get_raw("on-behalf-of").and_then(|raw| raw.one()),
+        Some(&b"tenant-x"[..])
+    );
+    assert!(api_headers(None).unwrap().get_raw("on-behalf-of").is_none());
+    assert!(api_headers(Some("tenant\r\nx-evil: 1")).is_err());
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn presized_json_bodies() {
+    let mut m = SGMailV3::new();
+    m.set_subject("Hello");
+    let want = serde_json::to_vec(&m).unwrap();
+
+    let body = m.gen_json(4096).unwrap();
+    assert_eq!(body, want);
+    assert!(body.capacity() >= 4096);
+    assert_eq!(m.gen_json(0).unwrap(), want);
+}
+
+#[test]
+fn shared_attachment_content() {
+    let shared = SharedContent::new(b"data");
+    let mut a = Attachment::new();
+    a.set_shared_content(&shared);
+    a.set_filename("report.txt");
+
+    let mut raw = a.clone();
+    raw.set_content(b"data");
+    assert_eq!(
+        ::serde_json::to_string(&a).unwrap(),
+        ::serde_json::to_string(&raw).unwrap()
+    );
+    assert_eq!(a.content.encoded_len(), 8);
+}
+
+#[test]
+fn non_ascii_attachment_filename() {
+    let mut a = Attachment::new();
+    a.set_content(b"data");
+    a.set_filename("Rechnungsübersicht.pdf");
+
+    let json = ::serde_json::to_string(&a).unwrap();
+    assert!(json.contains(r#""filename":"Rechnungsübersicht.pdf""#));
+}
+
+#[test]
+fn generated_message_id() {
+    let mut m = SGMailV3::new();
+    let message_id = m.generate_message_id("mail.example.com").unwrap();
+    assert_eq!(
+        m.generate_message_id("other.example.com").unwrap(),
+        message_id
+    );
+
+    let json = ::serde_json::to_string(&m).unwrap();
+    assert!(json.contains(&format!(r#""headers":{{"Message-ID":"{}"}}"#, message_id)));
+}
+
+#[test]
+fn unsubscribe_links() {
+    let mut html = Content::new();
+    html.set_content_type("text/html");
+    html.set_value("<p>News</p>");
+    let html = html.with_unsubscribe_link(UnsubscribeLink::Group, "Unsubscribe");
+    assert_eq!(
+        html.value,
+        r#"<p>News</p><p><a href="<%asm_group_unsubscribe_raw_url%>">Unsubscribe</a></p>"#
+    );
+
+    let mut text = Content::new();
+    text.set_content_type("text/plain");
+    text.set_value("News");
+    let text = text.with_unsubscribe_link(UnsubscribeLink::Preferences, "Preferences");
+    assert_eq!(
+        text.value,
+        "News\n\nPreferences: <%asm_preferences_raw_url%>\n"
+    );
+
+    let mut m = SGMailV3::new();
+    m.set_unsubscribe_group(1234, &[]);
+    let json = ::serde_json::to_string(&m).unwrap();
+    assert!(json.contains(r#""asm":{"group_id":1234}"#));
+}
+
+#[test]
+fn chunked_base64_attachment() {
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    let mut a = Attachment::new();
+    a.set_content(&data);
+
+    let json = serde_json::to_string(&a).unwrap();
+    assert!(json.starts_with(&format!(r#"{{"content":"{}","#, BASE64.encode(&data))));
+}
+
+#[test]
+fn canonical_body() {
+    let mut m = SGMailV3::new();
+    m.set_subject("Hi");
+    for key in &["zeta", "alpha", "mid"] {
+        m.add_custom_arg(key, 1);
+    }
+
+    let want = r#"{
+  "content": [],
+  "custom_args": {
+    "alpha": "1",
+    "mid": "1",
+    "zeta": "1"
+  },
+  "from": {
+    "email": ""
+  },
+  "personalizations": [],
+  "subject": "Hi"
+}"#;
+    assert_eq!(m.canonical_json().unwrap(), want);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn gzip_body() {
+    use std::io::Read;
+
+    let body = "{\"subject\":\"hello\"}".repeat(100);
+    let compressed = gzip(body.as_bytes()).unwrap();
+    assert!(compressed.len() < body.len());
+
+    let mut decoded = String::new();
+    gzip::Decoder::new(&compressed[..])
+        .unwrap()
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, body);
+}
+
+#[test]
+fn custom_args() {
+    let mut p = Personalization::new();
+    p.add_custom_arg("attempt", 2);
+    let mut m = SGMailV3::new();
+    m.add_custom_arg("user_id", "42");
+    m.add_personalization(p);
+
+    let json = serde_json::to_string(&m).unwrap();
+    assert!(json.contains(r#""custom_args":{"attempt":"2"}"#));
+    assert!(json.contains(r#""custom_args":{"user_id":"42"}"#));
+}
+
+#[test]
+fn trace_context() {
+    let mut m = SGMailV3::new();
+    m.set_trace_context("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        .unwrap();
+    let json = serde_json::to_string(&m).unwrap();
+    assert!(json.contains(r#""trace_id":"4bf92f3577b34da6a3ce929d0e0e4736""#));
+    assert!(json.contains(r#""span_id":"00f067aa0ba902b7""#));
+
+    assert!(m
+        .set_trace_context("00-4bf92f3577b34da6-00f067aa0ba902b7-01")
+        .is_err());
+    assert!(m
+        .set_trace_context("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+        .is_err());
+    for version in ["zz", "ff", "0", "+1"] {
+        let traceparent = format!(
+            "{}-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            version
+        );
+        assert!(m.set_trace_context(&traceparent).is_err());
+    }
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_send_body() {
+    let mut m = SGMailV3::new();
+    m.set_send_at(1_514_849_415);
+    m.add_category("news");
+    let mut p = Personalization::new();
+    let mut to = Email::new();
+    to.set_email("customer@example.com");
+    p.add_to(to.clone());
+    p.add_cc(to);
+    p.add_substitution("-name-", "Ferris");
+    m.add_personalization(p);
+
+    assert!(test_body(&m, &[]).is_err());
+    assert!(test_body(&m, &["not an address"]).is_err());
+
+    let body = test_body(&m, &["a@example.com", "b@example.com"]).unwrap();
+    let json: Value = serde_json::from_slice(&body).unwrap();
+    let ps = json["personalizations"].as_array().unwrap();
+    assert_eq!(ps.len(), 2);
+    assert_eq!(ps[1]["to"][0]["email"], "b@example.com");
+    assert_eq!(ps[1]["substitutions"]["-name-"], "Ferris");
+    assert!(ps[0].get("cc").is_none());
+    assert!(json.get("send_at").is_none());
+    assert_eq!(json["categories"][1], TEST_CATEGORY);
+    assert_eq!(json["mail_settings"]["sandbox_mode"]["enable"], false);
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_send_settings() {
+    let mut m = SGMailV3::new();
+    let mut p = Personalization::new();
+    let mut to = Email::new();
+    to.set_email("customer@example.com");
+    p.add_to(to);
+    m.add_personalization(p);
+
+    match *test_body(&m, &[]).unwrap_err().kind() {
+        SendgridErrorKind::NoSeedAddresses => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let mut message = serde_json::Map::new();
+    message.insert(
+        String::from("mail_settings"),
+        serde_json::from_str(
+            r#"{"footer": {"enable": true, "text": "Bye"}, "sandbox_mode": {"enable": true}}"#,
+        )
+        .unwrap(),
+    );
+    disable_sandbox(&mut message);
+    assert_eq!(message["mail_settings"]["footer"]["text"], "Bye");
+    assert_eq!(message["mail_settings"]["sandbox_mode"]["enable"], false);
+
+    for i in 0..MAX_CATEGORIES {
+        m.add_category(&format!("category-{}", i));
+    }
+    match *test_body(&m, &["a@example.com"]).unwrap_err().kind() {
+        SendgridErrorKind::TooManyCategories(11) => {}
+        ref kind => panic!("unexpected error: {}", kind),
+    }
 }
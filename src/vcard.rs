@@ -0,0 +1,151 @@
+//! A builder for contact cards that can be attached to a message.
+
+use v3::Attachment;
+
+// The line length vCard lines are folded at, in bytes.
+const LINE_LENGTH: usize = 75;
+
+/// A contact card in the vCard 3.0 format from RFC 2426. It is attached as a
+/// `text/vcard` file, which mail clients offer to add to the address book.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut card = VCard::new("Jane Doe");
+/// card.set_email("support@example.com");
+/// card.set_organization("Example Inc.");
+/// message.add_attachment(card.to_attachment("support.vcf"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct VCard {
+    name: String,
+    email: Option<String>,
+    phone: Option<String>,
+    organization: Option<String>,
+}
+
+impl VCard {
+    /// Construct a new contact card for the given full name.
+    pub fn new(name: &str) -> VCard {
+        VCard {
+            name: String::from(name),
+            ..Default::default()
+        }
+    }
+
+    /// Set the email address of the contact.
+    pub fn set_email(&mut self, email: &str) {
+        self.email = Some(String::from(email));
+    }
+
+    /// Set the phone number of the contact.
+    pub fn set_phone(&mut self, phone: &str) {
+        self.phone = Some(String::from(phone));
+    }
+
+    /// Set the organization the contact belongs to.
+    pub fn set_organization(&mut self, organization: &str) {
+        self.organization = Some(String::from(organization));
+    }
+
+    /// Render the contact card in the vCard format.
+    pub fn to_vcf(&self) -> String {
+        // The structured name is required, so make a guess at splitting the full name
+        // into the given and family names.
+        let name = self.name.trim();
+        let (given, family) = match name.rfind(' ') {
+            Some(i) => (name[..i].trim(), &name[i + 1..]),
+            None => (name, ""),
+        };
+
+        let mut vcf = String::new();
+        push_line(&mut vcf, "BEGIN:VCARD");
+        push_line(&mut vcf, "VERSION:3.0");
+        push_line(&mut vcf, &format!("FN:{}", escape(&self.name)));
+        push_line(
+            &mut vcf,
+            &format!("N:{};{};;;", escape(family), escape(given)),
+        );
+        if let Some(ref email) = self.email {
+            push_line(&mut vcf, &format!("EMAIL;TYPE=INTERNET:{}", escape(email)));
+        }
+        if let Some(ref phone) = self.phone {
+            push_line(&mut vcf, &format!("TEL;TYPE=VOICE:{}", escape(phone)));
+        }
+        if let Some(ref organization) = self.organization {
+            push_line(&mut vcf, &format!("ORG:{}", escape(organization)));
+        }
+        push_line(&mut vcf, "END:VCARD");
+        vcf
+    }
+
+    /// Turn the contact card into an attachment with the given filename, which
+    /// should end in `.vcf`.
+    pub fn to_attachment(&self, filename: &str) -> Attachment {
+        let mut a = Attachment::new();
+        a.set_content(self.to_vcf().as_bytes());
+        a.set_filename(filename);
+        a.set_mime_type("text/vcard");
+        a.set_disposition("attachment");
+        a
+    }
+}
+
+// Escape the characters that have a special meaning in vCard values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' | ',' | ';' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Write a content line, folding it onto continuation lines that start with a space when
+// it is too long.
+fn push_line(vcf: &mut String, line: &str) {
+    let mut rest = line;
+    let mut limit = LINE_LENGTH;
+
+    while rest.len() > limit {
+        let mut end = limit;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        vcf.push_str(&rest[..end]);
+        vcf.push_str("\r\n ");
+        rest = &rest[end..];
+        limit = LINE_LENGTH - 1;
+    }
+    vcf.push_str(rest);
+    vcf.push_str("\r\n");
+}
+
+#[test]
+fn contact_card() {
+    let mut card = VCard::new("Jane Q. Doe");
+    card.set_email("jane@example.com");
+    card.set_phone("+1 555 0100");
+    card.set_organization("Example, Inc.");
+
+    let want = "BEGIN:VCARD\r\n\
+                VERSION:3.0\r\n\
+                FN:Jane Q. Doe\r\n\
+                N:Doe;Jane Q.;;;\r\n\
+                EMAIL;TYPE=INTERNET:jane@example.com\r\n\
+                TEL;TYPE=VOICE:+1 555 0100\r\n\
+                ORG:Example\\, Inc.\r\n\
+                END:VCARD\r\n";
+    assert_eq!(card.to_vcf(), want);
+
+    let mut long = String::new();
+    push_line(&mut long, &format!("ORG:{}", "x".repeat(200)));
+    assert!(long.split("\r\n").all(|line| line.len() <= LINE_LENGTH));
+}
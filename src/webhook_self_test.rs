@@ -0,0 +1,215 @@
+//! Testing an Event Webhook handler with signed sample requests, without SendGrid in the
+//! loop. The requests are signed with a test keypair, whose public key the handler is
+//! configured with instead of the one from the webhook settings.
+
+use errors::SendgridResult;
+use webhooks::{sample_json, EventType, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+use data_encoding::BASE64;
+use openssl::ec::{EcGroup, EcKey, NAMED_CURVE};
+use openssl::hash::MessageDigest;
+use openssl::nid;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A request as the Event Webhook would post it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedRequest {
+    /// The JSON array of events.
+    pub body: Vec<u8>,
+    /// The value of the `SIGNATURE_HEADER` header.
+    pub signature: String,
+    /// The value of the `TIMESTAMP_HEADER` header.
+    pub timestamp: String,
+}
+
+impl SignedRequest {
+    /// The headers of the request, as name and value pairs.
+    pub fn headers(&self) -> [(&'static str, &str); 2] {
+        [
+            (SIGNATURE_HEADER, &self.signature[..]),
+            (TIMESTAMP_HEADER, &self.timestamp[..]),
+        ]
+    }
+}
+
+/// What went wrong in a self-test. An empty report means the handler accepted the
+/// signed sample events and rejected every request that was tampered with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SelfTestReport {
+    /// A description of every check the handler failed.
+    pub failures: Vec<String>,
+}
+
+impl SelfTestReport {
+    /// Whether the handler passed every check.
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.passed() {
+            return write!(f, "the webhook handler passed every check");
+        }
+        write!(
+            f,
+            "the webhook handler failed: {}",
+            self.failures.join("; ")
+        )
+    }
+}
+
+/// Signs sample Event Webhook requests with a generated P-256 keypair and feeds them to
+/// a handler.
+///
+/// # Examples
+///
+/// ```ignore
+/// let self_test = WebhookSelfTest::new()?;
+/// let mut verifiers = VerifierSet::new();
+/// verifiers.add_key(self_test.public_key())?;
+/// let report = self_test.run(|request| {
+///     // Or post the request to the handler running on localhost.
+///     verifiers.verify(&request.body, &request.signature, &request.timestamp)
+///         && handle(parse_events(&request.body).0).is_ok()
+/// })?;
+/// assert!(report.passed(), "{}", report);
+/// ```
+pub struct WebhookSelfTest {
+    key: PKey,
+    public_key: String,
+    sample: Vec<u8>,
+}
+
+impl WebhookSelfTest {
+    /// Construct a self-test with a new keypair.
+    pub fn new() -> SendgridResult<WebhookSelfTest> {
+        let key = generate_key()?;
+        let public_key = BASE64.encode(&key.public_key_to_der()?);
+        let events: Vec<&str> = EventType::ALL
+            .iter()
+            .map(|&kind| sample_json(kind))
+            .collect();
+        Ok(WebhookSelfTest {
+            key,
+            public_key,
+            sample: format!("[{}]", events.join(",")).into_bytes(),
+        })
+    }
+
+    /// The base64 encoded public key of the keypair, in the format the webhook settings
+    /// show it in, for the handler to verify requests with.
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// A batch with one sample event of every type.
+    pub fn sample_body(&self) -> &[u8] {
+        &self.sample
+    }
+
+    /// Sign a body with the current time as the timestamp.
+    pub fn sign(&self, body: &[u8]) -> SendgridResult<SignedRequest> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        sign_with(&self.key, body, &timestamp.to_string())
+    }
+
+    /// Feed signed sample requests to a handler, which returns whether it accepted the
+    /// request. The handler should accept the batch of sample events, and reject it once
+    /// the body, the timestamp or the signing key were changed or the signature was left
+    /// out.
+    pub fn run<F>(&self, mut handler: F) -> SendgridResult<SelfTestReport>
+    where
+        F: FnMut(&SignedRequest) -> bool,
+    {
+        let mut report = SelfTestReport::default();
+        let signed = self.sign(&self.sample)?;
+        if !handler(&signed) {
+            report.failures.push(String::from(
+                "rejected a correctly signed batch of sample events",
+            ));
+        }
+
+        let mut tampered = signed.clone();
+        tampered.body = String::from_utf8_lossy(&self.sample)
+            .replace("example@test.com", "other@test.com")
+            .into_bytes();
+        if handler(&tampered) {
+            report.failures.push(String::from(
+                "accepted a body that was changed after signing",
+            ));
+        }
+
+        let mut retimed = signed.clone();
+        retimed.timestamp = format!("{}0", signed.timestamp);
+        if handler(&retimed) {
+            report.failures.push(String::from(
+                "accepted a timestamp that was changed after signing",
+            ));
+        }
+
+        let other = sign_with(&generate_key()?, &signed.body, &signed.timestamp)?;
+        if handler(&other) {
+            report
+                .failures
+                .push(String::from("accepted a batch signed with another key"));
+        }
+
+        let mut unsigned = signed;
+        unsigned.signature = String::new();
+        if handler(&unsigned) {
+            report
+                .failures
+                .push(String::from("accepted a batch without a signature"));
+        }
+
+        Ok(report)
+    }
+}
+
+fn generate_key() -> SendgridResult<PKey> {
+    let mut group = EcGroup::from_curve_name(nid::X9_62_PRIME256V1)?;
+    // The public key has to name the curve for SendGrid's format.
+    group.set_asn1_flag(NAMED_CURVE);
+    let key = EcKey::generate(&group)?;
+    Ok(PKey::from_ec_key(key)?)
+}
+
+fn sign_with(key: &PKey, body: &[u8], timestamp: &str) -> SendgridResult<SignedRequest> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(timestamp.as_bytes())?;
+    signer.update(body)?;
+    Ok(SignedRequest {
+        body: body.to_vec(),
+        signature: BASE64.encode(&signer.sign_to_vec()?),
+        timestamp: String::from(timestamp),
+    })
+}
+
+#[test]
+fn self_test_handlers() {
+    use webhooks::{parse_events, VerifierSet};
+
+    let self_test = WebhookSelfTest::new().unwrap();
+    let mut verifiers = VerifierSet::new();
+    verifiers.add_key(self_test.public_key()).unwrap();
+
+    let report = self_test
+        .run(|request| {
+            verifiers.verify(&request.body, &request.signature, &request.timestamp)
+                && parse_events(&request.body).0.len() == 11
+        })
+        .unwrap();
+    assert!(report.passed(), "{}", report);
+
+    let report = self_test.run(|_| true).unwrap();
+    assert_eq!(report.failures.len(), 4);
+}
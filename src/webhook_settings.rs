@@ -0,0 +1,118 @@
+//! Managing the settings of the Event Webhook.
+
+use errors::SendgridResult;
+use scopes::Endpoint;
+use v3::V3Sender;
+
+use reqwest::Method;
+
+/// Testing and configuring the Event Webhook.
+pub struct WebhookSettings;
+
+impl Endpoint for WebhookSettings {
+    const SCOPES: &'static [&'static str] = &[
+        "user.webhooks.event.settings.read",
+        "user.webhooks.event.settings.update",
+        "user.webhooks.event.test.create",
+    ];
+}
+
+/// Where to post a test event, and how to authenticate when the endpoint uses OAuth.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TestEvent {
+    /// The URL of the endpoint to post the test event to.
+    pub url: String,
+    /// The ID of an existing webhook, to test it with its saved settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_token_url: Option<String>,
+}
+
+impl TestEvent {
+    /// Construct a test event for the given URL.
+    pub fn new(url: &str) -> TestEvent {
+        TestEvent {
+            url: String::from(url),
+            ..Default::default()
+        }
+    }
+}
+
+/// The settings of the Event Webhook: where it posts and which events it posts.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EventWebhookSettings {
+    pub enabled: bool,
+    pub url: String,
+    pub processed: bool,
+    pub dropped: bool,
+    pub delivered: bool,
+    pub deferred: bool,
+    pub bounce: bool,
+    pub open: bool,
+    pub click: bool,
+    pub spam_report: bool,
+    pub unsubscribe: bool,
+    pub group_unsubscribe: bool,
+    pub group_resubscribe: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oauth_token_url: Option<String>,
+    /// Event types and settings SendGrid added that don't have a field here yet.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct SignedSettings {
+    #[serde(default)]
+    public_key: String,
+}
+
+impl V3Sender {
+    /// Get the settings of the Event Webhook.
+    pub fn event_webhook_settings(&self) -> SendgridResult<EventWebhookSettings> {
+        self.api_get("/user/webhooks/event/settings")
+    }
+
+    /// Get the public key that signs Event Webhook requests, or `None` if signing is
+    /// turned off. Add it to a `webhooks::VerifierSet` to verify the requests.
+    pub fn event_webhook_public_key(&self) -> SendgridResult<Option<String>> {
+        let settings: SignedSettings = self.api_get("/user/webhooks/event/settings/signed")?;
+        if settings.public_key.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(settings.public_key))
+        }
+    }
+
+    /// Make SendGrid post a sample event to a webhook endpoint, e.g. to check a staging
+    /// endpoint from an integration test.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// sender.test_event_webhook(&TestEvent::new("https://staging.example.com/events"))?;
+    /// ```
+    pub fn test_event_webhook(&self, event: &TestEvent) -> SendgridResult<()> {
+        self.api_send_json(Method::Post, "/user/webhooks/event/test", event)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_event_body() {
+    let mut event = TestEvent::new("https://example.com/events");
+    event.oauth_client_id = Some(String::from("client"));
+    assert_eq!(
+        ::serde_json::to_string(&event).unwrap(),
+        r#"{"url":"https://example.com/events","oauth_client_id":"client"}"#
+    );
+}
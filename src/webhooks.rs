@@ -0,0 +1,702 @@
+//! Types for the events posted by the SendGrid Event Webhook.
+//!
+//! The webhook posts a JSON array of events. Every event has the fields in `EventInfo`,
+//! and the `event` field decides which other fields it has.
+//!
+//! # Web frameworks
+//!
+//! There are no extractors for web frameworks, as the crate is blocking and doesn't depend
+//! on an async runtime. A handler reads the whole body, checks the signature with
+//! `VerifierSet::verify` when the `signed-webhooks` feature is enabled, and passes the
+//! body to `parse_events`. Inbound Parse requests are parsed the same way with
+//! `inbound::InboundEmail::parse` and the Content-Type header. With actix-web, take the
+//! body as `web::Bytes` and read the headers from the `HttpRequest`.
+//!
+//! With axum, take the body as `Bytes` together with the `HeaderMap`, and answer
+//! `StatusCode::UNAUTHORIZED` when the signature doesn't verify.
+//!
+//! With warp, combine `warp::body::bytes()` with `warp::header::<String>` filters for
+//! `SIGNATURE_HEADER` and `TIMESTAMP_HEADER`, and reject requests that don't verify. The
+//! signature is ECDSA with SHA-256 over the timestamp and the body, not Ed25519, so the
+//! public key from the webhook settings goes to `VerifierSet::add_key` as it is.
+
+use bounces::BounceClass;
+use idempotency::{IdempotencyStore, LruStore};
+
+use serde::{Deserialize, Deserializer};
+use serde_json::{self, Value};
+
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "signed-webhooks")]
+use data_encoding::BASE64;
+#[cfg(feature = "signed-webhooks")]
+use errors::{SendgridErrorKind, SendgridResult};
+#[cfg(feature = "signed-webhooks")]
+use openssl::hash::MessageDigest;
+#[cfg(feature = "signed-webhooks")]
+use openssl::pkey::PKey;
+#[cfg(feature = "signed-webhooks")]
+use openssl::sign::Verifier;
+
+/// The header with the signature of a signed Event Webhook request.
+pub const SIGNATURE_HEADER: &str = "X-Twilio-Email-Event-Webhook-Signature";
+
+/// The header with the timestamp of a signed Event Webhook request.
+pub const TIMESTAMP_HEADER: &str = "X-Twilio-Email-Event-Webhook-Timestamp";
+
+/// The fields shared by all events.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct EventInfo {
+    /// The address of the recipient.
+    pub email: String,
+    /// The Unix timestamp of when the event happened.
+    pub timestamp: i64,
+    /// The SMTP ID attached to the message by SendGrid.
+    #[serde(rename = "smtp-id")]
+    pub smtp_id: Option<String>,
+    /// A unique ID for the event, which can be used to drop duplicate deliveries.
+    pub sg_event_id: String,
+    /// The ID of the message the event is about.
+    pub sg_message_id: Option<String>,
+    /// The categories of the message.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub category: Vec<String>,
+    /// The correlation ID of messages sent with `V3Sender::send_tracked`.
+    pub correlation_id: Option<String>,
+    /// The trace ID of messages stamped with `SGMailV3::set_trace_context`.
+    pub trace_id: Option<String>,
+    /// The span ID of messages stamped with `SGMailV3::set_trace_context`.
+    pub span_id: Option<String>,
+    /// The fields of the event this crate doesn't model yet.
+    #[cfg(feature = "extra-fields")]
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// A message was received and is ready to be delivered.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ProcessedEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+}
+
+/// A message was not delivered, e.g. because the recipient is suppressed.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DroppedEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// Why the message was dropped.
+    pub reason: String,
+}
+
+/// A message was accepted by the receiving server.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DeliveredEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The response of the receiving server.
+    pub response: Option<String>,
+}
+
+/// The receiving server temporarily rejected a message, which will be tried again.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DeferredEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The response of the receiving server.
+    pub response: Option<String>,
+    /// The number of delivery attempts so far.
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub attempt: Option<u32>,
+}
+
+/// The receiving server permanently rejected a message.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct BounceEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The reason given by the receiving server.
+    pub reason: String,
+    /// The SMTP status code, e.g. `5.0.0`.
+    pub status: Option<String>,
+    /// Either `bounce` or `blocked`.
+    #[serde(rename = "type")]
+    pub bounce_type: Option<String>,
+    /// SendGrid's classification of the bounce, e.g. `Invalid Address`.
+    pub bounce_classification: Option<String>,
+}
+
+impl BounceEvent {
+    /// Classify the bounce by its reason and status. When those don't tell, blocks
+    /// reported as such are `Blocked`, and SendGrid's own classification is used.
+    pub fn classification(&self) -> BounceClass {
+        let class = BounceClass::classify(&self.reason, self.status.as_deref());
+        match class {
+            BounceClass::HardBounce | BounceClass::Unknown
+                if self.bounce_type.as_deref() == Some("blocked") =>
+            {
+                BounceClass::Blocked
+            }
+            BounceClass::Unknown => self
+                .bounce_classification
+                .as_ref()
+                .map_or(BounceClass::Unknown, |c| BounceClass::from_sendgrid(c)),
+            class => class,
+        }
+    }
+}
+
+/// A recipient opened a message.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OpenEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The user agent of the mail client.
+    pub useragent: Option<String>,
+    /// The IP address the message was opened from.
+    pub ip: Option<String>,
+}
+
+/// A recipient clicked a link in a message.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ClickEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The URL that was clicked.
+    pub url: String,
+    /// The user agent of the browser.
+    pub useragent: Option<String>,
+    /// The IP address the link was clicked from.
+    pub ip: Option<String>,
+}
+
+/// A recipient marked a message as spam.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct SpamReportEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+}
+
+/// A recipient unsubscribed from all messages.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct UnsubscribeEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+}
+
+/// A recipient unsubscribed from or resubscribed to an unsubscribe group.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct GroupEvent {
+    #[serde(flatten)]
+    pub info: EventInfo,
+    /// The ID of the unsubscribe group.
+    pub asm_group_id: u64,
+    /// The user agent of the browser.
+    pub useragent: Option<String>,
+    /// The IP address of the recipient.
+    pub ip: Option<String>,
+}
+
+/// An event posted by the Event Webhook.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Processed(ProcessedEvent),
+    Dropped(DroppedEvent),
+    Delivered(DeliveredEvent),
+    Deferred(DeferredEvent),
+    Bounce(BounceEvent),
+    Open(OpenEvent),
+    Click(ClickEvent),
+    #[serde(rename = "spamreport")]
+    SpamReport(SpamReportEvent),
+    Unsubscribe(UnsubscribeEvent),
+    GroupUnsubscribe(GroupEvent),
+    GroupResubscribe(GroupEvent),
+}
+
+/// The types of events the Event Webhook posts.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventType {
+    Processed,
+    Dropped,
+    Delivered,
+    Deferred,
+    Bounce,
+    Open,
+    Click,
+    SpamReport,
+    Unsubscribe,
+    GroupUnsubscribe,
+    GroupResubscribe,
+}
+
+impl EventType {
+    /// Every type of event.
+    pub const ALL: [EventType; 11] = [
+        EventType::Processed,
+        EventType::Dropped,
+        EventType::Delivered,
+        EventType::Deferred,
+        EventType::Bounce,
+        EventType::Open,
+        EventType::Click,
+        EventType::SpamReport,
+        EventType::Unsubscribe,
+        EventType::GroupUnsubscribe,
+        EventType::GroupResubscribe,
+    ];
+
+    /// The value of the `event` field for this type.
+    pub fn name(self) -> &'static str {
+        match self {
+            EventType::Processed => "processed",
+            EventType::Dropped => "dropped",
+            EventType::Delivered => "delivered",
+            EventType::Deferred => "deferred",
+            EventType::Bounce => "bounce",
+            EventType::Open => "open",
+            EventType::Click => "click",
+            EventType::SpamReport => "spamreport",
+            EventType::Unsubscribe => "unsubscribe",
+            EventType::GroupUnsubscribe => "group_unsubscribe",
+            EventType::GroupResubscribe => "group_resubscribe",
+        }
+    }
+}
+
+impl Event {
+    /// The type of the event.
+    pub fn event_type(&self) -> EventType {
+        match *self {
+            Event::Processed(_) => EventType::Processed,
+            Event::Dropped(_) => EventType::Dropped,
+            Event::Delivered(_) => EventType::Delivered,
+            Event::Deferred(_) => EventType::Deferred,
+            Event::Bounce(_) => EventType::Bounce,
+            Event::Open(_) => EventType::Open,
+            Event::Click(_) => EventType::Click,
+            Event::SpamReport(_) => EventType::SpamReport,
+            Event::Unsubscribe(_) => EventType::Unsubscribe,
+            Event::GroupUnsubscribe(_) => EventType::GroupUnsubscribe,
+            Event::GroupResubscribe(_) => EventType::GroupResubscribe,
+        }
+    }
+
+    /// A realistic event of the given type, as the webhook would post it, for unit tests
+    /// of event handling.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for &kind in &EventType::ALL {
+    ///     handle(Event::sample(kind))?;
+    /// }
+    /// ```
+    #[cfg(feature = "testing")]
+    pub fn sample(kind: EventType) -> Event {
+        serde_json::from_str(sample_json(kind)).expect("the sample events are valid")
+    }
+
+    /// The fields shared by all events.
+    pub fn info(&self) -> &EventInfo {
+        match *self {
+            Event::Processed(ref e) => &e.info,
+            Event::Dropped(ref e) => &e.info,
+            Event::Delivered(ref e) => &e.info,
+            Event::Deferred(ref e) => &e.info,
+            Event::Bounce(ref e) => &e.info,
+            Event::Open(ref e) => &e.info,
+            Event::Click(ref e) => &e.info,
+            Event::SpamReport(ref e) => &e.info,
+            Event::Unsubscribe(ref e) => &e.info,
+            Event::GroupUnsubscribe(ref e) | Event::GroupResubscribe(ref e) => &e.info,
+        }
+    }
+}
+
+/// Drops events that were already seen, as SendGrid delivers every event at least once.
+/// Events are recognized by their `sg_event_id`, which is recorded in an
+/// `IdempotencyStore`, an in-memory `LruStore` by default.
+///
+/// # Examples
+///
+/// ```ignore
+/// let dedup = EventDeduplicator::with_capacity(100_000);
+/// let (events, _) = parse_events(&body);
+/// for event in dedup.filter(events) {
+///     handle(event);
+/// }
+/// ```
+pub struct EventDeduplicator<S: IdempotencyStore = LruStore> {
+    store: S,
+}
+
+impl EventDeduplicator<LruStore> {
+    /// Construct a deduplicator that remembers the IDs of up to `capacity` events.
+    pub fn with_capacity(capacity: usize) -> EventDeduplicator<LruStore> {
+        EventDeduplicator::new(LruStore::new(capacity))
+    }
+}
+
+impl<S: IdempotencyStore> EventDeduplicator<S> {
+    /// Construct a deduplicator that records the event IDs in the given store.
+    pub fn new(store: S) -> EventDeduplicator<S> {
+        EventDeduplicator { store }
+    }
+
+    /// Remove the events that were seen before, including duplicates within the batch.
+    pub fn filter(&self, events: Vec<Event>) -> Vec<Event> {
+        events
+            .into_iter()
+            .filter(|event| self.store.insert(&event.info().sg_event_id))
+            .collect()
+    }
+}
+
+/// Verifies the ECDSA signature of signed Event Webhook requests against a set of public
+/// keys. When the signing key is rotated, add the new key before switching and remove
+/// the old one once requests signed with it stopped coming in, so no request fails
+/// verification in between.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut verifiers = VerifierSet::new();
+/// if let Some(key) = sender.event_webhook_public_key()? {
+///     verifiers.add_key(&key)?;
+/// }
+/// if !verifiers.verify(&body, signature, timestamp) {
+///     return Err(Unauthorized);
+/// }
+/// ```
+#[cfg(feature = "signed-webhooks")]
+#[derive(Default)]
+pub struct VerifierSet {
+    keys: Vec<(String, PKey)>,
+}
+
+#[cfg(feature = "signed-webhooks")]
+impl VerifierSet {
+    /// Construct a set without any keys, which verifies no requests.
+    pub fn new() -> VerifierSet {
+        VerifierSet::default()
+    }
+
+    /// Add a base64 encoded public key, as shown in the webhook settings.
+    pub fn add_key(&mut self, public_key: &str) -> SendgridResult<()> {
+        let public_key = public_key.trim();
+        if self.keys.iter().any(|k| k.0 == public_key) {
+            return Ok(());
+        }
+
+        let invalid = || SendgridErrorKind::InvalidPublicKey(String::from(public_key));
+        let der = BASE64
+            .decode(public_key.as_bytes())
+            .map_err(|_| invalid())?;
+        let key = PKey::public_key_from_der(&der).map_err(|_| invalid())?;
+        self.keys.push((String::from(public_key), key));
+        Ok(())
+    }
+
+    /// Remove a public key, e.g. once it was rotated out.
+    pub fn remove_key(&mut self, public_key: &str) {
+        self.keys.retain(|k| k.0 != public_key.trim());
+    }
+
+    /// Check that a request body was signed by any of the keys, given the values of the
+    /// `SIGNATURE_HEADER` and `TIMESTAMP_HEADER` headers. The body must be exactly as
+    /// received.
+    pub fn verify(&self, body: &[u8], signature: &str, timestamp: &str) -> bool {
+        let signature = match BASE64.decode(signature.trim().as_bytes()) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        self.keys.iter().any(|k| {
+            let verify = || -> Result<bool, ::openssl::error::ErrorStack> {
+                let mut verifier = Verifier::new(MessageDigest::sha256(), &k.1)?;
+                verifier.update(timestamp.as_bytes())?;
+                verifier.update(body)?;
+                verifier.verify(&signature)
+            };
+            verify().unwrap_or(false)
+        })
+    }
+}
+
+/// An event of a webhook request that couldn't be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// The position of the event in the batch, or `None` when the body is not a JSON
+    /// array at all.
+    pub index: Option<usize>,
+    /// The `event` field of the event, if it has one.
+    pub event: Option<String>,
+    /// The event as it was posted.
+    pub raw: Value,
+    /// Why the event couldn't be parsed.
+    pub reason: String,
+}
+
+impl ParseError {
+    /// Whether the event is of a type this crate doesn't know yet, rather than a known
+    /// type with malformed fields.
+    pub fn is_unknown_type(&self) -> bool {
+        self.event
+            .as_ref()
+            .is_some_and(|event| EventType::ALL.iter().all(|kind| kind.name() != event))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "event {} couldn't be parsed: {}", index, self.reason),
+            None => write!(f, "the webhook body couldn't be parsed: {}", self.reason),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parse the body of an Event Webhook request into its events. Events that can't be
+/// parsed, such as events of types added to SendGrid after this crate, are returned
+/// next to the others instead of failing the whole batch, so the delivery can still be
+/// acknowledged.
+pub fn parse_events(body: &[u8]) -> (Vec<Event>, Vec<ParseError>) {
+    let batch: Vec<Value> = match serde_json::from_slice(body) {
+        Ok(batch) => batch,
+        Err(e) => {
+            let error = ParseError {
+                index: None,
+                event: None,
+                raw: Value::String(String::from_utf8_lossy(body).into_owned()),
+                reason: e.to_string(),
+            };
+            return (Vec::new(), vec![error]);
+        }
+    };
+
+    let mut events = Vec::with_capacity(batch.len());
+    let mut errors = Vec::new();
+    for (index, raw) in batch.into_iter().enumerate() {
+        match Event::deserialize(&raw) {
+            Ok(event) => events.push(event),
+            Err(e) => errors.push(ParseError {
+                index: Some(index),
+                event: raw["event"].as_str().map(String::from),
+                reason: e.to_string(),
+                raw,
+            }),
+        }
+    }
+    (events, errors)
+}
+
+/// The body of a webhook request with a sample event of every given type, for tests
+/// that feed the raw body to the handler.
+#[cfg(feature = "testing")]
+pub fn sample_body(kinds: &[EventType]) -> Vec<u8> {
+    let events: Vec<&str> = kinds.iter().map(|&kind| sample_json(kind)).collect();
+    format!("[{}]", events.join(",")).into_bytes()
+}
+
+// The JSON of a sample event of every type, following the examples in the Event Webhook
+// reference.
+#[cfg(any(feature = "signed-webhooks", feature = "testing"))]
+pub(crate) fn sample_json(kind: EventType) -> &'static str {
+    match kind {
+        EventType::Processed => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"processed","category":"cat facts","sg_event_id":"rbtnWrG1DVDGGGFHFyun0A==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.000000000000000000000"}"#
+        }
+        EventType::Dropped => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"dropped","category":"cat facts","sg_event_id":"zmzJhfJgAfUSOW80yEbPyw==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","reason":"Bounced Address","status":"5.0.0"}"#
+        }
+        EventType::Delivered => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"delivered","category":"cat facts","sg_event_id":"rWVYmVk90MjZJ9iohOBa3w==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","response":"250 OK"}"#
+        }
+        EventType::Deferred => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"deferred","category":"cat facts","sg_event_id":"t7LEShmowp86DTdUW8M-GQ==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","response":"400 try again later","attempt":"5"}"#
+        }
+        EventType::Bounce => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"bounce","category":"cat facts","sg_event_id":"6g4ZI7SA-xmRDv57GoPIPw==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","reason":"500 unknown recipient","status":"5.0.0","type":"bounce","bounce_classification":"Invalid Address"}"#
+        }
+        EventType::Open => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"event":"open","category":"cat facts","sg_event_id":"FOTFFO0ecsBE-zxFXfs6WA==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","useragent":"Mozilla/4.0 (compatible; MSIE 6.1; Windows XP; .NET CLR 1.1.4322; .NET CLR 2.0.50727)","ip":"255.255.255.255"}"#
+        }
+        EventType::Click => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"event":"click","category":"cat facts","sg_event_id":"kCAi1KttyQdEKHhdC-nuEA==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","useragent":"Mozilla/4.0 (compatible; MSIE 6.1; Windows XP; .NET CLR 1.1.4322; .NET CLR 2.0.50727)","ip":"255.255.255.255","url":"http://www.sendgrid.com/"}"#
+        }
+        EventType::SpamReport => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"spamreport","category":"cat facts","sg_event_id":"37nvH5QBz858KGVYCM4uOA==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0"}"#
+        }
+        EventType::Unsubscribe => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"event":"unsubscribe","category":"cat facts","sg_event_id":"zz_BjPgU_5pS-J8vlfB1sg==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0"}"#
+        }
+        EventType::GroupUnsubscribe => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"event":"group_unsubscribe","category":"cat facts","sg_event_id":"ahSCB7xYcXFb-hEaawsPRw==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","useragent":"Mozilla/4.0 (compatible; MSIE 6.1; Windows XP; .NET CLR 1.1.4322; .NET CLR 2.0.50727)","ip":"255.255.255.255","asm_group_id":10}"#
+        }
+        EventType::GroupResubscribe => {
+            r#"{"email":"example@test.com","timestamp":1513299569,"event":"group_resubscribe","category":"cat facts","sg_event_id":"w_u0vJhLT-OFfprar5N93g==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","useragent":"Mozilla/4.0 (compatible; MSIE 6.1; Windows XP; .NET CLR 1.1.4322; .NET CLR 2.0.50727)","ip":"255.255.255.255","asm_group_id":10}"#
+        }
+    }
+}
+
+// Categories are sent as a string when a message has one and as an array otherwise.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(category) => vec![category],
+        OneOrMany::Many(categories) => categories,
+    })
+}
+
+// Some numeric fields are sent as strings.
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(u32),
+        String(String),
+    }
+
+    Ok(match Option::<NumberOrString>::deserialize(deserializer)? {
+        Some(NumberOrString::Number(n)) => Some(n),
+        Some(NumberOrString::String(s)) => s.parse().ok(),
+        None => None,
+    })
+}
+
+#[test]
+fn parse_event_batch() {
+    let body = r#"[
+        {"email":"a@example.com","timestamp":1513299569,"smtp-id":"<14c5d75ce93.dfd.64b469@ismtpd-555>","event":"bounce","category":"cat facts","sg_event_id":"6g4ZI7SA-xmRDv57GoPIPw==","sg_message_id":"14c5d75ce93.dfd.64b469.filter0001.16648.5515E0B88.0","reason":"500 unknown recipient","status":"5.0.0","type":"bounce"},
+        {"email":"b@example.com","timestamp":1513299570,"event":"click","category":["a","b"],"sg_event_id":"kCAi1KttyQdEKHhdC-nuEA==","url":"https://example.com","useragent":"Mozilla/5.0","ip":"255.255.255.255","url_offset":{"index":0,"type":"html"}},
+        {"email":"c@example.com","timestamp":1513299571,"event":"deferred","sg_event_id":"x","response":"400 try again later","attempt":"5"},
+        {"email":"d@example.com","timestamp":1513299572,"event":"group_unsubscribe","sg_event_id":"y","asm_group_id":10}
+    ]"#;
+    let (events, errors) = parse_events(body.as_bytes());
+    assert!(errors.is_empty());
+
+    match events[0] {
+        Event::Bounce(ref e) => {
+            assert_eq!(e.reason, "500 unknown recipient");
+            assert_eq!(e.bounce_type, Some(String::from("bounce")));
+            assert_eq!(e.info.category, vec!["cat facts"]);
+        }
+        ref e => panic!("unexpected event: {:?}", e),
+    }
+    match events[1] {
+        Event::Click(ref e) => {
+            assert_eq!(e.url, "https://example.com");
+            assert_eq!(e.info.category, vec!["a", "b"]);
+        }
+        ref e => panic!("unexpected event: {:?}", e),
+    }
+    match events[2] {
+        Event::Deferred(ref e) => assert_eq!(e.attempt, Some(5)),
+        ref e => panic!("unexpected event: {:?}", e),
+    }
+    match events[3] {
+        Event::GroupUnsubscribe(ref e) => assert_eq!(e.asm_group_id, 10),
+        ref e => panic!("unexpected event: {:?}", e),
+    }
+    assert_eq!(events[3].info().email, "d@example.com");
+}
+
+#[test]
+fn parse_partial_batch() {
+    let body = br#"[
+        {"email":"a@example.com","timestamp":1,"event":"processed","sg_event_id":"1"},
+        {"email":"a@example.com","timestamp":1,"event":"machine_opened","sg_event_id":"2"},
+        {"email":"a@example.com","timestamp":1,"event":"bounce","sg_event_id":"3"}
+    ]"#;
+    let (events, errors) = parse_events(body);
+    assert_eq!(events.len(), 1);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].index, Some(1));
+    assert!(errors[0].is_unknown_type());
+    assert_eq!(errors[1].event, Some(String::from("bounce")));
+    assert!(!errors[1].is_unknown_type());
+    assert_eq!(errors[1].raw["sg_event_id"], "3");
+
+    let (events, errors) = parse_events(b"not json");
+    assert!(events.is_empty());
+    assert_eq!(errors[0].index, None);
+}
+
+#[cfg(feature = "extra-fields")]
+#[test]
+fn keep_unknown_fields() {
+    let body = br#"[{"email":"a@example.com","timestamp":1,"event":"bounce","sg_event_id":"1","reason":"500 unknown recipient","pool":{"name":"new_MY_test","id":210},"tls":1}]"#;
+    let (events, _) = parse_events(body);
+    let info = events[0].info();
+    assert_eq!(info.extra["pool"]["id"], 210);
+    assert_eq!(info.extra["tls"], 1);
+    assert!(!info.extra.contains_key("reason"));
+    assert!(!info.extra.contains_key("event"));
+}
+
+#[test]
+fn deduplicate_events() {
+    let batch = |ids: &[&str]| {
+        let events: Vec<String> = ids
+            .iter()
+            .map(|id| {
+                format!(
+                    r#"{{"email":"a@example.com","timestamp":1,"event":"processed","sg_event_id":"{}"}}"#,
+                    id
+                )
+            })
+            .collect();
+        parse_events(format!("[{}]", events.join(",")).as_bytes()).0
+    };
+
+    let dedup = EventDeduplicator::with_capacity(10);
+    assert_eq!(dedup.filter(batch(&["1", "2", "1"])).len(), 2);
+    let events = dedup.filter(batch(&["2", "3"]));
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].info().sg_event_id, "3");
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn sample_events() {
+    for &kind in &EventType::ALL {
+        assert_eq!(Event::sample(kind).event_type(), kind);
+    }
+    let (events, errors) = parse_events(&sample_body(&EventType::ALL));
+    assert_eq!(events.len(), EventType::ALL.len());
+    assert!(errors.is_empty());
+}
+
+#[cfg(feature = "signed-webhooks")]
+#[test]
+fn verify_signatures() {
+    let old_key = "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAENr0++/3ZPhLHavLbN8wdkKcnIHGX27qDXG6YF/jjqFKosTvx4wgbXVT6nnzKS+uncmqIQFU2HfqJZw3JRUwQyw==";
+    let new_key = "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEMaT3PeCANSubZak+KIjwBDia5BzVyYi7Eg9K6LuniKFf2yynjVUvfhCE7BOLqFRQlnqrF9OoGTJK3ysfw3XPHQ==";
+    // Signed with the old key.
+    let signature = "MEUCIQCmNX6uv+KaVD3ele0OYfJ+lBSfiUupcQ9E86h3FxB9jwIgaw4cY6K5ekdQTydv1mfnEqJffqbwz7+6w0lIwUeZLJY=";
+    let body = br#"[{"event":"processed"}]"#;
+
+    let mut verifiers = VerifierSet::new();
+    assert!(!verifiers.verify(body, signature, "1600000000"));
+    verifiers.add_key(new_key).unwrap();
+    verifiers.add_key(old_key).unwrap();
+    assert!(verifiers.verify(body, signature, "1600000000"));
+    assert!(!verifiers.verify(body, signature, "1600000001"));
+    assert!(!verifiers.verify(b"[]", signature, "1600000000"));
+
+    verifiers.remove_key(old_key);
+    assert!(!verifiers.verify(body, signature, "1600000000"));
+    assert!(verifiers.add_key("not a key").is_err());
+}